@@ -338,8 +338,12 @@ class RenderDaemon:
 
         return png
 
-    def render_batch(self, views: list[dict[str, Any]]) -> list[bytes]:
-        """Render multiple views efficiently."""
+    def render_batch(self, views: list[dict[str, Any]]) -> list[bytes | MlnativeError]:
+        """Render multiple views efficiently.
+
+        Returns each view's PNG bytes, or an MlnativeError for a view that failed.
+        Raises only if the whole batch failed.
+        """
         if not self._initialized:
             raise MlnativeError("Renderer not initialized")
         if len(views) > MAX_BATCH_VIEWS:
@@ -361,10 +365,19 @@ class RenderDaemon:
             )
 
         pngs = response.get("pngs")
-        if not isinstance(pngs, list):
+        outcomes = response.get("views")
+        if not isinstance(pngs, list) or not isinstance(outcomes, list):
             raise MlnativeError("Batch render returned no image data")
 
-        return pngs
+        payloads = iter(pngs)
+        results: list[bytes | MlnativeError] = []
+        for i, outcome in enumerate(outcomes):
+            if "png_len" in outcome:
+                results.append(next(payloads))
+            else:
+                error = self._format_renderer_error(outcome.get("error"))
+                results.append(MlnativeError(f"view {i}: {error}"))
+        return results
 
     def reload_style(self, style: str) -> None:
         """Reload the style without restarting the daemon.
@@ -391,6 +404,33 @@ class RenderDaemon:
                 f"Style reload failed: {self._format_renderer_error(response.get('error'))}"
             )
 
+    def resize(self, width: int, height: int) -> None:
+        """Change the map size without reloading the style.
+
+        Args:
+            width: New width in pixels
+            height: New height in pixels
+
+        Raises:
+            MlnativeError: If the resize fails
+        """
+        if not self._initialized:
+            raise MlnativeError("Renderer not initialized")
+
+        cmd = {
+            "cmd": "resize",
+            "width": width,
+            "height": height,
+        }
+
+        response = self._send_command(cmd)
+
+        if response.get("status") != "ok":
+            self._log_renderer_error("renderer resize failed", response.get("error"))
+            raise MlnativeError(
+                f"Resize failed: {self._format_renderer_error(response.get('error'))}"
+            )
+
     def stop(self) -> None:
         """Stop the daemon."""
         if self._process is not None and self._process.poll() is None:
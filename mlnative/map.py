@@ -273,6 +273,30 @@ class Map:
         if self._daemon is not None:
             self._daemon.reload_style(_serialize_style(self._style))
 
+    def resize(self, width: int, height: int) -> None:
+        """
+        Change the map size, keeping the loaded style.
+
+        A running renderer is resized in place, so the style is not fetched again.
+
+        Args:
+            width: Image width in pixels (1-4096)
+            height: Image height in pixels (1-4096)
+
+        Raises:
+            MlnativeError: If the dimensions are invalid or the resize fails
+        """
+        if self._closed:
+            raise MlnativeError("Map has been closed")
+
+        _validate_dimension(width, height)
+
+        if self._daemon is not None:
+            self._daemon.resize(width, height)
+
+        self.width = width
+        self.height = height
+
     def render(self, center: Center, zoom: float, bearing: float = 0, pitch: float = 0) -> bytes:
         """
         Render the map to PNG bytes.
@@ -317,6 +341,17 @@ class Map:
                    - zoom: float
                    - bearing: float (optional, default 0)
                    - pitch: float (optional, default 0)
+                   - geojson: dict of source id to GeoJSON (optional), each a
+                     dict or a file path or http(s)/file URL the renderer
+                     reads it from; sets the data of those GeoJSON sources
+                     before the view renders
+                   - operations: list of layer edits (optional), as for the
+                     daemon's transaction command, e.g.
+                     {"op": "set_paint_property", "layer": "water",
+                     "name": "fill-color", "value": "#0000ff"}; applied for
+                     this view only and reverted after it renders
+                   - width, height: int (optional); map size for this view,
+                     defaulting to the map's own
 
         Returns:
             List of PNG image bytes
@@ -329,9 +364,34 @@ class Map:
             pngs = map.render_batch(views)
 
         Note:
-            Per-view GeoJSON updates are not supported in batch mode. Use
-            set_geojson() + render() in a loop when each view needs different
-            source data.
+            Per-view GeoJSON data applies to that view and every later view
+            until replaced. It updates the running renderer only, not the
+            loaded style, so the next set_geojson() or load_style() restores
+            the style's own data. Every key must name an existing GeoJSON
+            source in the style. Operations, unlike GeoJSON, never carry over
+            to later views, and need a style given as a dict or file.
+            A failing view raises MlnativeError naming it; use
+            render_batch_outcomes() to keep the other views' images.
+        """
+        pngs: list[bytes] = []
+        for result in self.render_batch_outcomes(views):
+            if isinstance(result, MlnativeError):
+                raise MlnativeError(f"Batch render failed: {result}")
+            pngs.append(result)
+        return pngs
+
+    def render_batch_outcomes(self, views: list[RenderView]) -> list[bytes | MlnativeError]:
+        """
+        Render multiple map views, keeping the images of the views that succeed.
+
+        Views are given as for render_batch(). A view that fails, e.g. because
+        one of its operations names a missing layer, gets an MlnativeError
+        ("view <index>: <reason>") in its place and the later views still
+        render. Invalid views are rejected before anything renders, and the
+        call raises if every view failed.
+
+        Returns:
+            One entry per view: PNG image bytes or the view's MlnativeError
         """
         if self._closed:
             raise MlnativeError("Map has been closed")
@@ -344,34 +404,56 @@ class Map:
                 f"render_batch supports at most {MAX_BATCH_VIEWS} views, got {len(views)}"
             )
 
-        output_pixels = int(self.width * self.height * (self.pixel_ratio**2) * len(views))
-        if output_pixels > MAX_BATCH_OUTPUT_PIXELS:
-            raise MlnativeError(
-                "render_batch output is too large for one in-memory batch. "
-                "Use fewer views or smaller dimensions."
-            )
-
-        # Per-view GeoJSON updates are intentionally unsupported in batch mode.
-        if any(view.get("geojson") for view in views):
-            raise MlnativeError(
-                "render_batch does not support per-view geojson updates. "
-                "Use set_geojson() and render() in a loop instead."
-            )
-
         # Validate and normalize views
         normalized_views = []
+        output_pixels = 0
         for i, view in enumerate(views):
             center = view.get("center")
             if center is None:
                 raise MlnativeError(f"View {i} center must be [longitude, latitude]")
-            normalized_views.append(
-                _normalize_view(
-                    center,
-                    view.get("zoom", 0),
-                    view.get("bearing", 0),
-                    view.get("pitch", 0),
-                    f"View {i}",
-                )
+            normalized = _normalize_view(
+                center,
+                view.get("zoom", 0),
+                view.get("bearing", 0),
+                view.get("pitch", 0),
+                f"View {i}",
+            )
+            geojson = view.get("geojson")
+            if geojson is not None:
+                if not isinstance(geojson, dict) or not all(
+                    isinstance(source_id, str) and isinstance(data, (dict, str, Path))
+                    for source_id, data in geojson.items()
+                ):
+                    raise MlnativeError(
+                        f"View {i} geojson must map source ids to GeoJSON dicts, paths or URLs"
+                    )
+                normalized["geojson"] = {
+                    source_id: data if isinstance(data, dict) else str(data)
+                    for source_id, data in geojson.items()
+                }
+            operations = view.get("operations")
+            if operations is not None:
+                if not isinstance(operations, list) or not all(
+                    isinstance(operation, dict) and isinstance(operation.get("op"), str)
+                    for operation in operations
+                ):
+                    raise MlnativeError(
+                        f"View {i} operations must be a list of dicts with an 'op' key"
+                    )
+                normalized["operations"] = operations
+            width = view.get("width", self.width)
+            height = view.get("height", self.height)
+            if (width, height) != (self.width, self.height):
+                _validate_dimension(width, height)
+                normalized["width"] = width
+                normalized["height"] = height
+            output_pixels += int(width * height * (self.pixel_ratio**2))
+            normalized_views.append(normalized)
+
+        if output_pixels > MAX_BATCH_OUTPUT_PIXELS:
+            raise MlnativeError(
+                "render_batch output is too large for one in-memory batch. "
+                "Use fewer views or smaller dimensions."
             )
 
         try:
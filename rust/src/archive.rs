@@ -0,0 +1,173 @@
+//! Tile pyramids packed into a single tar (optionally gzipped) archive.
+//!
+//! Entries carry fixed metadata (mode 0644, mtime 0, uid/gid 0) and are written in
+//! `z`, `x`, `y` order, so the same tiles always produce byte-identical archives.
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::f64::consts::PI;
+use std::io::{self, Write};
+
+use crate::camera::MAX_LATITUDE;
+
+/// Every `[z, x, y]` tile intersecting `bounds` (`[west, south, east, north]`) at each
+/// zoom in `zooms` (inclusive), in archive order.
+pub fn coverage(bounds: [f64; 4], zooms: [u8; 2]) -> Result<Vec<[u32; 3]>, String> {
+    let [west, south, east, north] = bounds;
+    if !(west < east && south < north) {
+        return Err("bounds must be [west, south, east, north]".to_string());
+    }
+    if zooms[0] > zooms[1] || zooms[1] > 24 {
+        return Err("zooms must be [min, max] with max at most 24".to_string());
+    }
+    let mut tiles = Vec::new();
+    for z in zooms[0]..=zooms[1] {
+        let n = 1u32 << z;
+        let column = |lng: f64| ((lng.clamp(-180.0, 180.0) + 180.0) / 360.0 * n as f64) as u32;
+        let row = |lat: f64| {
+            let sin = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians().sin();
+            let y = 0.5 - ((1.0 + sin) / (1.0 - sin)).ln() / (4.0 * PI);
+            (y * n as f64) as u32
+        };
+        for x in column(west)..=column(east).min(n - 1) {
+            for y in row(north)..=row(south).min(n - 1) {
+                tiles.push([u32::from(z), x, y]);
+            }
+        }
+    }
+    Ok(tiles)
+}
+
+enum Sink<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(inner) => inner.write(buf),
+            Sink::Gzip(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(inner) => inner.flush(),
+            Sink::Gzip(inner) => inner.flush(),
+        }
+    }
+}
+
+/// A tar archive being written to `W`.
+pub struct Archive<W: Write> {
+    builder: tar::Builder<Sink<W>>,
+}
+
+impl<W: Write> Archive<W> {
+    pub fn new(inner: W, gzip: bool) -> Self {
+        let sink = if gzip {
+            Sink::Gzip(GzEncoder::new(inner, Compression::default()))
+        } else {
+            Sink::Plain(inner)
+        };
+        Self {
+            builder: tar::Builder::new(sink),
+        }
+    }
+
+    pub fn append(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        self.builder.append_data(&mut header, path, data)
+    }
+
+    /// Write the end-of-archive marker (and gzip trailer), returning the writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self.builder.into_inner()? {
+            Sink::Plain(mut inner) => {
+                inner.flush()?;
+                Ok(inner)
+            }
+            Sink::Gzip(inner) => inner.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    const WORLD: [f64; 4] = [-180.0, -90.0, 180.0, 90.0];
+
+    fn build(gzip: bool) -> Vec<u8> {
+        let mut archive = Archive::new(Vec::new(), gzip);
+        archive.append("0/0/0.png", b"first").unwrap();
+        archive.append("1/0/0.png", b"second tile").unwrap();
+        archive.finish().unwrap()
+    }
+
+    fn entries(archive: impl Read) -> Vec<(String, Vec<u8>)> {
+        tar::Archive::new(archive)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                assert_eq!(entry.header().mode().unwrap(), 0o644);
+                assert_eq!(entry.header().mtime().unwrap(), 0);
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (path, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn coverage_lists_tiles_in_zxy_order() {
+        assert_eq!(
+            coverage(WORLD, [0, 1]).unwrap(),
+            [[0, 0, 0], [1, 0, 0], [1, 0, 1], [1, 1, 0], [1, 1, 1]]
+        );
+        // A small box north-east of null island at z2.
+        assert_eq!(
+            coverage([10.0, 10.0, 20.0, 20.0], [2, 2]).unwrap(),
+            [[2, 2, 1]]
+        );
+    }
+
+    #[test]
+    fn coverage_rejects_inverted_bounds_and_zooms() {
+        assert!(coverage([10.0, 0.0, -10.0, 10.0], [0, 1]).is_err());
+        assert!(coverage([0.0, 10.0, 10.0, 0.0], [0, 1]).is_err());
+        assert!(coverage(WORLD, [3, 2]).is_err());
+        assert!(coverage(WORLD, [0, 25]).is_err());
+    }
+
+    #[test]
+    fn archives_round_trip_with_fixed_metadata() {
+        let expected = [
+            ("0/0/0.png".to_string(), b"first".to_vec()),
+            ("1/0/0.png".to_string(), b"second tile".to_vec()),
+        ];
+        assert_eq!(entries(&build(false)[..]), expected);
+        let gzipped = build(true);
+        assert_eq!(&gzipped[..2], [0x1f, 0x8b]);
+        assert_eq!(
+            entries(flate2::read::GzDecoder::new(&gzipped[..])),
+            expected
+        );
+    }
+
+    #[test]
+    fn archives_are_byte_identical_across_runs() {
+        assert_eq!(build(false), build(false));
+        assert_eq!(build(true), build(true));
+    }
+}
@@ -0,0 +1,271 @@
+//! Checks that the GPU backend MapLibre Native was built for can start.
+//!
+//! A missing Vulkan driver or GPU aborts the process from inside MapLibre Native
+//! rather than returning an error, so the check renders a probe in a child copy of
+//! the daemon (`--probe-backend`) and only looks at how that exited. On Linux, when
+//! the default driver fails and Mesa's lavapipe software driver is installed, the
+//! daemon switches to it with a warning instead of failing.
+
+use std::io::Read;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use maplibre_native::ImageRendererBuilder;
+use serde::Serialize;
+
+use crate::{camera, camera_update, PROBE_SIZE, PROBE_STYLE};
+
+/// Backend the linked MapLibre Native renders with; `maplibre_native` picks Metal on
+/// macOS and Vulkan elsewhere unless built with another backend feature.
+pub const NAME: &str = if cfg!(target_os = "macos") {
+    "metal"
+} else {
+    "vulkan"
+};
+
+/// Largest 2D texture side every driver for the backend must support: the Vulkan
+/// minimum for `maxImageDimension2D`, and the Metal limit on Apple GPUs.
+pub const MAX_TEXTURE_SIZE: u32 = if cfg!(target_os = "macos") {
+    16384
+} else {
+    4096
+};
+
+/// How long a probe may take before it counts as failed, e.g. on a hung driver.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Directories the Vulkan loader reads driver manifests from.
+const ICD_DIRS: [&str; 2] = ["/usr/share/vulkan/icd.d", "/etc/vulkan/icd.d"];
+
+/// The backend in use, as reported by `init`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Backend {
+    pub name: &'static str,
+    /// `default` for the driver the system picks, `software` after falling back to
+    /// lavapipe.
+    pub mode: &'static str,
+}
+
+/// Sizes and memory a map of a given size and pixel ratio needs, from
+/// `estimate_resources`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceEstimate {
+    /// Size of the rendered images, in device pixels.
+    pub output_width: u32,
+    pub output_height: u32,
+    /// Size of the render surface, which differs from the output for render modes that
+    /// render at another ratio and resample.
+    pub texture_width: u32,
+    pub texture_height: u32,
+    /// Bytes of the RGBA render surface.
+    pub framebuffer_bytes: u64,
+    /// Bytes of one RGBA output image read back from it.
+    pub image_bytes: u64,
+    pub max_texture_size: u32,
+    /// Whether both texture sides are within `max_texture_size`.
+    pub fits: bool,
+}
+
+/// Estimate the resources of a `width`×`height` map output at `pixel_ratio` and
+/// rendered at `render_ratio`.
+pub fn estimate(width: u32, height: u32, pixel_ratio: f64, render_ratio: f64) -> ResourceEstimate {
+    let scale = |side: u32, ratio: f64| (f64::from(side) * ratio).round() as u32;
+    let [output_width, output_height] = [width, height].map(|side| scale(side, pixel_ratio));
+    let [texture_width, texture_height] = [width, height].map(|side| scale(side, render_ratio));
+    let bytes = |width: u32, height: u32| u64::from(width) * u64::from(height) * 4;
+    ResourceEstimate {
+        output_width,
+        output_height,
+        texture_width,
+        texture_height,
+        framebuffer_bytes: bytes(texture_width, texture_height),
+        image_bytes: bytes(output_width, output_height),
+        max_texture_size: MAX_TEXTURE_SIZE,
+        fits: texture_width <= MAX_TEXTURE_SIZE && texture_height <= MAX_TEXTURE_SIZE,
+    }
+}
+
+impl ResourceEstimate {
+    /// Fail, naming the limit, if the render surface is over `max_texture_size`.
+    pub fn check(&self) -> Result<(), String> {
+        if self.fits {
+            return Ok(());
+        }
+        Err(format!(
+            "a {}x{} render surface exceeds the {} texture limit of {}",
+            self.texture_width, self.texture_height, NAME, self.max_texture_size
+        ))
+    }
+}
+
+/// Render the probe in this process; the exit status of `--probe-backend`.
+pub fn run_probe() -> i32 {
+    let Some(size) = NonZeroU32::new(PROBE_SIZE) else {
+        return 1;
+    };
+    let mut renderer = ImageRendererBuilder::new()
+        .with_size(size, size)
+        .build_static_renderer();
+    let result = renderer
+        .load_style_from_json_str(PROBE_STYLE)
+        .wait()
+        .map_err(|e| e.to_string())
+        .and_then(|()| {
+            renderer
+                .render_static(&camera_update(
+                    [0.0, 0.0],
+                    0.0,
+                    0.0,
+                    0.0,
+                    camera::Padding::default(),
+                ))
+                .map(drop)
+                .map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("{}", e);
+            1
+        }
+    }
+}
+
+/// Run `--probe-backend` in a child process with `driver` as the only Vulkan driver,
+/// if given, returning why it failed.
+fn probe(driver: Option<&Path>) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("cannot locate daemon: {}", e))?;
+    let mut command = Command::new(exe);
+    command
+        .arg("--probe-backend")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    if let Some(driver) = driver {
+        command
+            .env("VK_DRIVER_FILES", driver)
+            .env("VK_ICD_FILENAMES", driver);
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot start probe: {}", e))?;
+    // Drain stderr as it is written, so a chatty driver can't fill the pipe and stall.
+    let stderr = child.stderr.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = pipe.read_to_string(&mut text);
+            text
+        })
+    });
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if started.elapsed() < PROBE_TIMEOUT => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break Err(format!(
+                    "probe render did not finish within {}s",
+                    PROBE_TIMEOUT.as_secs()
+                ));
+            }
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break Err(format!("waiting for probe failed: {}", e));
+            }
+        }
+    };
+    // Join the drain thread whatever happened: `check` must leave no thread behind.
+    let stderr = stderr
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    let status = status?;
+    if status.success() {
+        return Ok(());
+    }
+    let detail = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .unwrap_or("no output");
+    Err(format!("probe render exited with {} ({})", status, detail))
+}
+
+/// Mesa's lavapipe driver manifest, if installed.
+fn lavapipe_driver() -> Option<PathBuf> {
+    ICD_DIRS
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("lvp_icd") && name.ends_with(".json"))
+        })
+}
+
+/// Check the backend can render, falling back to lavapipe where possible. Returns
+/// the backend in use and a warning if it is the fallback, or a message saying what
+/// to install.
+///
+/// The fallback selects lavapipe by setting the Vulkan loader's environment
+/// variables, so this must run before the daemon starts any other thread.
+pub fn check() -> Result<(Backend, Option<String>), String> {
+    let error = match probe(None) {
+        Ok(()) => {
+            return Ok((
+                Backend {
+                    name: NAME,
+                    mode: "default",
+                },
+                None,
+            ))
+        }
+        Err(e) => e,
+    };
+    if NAME == "metal" {
+        return Err(format!(
+            "Metal could not start: {}. A Metal-capable GPU is required",
+            error
+        ));
+    }
+    // Respect a driver the user chose explicitly.
+    let user_driver =
+        std::env::var_os("VK_DRIVER_FILES").or_else(|| std::env::var_os("VK_ICD_FILENAMES"));
+    if let (None, Some(driver)) = (user_driver, lavapipe_driver()) {
+        if probe(Some(&driver)).is_ok() {
+            // No renderer or other thread exists yet, so nothing reads these
+            // concurrently and MapLibre's Vulkan loader will pick them up.
+            std::env::set_var("VK_DRIVER_FILES", &driver);
+            std::env::set_var("VK_ICD_FILENAMES", &driver);
+            return Ok((
+                Backend {
+                    name: NAME,
+                    mode: "software",
+                },
+                Some(format!(
+                    "No usable Vulkan GPU driver ({}); rendering on the CPU with Mesa's \
+                     lavapipe driver ({}), which is slower",
+                    error,
+                    driver.display()
+                )),
+            ));
+        }
+    }
+    Err(format!(
+        "no Vulkan driver could start: {}. Install a GPU Vulkan driver, or Mesa's \
+         lavapipe software driver (mesa-vulkan-drivers on Debian/Ubuntu) to render on the \
+         CPU; VK_DRIVER_FILES can point at a specific driver's ICD JSON",
+        error
+    ))
+}
@@ -0,0 +1,158 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// HTTP caching hints for a rendered image, derived from `tile_ttl_seconds`.
+#[derive(Debug, Serialize)]
+pub struct CacheHints {
+    pub cache_control: String,
+    /// RFC 7231 HTTP-date; absent when the image must not be cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+/// Hints for an image rendered now. A TTL of zero means `no-cache`.
+pub fn hints(ttl_seconds: u64) -> CacheHints {
+    if ttl_seconds == 0 {
+        return CacheHints {
+            cache_control: "no-cache".to_string(),
+            expires: None,
+        };
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    CacheHints {
+        cache_control: format!("public, max-age={}", ttl_seconds),
+        expires: Some(http_date(now.saturating_add(ttl_seconds))),
+    }
+}
+
+/// Calendar `(year, month, day)` of a Unix timestamp, proleptic Gregorian UTC.
+pub fn civil_date(unix_seconds: u64) -> (i64, i64, i64) {
+    // Civil-from-days (Howard Hinnant).
+    let z = (unix_seconds / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Format Unix seconds as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(unix_seconds: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day) = civil_date(unix_seconds);
+    let seconds = unix_seconds % 86_400;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(unix_seconds / 86_400 % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// A least-recently-used cache holding at most `capacity` entries; zero disables it.
+/// Lookups scan every entry, which is cheap next to the renders it saves.
+pub struct Lru<K, V> {
+    capacity: usize,
+    /// Least recently used first.
+    entries: VecDeque<(K, V)>,
+}
+
+impl<K: PartialEq, V> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// The entry for `key`, marking it the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(index)?;
+        self.entries.push_back(entry);
+        self.entries.back().map(|(_, value)| value)
+    }
+
+    /// Store `value`, evicting the least recently used entry when full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if !self.enabled() {
+            return;
+        }
+        self.entries.retain(|(k, _)| *k != key);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, value));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_dates_match_rfc_7231() {
+        // The example date of RFC 7231 section 7.1.1.1.
+        assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        // A leap day.
+        assert_eq!(civil_date(951_782_400), (2000, 2, 29));
+    }
+
+    #[test]
+    fn zero_ttl_is_no_cache() {
+        let hints = hints(0);
+        assert_eq!(hints.cache_control, "no-cache");
+        assert_eq!(hints.expires, None);
+        let hints = super::hints(60);
+        assert_eq!(hints.cache_control, "public, max-age=60");
+        assert!(hints.expires.unwrap().ends_with(" GMT"));
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used() {
+        let mut cache = Lru::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        // Replacing a key keeps a single entry for it.
+        cache.insert("c", 4);
+        cache.insert("d", 5);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"c"), Some(&4));
+        cache.clear();
+        assert_eq!(cache.get(&"d"), None);
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let mut cache = Lru::new(0);
+        assert!(!cache.enabled());
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), None);
+    }
+}
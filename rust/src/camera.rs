@@ -0,0 +1,1201 @@
+use serde::{Deserialize, Serialize};
+
+/// Web Mercator latitude limit; MapLibre Native clamps centers beyond it.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_6;
+pub const MIN_ZOOM: f64 = 0.0;
+/// MapLibre Native's default maximum zoom.
+pub const MAX_ZOOM: f64 = 25.5;
+/// Highest zoom a session renders at unless `init` sets `max_zoom`; the deepest
+/// tile level clients address.
+pub const DEFAULT_MAX_ZOOM: f64 = 24.0;
+pub const MIN_PITCH: f64 = 0.0;
+/// MapLibre Native's default maximum pitch, in degrees.
+pub const MAX_PITCH: f64 = 60.0;
+
+/// Camera values as the renderer would actually apply them.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CameraValues {
+    pub center: [f64; 2],
+    pub zoom: f64,
+    pub bearing: f64,
+    pub pitch: f64,
+}
+
+/// Camera fields set at `init` for renders that leave them out.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Defaults {
+    #[serde(default, rename = "default_center")]
+    pub center: Option<[f64; 2]>,
+    #[serde(default, rename = "default_zoom")]
+    pub zoom: Option<f64>,
+    #[serde(default, rename = "default_bearing")]
+    pub bearing: Option<f64>,
+    #[serde(default, rename = "default_pitch")]
+    pub pitch: Option<f64>,
+}
+
+impl Defaults {
+    /// Fill in the fields a render left out, naming the first one that has no default.
+    pub fn complete(
+        &self,
+        center: Option<[f64; 2]>,
+        zoom: Option<f64>,
+        bearing: Option<f64>,
+        pitch: Option<f64>,
+    ) -> Result<CameraValues, String> {
+        let missing = |name| format!("{} is required, as init set no default_{}", name, name);
+        Ok(CameraValues {
+            center: center.or(self.center).ok_or_else(|| missing("center"))?,
+            zoom: zoom.or(self.zoom).ok_or_else(|| missing("zoom"))?,
+            bearing: bearing.or(self.bearing).unwrap_or(0.0),
+            pitch: pitch.or(self.pitch).unwrap_or(0.0),
+        })
+    }
+}
+
+/// Insets from the edges of the map in logical pixels, as in MapLibre's camera
+/// padding: the camera center lands in the middle of the area inside them instead of
+/// the middle of the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Padding {
+    #[serde(default)]
+    pub top: f64,
+    #[serde(default)]
+    pub right: f64,
+    #[serde(default)]
+    pub bottom: f64,
+    #[serde(default)]
+    pub left: f64,
+}
+
+impl Padding {
+    /// Reject insets that are negative or not finite, or that leave nothing of a
+    /// `size` map (logical pixels) between them.
+    pub fn check(&self, [width, height]: [f64; 2]) -> Result<(), String> {
+        let insets = [
+            ("top", self.top),
+            ("right", self.right),
+            ("bottom", self.bottom),
+            ("left", self.left),
+        ];
+        if let Some((name, value)) = insets
+            .into_iter()
+            .find(|(_, value)| !value.is_finite() || *value < 0.0)
+        {
+            return Err(format!(
+                "padding {} must be a non-negative number, got {}",
+                name, value
+            ));
+        }
+        if self.left + self.right >= width || self.top + self.bottom >= height {
+            return Err(format!(
+                "padding must leave part of the {}x{} map uncovered",
+                width, height
+            ));
+        }
+        Ok(())
+    }
+
+    /// Offset of the padded area's middle from the middle of the map, in logical
+    /// pixels (x right, y down).
+    pub fn center_offset(&self) -> [f64; 2] {
+        [
+            (self.left - self.right) / 2.0,
+            (self.top - self.bottom) / 2.0,
+        ]
+    }
+}
+
+/// Advisory result of `validate_camera`: which inputs fall outside the renderer's
+/// limits and the values they would be snapped to.
+#[derive(Debug, Serialize)]
+pub struct CameraValidation {
+    pub within_limits: bool,
+    /// Names of the inputs that would be changed (`"longitude"`, `"latitude"`,
+    /// `"zoom"`, `"bearing"`, `"pitch"`).
+    pub adjusted: Vec<&'static str>,
+    pub camera: CameraValues,
+}
+
+/// What a render does with a zoom outside the session's `min_zoom`..`max_zoom`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoomOutOfRange {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+/// Zooms a session renders at, set by `init`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomRange {
+    pub min: f64,
+    pub max: f64,
+    pub out_of_range: ZoomOutOfRange,
+}
+
+impl Default for ZoomRange {
+    fn default() -> Self {
+        ZoomRange {
+            min: MIN_ZOOM,
+            max: DEFAULT_MAX_ZOOM,
+            out_of_range: ZoomOutOfRange::default(),
+        }
+    }
+}
+
+impl ZoomRange {
+    /// Check bounds given to `init`: finite, within the renderer's limits, and
+    /// `min <= max`.
+    pub fn new(min: f64, max: f64, out_of_range: ZoomOutOfRange) -> Result<Self, String> {
+        if !min.is_finite() || !max.is_finite() {
+            return Err("min_zoom and max_zoom must be finite numbers".to_string());
+        }
+        if min < MIN_ZOOM || max > MAX_ZOOM {
+            return Err(format!(
+                "min_zoom and max_zoom must be between {} and {}",
+                MIN_ZOOM, MAX_ZOOM
+            ));
+        }
+        if min > max {
+            return Err(format!("min_zoom {} is greater than max_zoom {}", min, max));
+        }
+        Ok(ZoomRange {
+            min,
+            max,
+            out_of_range,
+        })
+    }
+
+    /// The zoom to render for a requested one: unchanged inside the range, snapped to
+    /// the nearest bound when clamping, and an error naming the range otherwise.
+    pub fn apply(&self, zoom: f64) -> Result<f64, String> {
+        if (self.min..=self.max).contains(&zoom) {
+            return Ok(zoom);
+        }
+        match self.out_of_range {
+            ZoomOutOfRange::Clamp => Ok(zoom.clamp(self.min, self.max)),
+            ZoomOutOfRange::Reject => Err(format!(
+                "zoom {} is outside the allowed range {} to {}",
+                zoom, self.min, self.max
+            )),
+        }
+    }
+}
+
+/// Wrap longitude into [-180, 180].
+fn wrap_longitude(lng: f64) -> f64 {
+    if (-180.0..=180.0).contains(&lng) {
+        lng
+    } else {
+        (lng + 180.0).rem_euclid(360.0) - 180.0
+    }
+}
+
+/// Normalize bearing into [0, 360).
+fn normalize_bearing(bearing: f64) -> f64 {
+    bearing.rem_euclid(360.0)
+}
+
+/// Reject NaN and infinite camera values, naming the first offending input.
+///
+/// The renderer has no defined behaviour for them: a NaN zoom draws garbage or never
+/// settles, so they must be caught before any camera reaches it.
+pub fn check_finite(values: CameraValues) -> Result<(), String> {
+    let CameraValues {
+        center: [lng, lat],
+        zoom,
+        bearing,
+        pitch,
+    } = values;
+    [
+        ("longitude", lng),
+        ("latitude", lat),
+        ("zoom", zoom),
+        ("bearing", bearing),
+        ("pitch", pitch),
+    ]
+    .into_iter()
+    .find(|(_, value)| !value.is_finite())
+    .map_or(Ok(()), |(name, _)| {
+        Err(format!("{} must be a finite number", name))
+    })
+}
+
+/// Reject a latitude beyond the poles and bring the other inputs of a finite camera
+/// within the renderer's limits: longitude wrapped into [-180, 180] and pitch
+/// clamped to [0, 60].
+///
+/// A latitude past ±90 has no position to snap to, so it is an error naming the value;
+/// one between the Web Mercator limit and the pole is left for the renderer to clamp.
+pub fn check_range(values: CameraValues) -> Result<CameraValues, String> {
+    let [lng, lat] = values.center;
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(format!("latitude {} is outside -90 to 90", lat));
+    }
+    Ok(CameraValues {
+        center: [wrap_longitude(lng), lat],
+        pitch: values.pitch.clamp(MIN_PITCH, MAX_PITCH),
+        ..values
+    })
+}
+
+/// Reject `[west, south, east, north]` bounds with a NaN or infinite edge.
+pub fn check_finite_bounds(bounds: [f64; 4]) -> Result<(), String> {
+    ["west", "south", "east", "north"]
+        .into_iter()
+        .zip(bounds)
+        .find(|(_, value)| !value.is_finite())
+        .map_or(Ok(()), |(name, _)| {
+            Err(format!("bounds {} must be a finite number", name))
+        })
+}
+
+/// Check a camera against the renderer limits without rendering.
+///
+/// Non-finite values can't be snapped to anything meaningful and are rejected.
+pub fn validate(values: CameraValues) -> Result<CameraValidation, String> {
+    check_finite(values)?;
+    let CameraValues {
+        center: [lng, lat],
+        zoom,
+        bearing,
+        pitch,
+    } = values;
+
+    let camera = CameraValues {
+        center: [wrap_longitude(lng), lat.clamp(-MAX_LATITUDE, MAX_LATITUDE)],
+        zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM),
+        bearing: normalize_bearing(bearing),
+        pitch: pitch.clamp(MIN_PITCH, MAX_PITCH),
+    };
+    let adjusted: Vec<&'static str> = [
+        ("longitude", lng, camera.center[0]),
+        ("latitude", lat, camera.center[1]),
+        ("zoom", zoom, camera.zoom),
+        ("bearing", bearing, camera.bearing),
+        ("pitch", pitch, camera.pitch),
+    ]
+    .into_iter()
+    .filter(|&(_, given, applied)| given != applied)
+    .map(|(name, _, _)| name)
+    .collect();
+
+    Ok(CameraValidation {
+        within_limits: adjusted.is_empty(),
+        adjusted,
+        camera,
+    })
+}
+
+/// Logical size of one tile in MapLibre Native's world pixel space.
+pub const WORLD_TILE_SIZE: f64 = 512.0;
+
+/// Width (and height) of the world in logical pixels at `zoom`.
+pub fn world_size(zoom: f64) -> f64 {
+    WORLD_TILE_SIZE * 2f64.powf(zoom)
+}
+
+/// Convert a point in the global pixel space of `zoom` to `[lng, lat]`.
+pub fn pixel_to_lng_lat(pixel: [f64; 2], zoom: f64) -> [f64; 2] {
+    let size = world_size(zoom);
+    let lng = pixel[0] / size * 360.0 - 180.0;
+    let lat = (std::f64::consts::PI * (1.0 - 2.0 * pixel[1] / size))
+        .sinh()
+        .atan()
+        .to_degrees();
+    [lng, lat]
+}
+
+/// `[lng, lat]` as a fraction of the Web Mercator world, `[0, 1]` from the
+/// north-west corner.
+pub fn mercator_fraction(lng_lat: [f64; 2]) -> [f64; 2] {
+    let sin = lng_lat[1]
+        .clamp(-MAX_LATITUDE, MAX_LATITUDE)
+        .to_radians()
+        .sin();
+    [
+        (lng_lat[0] + 180.0) / 360.0,
+        0.5 - ((1.0 + sin) / (1.0 - sin)).ln() / (4.0 * std::f64::consts::PI),
+    ]
+}
+
+/// Most frames one `render_path` may render; each is a full render.
+pub const MAX_PATH_FRAMES: u32 = 1000;
+
+/// Pacing of a `render_path` transition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts and ends slowly (smoothstep).
+    EaseInOut,
+}
+
+impl Easing {
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Cameras for `frames` evenly paced steps from `from` to `to`, both included.
+///
+/// Zoom moves linearly, so the map scales by the same factor every step, and the
+/// center moves in a straight line across the Mercator plane. Bearing turns the
+/// shorter way, e.g. from 350 through 0 to 10.
+pub fn path(
+    from: CameraValues,
+    to: CameraValues,
+    frames: u32,
+    easing: Easing,
+) -> Result<Vec<CameraValues>, String> {
+    if !(2..=MAX_PATH_FRAMES).contains(&frames) {
+        return Err(format!("frames must be between 2 and {}", MAX_PATH_FRAMES));
+    }
+    let start = mercator_fraction(from.center);
+    let end = mercator_fraction(to.center);
+    let turn = (to.bearing - from.bearing + 180.0).rem_euclid(360.0) - 180.0;
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    Ok((0..frames)
+        .map(|frame| {
+            let t = easing.apply(f64::from(frame) / f64::from(frames - 1));
+            let fraction = [lerp(start[0], end[0], t), lerp(start[1], end[1], t)];
+            CameraValues {
+                center: pixel_to_lng_lat(
+                    [fraction[0] * WORLD_TILE_SIZE, fraction[1] * WORLD_TILE_SIZE],
+                    0.0,
+                ),
+                zoom: lerp(from.zoom, to.zoom, t),
+                bearing: normalize_bearing(from.bearing + turn * t),
+                pitch: lerp(from.pitch, to.pitch, t),
+            }
+        })
+        .collect())
+}
+
+/// Center of the `size` logical-pixel window at `origin` in the global pixel space of
+/// `zoom`, which it must lie within.
+pub fn window_center(zoom: f64, origin: [f64; 2], size: [u32; 2]) -> Result<[f64; 2], String> {
+    let [width, height] = size.map(f64::from);
+    if width == 0.0 || height == 0.0 {
+        return Err("width and height must be non-zero".to_string());
+    }
+    let extent = world_size(zoom);
+    let [x, y] = origin;
+    if !(x >= 0.0 && y >= 0.0 && x + width <= extent && y + height <= extent) {
+        return Err(format!(
+            "window must lie within the zoom {} pixel extent [0, {}]",
+            zoom, extent
+        ));
+    }
+    Ok(pixel_to_lng_lat([x + width / 2.0, y + height / 2.0], zoom))
+}
+
+/// Zoom and global pixel origin at which a `width`×`height` logical-pixel window
+/// covers `bounds` (`[west, south, east, north]`) exactly.
+///
+/// Fails unless the bounds' pixels are square in Web Mercator, as they are for
+/// EPSG:3857 rasters; only then can one render share the reference's pixel grid.
+pub fn window_for_bounds(
+    bounds: [f64; 4],
+    width: f64,
+    height: f64,
+) -> Result<(f64, [f64; 2]), String> {
+    let [west, south, east, north] = bounds;
+    if !(west < east && south < north) {
+        return Err(
+            "bounds must be [west, south, east, north] without crossing the antimeridian"
+                .to_string(),
+        );
+    }
+    let north_west = mercator_fraction([west, north]);
+    let south_east = mercator_fraction([east, south]);
+    let zoom = (width / (WORLD_TILE_SIZE * (south_east[0] - north_west[0]))).log2();
+    let zoom_y = (height / (WORLD_TILE_SIZE * (south_east[1] - north_west[1]))).log2();
+    // 0.001 in zoom is a 0.07% pixel size difference.
+    if (zoom - zoom_y).abs() > 1e-3 {
+        return Err(format!(
+            "reference pixels are not square in Web Mercator (zoom {:.4} across, {:.4} down)",
+            zoom, zoom_y
+        ));
+    }
+    let size = world_size(zoom);
+    Ok((zoom, [north_west[0] * size, north_west[1] * size]))
+}
+
+/// Center and zoom at which `bounds` (`[[west, south], [east, north]]`) fit a
+/// `size` logical-pixel view inset by `padding` on every side.
+///
+/// `east < west` means the bounds cross the antimeridian. The zoom is clamped to
+/// the renderer limits, so very small bounds may still not fill the view.
+pub fn fit_bounds(
+    bounds: [[f64; 2]; 2],
+    size: [f64; 2],
+    padding: f64,
+) -> Result<([f64; 2], f64), String> {
+    let [[west, south], [east, north]] = bounds;
+    if !(padding >= 0.0 && 2.0 * padding < size[0] && 2.0 * padding < size[1]) {
+        return Err(format!(
+            "padding must be non-negative and leave room in the {}x{} view",
+            size[0], size[1]
+        ));
+    }
+    if south > north {
+        return Err("bounds must be [[west, south], [east, north]]".to_string());
+    }
+    let east = if east < west { east + 360.0 } else { east };
+    let north_west = mercator_fraction([west, north]);
+    let south_east = mercator_fraction([east, south]);
+    let span = [south_east[0] - north_west[0], south_east[1] - north_west[1]];
+    if !(span[0] > 0.0 && span[1] > 0.0) {
+        return Err("bounds must have a non-zero area".to_string());
+    }
+    let zoom = (0..2)
+        .map(|axis| ((size[axis] - 2.0 * padding) / (WORLD_TILE_SIZE * span[axis])).log2())
+        .fold(f64::INFINITY, f64::min)
+        .clamp(MIN_ZOOM, MAX_ZOOM);
+    let [lng, lat] = pixel_to_lng_lat(
+        [
+            (north_west[0] + south_east[0]) / 2.0 * WORLD_TILE_SIZE,
+            (north_west[1] + south_east[1]) / 2.0 * WORLD_TILE_SIZE,
+        ],
+        0.0,
+    );
+    Ok(([wrap_longitude(lng), lat], zoom))
+}
+
+/// Center and zoom at which slippy-map tile `z`/`x`/`y` exactly fills the shorter
+/// side of a `size` logical-pixel view; a non-square view also shows part of the
+/// neighbouring tiles along its longer side.
+pub fn tile_view(tile: [u32; 3], size: [f64; 2]) -> Result<([f64; 2], f64), String> {
+    let [z, x, y] = tile;
+    if z > 24 {
+        return Err("z must be at most 24".to_string());
+    }
+    let n = 1u32 << z;
+    if x >= n || y >= n {
+        return Err(format!("x and y must be in 0..{} at zoom {}", n, z));
+    }
+    let zoom = f64::from(z) + (size[0].min(size[1]) / WORLD_TILE_SIZE).log2();
+    let center = pixel_to_lng_lat(
+        [
+            (f64::from(x) + 0.5) * WORLD_TILE_SIZE,
+            (f64::from(y) + 0.5) * WORLD_TILE_SIZE,
+        ],
+        f64::from(z),
+    );
+    Ok((center, zoom))
+}
+
+/// Equatorial circumference of the Web Mercator sphere, in metres.
+const EARTH_CIRCUMFERENCE: f64 = 40_075_016.685_578_5;
+const METERS_PER_INCH: f64 = 0.0254;
+
+/// Zoom at which an image printed at `dpi` has scale 1:`scale_denominator` at
+/// `latitude`, given that one logical pixel covers `pixel_ratio` output pixels.
+///
+/// Mercator stretches distances by 1/cos(latitude), so the same scale needs a
+/// lower zoom away from the equator.
+pub fn zoom_for_scale(scale_denominator: f64, dpi: f64, pixel_ratio: f64, latitude: f64) -> f64 {
+    let ground_per_output_pixel = scale_denominator * METERS_PER_INCH / dpi;
+    let ground_per_logical_pixel = ground_per_output_pixel * pixel_ratio;
+    let equator_meters_per_pixel_z0 = EARTH_CIRCUMFERENCE / WORLD_TILE_SIZE;
+    (equator_meters_per_pixel_z0 * latitude.to_radians().cos() / ground_per_logical_pixel).log2()
+}
+
+/// Vertical field of view MapLibre Native renders with, in radians.
+const FIELD_OF_VIEW: f64 = 0.643_501_108_793_284_4;
+
+/// Upper bound on the ground distance, in logical pixels at the camera's zoom, from
+/// the centre of a `size` view pitched by `pitch` degrees to any point it shows.
+pub fn ground_radius(pitch: f64, size: [f64; 2]) -> f64 {
+    let far_edge = (pitch.to_radians() + FIELD_OF_VIEW / 2.0).min(85f64.to_radians());
+    size[0].hypot(size[1]) / 2.0 / far_edge.cos()
+}
+
+/// What `describe_view` reports: the ground a camera shows and the tiles it needs,
+/// worked out without rendering.
+#[derive(Debug, Serialize)]
+pub struct ViewDescription {
+    /// The camera as the renderer would apply it.
+    pub camera: CameraValues,
+    /// `[west, south, east, north]` around everything the view shows. `west` is greater
+    /// than `east` when the view crosses the antimeridian; a view wider than the world
+    /// spans -180 to 180.
+    pub bounds: [f64; 4],
+    /// Zoom of the tiles a source of 512-pixel tiles loads for the view.
+    pub tile_zoom: u32,
+    /// `[z, x, y]` of each of those tiles the view shows, with `x` wrapped across the
+    /// antimeridian.
+    pub tiles: Vec<[u32; 3]>,
+    /// Ground metres one output pixel covers at the center.
+    pub meters_per_pixel: f64,
+}
+
+/// The global pixel, at the camera's zoom, on the ground at `point` of a `size` view
+/// (logical pixels from its top-left). Pitch is at most 60 degrees, so every point of
+/// the view is on the ground.
+fn ground_pixel(values: CameraValues, size: [f64; 2], point: [f64; 2]) -> [f64; 2] {
+    let [width, height] = size;
+    let distance = 0.5 / (FIELD_OF_VIEW / 2.0).tan() * height;
+    let (sin_pitch, cos_pitch) = values.pitch.to_radians().sin_cos();
+    let right = point[0] - width / 2.0;
+    let up = height / 2.0 - point[1];
+    // How far along the ray from the camera through the point the ground is, 1 being
+    // the plane through the center that faces the camera.
+    let t = distance * cos_pitch / (distance * cos_pitch - up * sin_pitch);
+    let across = t * right;
+    let ahead = t * (distance * sin_pitch + up * cos_pitch) - distance * sin_pitch;
+    let world = world_size(values.zoom);
+    let [x, y] = mercator_fraction(values.center).map(|fraction| fraction * world);
+    // Up the view faces the bearing; global y grows southwards.
+    let (sin_bearing, cos_bearing) = values.bearing.to_radians().sin_cos();
+    [
+        x + across * cos_bearing + ahead * sin_bearing,
+        y + across * sin_bearing - ahead * cos_bearing,
+    ]
+}
+
+/// Whether the axis-aligned `rect` (`[x_min, y_min, x_max, y_max]`) and the convex
+/// `quad` overlap by more than an edge.
+fn overlaps(quad: &[[f64; 2]; 4], rect: [f64; 4]) -> bool {
+    let corners = [
+        [rect[0], rect[1]],
+        [rect[2], rect[1]],
+        [rect[2], rect[3]],
+        [rect[0], rect[3]],
+    ];
+    let edge_normals = (0..4).map(|i| {
+        let ([x0, y0], [x1, y1]) = (quad[i], quad[(i + 1) % 4]);
+        [y1 - y0, x0 - x1]
+    });
+    // Separating axis test: convex shapes overlap unless some axis separates them.
+    [[1.0, 0.0], [0.0, 1.0]]
+        .into_iter()
+        .chain(edge_normals)
+        .all(|axis| {
+            let extent = |points: &[[f64; 2]; 4]| {
+                points
+                    .iter()
+                    .map(|point| point[0] * axis[0] + point[1] * axis[1])
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+                        (min.min(value), max.max(value))
+                    })
+            };
+            let ((quad_min, quad_max), (rect_min, rect_max)) = (extent(quad), extent(&corners));
+            quad_min < rect_max && rect_min < quad_max
+        })
+}
+
+/// Describe what a `size` view (logical pixels) rendered at `pixel_ratio` shows of
+/// `camera`, already within the renderer's limits.
+pub fn describe_view(camera: CameraValues, size: [f64; 2], pixel_ratio: f64) -> ViewDescription {
+    let [width, height] = size;
+    let quad = [[0.0, 0.0], [width, 0.0], [width, height], [0.0, height]]
+        .map(|point| ground_pixel(camera, size, point));
+    let world = world_size(camera.zoom);
+    let (x_min, x_max, y_min, y_max) = quad.iter().fold(
+        (
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |(x_min, x_max, y_min, y_max), &[x, y]| {
+            (x_min.min(x), x_max.max(x), y_min.min(y), y_max.max(y))
+        },
+    );
+    let (y_min, y_max) = (y_min.max(0.0), y_max.min(world));
+    let [west, east] = if x_max - x_min >= world {
+        [-180.0, 180.0]
+    } else {
+        [x_min, x_max].map(|x| wrap_longitude(x / world * 360.0 - 180.0))
+    };
+    let north = pixel_to_lng_lat([0.0, y_min], camera.zoom)[1];
+    let south = pixel_to_lng_lat([0.0, y_max], camera.zoom)[1];
+
+    let tile_zoom = camera.zoom.floor() as u32;
+    let n = 1i64 << tile_zoom;
+    let span = world / n as f64;
+    let mut tiles = Vec::new();
+    for column in (x_min / span).floor() as i64..=(x_max / span).floor() as i64 {
+        for row in (y_min / span).floor() as i64..=((y_max / span).floor() as i64).min(n - 1) {
+            let rect = [column, row, column + 1, row + 1].map(|edge| edge as f64 * span);
+            if overlaps(&quad, rect) {
+                tiles.push([tile_zoom, column.rem_euclid(n) as u32, row as u32]);
+            }
+        }
+    }
+    tiles.sort_unstable();
+    tiles.dedup();
+
+    ViewDescription {
+        camera,
+        bounds: [west, south, east, north],
+        tile_zoom,
+        tiles,
+        meters_per_pixel: EARTH_CIRCUMFERENCE * camera.center[1].to_radians().cos()
+            / world
+            / pixel_ratio,
+    }
+}
+
+/// A 4×4 matrix in column-major order.
+pub type Matrix = [f64; 16];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum()
+    })
+}
+
+fn diagonal(x: f64, y: f64, z: f64) -> Matrix {
+    let mut m = [0.0; 16];
+    m[0] = x;
+    m[5] = y;
+    m[10] = z;
+    m[15] = 1.0;
+    m
+}
+
+fn translation(x: f64, y: f64, z: f64) -> Matrix {
+    let mut m = diagonal(1.0, 1.0, 1.0);
+    m[12] = x;
+    m[13] = y;
+    m[14] = z;
+    m
+}
+
+fn rotation_x(angle: f64) -> Matrix {
+    let (s, c) = angle.sin_cos();
+    let mut m = diagonal(1.0, c, c);
+    m[6] = s;
+    m[9] = -s;
+    m
+}
+
+fn rotation_z(angle: f64) -> Matrix {
+    let (s, c) = angle.sin_cos();
+    let mut m = diagonal(c, c, 1.0);
+    m[1] = s;
+    m[4] = -s;
+    m
+}
+
+fn perspective(fov: f64, aspect: f64, near: f64, far: f64) -> Matrix {
+    let f = 1.0 / (fov / 2.0).tan();
+    let mut m = [0.0; 16];
+    m[0] = f / aspect;
+    m[5] = f;
+    m[10] = (far + near) / (near - far);
+    m[11] = -1.0;
+    m[14] = 2.0 * far * near / (near - far);
+    m
+}
+
+/// One piece of a tiled `render_large` image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargeTile {
+    /// Top-left corner and size, in logical pixels, of the part of the large image the
+    /// tile fills.
+    pub origin: [u32; 2],
+    pub size: [u32; 2],
+    /// `[lng, lat]` center of the tile's view, which reaches `overlap` pixels past
+    /// that part on every side.
+    pub center: [f64; 2],
+}
+
+/// Split an unpitched `size` (logical pixels) view into tiles of at most `tile`
+/// pixels a side, row by row from the top left.
+///
+/// Without pitch, moving the camera by a screen offset rotated by the bearing moves
+/// the whole view by exactly that offset, so each tile's camera is the large view's
+/// moved to the tile's middle and neighbouring tiles meet pixel for pixel. Fails if
+/// the view, widened by `overlap`, reaches past the north or south edge of the world,
+/// where MapLibre Native would shift the camera and break the seams.
+pub fn large_tiles(
+    center: [f64; 2],
+    zoom: f64,
+    bearing: f64,
+    size: [u32; 2],
+    tile: u32,
+    overlap: u32,
+) -> Result<Vec<LargeTile>, String> {
+    if tile == 0 {
+        return Err("tile_size must be non-zero".to_string());
+    }
+    let extent = world_size(zoom);
+    let middle = mercator_fraction(center).map(|fraction| fraction * extent);
+    let (sin, cos) = bearing.to_radians().sin_cos();
+    let half = size.map(|side| f64::from(side) / 2.0 + f64::from(overlap));
+    let reach = half[0] * sin.abs() + half[1] * cos.abs();
+    if middle[1] - reach < 0.0 || middle[1] + reach > extent {
+        return Err(format!(
+            "the view reaches past latitude ±{:.2}, the edge of the map, where tiles cannot \
+             be aligned; zoom in or move the center",
+            MAX_LATITUDE
+        ));
+    }
+    let mut tiles = Vec::new();
+    for y in (0..size[1]).step_by(tile as usize) {
+        for x in (0..size[0]).step_by(tile as usize) {
+            let tile_size = [tile.min(size[0] - x), tile.min(size[1] - y)];
+            let offset = [
+                f64::from(x) + f64::from(tile_size[0]) / 2.0 - f64::from(size[0]) / 2.0,
+                f64::from(y) + f64::from(tile_size[1]) / 2.0 - f64::from(size[1]) / 2.0,
+            ];
+            let ground = [
+                middle[0] + offset[0] * cos - offset[1] * sin,
+                middle[1] + offset[0] * sin + offset[1] * cos,
+            ];
+            tiles.push(LargeTile {
+                origin: [x, y],
+                size: tile_size,
+                center: pixel_to_lng_lat([ground[0].rem_euclid(extent), ground[1]], zoom),
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+/// Model-view-projection matrix of a `size` (logical pixels) view, built the way
+/// MapLibre's transform builds its own.
+///
+/// It maps `[x, y, altitude, 1]`, with `x`/`y` in the global pixel space of the
+/// camera's zoom (origin top-left, y down) and altitude in metres, to OpenGL clip
+/// space: after dividing by `w`, the view spans -1..1 in x (left to right) and y
+/// (bottom to top), and depth -1..1 from the near to the far plane. `padding` moves
+/// the camera center off the middle of the view as MapLibre does.
+pub fn projection_matrix(values: CameraValues, size: [f64; 2], padding: Padding) -> Matrix {
+    let [width, height] = size;
+    let pitch = values.pitch.to_radians();
+    let half_fov = FIELD_OF_VIEW / 2.0;
+    let camera_to_center = 0.5 / half_fov.tan() * height;
+    // Distance to the far edge of the ground plane at the top of the view.
+    let ground_angle = std::f64::consts::FRAC_PI_2 + pitch;
+    let top_half_surface = half_fov.sin() * camera_to_center
+        / (std::f64::consts::PI - ground_angle - half_fov)
+            .clamp(0.01, std::f64::consts::PI - 0.01)
+            .sin();
+    let far =
+        ((std::f64::consts::FRAC_PI_2 - pitch).cos() * top_half_surface + camera_to_center) * 1.01;
+    let near = height / 50.0;
+
+    let world = world_size(values.zoom);
+    let [x, y] = mercator_fraction(values.center);
+    let pixels_per_meter = world / (EARTH_CIRCUMFERENCE * values.center[1].to_radians().cos());
+    let mut projection = perspective(FIELD_OF_VIEW, width / height, near, far);
+    // Shift the vanishing point, so the center projects to the middle of the padding.
+    let [offset_x, offset_y] = padding.center_offset();
+    projection[8] = -offset_x * 2.0 / width;
+    projection[9] = offset_y * 2.0 / height;
+    [
+        diagonal(1.0, -1.0, 1.0),
+        translation(0.0, 0.0, -camera_to_center),
+        rotation_x(pitch),
+        rotation_z(-values.bearing.to_radians()),
+        translation(-x * world, -y * world, 0.0),
+        diagonal(1.0, 1.0, pixels_per_meter),
+    ]
+    .iter()
+    .fold(projection, |m, step| multiply(&m, step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Project a global pixel at ground level to normalized device coordinates.
+    fn project(matrix: &Matrix, pixel: [f64; 2]) -> [f64; 2] {
+        let clip: Vec<f64> = (0..4)
+            .map(|row| matrix[row] * pixel[0] + matrix[4 + row] * pixel[1] + matrix[12 + row])
+            .collect();
+        [clip[0] / clip[3], clip[1] / clip[3]]
+    }
+
+    fn assert_near(actual: [f64; 2], expected: [f64; 2]) {
+        for axis in 0..2 {
+            assert!(
+                (actual[axis] - expected[axis]).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn check_finite_rejects_nan_and_infinity_in_each_field() {
+        let camera = CameraValues {
+            center: [10.0, 20.0],
+            zoom: 5.0,
+            bearing: 30.0,
+            pitch: 40.0,
+        };
+        assert!(check_finite(camera).is_ok());
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let cases = [
+                (
+                    "longitude",
+                    CameraValues {
+                        center: [bad, 20.0],
+                        ..camera
+                    },
+                ),
+                (
+                    "latitude",
+                    CameraValues {
+                        center: [10.0, bad],
+                        ..camera
+                    },
+                ),
+                (
+                    "zoom",
+                    CameraValues {
+                        zoom: bad,
+                        ..camera
+                    },
+                ),
+                (
+                    "bearing",
+                    CameraValues {
+                        bearing: bad,
+                        ..camera
+                    },
+                ),
+                (
+                    "pitch",
+                    CameraValues {
+                        pitch: bad,
+                        ..camera
+                    },
+                ),
+            ];
+            for (name, values) in cases {
+                let error = check_finite(values).unwrap_err();
+                assert!(error.starts_with(name), "{}: {}", name, error);
+                assert!(validate(values).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn check_range_rejects_latitude_beyond_the_poles_and_clamps_pitch() {
+        let camera = CameraValues {
+            center: [190.0, 90.0],
+            zoom: 5.0,
+            bearing: 30.0,
+            pitch: 85.0,
+        };
+        let checked = check_range(camera).unwrap();
+        assert_near(checked.center, [-170.0, 90.0]);
+        assert_eq!(checked.pitch, MAX_PITCH);
+        assert_eq!(checked.zoom, camera.zoom);
+        assert_eq!(checked.bearing, camera.bearing);
+        assert_eq!(
+            check_range(CameraValues {
+                pitch: -5.0,
+                ..camera
+            })
+            .unwrap()
+            .pitch,
+            MIN_PITCH
+        );
+        for lat in [95.0, -90.5] {
+            let error = check_range(CameraValues {
+                center: [0.0, lat],
+                ..camera
+            })
+            .unwrap_err();
+            assert!(error.starts_with("latitude"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn zoom_range_rejects_or_clamps_outside_its_bounds() {
+        let reject = ZoomRange::new(2.0, 10.0, ZoomOutOfRange::Reject).unwrap();
+        assert_eq!(reject.apply(2.0), Ok(2.0));
+        assert_eq!(reject.apply(10.0), Ok(10.0));
+        for zoom in [1.999, 10.001] {
+            let error = reject.apply(zoom).unwrap_err();
+            assert!(error.contains("2 to 10"), "{}", error);
+        }
+
+        let clamp = ZoomRange {
+            out_of_range: ZoomOutOfRange::Clamp,
+            ..reject
+        };
+        assert_eq!(clamp.apply(2.0), Ok(2.0));
+        assert_eq!(clamp.apply(10.0), Ok(10.0));
+        assert_eq!(clamp.apply(0.0), Ok(2.0));
+        assert_eq!(clamp.apply(10.5), Ok(10.0));
+    }
+
+    #[test]
+    fn zoom_range_rejects_inverted_or_out_of_limit_bounds() {
+        assert!(ZoomRange::new(5.0, 5.0, ZoomOutOfRange::Reject).is_ok());
+        assert!(ZoomRange::new(6.0, 5.0, ZoomOutOfRange::Reject).is_err());
+        assert!(ZoomRange::new(-1.0, 5.0, ZoomOutOfRange::Reject).is_err());
+        assert!(ZoomRange::new(0.0, MAX_ZOOM + 1.0, ZoomOutOfRange::Reject).is_err());
+        assert!(ZoomRange::new(f64::NAN, 5.0, ZoomOutOfRange::Reject).is_err());
+    }
+
+    #[test]
+    fn path_runs_end_to_end_turning_the_short_way() {
+        let from = CameraValues {
+            center: [-10.0, 20.0],
+            zoom: 2.0,
+            bearing: 350.0,
+            pitch: 0.0,
+        };
+        let to = CameraValues {
+            center: [30.0, -40.0],
+            zoom: 6.0,
+            bearing: 10.0,
+            pitch: 40.0,
+        };
+        for easing in [Easing::Linear, Easing::EaseInOut] {
+            let cameras = path(from, to, 3, easing).unwrap();
+            assert_eq!(cameras.len(), 3);
+            assert_near(cameras[0].center, from.center);
+            assert_near(cameras[2].center, to.center);
+            assert!((cameras[1].zoom - 4.0).abs() < 1e-9);
+            assert!(cameras[1].bearing.abs() < 1e-9, "{}", cameras[1].bearing);
+            assert!((cameras[2].bearing - 10.0).abs() < 1e-9);
+            assert!((cameras[2].pitch - 40.0).abs() < 1e-9);
+        }
+        let eased = path(from, to, 5, Easing::EaseInOut).unwrap();
+        assert!(eased[1].zoom - from.zoom < 1.0);
+        assert!(path(from, to, 1, Easing::Linear).is_err());
+    }
+
+    #[test]
+    fn check_finite_bounds_rejects_nan_and_infinity_in_each_edge() {
+        let bounds = [-10.0, -5.0, 10.0, 5.0];
+        assert!(check_finite_bounds(bounds).is_ok());
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            for (edge, name) in ["west", "south", "east", "north"].into_iter().enumerate() {
+                let mut values = bounds;
+                values[edge] = bad;
+                let error = check_finite_bounds(values).unwrap_err();
+                assert!(error.contains(name), "{}: {}", name, error);
+            }
+        }
+    }
+
+    #[test]
+    fn window_center_stays_within_the_world() {
+        // The zoom 1 world is 1024 pixels; its top-left quadrant centers on the
+        // north-west of the Mercator square.
+        let center = window_center(1.0, [0.0, 0.0], [512, 512]).unwrap();
+        assert!((center[0] + 90.0).abs() < 1e-9, "{:?}", center);
+        assert!((center[1] - 66.51326044311186).abs() < 1e-9, "{:?}", center);
+
+        assert!(window_center(1.0, [0.0, 0.0], [0, 512]).is_err());
+        assert!(window_center(1.0, [-1.0, 0.0], [512, 512]).is_err());
+        let error = window_center(1.0, [600.0, 0.0], [512, 512]).unwrap_err();
+        assert!(error.contains("zoom 1 pixel extent"), "{}", error);
+    }
+
+    #[test]
+    fn fit_bounds_fills_the_padded_view() {
+        // One zoom 2 tile is 90 degrees wide and fills 512 pixels.
+        let (center, zoom) = fit_bounds([[0.0, 0.0], [90.0, 66.5]], [612.0, 900.0], 50.0).unwrap();
+        assert!((zoom - 2.0).abs() < 1e-9, "{}", zoom);
+        assert!((center[0] - 45.0).abs() < 1e-9, "{:?}", center);
+
+        // Crossing the antimeridian centers on it rather than on the prime meridian.
+        let (center, zoom) =
+            fit_bounds([[170.0, -10.0], [-170.0, 10.0]], [512.0, 1024.0], 0.0).unwrap();
+        assert!((center[0].abs() - 180.0).abs() < 1e-9, "{:?}", center);
+        assert!(center[1].abs() < 1e-9, "{:?}", center);
+        assert!((zoom - 18f64.log2()).abs() < 1e-9, "{}", zoom);
+    }
+
+    #[test]
+    fn fit_bounds_rejects_degenerate_bounds() {
+        for bounds in [[[5.0, -5.0], [5.0, 5.0]], [[-5.0, 5.0], [5.0, 5.0]]] {
+            let error = fit_bounds(bounds, [512.0, 512.0], 0.0).unwrap_err();
+            assert!(error.contains("non-zero area"), "{}", error);
+        }
+        assert!(fit_bounds([[0.0, 10.0], [10.0, 0.0]], [512.0, 512.0], 0.0).is_err());
+        assert!(fit_bounds([[0.0, 0.0], [10.0, 10.0]], [512.0, 512.0], 256.0).is_err());
+    }
+
+    #[test]
+    fn tile_view_centers_the_tile() {
+        let (center, zoom) = tile_view([0, 0, 0], [256.0, 256.0]).unwrap();
+        assert_near(center, [0.0, 0.0]);
+        assert!((zoom + 1.0).abs() < 1e-9, "{}", zoom);
+
+        // Tile 2/3/0 spans 90..180 east and 66.51..85.05 north.
+        let (center, zoom) = tile_view([2, 3, 0], [512.0, 1024.0]).unwrap();
+        assert!((center[0] - 135.0).abs() < 1e-9, "{:?}", center);
+        assert!(center[1] > 66.5 && center[1] < MAX_LATITUDE, "{:?}", center);
+        assert!((zoom - 2.0).abs() < 1e-9, "{}", zoom);
+
+        assert!(tile_view([2, 4, 0], [512.0, 512.0]).is_err());
+        assert!(tile_view([2, 0, 4], [512.0, 512.0]).is_err());
+        assert!(tile_view([25, 0, 0], [512.0, 512.0]).is_err());
+    }
+
+    #[test]
+    fn projection_matrix_maps_view_edges_to_clip_space() {
+        let camera = CameraValues {
+            center: [0.0, 0.0],
+            zoom: 3.0,
+            bearing: 0.0,
+            pitch: 0.0,
+        };
+        let matrix = projection_matrix(camera, [800.0, 600.0], Padding::default());
+        let center = world_size(3.0) / 2.0;
+        assert_near(project(&matrix, [center, center]), [0.0, 0.0]);
+        // The right edge is 400 px east and the top edge 300 px north (up) of the centre.
+        assert_near(project(&matrix, [center + 400.0, center]), [1.0, 0.0]);
+        assert_near(project(&matrix, [center, center - 300.0]), [0.0, 1.0]);
+
+        // Facing east, points east of the centre appear above it.
+        let east = projection_matrix(
+            CameraValues {
+                bearing: 90.0,
+                ..camera
+            },
+            [800.0, 600.0],
+            Padding::default(),
+        );
+        assert_near(project(&east, [center + 300.0, center]), [0.0, 1.0]);
+
+        // With 200 px of left padding the centre sits 100 px right of the middle.
+        let padded = projection_matrix(
+            camera,
+            [800.0, 600.0],
+            Padding {
+                left: 200.0,
+                ..Padding::default()
+            },
+        );
+        assert_near(project(&padded, [center, center]), [0.25, 0.0]);
+        assert_near(project(&padded, [center + 300.0, center]), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn ground_pixel_of_each_corner_projects_back_to_it() {
+        let camera = CameraValues {
+            center: [151.2, -33.9],
+            zoom: 12.0,
+            bearing: 30.0,
+            pitch: 45.0,
+        };
+        let size = [800.0, 600.0];
+        let matrix = projection_matrix(camera, size, Padding::default());
+        for (point, ndc) in [
+            ([0.0, 0.0], [-1.0, 1.0]),
+            ([800.0, 0.0], [1.0, 1.0]),
+            ([800.0, 600.0], [1.0, -1.0]),
+            ([0.0, 600.0], [-1.0, -1.0]),
+        ] {
+            let ground = ground_pixel(camera, size, point);
+            let projected = project(&matrix, ground);
+            assert!(
+                (projected[0] - ndc[0]).abs() < 1e-6 && (projected[1] - ndc[1]).abs() < 1e-6,
+                "{:?} != {:?}",
+                projected,
+                ndc
+            );
+        }
+    }
+
+    #[test]
+    fn describe_view_lists_tiles_at_zoom_2_on_the_equator() {
+        let camera = CameraValues {
+            center: [0.0, 0.0],
+            zoom: 2.0,
+            bearing: 0.0,
+            pitch: 0.0,
+        };
+        let view = describe_view(camera, [512.0, 512.0], 2.0);
+        assert_eq!(view.tile_zoom, 2);
+        assert_eq!(view.tiles, vec![[2, 1, 1], [2, 1, 2], [2, 2, 1], [2, 2, 2]]);
+        let [west, south, east, north] = view.bounds;
+        assert_near([west, east], [-45.0, 45.0]);
+        assert_near(
+            [south, north],
+            [-40.979_898_069_620_13, 40.979_898_069_620_13],
+        );
+        assert!((view.meters_per_pixel - EARTH_CIRCUMFERENCE / 2048.0 / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn describe_view_wraps_tiles_across_the_antimeridian() {
+        let camera = CameraValues {
+            center: [180.0, 0.0],
+            zoom: 2.0,
+            bearing: 0.0,
+            pitch: 0.0,
+        };
+        let view = describe_view(camera, [512.0, 512.0], 1.0);
+        assert_eq!(view.tiles, vec![[2, 0, 1], [2, 0, 2], [2, 3, 1], [2, 3, 2]]);
+        assert_near([view.bounds[0], view.bounds[2]], [135.0, -135.0]);
+    }
+
+    #[test]
+    fn describe_view_skips_tiles_outside_a_rotated_view() {
+        let camera = CameraValues {
+            center: [0.0, 0.0],
+            zoom: 3.0,
+            bearing: 45.0,
+            pitch: 0.0,
+        };
+        // A diamond reaching 1448 pixels from (2048, 2048) along each axis: 24 of the 36
+        // tiles of its bounding box.
+        let view = describe_view(camera, [2048.0, 2048.0], 1.0);
+        assert!(view.tiles.contains(&[3, 3, 3]));
+        assert!(view.tiles.contains(&[3, 1, 3]));
+        assert!(!view.tiles.contains(&[3, 1, 1]));
+        assert!(!view.tiles.contains(&[3, 1, 2]));
+        assert_eq!(view.tiles.len(), 24);
+    }
+
+    #[test]
+    fn large_tiles_move_the_camera_to_each_tile() {
+        let tiles = large_tiles([0.0, 0.0], 1.0, 0.0, [256, 256], 128, 16).unwrap();
+        let origins: Vec<[u32; 2]> = tiles.iter().map(|tile| tile.origin).collect();
+        assert_eq!(origins, vec![[0, 0], [128, 0], [0, 128], [128, 128]]);
+        assert!(tiles.iter().all(|tile| tile.size == [128, 128]));
+        // 64 pixels of a 1024-pixel world.
+        assert_near(tiles[0].center, [-22.5, -tiles[3].center[1]]);
+        assert_near([tiles[1].center[0], tiles[3].center[0]], [22.5, 22.5]);
+        assert!(tiles[0].center[1] > 0.0);
+    }
+
+    #[test]
+    fn large_tiles_trim_the_last_row_and_column() {
+        let tiles = large_tiles([10.0, 20.0], 4.0, 0.0, [300, 200], 128, 0).unwrap();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2].origin, [256, 0]);
+        assert_eq!(tiles[2].size, [44, 128]);
+        assert_eq!(tiles[5].size, [44, 72]);
+    }
+
+    #[test]
+    fn large_tiles_follow_the_bearing() {
+        // Facing east, the top right of the screen is south-east of the center.
+        let tiles = large_tiles([0.0, 0.0], 1.0, 90.0, [256, 256], 128, 0).unwrap();
+        assert_near(tiles[1].center, [22.5, -tiles[0].center[1]]);
+        assert!(tiles[1].center[1] < 0.0);
+        assert!(tiles[0].center[0] > 0.0);
+    }
+
+    #[test]
+    fn large_tiles_reject_views_past_the_poles() {
+        assert!(large_tiles([0.0, 0.0], 0.0, 0.0, [1024, 256], 128, 0).is_ok());
+        assert!(large_tiles([0.0, 0.0], 0.0, 0.0, [256, 1024], 128, 0).is_err());
+        assert!(large_tiles([0.0, 84.0], 3.0, 0.0, [512, 512], 128, 0).is_err());
+        assert!(large_tiles([0.0, 0.0], 1.0, 0.0, [256, 256], 0, 0).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+//! Best-effort cancellation of `render_batch` and `render_path`.
+//!
+//! The stdin reader flags a `cancel` line as soon as it arrives, while the main loop
+//! may still be busy with the command to cancel, which checks the flag before each
+//! view and stops early. The `cancel` command itself reaches the main loop after that
+//! command and clears the flag, so a cancel sent while nothing runs only stops
+//! commands sent before it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Error of a view skipped because of `cancel`.
+pub const CANCELLED: &str = "cancelled";
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask the running command to stop before its next view.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the running command should stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Forget the request once the `cancel` command is handled.
+pub fn clear() {
+    REQUESTED.store(false, Ordering::SeqCst);
+}
@@ -0,0 +1,538 @@
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+const LOUPE_BORDER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Source-over blend of `src` onto `dst` with an extra coverage factor in [0, 1].
+fn blend(dst: &mut Rgba<u8>, src: Rgba<u8>, coverage: f32) {
+    let sa = src[3] as f32 / 255.0 * coverage;
+    if sa <= 0.0 {
+        return;
+    }
+    let da = dst[3] as f32 / 255.0;
+    let out_a = sa + da * (1.0 - sa);
+    for c in 0..3 {
+        let value = (src[c] as f32 * sa + dst[c] as f32 * da * (1.0 - sa)) / out_a;
+        dst[c] = value.round().clamp(0.0, 255.0) as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+/// Composite the middle of `detail` into `base` as a circular loupe centred at
+/// `center` (physical pixels), ringed by a `border`-wide outline.
+///
+/// Edges are antialiased by pixel coverage so the loupe looks clean at any pixel ratio.
+pub fn loupe(base: &mut RgbaImage, detail: &RgbaImage, center: [f64; 2], radius: f64, border: f64) {
+    let outer = radius + border;
+    let (detail_cx, detail_cy) = (detail.width() as f64 / 2.0, detail.height() as f64 / 2.0);
+    let x0 = (center[0] - outer).floor().max(0.0) as u32;
+    let y0 = (center[1] - outer).floor().max(0.0) as u32;
+    let x1 = ((center[0] + outer).ceil() as u32).min(base.width());
+    let y1 = ((center[1] + outer).ceil() as u32).min(base.height());
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let dx = x as f64 + 0.5 - center[0];
+            let dy = y as f64 + 0.5 - center[1];
+            let distance = (dx * dx + dy * dy).sqrt();
+            let inside = (radius - distance + 0.5).clamp(0.0, 1.0) as f32;
+            let ring = (outer - distance + 0.5).clamp(0.0, 1.0) as f32;
+
+            let pixel = base.get_pixel_mut(x, y);
+            if ring > 0.0 {
+                blend(pixel, LOUPE_BORDER, ring);
+            }
+            if inside > 0.0 {
+                let sx = (detail_cx + dx).floor();
+                let sy = (detail_cy + dy).floor();
+                if sx >= 0.0
+                    && sy >= 0.0
+                    && (sx as u32) < detail.width()
+                    && (sy as u32) < detail.height()
+                {
+                    blend(pixel, *detail.get_pixel(sx as u32, sy as u32), inside);
+                }
+            }
+        }
+    }
+}
+
+/// Place `panel` beside `map`, on the left or right, in one image. Both must be the
+/// same height.
+pub fn dock(map: &RgbaImage, panel: &RgbaImage, left: bool) -> RgbaImage {
+    let mut out = RgbaImage::new(
+        map.width() + panel.width(),
+        map.height().max(panel.height()),
+    );
+    let (map_x, panel_x) = if left {
+        (panel.width(), 0)
+    } else {
+        (0, map.width())
+    };
+    image::imageops::replace(&mut out, map, i64::from(map_x), 0);
+    image::imageops::replace(&mut out, panel, i64::from(panel_x), 0);
+    out
+}
+
+/// Copy the opaque pixels of `top` onto `base`; `top` must have binary alpha (see
+/// `raster::harden_alpha`) and the same size.
+pub fn stamp(base: &mut RgbaImage, top: &RgbaImage) {
+    for (dst, src) in base.pixels_mut().zip(top.pixels()) {
+        if src[3] == 255 {
+            *dst = *src;
+        }
+    }
+}
+
+/// Source-over composite `top` onto `base`; both straight alpha and the same size.
+pub fn over(base: &mut RgbaImage, top: &RgbaImage) {
+    for (dst, src) in base.pixels_mut().zip(top.pixels()) {
+        blend(dst, *src, 1.0);
+    }
+}
+
+/// Arrange equally sized `cells` row by row, `columns` to a row, `gap` pixels apart
+/// on a white background.
+pub fn grid(cells: &[RgbaImage], columns: usize, gap: u32) -> RgbaImage {
+    let (width, height) = cells
+        .first()
+        .map_or((0, 0), |cell| (cell.width(), cell.height()));
+    let columns = columns.clamp(1, cells.len().max(1));
+    let rows = cells.len().div_ceil(columns);
+    let span =
+        |count: usize, size: u32| count as u32 * size + (count as u32).saturating_sub(1) * gap;
+    let mut out = RgbaImage::from_pixel(
+        span(columns, width),
+        span(rows, height),
+        Rgba([255, 255, 255, 255]),
+    );
+    for (index, cell) in cells.iter().enumerate() {
+        let x = (index % columns) as u32 * (width + gap);
+        let y = (index / columns) as u32 * (height + gap);
+        image::imageops::overlay(&mut out, cell, i64::from(x), i64::from(y));
+    }
+    out
+}
+
+/// 3×5 bitmaps of the characters labels can show, one row per entry, leftmost pixel
+/// in bit 2. Other lowercase letters are drawn as capitals.
+const LABEL_GLYPHS: [(char, [u8; 5]); 55] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('N', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('E', [0b111, 0b100, 0b111, 0b100, 0b111]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('F', [0b111, 0b100, 0b111, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    (';', [0b000, 0b010, 0b000, 0b010, 0b100]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b111, 0b001, 0b010, 0b000, 0b010]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('|', [0b010, 0b010, 0b010, 0b010, 0b010]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+];
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_TEXT: Rgba<u8> = Rgba([34, 34, 34, 255]);
+/// Box behind attribution text: white at 75% opacity, so the map shows through.
+const ATTRIBUTION_BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 191]);
+
+/// Where on the image attribution and watermarks are placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl Anchor {
+    /// Top-left pixel of a `size` box placed `margin` pixels in from the `canvas`
+    /// edges this anchor names.
+    fn origin(self, canvas: (u32, u32), size: [u32; 2], margin: u32) -> [u32; 2] {
+        let place = |extent: u32, length: u32, start: bool, end: bool| {
+            let free = extent.saturating_sub(length);
+            match (start, end) {
+                (true, _) => margin.min(free),
+                (_, true) => free.saturating_sub(margin),
+                _ => free / 2,
+            }
+        };
+        let (left, right, top, bottom) = match self {
+            Anchor::TopLeft => (true, false, true, false),
+            Anchor::TopRight => (false, true, true, false),
+            Anchor::BottomLeft => (true, false, false, true),
+            Anchor::BottomRight => (false, true, false, true),
+            Anchor::Center => (false, false, false, false),
+        };
+        [
+            place(canvas.0, size[0], left, right),
+            place(canvas.1, size[1], top, bottom),
+        ]
+    }
+}
+
+/// A logo `render` composites over the finished image.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Watermark {
+    /// File path, URL, `data:` URI or bare base64 of a PNG, JPEG or WebP image.
+    pub image: String,
+    #[serde(default)]
+    pub position: Anchor,
+    #[serde(default = "default_watermark_opacity")]
+    pub opacity: f64,
+    /// Logical pixels between the logo and the edges it is placed against.
+    #[serde(default)]
+    pub margin: f64,
+}
+
+fn default_watermark_opacity() -> f64 {
+    1.0
+}
+
+impl Watermark {
+    /// Reject an opacity outside 0..=1 or a negative margin.
+    pub fn check(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.opacity) {
+            return Err(format!(
+                "watermark opacity must be between 0 and 1, got {}",
+                self.opacity
+            ));
+        }
+        if !(self.margin.is_finite() && self.margin >= 0.0) {
+            return Err(format!(
+                "watermark margin must be a non-negative number, got {}",
+                self.margin
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The bitmap for `character`, falling back to the capital of a lowercase letter.
+fn glyph(character: char) -> Option<&'static [u8; 5]> {
+    [character, character.to_ascii_uppercase()]
+        .into_iter()
+        .find_map(|c| LABEL_GLYPHS.iter().find(|(glyph, _)| *glyph == c))
+        .map(|(_, rows)| rows)
+}
+
+/// Width and height in pixels of the box `label` draws for `text`.
+pub fn label_size(text: &str, scale: u32) -> [u32; 2] {
+    let scale = scale.max(1);
+    [(text.chars().count() as u32 * 4 + 1) * scale, 7 * scale]
+}
+
+/// Blend `color` onto `base` weighted by `coverage`, one value in [0, 1] per pixel
+/// in row-major order.
+pub fn paint_mask(base: &mut RgbaImage, coverage: &[f32], color: Rgba<u8>) {
+    for (pixel, &coverage) in base.pixels_mut().zip(coverage) {
+        if coverage > 0.0 {
+            blend(pixel, color, coverage);
+        }
+    }
+}
+
+/// Draw `text` in a white box with its top-left corner at `origin` (physical
+/// pixels), each font pixel `scale` pixels square. Characters without a bitmap are
+/// left blank.
+///
+/// Labels are drawn from a built-in bitmap font rather than the style's glyphs, so
+/// they work with any style, including ones without a `glyphs` URL.
+pub fn label(base: &mut RgbaImage, text: &str, origin: [u32; 2], scale: u32) {
+    draw_label(base, text, origin, scale, LABEL_BACKGROUND);
+}
+
+/// `label` over a box of `background`, blended onto the image.
+fn draw_label(
+    base: &mut RgbaImage,
+    text: &str,
+    origin: [u32; 2],
+    scale: u32,
+    background: Rgba<u8>,
+) {
+    let scale = scale.max(1);
+    let [box_width, box_height] = label_size(text, scale);
+    let (width, height) = base.dimensions();
+    let clip = |x: u32, y: u32| x < width && y < height;
+    for y in origin[1]..origin[1] + box_height {
+        for x in origin[0]..origin[0] + box_width {
+            if clip(x, y) {
+                blend(base.get_pixel_mut(x, y), background, 1.0);
+            }
+        }
+    }
+    for (index, character) in text.chars().enumerate() {
+        let Some(rows) = glyph(character) else {
+            continue;
+        };
+        let left = origin[0] + (index as u32 * 4 + 1) * scale;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                let (x0, y0) = (left + column * scale, origin[1] + (row as u32 + 1) * scale);
+                for y in y0..y0 + scale {
+                    for x in x0..x0 + scale {
+                        if clip(x, y) {
+                            base.put_pixel(x, y, LABEL_TEXT);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draw attribution `text` in a translucent box flush with `corner`, each font pixel
+/// `scale` pixels square. Text wider than the image is drawn smaller, down to a scale
+/// of 1, and then cut off at the far edge.
+pub fn attribution(base: &mut RgbaImage, text: &str, corner: Anchor, scale: u32) {
+    let text = text.replace('©', "(C)");
+    let (width, height) = base.dimensions();
+    let mut scale = scale.max(1);
+    while scale > 1 && label_size(&text, scale)[0] > width {
+        scale -= 1;
+    }
+    let size = label_size(&text, scale);
+    let origin = corner.origin((width, height), size, 0);
+    draw_label(base, &text, origin, scale, ATTRIBUTION_BACKGROUND);
+}
+
+/// Blend `logo` onto `base` at `position`, `margin` pixels from the edges it is placed
+/// against, with its alpha scaled by `opacity`. A logo that doesn't fit inside the
+/// margins is shrunk to fit, keeping its aspect ratio.
+pub fn watermark(
+    base: &mut RgbaImage,
+    logo: &RgbaImage,
+    position: Anchor,
+    opacity: f64,
+    margin: u32,
+) {
+    let (width, height) = base.dimensions();
+    let room = [
+        width.saturating_sub(2 * margin).max(1),
+        height.saturating_sub(2 * margin).max(1),
+    ];
+    let fitted;
+    let logo = if logo.width() > room[0] || logo.height() > room[1] {
+        let factor = f64::min(
+            f64::from(room[0]) / f64::from(logo.width()),
+            f64::from(room[1]) / f64::from(logo.height()),
+        );
+        let size = [logo.width(), logo.height()]
+            .map(|side| ((f64::from(side) * factor).round() as u32).max(1));
+        fitted = image::imageops::resize(logo, size[0], size[1], FilterType::Triangle);
+        &fitted
+    } else {
+        logo
+    };
+    let [left, top] = position.origin((width, height), [logo.width(), logo.height()], margin);
+    for (x, y, pixel) in logo.enumerate_pixels() {
+        if left + x < width && top + y < height {
+            blend(
+                base.get_pixel_mut(left + x, top + y),
+                *pixel,
+                opacity as f32,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    #[test]
+    fn blending_weights_source_by_alpha_and_coverage() {
+        let mut pixel = BLUE;
+        blend(&mut pixel, Rgba([255, 0, 0, 128]), 1.0);
+        assert_eq!(pixel, Rgba([128, 0, 127, 255]));
+
+        let mut pixel = BLUE;
+        blend(&mut pixel, RED, 0.0);
+        assert_eq!(pixel, BLUE);
+
+        // Onto transparency, colour is kept and only alpha is reduced.
+        let mut pixel = Rgba([0, 0, 0, 0]);
+        blend(&mut pixel, RED, 0.5);
+        assert_eq!(pixel, Rgba([255, 0, 0, 128]));
+    }
+
+    #[test]
+    fn loupe_shows_detail_inside_a_white_ring() {
+        let mut base = RgbaImage::from_pixel(40, 40, BLUE);
+        let detail = RgbaImage::from_pixel(20, 20, RED);
+        loupe(&mut base, &detail, [20.0, 20.0], 8.0, 2.0);
+        assert_eq!(*base.get_pixel(20, 20), RED);
+        // 8.5px from the centre: fully in the ring, just outside the detail.
+        assert_eq!(*base.get_pixel(20, 11), LOUPE_BORDER);
+        assert_eq!(*base.get_pixel(0, 0), BLUE);
+        assert_eq!(*base.get_pixel(20, 5), BLUE);
+    }
+
+    #[test]
+    fn loupe_is_clipped_at_the_image_edge() {
+        let mut base = RgbaImage::from_pixel(10, 10, BLUE);
+        let detail = RgbaImage::from_pixel(30, 30, RED);
+        loupe(&mut base, &detail, [0.0, 0.0], 6.0, 1.0);
+        assert_eq!(*base.get_pixel(0, 0), RED);
+        assert_eq!(*base.get_pixel(9, 9), BLUE);
+    }
+
+    #[test]
+    fn dock_places_the_panel_on_either_side() {
+        let map = RgbaImage::from_pixel(4, 3, BLUE);
+        let panel = RgbaImage::from_pixel(2, 3, RED);
+        let right = dock(&map, &panel, false);
+        assert_eq!(right.dimensions(), (6, 3));
+        assert_eq!(*right.get_pixel(0, 0), BLUE);
+        assert_eq!(*right.get_pixel(5, 2), RED);
+        let left = dock(&map, &panel, true);
+        assert_eq!(*left.get_pixel(0, 0), RED);
+        assert_eq!(*left.get_pixel(5, 2), BLUE);
+    }
+
+    #[test]
+    fn stamp_copies_only_opaque_pixels() {
+        let mut base = RgbaImage::from_pixel(2, 1, BLUE);
+        let mut top = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 0]));
+        top.put_pixel(1, 0, RED);
+        stamp(&mut base, &top);
+        assert_eq!(base.pixels().copied().collect::<Vec<_>>(), [BLUE, RED]);
+    }
+
+    #[test]
+    fn grid_lays_cells_out_row_by_row() {
+        let cells: Vec<_> = [RED, BLUE, RED]
+            .iter()
+            .map(|color| RgbaImage::from_pixel(4, 3, *color))
+            .collect();
+        let out = grid(&cells, 2, 1);
+        assert_eq!(out.dimensions(), (9, 7));
+        assert_eq!(*out.get_pixel(0, 0), RED);
+        assert_eq!(*out.get_pixel(4, 0), LABEL_BACKGROUND);
+        assert_eq!(*out.get_pixel(5, 0), BLUE);
+        assert_eq!(*out.get_pixel(0, 4), RED);
+        // The missing fourth cell leaves the background showing.
+        assert_eq!(*out.get_pixel(8, 6), LABEL_BACKGROUND);
+        // More columns than cells collapses to a single row.
+        assert_eq!(grid(&cells, 10, 0).dimensions(), (12, 3));
+    }
+
+    #[test]
+    fn lowercase_falls_back_to_capitals() {
+        assert_eq!(glyph('a'), glyph('A'));
+        assert!(glyph('z').is_some());
+        assert_eq!(glyph('é'), None);
+        assert_eq!(label_size("z12", 2), [26, 14]);
+    }
+
+    #[test]
+    fn label_draws_text_over_its_box() {
+        let mut base = RgbaImage::from_pixel(20, 10, BLUE);
+        label(&mut base, "1", [2, 2], 1);
+        // The box is 5×7; the top row of "1" is its middle column.
+        assert_eq!(*base.get_pixel(2, 2), LABEL_BACKGROUND);
+        assert_eq!(*base.get_pixel(4, 3), LABEL_TEXT);
+        assert_eq!(*base.get_pixel(3, 3), LABEL_BACKGROUND);
+        assert_eq!(*base.get_pixel(7, 2), BLUE);
+    }
+
+    #[test]
+    fn anchors_place_boxes_inside_the_margin() {
+        let canvas = (100, 50);
+        let origin = |anchor: Anchor| anchor.origin(canvas, [20, 10], 5);
+        assert_eq!(origin(Anchor::TopLeft), [5, 5]);
+        assert_eq!(origin(Anchor::TopRight), [75, 5]);
+        assert_eq!(origin(Anchor::BottomLeft), [5, 35]);
+        assert_eq!(origin(Anchor::BottomRight), [75, 35]);
+        assert_eq!(origin(Anchor::Center), [40, 20]);
+        // A box wider than the canvas is pinned to the start.
+        assert_eq!(Anchor::BottomRight.origin(canvas, [120, 10], 5), [0, 35]);
+    }
+
+    #[test]
+    fn attribution_shrinks_to_fit_the_width() {
+        let mut base = RgbaImage::from_pixel(30, 20, BLUE);
+        // 5 characters are 21px wide at scale 1 and 42px at scale 2.
+        attribution(&mut base, "© OSM", Anchor::BottomRight, 2);
+        assert_ne!(*base.get_pixel(29, 19), BLUE);
+        assert_eq!(*base.get_pixel(29, 12), BLUE);
+    }
+
+    #[test]
+    fn watermark_check_rejects_bad_values() {
+        let watermark = |opacity, margin| Watermark {
+            image: String::new(),
+            position: Anchor::default(),
+            opacity,
+            margin,
+        };
+        assert!(watermark(0.5, 4.0).check().is_ok());
+        assert!(watermark(1.5, 0.0).check().is_err());
+        assert!(watermark(f64::NAN, 0.0).check().is_err());
+        assert!(watermark(1.0, -1.0).check().is_err());
+    }
+
+    #[test]
+    fn watermark_is_shrunk_to_fit_the_margins() {
+        let mut base = RgbaImage::from_pixel(20, 20, BLUE);
+        let logo = RgbaImage::from_pixel(40, 10, RED);
+        watermark(&mut base, &logo, Anchor::TopLeft, 1.0, 2);
+        // Fitted to 16×4 at the top-left margin.
+        assert_eq!(*base.get_pixel(2, 2), RED);
+        assert_eq!(*base.get_pixel(17, 5), RED);
+        assert_eq!(*base.get_pixel(18, 2), BLUE);
+        assert_eq!(*base.get_pixel(2, 6), BLUE);
+    }
+}
@@ -0,0 +1,300 @@
+//! Region-locked cameras for `render_constrained`.
+//!
+//! Work happens in Web Mercator world fractions, where the view is a rectangle of
+//! `size / world_size(zoom)` around the centre, rotated by the bearing. Pitch is not
+//! taken into account, and boundaries must not cross the antimeridian.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::camera::{self, CameraValues, WORLD_TILE_SIZE};
+
+/// Positions closer than this (in world fractions, ~0.1 mm at the equator) are equal.
+const EPSILON: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Zoom out and pan as needed so the boundary's bounding box is fully in view.
+    Contain,
+    /// Move the centre to the nearest point of the boundary if it lies outside.
+    ClampCenter,
+}
+
+/// The camera after constraining, and which of `"zoom"` and `"center"` changed.
+#[derive(Debug, Serialize)]
+pub struct Constrained {
+    pub adjusted: Vec<&'static str>,
+    pub camera: CameraValues,
+}
+
+type Ring = Vec<[f64; 2]>;
+
+fn ring(coordinates: &Value) -> Ring {
+    coordinates
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| Some(camera::mercator_fraction([c[0].as_f64()?, c[1].as_f64()?])))
+        .collect()
+}
+
+/// Polygons (outer ring then holes) of a FeatureCollection, Feature or geometry.
+fn polygons(value: &Value, out: &mut Vec<Vec<Ring>>) {
+    let polygon = |rings: &Value| rings.as_array().into_iter().flatten().map(ring).collect();
+    match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                polygons(feature, out);
+            }
+        }
+        Some("Feature") => polygons(&value["geometry"], out),
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                polygons(geometry, out);
+            }
+        }
+        Some("Polygon") => out.push(polygon(&value["coordinates"])),
+        Some("MultiPolygon") => {
+            for coordinates in value["coordinates"].as_array().into_iter().flatten() {
+                out.push(polygon(coordinates));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn point_in_ring(point: [f64; 2], ring: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[j]);
+        if (a[1] > point[1]) != (b[1] > point[1])
+            && point[0] < (b[0] - a[0]) * (point[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn nearest_on_segment(point: [f64; 2], a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq == 0.0 {
+        0.0
+    } else {
+        (((point[0] - a[0]) * dx + (point[1] - a[1]) * dy) / length_sq).clamp(0.0, 1.0)
+    };
+    [a[0] + t * dx, a[1] + t * dy]
+}
+
+fn to_lng_lat(fraction: [f64; 2]) -> [f64; 2] {
+    camera::pixel_to_lng_lat(
+        [fraction[0] * WORLD_TILE_SIZE, fraction[1] * WORLD_TILE_SIZE],
+        0.0,
+    )
+}
+
+fn contain(camera: &mut CameraValues, rings: &[&Ring], size: [f64; 2]) -> Vec<&'static str> {
+    let points = rings.iter().flat_map(|ring| ring.iter());
+    let (mut min, mut max) = ([f64::MAX; 2], [f64::MIN; 2]);
+    for point in points {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    let (sin, cos) = camera.bearing.to_radians().sin_cos();
+    let (width, height) = (max[0] - min[0], max[1] - min[1]);
+    // Extent of the rotated bounding box along the screen axes.
+    let extent = [
+        width * cos.abs() + height * sin.abs(),
+        width * sin.abs() + height * cos.abs(),
+    ];
+    let mut adjusted = Vec::new();
+    let fit = (0..2)
+        .map(|axis| (size[axis] / (WORLD_TILE_SIZE * extent[axis])).log2())
+        .fold(f64::INFINITY, f64::min)
+        .max(camera::MIN_ZOOM);
+    if camera.zoom > fit {
+        camera.zoom = fit;
+        adjusted.push("zoom");
+    }
+
+    let world = camera::world_size(camera.zoom);
+    let middle = [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0];
+    let center = camera::mercator_fraction(camera.center);
+    let offset = [center[0] - middle[0], center[1] - middle[1]];
+    let screen = [
+        offset[0] * cos + offset[1] * sin,
+        -offset[0] * sin + offset[1] * cos,
+    ];
+    let slack: Vec<f64> = (0..2)
+        .map(|axis| (size[axis] / 2.0 / world - extent[axis] / 2.0).max(0.0))
+        .collect();
+    let clamped = [
+        screen[0].clamp(-slack[0], slack[0]),
+        screen[1].clamp(-slack[1], slack[1]),
+    ];
+    if (0..2).any(|axis| (clamped[axis] - screen[axis]).abs() > EPSILON) {
+        let offset = [
+            clamped[0] * cos - clamped[1] * sin,
+            clamped[0] * sin + clamped[1] * cos,
+        ];
+        camera.center = to_lng_lat([middle[0] + offset[0], middle[1] + offset[1]]);
+        adjusted.push("center");
+    }
+    adjusted
+}
+
+fn clamp_center(camera: &mut CameraValues, polygons: &[Vec<Ring>]) -> Vec<&'static str> {
+    let center = camera::mercator_fraction(camera.center);
+    let inside = polygons.iter().any(|rings| match rings.split_first() {
+        Some((outer, holes)) => {
+            point_in_ring(center, outer) && !holes.iter().any(|hole| point_in_ring(center, hole))
+        }
+        None => false,
+    });
+    if inside {
+        return Vec::new();
+    }
+    let nearest = polygons
+        .iter()
+        .flatten()
+        .flat_map(|ring| {
+            let closing = ring.last().zip(ring.first());
+            ring.windows(2)
+                .map(|pair| (&pair[0], &pair[1]))
+                .chain(closing)
+                .map(|(a, b)| nearest_on_segment(center, *a, *b))
+                .collect::<Vec<_>>()
+        })
+        .min_by(|a, b| {
+            let distance = |p: &[f64; 2]| (p[0] - center[0]).hypot(p[1] - center[1]);
+            distance(a).total_cmp(&distance(b))
+        });
+    match nearest {
+        Some(nearest) => {
+            camera.center = to_lng_lat(nearest);
+            vec!["center"]
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Constrain `camera` for a `size` (logical pixels) view to `boundary`.
+pub fn constrain(
+    mut camera: CameraValues,
+    boundary: &Value,
+    mode: Mode,
+    size: [f64; 2],
+) -> Result<Constrained, String> {
+    let mut found = Vec::new();
+    polygons(boundary, &mut found);
+    found.retain(|rings| rings.first().is_some_and(|outer| outer.len() >= 3));
+    if found.is_empty() {
+        return Err("boundary has no Polygon or MultiPolygon geometry".to_string());
+    }
+    let adjusted = match mode {
+        Mode::Contain => {
+            let outers: Vec<&Ring> = found.iter().filter_map(|rings| rings.first()).collect();
+            contain(&mut camera, &outers, size)
+        }
+        Mode::ClampCenter => clamp_center(&mut camera, &found),
+    };
+    Ok(Constrained { adjusted, camera })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const VIEW: [f64; 2] = [512.0, 512.0];
+
+    fn square(half: f64) -> Vec<[f64; 2]> {
+        vec![
+            [-half, -half],
+            [half, -half],
+            [half, half],
+            [-half, half],
+            [-half, -half],
+        ]
+    }
+
+    fn boundary() -> Value {
+        json!({"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [square(10.0)]}})
+    }
+
+    fn camera(center: [f64; 2], zoom: f64) -> CameraValues {
+        CameraValues {
+            center,
+            zoom,
+            bearing: 0.0,
+            pitch: 0.0,
+        }
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn contain_zooms_out_to_fit_the_boundary() {
+        let result = constrain(camera([0.0, 0.0], 10.0), &boundary(), Mode::Contain, VIEW).unwrap();
+        assert_eq!(result.adjusted, ["zoom"]);
+        // The box is a little taller than wide in Mercator, so height decides.
+        let height =
+            camera::mercator_fraction([0.0, -10.0])[1] - camera::mercator_fraction([0.0, 10.0])[1];
+        assert_near(result.camera.zoom, (1.0 / height).log2());
+    }
+
+    #[test]
+    fn contain_pans_back_until_the_boundary_is_in_view() {
+        let result =
+            constrain(camera([100.0, 0.0], 2.0), &boundary(), Mode::Contain, VIEW).unwrap();
+        assert_eq!(result.adjusted, ["center"]);
+        // 512px of a 2048px world spans 90°, so the centre may sit 35° east.
+        assert_near(result.camera.center[0], 35.0);
+        assert_near(result.camera.zoom, 2.0);
+
+        let inside = constrain(camera([5.0, 0.0], 2.0), &boundary(), Mode::Contain, VIEW).unwrap();
+        assert!(inside.adjusted.is_empty());
+    }
+
+    #[test]
+    fn clamp_center_moves_outside_centres_to_the_nearest_edge() {
+        let clamp =
+            |center| constrain(camera(center, 4.0), &boundary(), Mode::ClampCenter, VIEW).unwrap();
+        assert!(clamp([3.0, 4.0]).adjusted.is_empty());
+        let moved = clamp([20.0, 0.0]);
+        assert_eq!(moved.adjusted, ["center"]);
+        assert_near(moved.camera.center[0], 10.0);
+        assert_near(moved.camera.center[1], 0.0);
+        assert_near(moved.camera.zoom, 4.0);
+    }
+
+    #[test]
+    fn clamp_center_treats_holes_as_outside() {
+        let holed = json!({"type": "MultiPolygon", "coordinates": [[square(10.0), square(2.0)]]});
+        let result = constrain(camera([1.5, 0.0], 4.0), &holed, Mode::ClampCenter, VIEW).unwrap();
+        assert_eq!(result.adjusted, ["center"]);
+        assert_near(result.camera.center[0], 2.0);
+    }
+
+    #[test]
+    fn boundaries_need_a_polygon() {
+        let point = json!({"type": "Point", "coordinates": [0.0, 0.0]});
+        let degenerate = json!({"type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 1.0]]]});
+        for boundary in [point, degenerate] {
+            assert!(constrain(camera([0.0, 0.0], 1.0), &boundary, Mode::Contain, VIEW).is_err());
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Environment variable setting the stderr log level until an `init` sets `log_level`.
+const LOG_ENV: &str = "RUST_LOG";
+
+/// Asset load status gathered from MapLibre Native's log events during one render.
+#[derive(Debug, Serialize)]
+pub struct ResourceReport {
+    pub sprite_loaded: bool,
+    pub failed_glyph_ranges: Vec<String>,
+    pub source_errors: Vec<String>,
+}
+
+struct NativeEvent {
+    event: String,
+    message: String,
+}
+
+/// Receives MapLibre Native log events (forwarded through the `log` crate) and
+/// keeps warnings and errors while a capture is active. Records of the daemon's own and
+/// MapLibre Native's up to the log level are also written to stderr, never stdout,
+/// which carries the protocol.
+struct NativeLog {
+    capturing: AtomicBool,
+    events: Mutex<Vec<NativeEvent>>,
+    /// `LevelFilter` as `usize`; `Off` (0) until `RUST_LOG` or `init` sets one.
+    level: AtomicUsize,
+}
+
+static LOGGER: NativeLog = NativeLog {
+    capturing: AtomicBool::new(false),
+    events: Mutex::new(Vec::new()),
+    level: AtomicUsize::new(0),
+};
+
+impl NativeLog {
+    fn writes(&self, level: Level) -> bool {
+        level as usize <= self.level.load(Ordering::Relaxed)
+    }
+
+    fn captures(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+            && metadata.target().starts_with("maplibre_native")
+            && self.capturing.load(Ordering::Relaxed)
+    }
+}
+
+impl Log for NativeLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.writes(metadata.level()) || self.captures(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.writes(record.level()) {
+            let _ = writeln!(
+                std::io::stderr().lock(),
+                "mlnative-render {} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+        if !self.captures(record.metadata()) {
+            return;
+        }
+        // maplibre_native formats events as "{event:?} (code={code}) {message}".
+        let line = record.args().to_string();
+        let (event, message) = match line.split_once(" (code=") {
+            Some((event, rest)) => (
+                event.to_string(),
+                rest.split_once(") ")
+                    .map_or(rest, |(_, message)| message)
+                    .to_string(),
+            ),
+            None => (String::new(), line),
+        };
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(NativeEvent { event, message });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger, at the level `RUST_LOG` names if set. Native events are
+/// delivered synchronously so they land inside the capture window of the render that
+/// caused them.
+pub fn install() {
+    if log::set_logger(&LOGGER).is_ok() {
+        let level = match std::env::var(LOG_ENV) {
+            Ok(value) => parse_level(&value).unwrap_or_else(|e| {
+                eprintln!("mlnative-render: ignoring {}: {}", LOG_ENV, e);
+                LevelFilter::Off
+            }),
+            Err(_) => LevelFilter::Off,
+        };
+        set_level(level);
+    }
+    maplibre_native::set_log_thread_enabled(false);
+}
+
+/// A log level by name: `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub fn parse_level(name: &str) -> Result<LevelFilter, String> {
+    name.trim().parse().map_err(|_| {
+        format!(
+            "log level must be one of off, error, warn, info, debug or trace, got '{}'",
+            name
+        )
+    })
+}
+
+/// Write log records up to `level` to stderr from now on.
+pub fn set_level(level: LevelFilter) {
+    LOGGER.level.store(level as usize, Ordering::Relaxed);
+    // Warnings are always let through for resource reports.
+    log::set_max_level(level.max(LevelFilter::Warn));
+}
+
+pub fn begin_capture() {
+    LOGGER
+        .events
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+    LOGGER.capturing.store(true, Ordering::Relaxed);
+}
+
+pub fn finish_capture() -> ResourceReport {
+    LOGGER.capturing.store(false, Ordering::Relaxed);
+    let events = std::mem::take(&mut *LOGGER.events.lock().unwrap_or_else(|e| e.into_inner()));
+
+    let mut report = ResourceReport {
+        sprite_loaded: true,
+        failed_glyph_ranges: Vec::new(),
+        source_errors: Vec::new(),
+    };
+    for NativeEvent { event, message } in events {
+        match event.as_str() {
+            "Sprite" => report.sprite_loaded = false,
+            "Glyph" => report.failed_glyph_ranges.push(message),
+            "HttpRequest" | "ParseTile" | "Style" | "Database" => {
+                report.source_errors.push(message)
+            }
+            _ => {}
+        }
+    }
+    report
+}
+
+/// Process memory usage alongside render-surface counters.
+#[derive(Debug, Serialize)]
+pub struct MemoryStats {
+    /// Resident set size; `None` where `/proc/self/status` is unavailable.
+    pub rss_bytes: Option<u64>,
+    pub peak_rss_bytes: Option<u64>,
+    pub renders: u64,
+    pub surfaces_created: u64,
+    /// False while an idle session's renderer is released.
+    pub renderer_loaded: bool,
+}
+
+/// Read a `kB` field such as `VmRSS` from `/proc/self/status`.
+fn proc_status_bytes(status: &str, field: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kib: u64 = value.trim().strip_suffix("kB")?.trim().parse().ok()?;
+        Some(kib * 1024)
+    })
+}
+
+pub fn memory_stats(renders: u64, surfaces_created: u64, renderer_loaded: bool) -> MemoryStats {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    MemoryStats {
+        rss_bytes: proc_status_bytes(&status, "VmRSS"),
+        peak_rss_bytes: proc_status_bytes(&status, "VmHWM"),
+        renders,
+        surfaces_created,
+        renderer_loaded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn native_warning(line: &str) {
+        LOGGER.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("maplibre_native::log")
+                .args(format_args!("{}", line))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn captures_sort_native_events_into_the_report() {
+        native_warning("Glyph (code=0) before the capture");
+        begin_capture();
+        native_warning("Sprite (code=404) Failed to load sprite");
+        native_warning("Glyph (code=0) Noto Sans 0-255");
+        native_warning("HttpRequest (code=500) Server error for tile");
+        native_warning("Render (code=0) ignored");
+        native_warning("not an event");
+        let report = finish_capture();
+        assert!(!report.sprite_loaded);
+        assert_eq!(report.failed_glyph_ranges, ["Noto Sans 0-255"]);
+        assert_eq!(report.source_errors, ["Server error for tile"]);
+
+        native_warning("Sprite (code=404) after the capture");
+        begin_capture();
+        assert!(finish_capture().sprite_loaded);
+    }
+
+    #[test]
+    fn log_levels_parse_by_name() {
+        assert_eq!(parse_level(" debug ").unwrap(), LevelFilter::Debug);
+        assert_eq!(parse_level("OFF").unwrap(), LevelFilter::Off);
+        assert!(parse_level("loud").unwrap_err().contains("got 'loud'"));
+    }
+
+    #[test]
+    fn proc_status_fields_are_read_in_bytes() {
+        let status = "Name:\tmlnative-render\nVmHWM:\t  2048 kB\nVmRSS:\t  1024 kB\n";
+        assert_eq!(proc_status_bytes(status, "VmRSS"), Some(1024 * 1024));
+        assert_eq!(proc_status_bytes(status, "VmHWM"), Some(2048 * 1024));
+        assert_eq!(proc_status_bytes(status, "VmSwap"), None);
+        assert_eq!(proc_status_bytes("VmRSS:\tmany\n", "VmRSS"), None);
+    }
+}
@@ -0,0 +1,303 @@
+//! Feature-level diffs between two GeoJSON documents for `render_geojson_diff`.
+//!
+//! Features are matched by their `id` when they have one, and otherwise by identical
+//! geometry. Matched features whose geometry or properties differ are `changed`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Property carrying a feature's change class on the diff source.
+pub const CHANGE_PROPERTY: &str = "mlnative:change";
+pub const SOURCE_ID: &str = "mlnative-geojson-diff";
+
+/// How one change class is drawn.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangeStyle {
+    pub color: String,
+    /// Fill opacity for polygons.
+    #[serde(default = "default_fill_opacity")]
+    pub fill_opacity: f64,
+    /// Width of lines and polygon outlines, in logical pixels.
+    #[serde(default = "default_line_width")]
+    pub line_width: f64,
+    /// Radius of points, in logical pixels.
+    #[serde(default = "default_circle_radius")]
+    pub circle_radius: f64,
+}
+
+fn default_fill_opacity() -> f64 {
+    0.4
+}
+
+fn default_line_width() -> f64 {
+    2.0
+}
+
+fn default_circle_radius() -> f64 {
+    5.0
+}
+
+impl ChangeStyle {
+    fn with_color(color: &str) -> Self {
+        Self {
+            color: color.to_string(),
+            fill_opacity: default_fill_opacity(),
+            line_width: default_line_width(),
+            circle_radius: default_circle_radius(),
+        }
+    }
+
+    pub fn added() -> Self {
+        Self::with_color("#2ca02c")
+    }
+
+    pub fn removed() -> Self {
+        Self::with_color("#d62728")
+    }
+
+    pub fn changed() -> Self {
+        Self::with_color("#ff7f0e")
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+/// The features of a FeatureCollection, Feature or bare geometry.
+fn features(document: &Value, name: &str) -> Result<Vec<Value>, String> {
+    match document["type"].as_str() {
+        Some("FeatureCollection") => document["features"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| format!("{}: FeatureCollection has no features array", name)),
+        Some("Feature") => Ok(vec![document.clone()]),
+        Some(_) => Ok(vec![
+            json!({"type": "Feature", "geometry": document, "properties": {}}),
+        ]),
+        None => Err(format!("{}: not a GeoJSON object", name)),
+    }
+}
+
+/// Matching key: the feature id, or its geometry for features without one.
+/// `serde_json` keeps object keys sorted, so equal geometries serialize identically.
+fn key(feature: &Value) -> String {
+    match feature.get("id").filter(|id| !id.is_null()) {
+        Some(id) => format!("id:{}", id),
+        None => format!("geometry:{}", feature["geometry"]),
+    }
+}
+
+fn tagged(mut feature: Value, change: &str) -> Value {
+    if !feature["properties"].is_object() {
+        feature["properties"] = json!({});
+    }
+    feature["properties"][CHANGE_PROPERTY] = json!(change);
+    feature
+}
+
+/// Diff `before` against `after`, returning every added, removed (as it was) and
+/// changed (as it is now) feature tagged with its change class.
+pub fn diff(before: &Value, after: &Value) -> Result<(Vec<Value>, Summary), String> {
+    let mut unmatched: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for feature in features(before, "before")? {
+        unmatched.entry(key(&feature)).or_default().push(feature);
+    }
+    let mut summary = Summary::default();
+    let mut tagged_features = Vec::new();
+    for feature in features(after, "after")? {
+        let previous = unmatched.get_mut(&key(&feature)).and_then(|candidates| {
+            // Features without ids but with equal geometry are paired in order.
+            (!candidates.is_empty()).then(|| candidates.remove(0))
+        });
+        match previous {
+            None => {
+                summary.added += 1;
+                tagged_features.push(tagged(feature, "added"));
+            }
+            Some(previous)
+                if previous["geometry"] == feature["geometry"]
+                    && previous["properties"] == feature["properties"] =>
+            {
+                summary.unchanged += 1;
+            }
+            Some(_) => {
+                summary.changed += 1;
+                tagged_features.push(tagged(feature, "changed"));
+            }
+        }
+    }
+    for feature in unmatched.into_values().flatten() {
+        summary.removed += 1;
+        tagged_features.push(tagged(feature, "removed"));
+    }
+    Ok((tagged_features, summary))
+}
+
+/// Layers drawing each change class from the diff source, polygons under lines
+/// under points.
+pub fn layers(styles: [(&str, &ChangeStyle); 3]) -> Vec<Value> {
+    let mut layers = Vec::new();
+    for kind in ["fill", "line", "circle"] {
+        for (change, style) in &styles {
+            let is_change = json!(["==", ["get", CHANGE_PROPERTY], change]);
+            let (geometry, paint) = match kind {
+                "fill" => (
+                    json!(["==", ["geometry-type"], "Polygon"]),
+                    json!({"fill-color": style.color, "fill-opacity": style.fill_opacity}),
+                ),
+                "line" => (
+                    json!(["!=", ["geometry-type"], "Point"]),
+                    json!({"line-color": style.color, "line-width": style.line_width}),
+                ),
+                _ => (
+                    json!(["==", ["geometry-type"], "Point"]),
+                    json!({"circle-color": style.color, "circle-radius": style.circle_radius,
+                           "circle-stroke-color": "#ffffff", "circle-stroke-width": 1}),
+                ),
+            };
+            layers.push(json!({
+                "id": format!("{}-{}-{}", SOURCE_ID, change, kind),
+                "type": kind,
+                "source": SOURCE_ID,
+                "filter": ["all", is_change, geometry],
+                "paint": paint,
+            }));
+        }
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: Option<u32>, lng: f64, name: &str) -> Value {
+        let mut feature = json!({
+            "type": "Feature",
+            "geometry": {"type": "Point", "coordinates": [lng, 0.0]},
+            "properties": {"name": name}
+        });
+        if let Some(id) = id {
+            feature["id"] = json!(id);
+        }
+        feature
+    }
+
+    fn collection(features: Vec<Value>) -> Value {
+        json!({"type": "FeatureCollection", "features": features})
+    }
+
+    /// `(change, name)` of each tagged feature.
+    fn changes(features: &[Value]) -> Vec<(&str, &str)> {
+        features
+            .iter()
+            .map(|f| {
+                (
+                    f["properties"][CHANGE_PROPERTY].as_str().unwrap(),
+                    f["properties"]["name"].as_str().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn features_are_matched_by_id() {
+        let before = collection(vec![
+            point(Some(1), 0.0, "kept"),
+            point(Some(2), 1.0, "renamed"),
+            point(Some(3), 2.0, "deleted"),
+        ]);
+        let after = collection(vec![
+            point(Some(1), 0.0, "kept"),
+            point(Some(2), 1.0, "new name"),
+            point(Some(4), 3.0, "created"),
+        ]);
+        let (features, summary) = diff(&before, &after).unwrap();
+        assert_eq!(
+            changes(&features),
+            [
+                ("changed", "new name"),
+                ("added", "created"),
+                ("removed", "deleted")
+            ]
+        );
+        assert_eq!(
+            (
+                summary.added,
+                summary.removed,
+                summary.changed,
+                summary.unchanged
+            ),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn features_without_ids_are_matched_by_geometry() {
+        let before = collection(vec![point(None, 0.0, "a"), point(None, 0.0, "b")]);
+        // The same geometry twice pairs in order; the second's properties changed.
+        let after = collection(vec![point(None, 0.0, "a"), point(None, 0.0, "c")]);
+        let (features, summary) = diff(&before, &after).unwrap();
+        assert_eq!(changes(&features), [("changed", "c")]);
+        assert_eq!(summary.unchanged, 1);
+
+        let moved = collection(vec![point(None, 5.0, "a"), point(None, 0.0, "b")]);
+        let (features, _) = diff(&before, &moved).unwrap();
+        // Moving "a" makes it new; "b" pairs with the first feature left at 0,0.
+        assert_eq!(
+            changes(&features),
+            [("added", "a"), ("changed", "b"), ("removed", "b")]
+        );
+    }
+
+    #[test]
+    fn bare_geometries_and_features_are_accepted() {
+        let geometry = json!({"type": "Point", "coordinates": [0.0, 0.0]});
+        let (features, summary) = diff(&geometry, &point(None, 0.0, "named")).unwrap();
+        assert_eq!(changes(&features), [("changed", "named")]);
+        assert_eq!(summary.changed, 1);
+
+        let (features, _) = diff(&collection(vec![]), &geometry).unwrap();
+        assert_eq!(features[0]["properties"], json!({CHANGE_PROPERTY: "added"}));
+    }
+
+    #[test]
+    fn invalid_documents_name_the_side() {
+        let valid = collection(vec![]);
+        assert_eq!(
+            diff(&json!({"features": []}), &valid).unwrap_err(),
+            "before: not a GeoJSON object"
+        );
+        assert_eq!(
+            diff(&valid, &json!({"type": "FeatureCollection"})).unwrap_err(),
+            "after: FeatureCollection has no features array"
+        );
+    }
+
+    #[test]
+    fn layers_draw_polygons_under_lines_under_points() {
+        let (added, removed, changed) = (
+            ChangeStyle::added(),
+            ChangeStyle::removed(),
+            ChangeStyle::changed(),
+        );
+        let layers = layers([
+            ("added", &added),
+            ("removed", &removed),
+            ("changed", &changed),
+        ]);
+        let ids: Vec<_> = layers.iter().map(|l| l["id"].as_str().unwrap()).collect();
+        assert_eq!(ids.len(), 9);
+        assert_eq!(ids[0], "mlnative-geojson-diff-added-fill");
+        assert_eq!(ids[4], "mlnative-geojson-diff-removed-line");
+        assert_eq!(ids[8], "mlnative-geojson-diff-changed-circle");
+        assert_eq!(layers[1]["paint"]["fill-color"], "#d62728");
+        assert!(layers.iter().all(|l| l["source"] == SOURCE_ID));
+    }
+}
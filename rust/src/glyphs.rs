@@ -0,0 +1,216 @@
+use maplibre_native::ResourceKind;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::resources;
+
+/// A glyph range that could not be fetched.
+#[derive(Debug, Serialize)]
+pub struct FailedRange {
+    pub range: String,
+    pub error: String,
+}
+
+/// Glyph coverage of one font stack over the sample texts.
+#[derive(Debug, Serialize)]
+pub struct StackCoverage {
+    pub font_stack: Vec<String>,
+    pub complete: bool,
+    pub checked_codepoints: usize,
+    /// Codepoints absent from fetched ranges, as `U+XXXX`.
+    pub missing_codepoints: Vec<String>,
+    pub failed_ranges: Vec<FailedRange>,
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters, as MapLibre does
+/// for `{fontstack}`.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Iterate `(field, wire_type, varint, bytes)` over a protobuf message: `varint` is set
+/// for wire type 0 and `bytes` for wire type 2 (length-delimited).
+fn fields(data: &[u8]) -> impl Iterator<Item = (u64, u64, u64, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos >= data.len() {
+            return None;
+        }
+        let key = read_varint(data, &mut pos)?;
+        let (field, wire_type) = (key >> 3, key & 7);
+        match wire_type {
+            0 => read_varint(data, &mut pos).map(|v| (field, wire_type, v, &data[0..0])),
+            1 => {
+                pos += 8;
+                Some((field, wire_type, 0, &data[0..0]))
+            }
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data.get(pos..pos + len)?;
+                pos += len;
+                Some((field, wire_type, 0, bytes))
+            }
+            5 => {
+                pos += 4;
+                Some((field, wire_type, 0, &data[0..0]))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Glyph ids present in a glyph PBF (`glyphs.stacks[].glyphs[].id`).
+fn glyph_ids(pbf: &[u8]) -> BTreeSet<u32> {
+    let mut ids = BTreeSet::new();
+    for (_, _, _, stack) in fields(pbf).filter(|&(field, wire, _, _)| field == 1 && wire == 2) {
+        for (_, _, _, glyph) in fields(stack).filter(|&(field, wire, _, _)| field == 3 && wire == 2)
+        {
+            if let Some((_, _, id, _)) =
+                fields(glyph).find(|&(field, wire, _, _)| field == 1 && wire == 0)
+            {
+                ids.insert(id as u32);
+            }
+        }
+    }
+    ids
+}
+
+/// Fetch the glyph ranges the sample texts need from `glyphs_url` (a style `glyphs`
+/// template) and report which codepoints each font stack lacks.
+pub fn check_coverage(
+    glyphs_url: &str,
+    font_stacks: &[Vec<String>],
+    sample_texts: &[String],
+) -> Vec<StackCoverage> {
+    let codepoints: BTreeSet<u32> = sample_texts
+        .iter()
+        .flat_map(|text| text.chars())
+        .filter(|c| !c.is_control() && !c.is_whitespace())
+        .map(u32::from)
+        .collect();
+    let mut by_range: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for &cp in &codepoints {
+        by_range.entry(cp / 256 * 256).or_default().push(cp);
+    }
+
+    font_stacks
+        .iter()
+        .map(|stack| {
+            let fontstack = percent_encode(&stack.join(","));
+            let mut missing: Vec<u32> = Vec::new();
+            let mut failed_ranges = Vec::new();
+            for (&start, cps) in &by_range {
+                let range = format!("{}-{}", start, start + 255);
+                let url = glyphs_url
+                    .replace("{fontstack}", &fontstack)
+                    .replace("{range}", &range);
+                match resources::fetch(&url, ResourceKind::Glyphs) {
+                    Ok(pbf) => {
+                        let ids = glyph_ids(&pbf);
+                        missing.extend(cps.iter().filter(|cp| !ids.contains(cp)));
+                    }
+                    Err(error) => {
+                        missing.extend(cps);
+                        failed_ranges.push(FailedRange { range, error });
+                    }
+                }
+            }
+            StackCoverage {
+                font_stack: stack.clone(),
+                complete: missing.is_empty(),
+                checked_codepoints: codepoints.len(),
+                missing_codepoints: missing.iter().map(|cp| format!("U+{:04X}", cp)).collect(),
+                failed_ranges,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length_field(field: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![(field << 3) | 2, payload.len() as u8];
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A glyph PBF with one stack holding `ids`, each glyph with an id and a bitmap.
+    fn pbf(ids: &[u8]) -> Vec<u8> {
+        let mut stack = length_field(1, b"Test Regular");
+        for &id in ids {
+            let mut glyph = vec![1 << 3, id];
+            glyph.extend(length_field(2, &[0; 4]));
+            // A fixed32 field, which the reader has to skip.
+            glyph.extend([(9 << 3) | 5, 0, 0, 0, 0]);
+            stack.extend(length_field(3, &glyph));
+        }
+        length_field(1, &stack)
+    }
+
+    #[test]
+    fn glyph_ids_are_read_from_every_glyph() {
+        assert_eq!(glyph_ids(&pbf(&[65, 66, 97])), BTreeSet::from([65, 66, 97]));
+        assert!(glyph_ids(&[]).is_empty());
+        // Truncated data yields what was read before it.
+        let data = pbf(&[65]);
+        assert!(glyph_ids(&data[..data.len() - 1]).is_empty());
+    }
+
+    #[test]
+    fn font_stacks_are_encoded_like_maplibre() {
+        assert_eq!(
+            percent_encode("Open Sans Bold,Arial Unicode MS Regular"),
+            "Open%20Sans%20Bold%2CArial%20Unicode%20MS%20Regular"
+        );
+    }
+
+    #[test]
+    fn coverage_reports_missing_codepoints_and_ranges() {
+        let dir = std::env::temp_dir().join(format!("mlnative-glyphs-{}", std::process::id()));
+        let stack_dir = dir.join("Test%20Regular");
+        std::fs::create_dir_all(&stack_dir).unwrap();
+        std::fs::write(stack_dir.join("0-255.pbf"), pbf(b"Hello")).unwrap();
+        let url = format!("file://{}/{{fontstack}}/{{range}}.pbf", dir.display());
+
+        let texts = ["Hello World".to_string(), "Ω".to_string()];
+        let coverage = check_coverage(&url, &[vec!["Test Regular".to_string()]], &texts);
+        assert_eq!(coverage.len(), 1);
+        let stack = &coverage[0];
+        assert!(!stack.complete);
+        // Whitespace isn't checked: H e l o W r d Ω.
+        assert_eq!(stack.checked_codepoints, 8);
+        assert_eq!(
+            stack.missing_codepoints,
+            ["U+0057", "U+0064", "U+0072", "U+03A9"]
+        );
+        let ranges: Vec<_> = stack
+            .failed_ranges
+            .iter()
+            .map(|r| r.range.as_str())
+            .collect();
+        assert_eq!(ranges, ["768-1023"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! Latitude/longitude grid lines drawn over a render for `render_with_graticule`.
+//!
+//! Lines are projected with the camera's model-view-projection matrix, so they follow
+//! bearing and pitch. Meridians are drawn at their unwrapped world positions, like
+//! MapLibre's repeated world copies, so views across the antimeridian get continuous
+//! lines; their labels are wrapped back to [-180, 180]. Parallels stop at the Web
+//! Mercator latitude limit, where the map itself ends.
+
+use image::{Rgba, RgbaImage};
+
+use crate::camera::{self, CameraValues};
+use crate::compose;
+
+/// Intervals the automatic spacing picks from, in degrees.
+const STEPS: [f64; 20] = [
+    90.0, 45.0, 30.0, 20.0, 10.0, 5.0, 2.0, 1.0, 0.5, 0.25, 0.1, 0.05, 0.025, 0.01, 0.005, 0.0025,
+    0.001, 0.0005, 0.00025, 0.0001,
+];
+/// Meridian spacing, in logical pixels, the automatic interval aims for at least.
+const TARGET_SPACING: f64 = 128.0;
+/// Meridians closer than this (logical pixels) are thinned to every nth line.
+const MIN_SPACING: f64 = 48.0;
+/// Straight pieces each line is projected in; pitched views bend nothing, but the
+/// pieces let lines that pass behind the camera be clipped.
+const SAMPLES: usize = 64;
+
+/// Logical pixels between meridians `interval` degrees apart at `zoom`.
+fn spacing(interval: f64, zoom: f64) -> f64 {
+    interval / 360.0 * camera::world_size(zoom)
+}
+
+/// Line interval to draw at `zoom`: `requested`, thinned to a multiple of it if its
+/// lines would crowd together, or a round interval about `TARGET_SPACING` apart.
+pub fn interval(requested: Option<f64>, zoom: f64) -> Result<f64, String> {
+    match requested {
+        Some(interval) if !(interval > 0.0 && interval <= 90.0) => {
+            Err("interval_degrees must be in (0, 90]".to_string())
+        }
+        Some(interval) => {
+            let every = (MIN_SPACING / spacing(interval, zoom)).ceil().max(1.0);
+            Ok(interval * every)
+        }
+        None => Ok(STEPS
+            .iter()
+            .copied()
+            .take_while(|&step| spacing(step, zoom) >= TARGET_SPACING)
+            .last()
+            .unwrap_or(STEPS[0])),
+    }
+}
+
+/// Decimal places needed to write multiples of `interval` exactly.
+fn decimals(interval: f64) -> usize {
+    (0..6)
+        .find(|&places| {
+            let scaled = interval * 10f64.powi(places as i32);
+            (scaled - scaled.round()).abs() < 1e-6
+        })
+        .unwrap_or(6)
+}
+
+/// `value` degrees with a hemisphere letter, e.g. `12.5S`; 0 and 180 have none.
+fn label_text(value: f64, places: usize, positive: char, negative: char) -> String {
+    let text = format!("{:.*}", places, value.abs());
+    if value.abs() < 1e-9 || (value.abs() - 180.0).abs() < 1e-9 {
+        text
+    } else {
+        format!("{}{}", text, if value > 0.0 { positive } else { negative })
+    }
+}
+
+/// Maps global pixels at the camera's zoom to image pixels.
+struct Projector {
+    matrix: camera::Matrix,
+    size: [f64; 2],
+    ratio: f64,
+}
+
+impl Projector {
+    /// `None` for points behind the camera.
+    fn project(&self, [x, y]: [f64; 2]) -> Option<[f64; 2]> {
+        let m = &self.matrix;
+        let clip: [f64; 4] = std::array::from_fn(|row| m[row] * x + m[4 + row] * y + m[12 + row]);
+        if clip[3] <= 1e-9 {
+            return None;
+        }
+        Some([
+            (clip[0] / clip[3] + 1.0) / 2.0 * self.size[0] * self.ratio,
+            (1.0 - clip[1] / clip[3]) / 2.0 * self.size[1] * self.ratio,
+        ])
+    }
+}
+
+/// Clip the segment `a`-`b` to the rectangle `[min, max]` (Liang-Barsky).
+fn clip(a: [f64; 2], b: [f64; 2], min: [f64; 2], max: [f64; 2]) -> Option<([f64; 2], [f64; 2])> {
+    let delta = [b[0] - a[0], b[1] - a[1]];
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for axis in 0..2 {
+        for (p, q) in [
+            (-delta[axis], a[axis] - min[axis]),
+            (delta[axis], max[axis] - a[axis]),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+    }
+    let at = |t: f64| [a[0] + t * delta[0], a[1] + t * delta[1]];
+    (t0 <= t1).then(|| (at(t0), at(t1)))
+}
+
+/// Distance from `p` to the segment `a`-`b`.
+fn distance_to_segment(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((p[0] - a[0]) * dx + (p[1] - a[1]) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (p[0] - a[0] - t * dx).hypot(p[1] - a[1] - t * dy)
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    coverage: Vec<f32>,
+    half_width: f64,
+}
+
+impl Canvas {
+    /// Add the antialiased segment `a`-`b` to the coverage mask.
+    fn segment(&mut self, a: [f64; 2], b: [f64; 2]) {
+        let margin = self.half_width + 1.0;
+        let (width, height) = (f64::from(self.width), f64::from(self.height));
+        let Some((a, b)) = clip(a, b, [-margin, -margin], [width + margin, height + margin]) else {
+            return;
+        };
+        let x0 = (a[0].min(b[0]) - margin).floor().max(0.0) as u32;
+        let y0 = (a[1].min(b[1]) - margin).floor().max(0.0) as u32;
+        let x1 = ((a[0].max(b[0]) + margin).ceil().max(0.0) as u32).min(self.width);
+        let y1 = ((a[1].max(b[1]) + margin).ceil().max(0.0) as u32).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let center = [f64::from(x) + 0.5, f64::from(y) + 0.5];
+                let distance = distance_to_segment(center, a, b);
+                let coverage = (self.half_width + 0.5 - distance).clamp(0.0, 1.0) as f32;
+                let cell = &mut self.coverage[(y * self.width + x) as usize];
+                *cell = cell.max(coverage);
+            }
+        }
+    }
+
+    /// Draw the line from `start` to `end` (global pixels), returning where it first
+    /// enters the image, if it does.
+    fn line(&mut self, projector: &Projector, start: [f64; 2], end: [f64; 2]) -> Option<[f64; 2]> {
+        let point = |i: usize| {
+            let t = i as f64 / SAMPLES as f64;
+            projector.project([
+                start[0] + t * (end[0] - start[0]),
+                start[1] + t * (end[1] - start[1]),
+            ])
+        };
+        let bounds = [f64::from(self.width), f64::from(self.height)];
+        let mut entry = None;
+        let mut previous = point(0);
+        for i in 1..=SAMPLES {
+            let current = point(i);
+            if let (Some(a), Some(b)) = (previous, current) {
+                self.segment(a, b);
+                if entry.is_none() {
+                    entry = clip(a, b, [0.0, 0.0], bounds).map(|(entry, _)| entry);
+                }
+            }
+            previous = current;
+        }
+        entry
+    }
+}
+
+/// Draw graticule lines `interval` degrees apart over `image`, a render of `camera`
+/// at `size` logical pixels, labelling meridians where they enter from the south
+/// and parallels where they enter from the west.
+pub fn draw(
+    image: &mut RgbaImage,
+    camera: CameraValues,
+    size: [f64; 2],
+    interval: f64,
+    color: [u8; 4],
+    label: bool,
+) {
+    let (width, height) = image.dimensions();
+    let ratio = f64::from(width) / size[0];
+    let projector = Projector {
+        matrix: camera::projection_matrix(camera, size, camera::Padding::default()),
+        size,
+        ratio,
+    };
+    let mut canvas = Canvas {
+        width,
+        height,
+        coverage: vec![0.0; (width * height) as usize],
+        half_width: (ratio / 2.0).max(0.5),
+    };
+
+    let world = camera::world_size(camera.zoom);
+    let [cx, cy] = camera::mercator_fraction(camera.center).map(|fraction| fraction * world);
+    let radius = camera::ground_radius(camera.pitch, size);
+    let (x_min, x_max) = (cx - radius, cx + radius);
+    let (y_min, y_max) = ((cy - radius).max(0.0), (cy + radius).min(world));
+    let to_lng = |x: f64| x / world * 360.0 - 180.0;
+    let to_y = |lat: f64| camera::mercator_fraction([0.0, lat])[1] * world;
+    let lat_min = camera::pixel_to_lng_lat([0.0, y_max], camera.zoom)[1];
+    let lat_max = camera::pixel_to_lng_lat([0.0, y_min], camera.zoom)[1];
+
+    let places = decimals(interval);
+    let mut labels = Vec::new();
+    let first = (to_lng(x_min) / interval).ceil() as i64;
+    let last = (to_lng(x_max) / interval).floor() as i64;
+    for k in first..=last {
+        let lng = k as f64 * interval;
+        let x = (lng + 180.0) / 360.0 * world;
+        // South to north, so the entry point is on the bottom edge when facing north.
+        if let Some(entry) = canvas.line(&projector, [x, y_max], [x, y_min]) {
+            let wrapped = (lng + 180.0).rem_euclid(360.0) - 180.0;
+            let wrapped = if wrapped == -180.0 { 180.0 } else { wrapped };
+            labels.push((label_text(wrapped, places, 'E', 'W'), entry));
+        }
+    }
+    let lat_limit = camera::MAX_LATITUDE;
+    let first = (lat_min.max(-lat_limit) / interval).ceil() as i64;
+    let last = (lat_max.min(lat_limit) / interval).floor() as i64;
+    for k in first..=last {
+        let lat = k as f64 * interval;
+        let y = to_y(lat);
+        if let Some(entry) = canvas.line(&projector, [x_min, y], [x_max, y]) {
+            labels.push((label_text(lat, places, 'N', 'S'), entry));
+        }
+    }
+
+    compose::paint_mask(image, &canvas.coverage, Rgba(color));
+    if !label {
+        return;
+    }
+    let scale = (2.0 * ratio).round().max(1.0) as u32;
+    let margin = scale;
+    let mut placed: Vec<[u32; 4]> = Vec::new();
+    for (text, [x, y]) in labels {
+        let [box_width, box_height] = compose::label_size(&text, scale);
+        if box_width + 2 * margin > width || box_height + 2 * margin > height {
+            continue;
+        }
+        let left = (x - f64::from(box_width) / 2.0)
+            .clamp(f64::from(margin), f64::from(width - box_width - margin))
+            as u32;
+        let top = (y - f64::from(box_height) / 2.0)
+            .clamp(f64::from(margin), f64::from(height - box_height - margin))
+            as u32;
+        let rect = [left, top, left + box_width, top + box_height];
+        // Skip labels that would cover one already drawn, e.g. in the corners.
+        if placed.iter().any(|other| {
+            rect[0] < other[2] && other[0] < rect[2] && rect[1] < other[3] && other[1] < rect[3]
+        }) {
+            continue;
+        }
+        compose::label(image, &text, [left, top], scale);
+        placed.push(rect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn render(label: bool) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(256, 256, BACKGROUND);
+        let camera = CameraValues {
+            center: [0.0, 0.0],
+            zoom: 1.0,
+            bearing: 0.0,
+            pitch: 0.0,
+        };
+        draw(
+            &mut image,
+            camera,
+            [256.0, 256.0],
+            30.0,
+            [255, 0, 0, 255],
+            label,
+        );
+        image
+    }
+
+    #[test]
+    fn automatic_intervals_keep_lines_apart() {
+        // The world is 512px at z0, so only 90° lines are 128px apart.
+        assert_eq!(interval(None, 0.0), Ok(90.0));
+        assert_eq!(interval(None, 2.0), Ok(30.0));
+        assert_eq!(interval(None, 30.0), Ok(*STEPS.last().unwrap()));
+        // 1° lines are 1.4px apart at z0, so every 34th is drawn.
+        assert_eq!(interval(Some(1.0), 0.0), Ok(34.0));
+        assert_eq!(interval(Some(10.0), 6.0), Ok(10.0));
+        assert!(interval(Some(0.0), 0.0).is_err());
+        assert!(interval(Some(120.0), 0.0).is_err());
+    }
+
+    #[test]
+    fn labels_use_hemisphere_letters() {
+        assert_eq!(decimals(10.0), 0);
+        assert_eq!(decimals(0.25), 2);
+        assert_eq!(label_text(12.5, 1, 'N', 'S'), "12.5N");
+        assert_eq!(label_text(-12.5, 1, 'N', 'S'), "12.5S");
+        assert_eq!(label_text(0.0, 0, 'E', 'W'), "0");
+        assert_eq!(label_text(-180.0, 0, 'E', 'W'), "180");
+    }
+
+    #[test]
+    fn clipping_keeps_the_inside_part() {
+        let inside = clip([-5.0, 5.0], [15.0, 5.0], [0.0, 0.0], [10.0, 10.0]);
+        assert_eq!(inside, Some(([0.0, 5.0], [10.0, 5.0])));
+        assert_eq!(
+            clip([-5.0, -5.0], [-1.0, 20.0], [0.0, 0.0], [10.0, 10.0]),
+            None
+        );
+        assert_eq!(
+            distance_to_segment([5.0, 3.0], [0.0, 0.0], [10.0, 0.0]),
+            3.0
+        );
+        assert_eq!(
+            distance_to_segment([13.0, 4.0], [0.0, 0.0], [10.0, 0.0]),
+            5.0
+        );
+    }
+
+    #[test]
+    fn lines_are_drawn_through_the_centre() {
+        let image = render(false);
+        // The prime meridian and the equator meet at the centre.
+        assert_ne!(*image.get_pixel(128, 20), BACKGROUND);
+        assert_ne!(*image.get_pixel(20, 128), BACKGROUND);
+        // 30° lines are 85px apart at z1, leaving this pixel clear.
+        assert_eq!(*image.get_pixel(60, 60), BACKGROUND);
+        assert_ne!(render(true), image);
+    }
+}
@@ -0,0 +1,323 @@
+//! Legend panels for `render_with_legend`.
+//!
+//! The panel is itself a MapLibre render: swatches and labels become GeoJSON
+//! features in a throwaway style laid out in pixel space, so text uses the map's own
+//! glyphs and colours are evaluated by MapLibre exactly as the map evaluates them.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::camera;
+
+/// Zoom the panel is laid out and rendered at, centred on `[0, 0]`. Any value works;
+/// this one keeps coordinates small.
+pub const LAYOUT_ZOOM: f64 = 10.0;
+const PADDING: f64 = 12.0;
+const TITLE_SIZE: f64 = 16.0;
+const ROW_HEIGHT: f64 = 24.0;
+const SWATCH_SIZE: f64 = 16.0;
+const CIRCLE_RADIUS: f64 = 7.0;
+const LABEL_SIZE: f64 = 13.0;
+const DEFAULT_FONT: &str = "Open Sans Regular";
+
+/// Side of the map the legend is docked on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Left,
+    #[default]
+    Right,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Options {
+    /// Layers to list, in style order; every fill, line and circle layer when absent.
+    #[serde(default)]
+    pub layers: Option<Vec<String>>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Display names by layer id; the id is shown otherwise.
+    #[serde(default)]
+    pub labels: Map<String, Value>,
+    #[serde(default)]
+    pub position: Position,
+    /// Panel width in logical pixels.
+    #[serde(default = "default_width")]
+    pub width: u32,
+}
+
+fn default_width() -> u32 {
+    200
+}
+
+/// Panel pixel to `[lng, lat]`, with the panel centred on the world origin.
+fn to_lng_lat(pixel: [f64; 2], size: [f64; 2]) -> [f64; 2] {
+    let half_world = camera::world_size(LAYOUT_ZOOM) / 2.0;
+    camera::pixel_to_lng_lat(
+        [
+            half_world + pixel[0] - size[0] / 2.0,
+            half_world + pixel[1] - size[1] / 2.0,
+        ],
+        LAYOUT_ZOOM,
+    )
+}
+
+fn feature(geometry: Value, properties: Value) -> Value {
+    json!({"type": "Feature", "geometry": geometry, "properties": properties})
+}
+
+/// Swatch geometry for a layer type centred at `center`, or `None` if the type has
+/// no simple swatch.
+fn swatch(layer_type: &str, center: [f64; 2], size: [f64; 2]) -> Option<Value> {
+    let half = SWATCH_SIZE / 2.0;
+    let at = |dx: f64, dy: f64| to_lng_lat([center[0] + dx, center[1] + dy], size);
+    match layer_type {
+        "fill" | "fill-extrusion" => Some(json!({
+            "type": "Polygon",
+            "coordinates": [[at(-half, -half), at(half, -half), at(half, half), at(-half, half), at(-half, -half)]],
+        })),
+        "line" => {
+            Some(json!({"type": "LineString", "coordinates": [at(-half, 0.0), at(half, 0.0)]}))
+        }
+        "circle" => Some(json!({"type": "Point", "coordinates": at(0.0, 0.0)})),
+        _ => None,
+    }
+}
+
+/// Paint for a swatch layer, reusing the map layer's colour (literal or expression).
+fn swatch_layer(id: String, layer: &Value) -> Option<Value> {
+    let paint = &layer["paint"];
+    let pick = |name: &str, default: Value| paint.get(name).cloned().unwrap_or(default);
+    let filter = json!(["==", ["get", "layer"], layer["id"]]);
+    let layer = match layer["type"].as_str()? {
+        "fill" | "fill-extrusion" => {
+            let color = paint
+                .get("fill-color")
+                .or_else(|| paint.get("fill-extrusion-color"))
+                .cloned()
+                .unwrap_or(json!("#000000"));
+            json!({"id": id, "type": "fill", "source": "legend", "filter": filter,
+                   "paint": {"fill-color": color, "fill-outline-color": "#666666"}})
+        }
+        "line" => json!({"id": id, "type": "line", "source": "legend", "filter": filter,
+                         "paint": {"line-color": pick("line-color", json!("#000000")),
+                                   "line-width": 3}}),
+        "circle" => json!({"id": id, "type": "circle", "source": "legend", "filter": filter,
+                           "paint": {"circle-color": pick("circle-color", json!("#000000")),
+                                     "circle-radius": CIRCLE_RADIUS}}),
+        _ => return None,
+    };
+    Some(layer)
+}
+
+/// First `text-font` used by the map, so labels only need glyphs it already has.
+fn font(style: &Value) -> Value {
+    style["layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|layer| layer["layout"]["text-font"].as_array().cloned())
+        .filter(|fonts| fonts.iter().all(Value::is_string))
+        .map(Value::Array)
+        .unwrap_or_else(|| json!([DEFAULT_FONT]))
+}
+
+/// Build the panel style for a `size` (logical pixels) panel, returning it and
+/// whether entries were left out because they didn't fit.
+pub fn style(
+    map_style: &Value,
+    options: &Options,
+    size: [f64; 2],
+) -> Result<(Value, bool), String> {
+    let glyphs = map_style["glyphs"]
+        .as_str()
+        .ok_or("legend labels need the style's glyphs URL")?;
+    let map_layers: Vec<&Value> = map_style["layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .collect();
+    let entries: Vec<&Value> = match &options.layers {
+        Some(ids) => ids
+            .iter()
+            .map(|id| {
+                map_layers
+                    .iter()
+                    .find(|layer| layer["id"] == id.as_str())
+                    .copied()
+                    .ok_or_else(|| format!("layer '{}' not found", id))
+            })
+            .collect::<Result<_, _>>()?,
+        None => map_layers
+            .iter()
+            .filter(|layer| matches!(layer["type"].as_str(), Some("fill" | "line" | "circle")))
+            .filter(|layer| layer["layout"]["visibility"] != "none")
+            .copied()
+            .collect(),
+    };
+
+    let mut features = Vec::new();
+    let mut layers = vec![json!({"id": "legend-background", "type": "background",
+                                 "paint": {"background-color": "#ffffff"}})];
+    let mut y = PADDING;
+    if let Some(title) = &options.title {
+        let anchor = [PADDING, y + TITLE_SIZE / 2.0];
+        features.push(feature(
+            json!({"type": "Point", "coordinates": to_lng_lat(anchor, size)}),
+            json!({"text": title, "size": TITLE_SIZE}),
+        ));
+        y += TITLE_SIZE + PADDING / 2.0;
+    }
+
+    // Keep one row free for the "+N more" note if everything doesn't fit.
+    let rows = ((size[1] - y - PADDING) / ROW_HEIGHT).floor().max(0.0) as usize;
+    let shown = if entries.len() > rows {
+        rows.saturating_sub(1)
+    } else {
+        entries.len()
+    };
+    for (index, layer) in entries.iter().take(shown).enumerate() {
+        let id = layer["id"].as_str().unwrap_or_default();
+        let row_center = y + ROW_HEIGHT * (index as f64 + 0.5);
+        let swatch_center = [PADDING + SWATCH_SIZE / 2.0, row_center];
+        let layer_type = layer["type"].as_str().unwrap_or_default();
+        if let (Some(geometry), Some(swatch)) = (
+            swatch(layer_type, swatch_center, size),
+            swatch_layer(format!("legend-swatch-{}", index), layer),
+        ) {
+            features.push(feature(geometry, json!({"layer": id})));
+            layers.push(swatch);
+        }
+        let label = options.labels.get(id).and_then(Value::as_str).unwrap_or(id);
+        let anchor = [PADDING * 2.0 + SWATCH_SIZE, row_center];
+        features.push(feature(
+            json!({"type": "Point", "coordinates": to_lng_lat(anchor, size)}),
+            json!({"text": label, "size": LABEL_SIZE}),
+        ));
+    }
+    let truncated = shown < entries.len();
+    if truncated {
+        let anchor = [PADDING, y + ROW_HEIGHT * (shown as f64 + 0.5)];
+        features.push(feature(
+            json!({"type": "Point", "coordinates": to_lng_lat(anchor, size)}),
+            json!({"text": format!("+{} more", entries.len() - shown), "size": LABEL_SIZE}),
+        ));
+    }
+
+    layers.push(json!({
+        "id": "legend-text",
+        "type": "symbol",
+        "source": "legend",
+        "filter": ["has", "text"],
+        "layout": {
+            "text-field": ["get", "text"],
+            "text-size": ["get", "size"],
+            "text-font": font(map_style),
+            "text-anchor": "left",
+            "text-allow-overlap": true,
+            "text-ignore-placement": true,
+        },
+        "paint": {"text-color": "#222222"},
+    }));
+    let style = json!({
+        "version": 8,
+        "glyphs": glyphs,
+        "sources": {"legend": {"type": "geojson", "data": {"type": "FeatureCollection", "features": features}}},
+        "layers": layers,
+    });
+    Ok((style, truncated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_style() -> Value {
+        json!({
+            "version": 8,
+            "glyphs": "https://example.com/{fontstack}/{range}.pbf",
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "water", "type": "fill", "source": "s", "paint": {"fill-color": "#0000ff"}},
+                {"id": "roads", "type": "line", "source": "s",
+                    "paint": {"line-color": ["get", "colour"]}},
+                {"id": "hidden", "type": "fill", "source": "s", "layout": {"visibility": "none"}},
+                {"id": "pois", "type": "circle", "source": "s"},
+                {"id": "labels", "type": "symbol", "source": "s",
+                    "layout": {"text-font": ["Noto Sans Bold"]}}
+            ]
+        })
+    }
+
+    fn options(value: Value) -> Options {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// The `text` of every label feature, in order.
+    fn texts(style: &Value) -> Vec<&str> {
+        style["sources"]["legend"]["data"]["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|f| f["properties"]["text"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn default_entries_are_visible_fill_line_and_circle_layers() {
+        let options = options(json!({"title": "Key", "labels": {"water": "Lakes"}}));
+        let (style, truncated) = style(&map_style(), &options, [200.0, 300.0]).unwrap();
+        assert!(!truncated);
+        assert_eq!(texts(&style), ["Key", "Lakes", "roads", "pois"]);
+        let layers = style["layers"].as_array().unwrap();
+        let ids: Vec<_> = layers.iter().map(|l| l["id"].as_str().unwrap()).collect();
+        assert_eq!(
+            ids,
+            [
+                "legend-background",
+                "legend-swatch-0",
+                "legend-swatch-1",
+                "legend-swatch-2",
+                "legend-text"
+            ]
+        );
+        // Swatches reuse the map's colours, expressions included.
+        assert_eq!(layers[1]["paint"]["fill-color"], "#0000ff");
+        assert_eq!(layers[2]["paint"]["line-color"], json!(["get", "colour"]));
+        assert_eq!(layers[4]["layout"]["text-font"], json!(["Noto Sans Bold"]));
+        assert_eq!(style["glyphs"], map_style()["glyphs"]);
+    }
+
+    #[test]
+    fn entries_that_do_not_fit_are_summarized() {
+        let options = options(json!({"layers": ["water", "roads", "hidden", "pois"]}));
+        // Room for three rows, so two entries and the note.
+        let (style, truncated) = style(&map_style(), &options, [200.0, 100.0]).unwrap();
+        assert!(truncated);
+        assert_eq!(texts(&style), ["water", "roads", "+2 more"]);
+    }
+
+    #[test]
+    fn panels_need_glyphs_and_known_layers() {
+        let mut no_glyphs = map_style();
+        no_glyphs.as_object_mut().unwrap().remove("glyphs");
+        assert!(style(&no_glyphs, &options(json!({})), [200.0, 100.0]).is_err());
+        let missing = options(json!({"layers": ["nope"]}));
+        assert_eq!(
+            style(&map_style(), &missing, [200.0, 100.0]).unwrap_err(),
+            "layer 'nope' not found"
+        );
+    }
+
+    #[test]
+    fn panel_pixels_map_around_the_origin() {
+        let size = [200.0, 100.0];
+        let center = to_lng_lat([100.0, 50.0], size);
+        assert!(center[0].abs() < 1e-9 && center[1].abs() < 1e-9);
+        let corner = to_lng_lat([0.0, 0.0], size);
+        assert!(corner[0] < 0.0 && corner[1] > 0.0);
+        assert_eq!(swatch("symbol", [0.0, 0.0], size), None);
+        assert_eq!(font(&json!({"layers": []})), json!([DEFAULT_FONT]));
+    }
+}
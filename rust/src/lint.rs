@@ -0,0 +1,712 @@
+//! Style linting against the MapLibre style spec for `lint_style`.
+//!
+//! The spec is bundled from `spec/v8.json`: every root, source, layer, layout and
+//! paint property with its value type, plus the expression operators. Values are
+//! checked structurally (types, enums, ranges, operator names); expression types are
+//! not inferred, so a well-formed expression that evaluates to the wrong type passes.
+
+use serde::Serialize;
+use serde_json::Value;
+
+const SPEC: &str = include_str!("../spec/v8.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The style is invalid and may fail to load or render incorrectly.
+    Error,
+    /// Ignored by the renderer, such as an unknown property, but likely a mistake.
+    Warning,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// Location in the style, e.g. `layers[3].paint.fill-color`.
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+pub struct Report {
+    pub violations: Vec<Violation>,
+    /// `$revision` of the bundled spec the style was checked against.
+    pub spec_revision: String,
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+pub fn is_hex_color(text: &str) -> bool {
+    text.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+struct Linter<'a> {
+    spec: &'a Value,
+    /// The style being checked, for cross-references such as layer sources.
+    style: &'a Value,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, path: &str, severity: Severity, message: String) {
+        self.violations.push(Violation {
+            path: path.to_string(),
+            severity,
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        self.report(path, Severity::Error, message);
+    }
+
+    fn contains(&self, list: &str, name: &str) -> bool {
+        self.spec[list]
+            .as_array()
+            .is_some_and(|names| names.iter().any(|n| n == name))
+    }
+
+    /// Check `value`'s keys against the spec section `section`.
+    fn object(&mut self, path: &str, value: &Value, section: &str) {
+        let Some(fields) = value.as_object() else {
+            self.error(path, format!("expected object, found {}", type_name(value)));
+            return;
+        };
+        let properties = &self.spec[section];
+        if let Some(known) = properties.as_object() {
+            for (name, property) in known {
+                if property["required"] == true && !fields.contains_key(name) {
+                    self.error(path, format!("missing required property \"{}\"", name));
+                }
+            }
+        }
+        for (name, field) in fields {
+            let field_path = join(path, name);
+            match properties.get(name) {
+                Some(property) => self.property(&field_path, field, property),
+                None => self.report(
+                    &field_path,
+                    Severity::Warning,
+                    format!("unknown property \"{}\"", name),
+                ),
+            }
+        }
+    }
+
+    fn property(&mut self, path: &str, value: &Value, property: &Value) {
+        match property["type"].as_str().unwrap_or("*") {
+            "object" => {
+                let section = property["properties"].as_str().unwrap_or_default();
+                self.object(path, value, section);
+            }
+            "sources" => self.sources(path, value),
+            "layers" => self.layers(path, value),
+            "sprite" => self.sprite(path, value),
+            _ => self.literal(path, value, property),
+        }
+    }
+
+    /// Check a literal (non-expression) value against a property spec.
+    fn literal(&mut self, path: &str, value: &Value, property: &Value) {
+        let expected = property["type"].as_str().unwrap_or("*");
+        let valid = match expected {
+            "*" | "variableAnchorOffsetCollection" => true,
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "string" | "resolvedImage" => value.is_string(),
+            "formatted" => value.is_string() || value.is_number(),
+            "color" => match value.as_str() {
+                Some(text) => {
+                    self.color(path, text);
+                    true
+                }
+                None => false,
+            },
+            "padding" => {
+                value.is_number()
+                    || value.as_array().is_some_and(|values| {
+                        (1..=4).contains(&values.len()) && values.iter().all(Value::is_number)
+                    })
+            }
+            "enum" => {
+                let values = property["values"].as_array();
+                if !values.is_some_and(|values| values.contains(value)) {
+                    let allowed: Vec<String> =
+                        values.into_iter().flatten().map(Value::to_string).collect();
+                    self.error(
+                        path,
+                        format!("expected one of [{}], found {}", allowed.join(", "), value),
+                    );
+                }
+                true
+            }
+            "array" => match value.as_array() {
+                Some(items) => {
+                    if let Some(length) = property["length"].as_u64() {
+                        if items.len() as u64 != length {
+                            self.error(
+                                path,
+                                format!(
+                                    "array length {} expected, length {} found",
+                                    length,
+                                    items.len()
+                                ),
+                            );
+                        }
+                    }
+                    let item_type = property["value"].as_str().unwrap_or("*");
+                    for (index, item) in items.iter().enumerate() {
+                        let matches = match item_type {
+                            "number" => item.is_number(),
+                            "string" | "enum" => item.is_string(),
+                            "array" => item.is_array(),
+                            _ => true,
+                        };
+                        if !matches {
+                            self.error(
+                                &format!("{}[{}]", path, index),
+                                format!("expected {}, found {}", item_type, type_name(item)),
+                            );
+                        }
+                    }
+                    true
+                }
+                None => false,
+            },
+            other => {
+                self.error(path, format!("spec has no value type \"{}\"", other));
+                true
+            }
+        };
+        if !valid {
+            self.error(
+                path,
+                format!("expected {}, found {}", expected, type_name(value)),
+            );
+            return;
+        }
+        if let Some(number) = value.as_f64() {
+            if let Some(minimum) = property["minimum"].as_f64().filter(|min| number < *min) {
+                self.error(
+                    path,
+                    format!("{} is less than the minimum value {}", number, minimum),
+                );
+            }
+            if let Some(maximum) = property["maximum"].as_f64().filter(|max| number > *max) {
+                self.error(
+                    path,
+                    format!("{} is greater than the maximum value {}", number, maximum),
+                );
+            }
+        }
+    }
+
+    fn color(&mut self, path: &str, text: &str) {
+        let lower = text.trim().to_ascii_lowercase();
+        let functional = ["rgb(", "rgba(", "hsl(", "hsla("]
+            .iter()
+            .any(|prefix| lower.starts_with(prefix))
+            && lower.ends_with(')');
+        if !(is_hex_color(&lower) || functional || self.contains("color_names", &lower)) {
+            self.error(path, format!("color expected, \"{}\" found", text));
+        }
+    }
+
+    /// Check a paint or layout value, which may be a literal, an expression or a
+    /// legacy `{stops: ...}` function.
+    fn style_value(&mut self, path: &str, value: &Value, property: &Value) {
+        let operator = value
+            .as_array()
+            .and_then(|items| items.first())
+            .and_then(Value::as_str);
+        let literal_array = property["type"] == "array";
+        match (value, operator) {
+            (Value::Array(_), Some(op)) if self.contains("expression_operators", op) => {
+                self.expression(path, value)
+            }
+            (Value::Array(_), _) if !literal_array => self.expression(path, value),
+            (Value::Object(function), _)
+                if function.contains_key("stops") || function.contains_key("property") =>
+            {
+                if let Some(stops) = function.get("stops").filter(|stops| !stops.is_array()) {
+                    self.error(
+                        &join(path, "stops"),
+                        format!("expected array, found {}", type_name(stops)),
+                    );
+                }
+            }
+            _ => self.literal(path, value, property),
+        }
+    }
+
+    /// Check that `value` is a well-formed expression with known operators.
+    fn expression(&mut self, path: &str, value: &Value) {
+        let Some(items) = value.as_array() else {
+            return;
+        };
+        let Some(op) = items.first().and_then(Value::as_str) else {
+            self.error(
+                path,
+                "expected an expression starting with an operator name; \
+                 wrap literal arrays in [\"literal\", ...]"
+                    .to_string(),
+            );
+            return;
+        };
+        if !self.contains("expression_operators", op) {
+            self.error(path, format!("unknown expression operator \"{}\"", op));
+            return;
+        }
+        for (index, argument) in items.iter().enumerate().skip(1) {
+            let skip = match op {
+                "literal" => true,
+                // Labels sit between the input and the fallback and may be arrays.
+                "match" => index >= 2 && index % 2 == 0 && index + 1 < items.len(),
+                "interpolate" | "interpolate-hcl" | "interpolate-lab" => index == 1,
+                _ => false,
+            };
+            if skip {
+                if index == 1 && op.starts_with("interpolate") {
+                    self.interpolation(&format!("{}[1]", path), argument);
+                }
+                continue;
+            }
+            if argument.is_array() {
+                self.expression(&format!("{}[{}]", path, index), argument);
+            }
+        }
+    }
+
+    fn interpolation(&mut self, path: &str, value: &Value) {
+        let kind = value
+            .as_array()
+            .and_then(|items| items.first())
+            .and_then(Value::as_str);
+        if !matches!(kind, Some("linear" | "exponential" | "cubic-bezier")) {
+            self.error(
+                path,
+                format!(
+                    "expected [\"linear\"], [\"exponential\", base] or [\"cubic-bezier\", ...], found {}",
+                    value
+                ),
+            );
+        }
+    }
+
+    fn filter(&mut self, path: &str, value: &Value) {
+        match value {
+            Value::Bool(_) => {}
+            Value::Array(_) if is_expression_filter(value) => self.expression(path, value),
+            Value::Array(items) => {
+                let op = items.first().and_then(Value::as_str).unwrap_or_default();
+                if !self.contains("legacy_filter_operators", op) {
+                    self.error(path, format!("unknown filter operator \"{}\"", op));
+                    return;
+                }
+                match op {
+                    "all" | "any" | "none" => {
+                        for (index, filter) in items.iter().enumerate().skip(1) {
+                            self.filter(&format!("{}[{}]", path, index), filter);
+                        }
+                    }
+                    _ if !items.get(1).is_some_and(Value::is_string) => {
+                        self.error(path, format!("\"{}\" filter needs a property key", op))
+                    }
+                    _ => {}
+                }
+            }
+            other => self.error(
+                path,
+                format!("expected array or boolean, found {}", type_name(other)),
+            ),
+        }
+    }
+
+    fn sprite(&mut self, path: &str, value: &Value) {
+        match value {
+            Value::String(_) => {}
+            Value::Array(sheets) => {
+                for (index, sheet) in sheets.iter().enumerate() {
+                    if !(sheet["id"].is_string() && sheet["url"].is_string()) {
+                        self.error(
+                            &format!("{}[{}]", path, index),
+                            "sprite entries need string \"id\" and \"url\"".to_string(),
+                        );
+                    }
+                }
+            }
+            other => self.error(
+                path,
+                format!("expected string or array, found {}", type_name(other)),
+            ),
+        }
+    }
+
+    fn sources(&mut self, path: &str, value: &Value) {
+        let Some(sources) = value.as_object() else {
+            self.error(path, format!("expected object, found {}", type_name(value)));
+            return;
+        };
+        for (id, source) in sources {
+            let source_path = join(path, id);
+            match source["type"].as_str() {
+                Some(kind) if self.spec[format!("source_{}", kind)].is_object() => {
+                    self.object(&source_path, source, &format!("source_{}", kind))
+                }
+                Some(kind) => self.error(
+                    &join(&source_path, "type"),
+                    format!("unknown source type \"{}\"", kind),
+                ),
+                None => self.error(&source_path, "source needs a \"type\"".to_string()),
+            }
+        }
+    }
+
+    fn layers(&mut self, path: &str, value: &Value) {
+        let Some(layers) = value.as_array() else {
+            self.error(path, format!("expected array, found {}", type_name(value)));
+            return;
+        };
+        let mut seen = std::collections::HashSet::new();
+        for (index, layer) in layers.iter().enumerate() {
+            let layer_path = format!("{}[{}]", path, index);
+            if !layer.is_object() {
+                self.error(
+                    &layer_path,
+                    format!("expected object, found {}", type_name(layer)),
+                );
+                continue;
+            }
+            // Only the layer's own keys here; layout, paint and filter are checked
+            // against the type-specific sections below.
+            let mut header = layer.clone();
+            if let Some(fields) = header.as_object_mut() {
+                fields.retain(|key, _| !matches!(key.as_str(), "layout" | "paint" | "filter"));
+            }
+            self.object(&layer_path, &header, "layer");
+            if let Some(id) = layer["id"].as_str() {
+                if !seen.insert(id) {
+                    self.error(
+                        &join(&layer_path, "id"),
+                        format!("duplicate layer id \"{}\"", id),
+                    );
+                }
+            }
+            let Some(kind) = layer["type"].as_str() else {
+                continue;
+            };
+            self.layer_source(&layer_path, layer, kind);
+            if let Some(filter) = layer.get("filter") {
+                self.filter(&join(&layer_path, "filter"), filter);
+            }
+            for group in ["layout", "paint"] {
+                if let Some(properties) = layer.get(group) {
+                    self.layer_properties(&join(&layer_path, group), properties, group, kind);
+                }
+            }
+        }
+    }
+
+    fn layer_source(&mut self, path: &str, layer: &Value, kind: &str) {
+        let Some(allowed) = self.spec["layer_sources"][kind].as_array() else {
+            return;
+        };
+        let Some(source_id) = layer["source"].as_str() else {
+            self.error(path, format!("{} layers need a \"source\"", kind));
+            return;
+        };
+        let source_type = self.style["sources"][source_id]["type"].as_str();
+        match source_type {
+            None => self.error(
+                &join(path, "source"),
+                format!("source \"{}\" not found", source_id),
+            ),
+            Some(source_type) if !allowed.iter().any(|t| t == source_type) => self.error(
+                &join(path, "source"),
+                format!(
+                    "{} layers cannot use the {} source \"{}\"",
+                    kind, source_type, source_id
+                ),
+            ),
+            Some("vector") if !layer["source-layer"].is_string() => self.error(
+                path,
+                "layers using a vector source need a \"source-layer\"".to_string(),
+            ),
+            Some(_) => {}
+        }
+    }
+
+    fn layer_properties(&mut self, path: &str, value: &Value, group: &str, kind: &str) {
+        let Some(fields) = value.as_object() else {
+            self.error(path, format!("expected object, found {}", type_name(value)));
+            return;
+        };
+        let section = &self.spec[format!("{}_{}", group, kind)];
+        for (name, field) in fields {
+            let field_path = join(path, name);
+            let transition = name
+                .strip_suffix("-transition")
+                .filter(|base| group == "paint" && section.get(*base).is_some());
+            if transition.is_some() {
+                self.object(&field_path, field, "transition");
+            } else if let Some(property) = section.get(name) {
+                self.style_value(&field_path, field, property);
+            } else {
+                self.report(
+                    &field_path,
+                    Severity::Warning,
+                    format!(
+                        "unknown {} property \"{}\" for {} layers",
+                        group, name, kind
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Whether a filter uses expression syntax rather than the legacy filter syntax,
+/// following MapLibre's own disambiguation.
+fn is_expression_filter(filter: &Value) -> bool {
+    let Some(items) = filter.as_array() else {
+        return false;
+    };
+    match items.first().and_then(Value::as_str) {
+        Some("has") => items.len() >= 2 && !matches!(items[1].as_str(), Some("$id" | "$type")),
+        Some("in") => items.len() >= 3 && (!items[1].is_string() || items[2].is_array()),
+        Some("!in" | "!has" | "none") => false,
+        Some("==" | "!=" | ">" | ">=" | "<" | "<=") => {
+            items.len() != 3 || items[1].is_array() || items[2].is_array()
+        }
+        Some("any" | "all") => items[1..]
+            .iter()
+            .all(|f| f.is_boolean() || is_expression_filter(f)),
+        _ => true,
+    }
+}
+
+/// Lint `style` against the bundled spec.
+pub fn lint(style: &Value) -> Report {
+    let spec: Value = serde_json::from_str(SPEC).expect("bundled style spec is valid JSON");
+    let spec_revision = spec["$revision"].as_str().unwrap_or_default().to_string();
+    let mut linter = Linter {
+        spec: &spec,
+        style,
+        violations: Vec::new(),
+    };
+    linter.object("", style, "root");
+    Report {
+        violations: linter.violations,
+        spec_revision,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn valid() -> Value {
+        json!({
+            "version": 8,
+            "sources": {
+                "land": {"type": "geojson", "data": {"type": "FeatureCollection", "features": []}},
+                "tiles": {"type": "vector", "url": "https://example.com/tiles.json"}
+            },
+            "layers": [
+                {"id": "background", "type": "background", "paint": {
+                    "background-color": "#f8f4f0",
+                    "background-opacity-transition": {"duration": 300}
+                }},
+                {"id": "land", "type": "fill", "source": "land",
+                    "filter": ["==", "$type", "Polygon"],
+                    "paint": {
+                        "fill-color": ["interpolate", ["linear"], ["zoom"], 0, "red", 10, "rgba(0, 0, 255, 0.5)"],
+                        "fill-opacity": {"stops": [[0, 0.5], [10, 1]]}
+                    }},
+                {"id": "roads", "type": "line", "source": "tiles", "source-layer": "roads",
+                    "filter": ["all", ["has", "name"], ["in", ["get", "class"], ["literal", ["a", "b"]]]],
+                    "layout": {"line-cap": "round"},
+                    "paint": {"line-dasharray": [2, 1], "line-width": ["match", ["get", "class"], ["a", "b"], 2, 1]}}
+            ]
+        })
+    }
+
+    /// `(path, severity)` of each violation.
+    fn violations(style: &Value) -> Vec<(String, Severity)> {
+        lint(style)
+            .violations
+            .into_iter()
+            .map(|v| (v.path, v.severity))
+            .collect()
+    }
+
+    fn with_layer(layer: Value) -> Value {
+        let mut style = valid();
+        style["layers"].as_array_mut().unwrap().push(layer);
+        style
+    }
+
+    #[test]
+    fn valid_style_has_no_violations() {
+        let report = lint(&valid());
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        assert!(!report.spec_revision.is_empty());
+    }
+
+    #[test]
+    fn hex_colors_need_3_4_6_or_8_digits() {
+        for color in ["#abc", "#abcd", "#AABBCC", "#aabbccdd"] {
+            assert!(is_hex_color(color), "{}", color);
+        }
+        for color in ["abc", "#ab", "#abcde", "#ggg"] {
+            assert!(!is_hex_color(color), "{}", color);
+        }
+    }
+
+    #[test]
+    fn root_problems_are_reported_at_their_path() {
+        let mut style = valid();
+        style.as_object_mut().unwrap().remove("version");
+        style["extra"] = json!(true);
+        style["sources"]["bad"] = json!({"type": "hologram"});
+        assert_eq!(
+            violations(&style),
+            [
+                (String::new(), Severity::Error),
+                ("extra".to_string(), Severity::Warning),
+                ("sources.bad.type".to_string(), Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn layer_values_are_checked_against_the_spec() {
+        let style = with_layer(json!({
+            "id": "bad", "type": "fill", "source": "land",
+            "layout": {"visibility": "hidden", "line-cap": "round"},
+            "paint": {"fill-color": "not-a-color", "fill-opacity": 2, "fill-antialias": "yes"}
+        }));
+        assert_eq!(
+            violations(&style),
+            [
+                ("layers[3].layout.line-cap".to_string(), Severity::Warning),
+                ("layers[3].layout.visibility".to_string(), Severity::Error),
+                (
+                    "layers[3].paint.fill-antialias".to_string(),
+                    Severity::Error
+                ),
+                ("layers[3].paint.fill-color".to_string(), Severity::Error),
+                ("layers[3].paint.fill-opacity".to_string(), Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn expressions_need_known_operators() {
+        let style = with_layer(json!({
+            "id": "bad", "type": "fill", "source": "land",
+            "paint": {
+                "fill-color": ["coalesce", ["frobnicate", 1], "red"],
+                "fill-opacity": ["interpolate", ["smooth"], ["zoom"], 0, 0, 1, 1],
+                "fill-translate": [1, 2, 3]
+            }
+        }));
+        let report = lint(&style);
+        let messages: Vec<_> = report
+            .violations
+            .iter()
+            .map(|v| (v.path.as_str(), v.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (
+                    "layers[3].paint.fill-color[1]",
+                    "unknown expression operator \"frobnicate\""
+                ),
+                (
+                    "layers[3].paint.fill-opacity[1]",
+                    "expected [\"linear\"], [\"exponential\", base] or [\"cubic-bezier\", ...], \
+                     found [\"smooth\"]"
+                ),
+                (
+                    "layers[3].paint.fill-translate",
+                    "array length 2 expected, length 3 found"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_and_expression_filters_are_told_apart() {
+        assert!(!is_expression_filter(&json!(["==", "class", "road"])));
+        assert!(is_expression_filter(&json!([
+            "==",
+            ["get", "class"],
+            "road"
+        ])));
+        assert!(!is_expression_filter(&json!(["in", "class", "a", "b"])));
+        assert!(is_expression_filter(&json!(["in", "a", ["get", "tags"]])));
+        assert!(!is_expression_filter(&json!(["has", "$id"])));
+        assert!(is_expression_filter(&json!(["all", ["has", "name"], true])));
+
+        let style = with_layer(json!({
+            "id": "bad", "type": "fill", "source": "land",
+            "filter": ["all", ["!has"], ["within-ish", "name"]]
+        }));
+        assert_eq!(
+            violations(&style),
+            [
+                ("layers[3].filter[1]".to_string(), Severity::Error),
+                ("layers[3].filter[2]".to_string(), Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn layer_sources_must_exist_and_suit_the_layer() {
+        let mut style = valid();
+        let layers = style["layers"].as_array_mut().unwrap();
+        layers.push(json!({"id": "missing", "type": "fill", "source": "nowhere"}));
+        layers.push(json!({"id": "no-layer", "type": "line", "source": "tiles"}));
+        layers.push(json!({"id": "land", "type": "fill", "source": "land"}));
+        layers.push(json!({"id": "sourceless", "type": "circle"}));
+        let report = lint(&style);
+        let messages: Vec<_> = report
+            .violations
+            .iter()
+            .map(|v| (v.path.as_str(), v.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("layers[3].source", "source \"nowhere\" not found"),
+                (
+                    "layers[4]",
+                    "layers using a vector source need a \"source-layer\""
+                ),
+                ("layers[5].id", "duplicate layer id \"land\""),
+                ("layers[6]", "circle layers need a \"source\""),
+            ]
+        );
+    }
+}
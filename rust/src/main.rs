@@ -1,363 +1,8557 @@
+use image::RgbaImage;
 use maplibre_native::{
-    CameraUpdate, Image, ImageRenderer, ImageRendererBuilder, LatLng, RenderingError, Static,
+    AnyLayer, AnySource, CameraUpdate, Continuous, EdgeInsets, GeoJson, Image, ImageRenderer,
+    ImageRendererBuilder, LatLng, MapDebugOptions, MapLoadErrorKind, RenderingError, ResourceKind,
+    RunLoopHandle, Size, SourceRefMut, Static, StyleLoadRequest,
 };
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, Seek, SeekFrom, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
 use std::num::NonZeroU32;
-use tempfile::NamedTempFile;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+mod archive;
+mod backend;
+mod cache;
+mod camera;
+mod cancel;
+mod compose;
+mod constrain;
+mod diagnostics;
+mod geojson_diff;
+mod glyphs;
+mod graticule;
+mod legend;
+mod lint;
+mod mvt;
+mod pattern;
+mod pipeline;
+mod pool;
+mod preset;
+mod profile;
+mod raster;
+mod resources;
+#[cfg(feature = "s3")]
+mod s3;
+mod shutdown;
+mod sprite;
+mod style_edit;
+mod track;
+mod utfgrid;
+
+/// `MAJOR.MINOR`; clients with the same major version are compatible.
 const PROTOCOL_VERSION: &str = "2.0";
 
+/// Every command this build accepts, as reported by `capabilities`.
+const COMMANDS: &[&str] = &[
+    "capabilities",
+    "init",
+    "close_session",
+    "reload_style",
+    "resize",
+    "render",
+    "set_preset",
+    "set_bookmark",
+    "render_batch",
+    "render_loupe",
+    "render_with_graticule",
+    "profile",
+    "render_utfgrid",
+    "render_window",
+    "render_match_raster",
+    "render_bounds",
+    "render_tile",
+    "render_scale",
+    "render_dpi_set",
+    "render_large",
+    "set_layer_opacity",
+    "set_layer_visibility",
+    "set_layer_property",
+    "set_layer_filter",
+    "set_source_tile_size",
+    "add_source",
+    "remove_source",
+    "add_layer",
+    "remove_layer",
+    "set_hillshade",
+    "set_debug",
+    "set_feature_state",
+    "remove_feature_state",
+    "transaction",
+    "reload_resources",
+    "render_tile_archive",
+    #[cfg(feature = "s3")]
+    "render_to_s3",
+    "render_layer_groups",
+    "render_with_legend",
+    "render_zoom_ladder",
+    "feature_counts",
+    "point_in_layer",
+    "query_features",
+    "check_glyph_coverage",
+    "build_sprite",
+    "render_geojson_diff",
+    "render_constrained",
+    "render_track_animation",
+    "render_path",
+    "refresh_and_render",
+    "lint_style",
+    "validate_style",
+    "describe_style",
+    "get_style",
+    "memory_stats",
+    "validate_camera",
+    "describe_view",
+    "estimate_resources",
+    "resource_response",
+    "flush",
+    "pipeline",
+    "cancel",
+    "quit",
+];
+
+/// `init` `style` asking for the style to be read from the bytes after the command.
+const STYLE_FROM_STDIN: &str = "@stdin";
+
 fn default_pixel_ratio() -> f64 {
     1.0
 }
 
+/// Encoding of the image payload that follows a response line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    #[default]
+    Png,
+    /// 256-colour indexed PNG; see `raster::Dither`.
+    Png8,
+    /// Uncompressed RGBA8 rows; see `raster::RawLayout`.
+    Rgba,
+    /// Baseline JPEG at `quality`; translucent pixels are flattened over white.
+    Jpeg,
+    /// Lossless WebP with alpha.
+    Webp,
+}
+
+impl OutputFormat {
+    const ALL: [OutputFormat; 5] = [
+        OutputFormat::Png,
+        OutputFormat::Png8,
+        OutputFormat::Rgba,
+        OutputFormat::Jpeg,
+        OutputFormat::Webp,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Png8 => "png8",
+            OutputFormat::Rgba => "rgba",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    /// File extension for images written to disk.
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::Png8 => "png",
+            OutputFormat::Rgba => "rgba",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+}
+
+/// How the `style` of `init` and `reload_style` is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+enum StyleEncoding {
+    /// A URL, file path or style JSON, as given.
+    #[default]
+    #[serde(rename = "plain")]
+    Plain,
+    /// Base64 of gzipped style JSON, for inline styles too large to send as is.
+    #[serde(rename = "gzip+base64")]
+    GzipBase64,
+}
+
+impl StyleEncoding {
+    /// The style as `load_style` takes it.
+    fn decode(self, style: String) -> Result<String, String> {
+        match self {
+            StyleEncoding::Plain => Ok(style),
+            StyleEncoding::GzipBase64 => {
+                use base64::Engine;
+                let compressed = base64::engine::general_purpose::STANDARD
+                    .decode(style.trim())
+                    .map_err(|e| format!("style is not valid base64: {}", e))?;
+                let mut json = String::new();
+                flate2::read::GzDecoder::new(compressed.as_slice())
+                    .read_to_string(&mut json)
+                    .map_err(|e| format!("style is not valid gzipped UTF-8: {}", e))?;
+                Ok(json)
+            }
+        }
+    }
+}
+
+/// Text `attribution` draws over renders: given outright, or `true` for the sources'
+/// own `attribution` in the style; `false` turns off the `init` default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Attribution {
+    Style(bool),
+    Text(String),
+}
+
+impl Attribution {
+    /// The text to draw over renders of `style`, if any.
+    fn resolve(self, style: &str) -> Result<Option<String>, String> {
+        match self {
+            Attribution::Text(text) => Ok(Some(text)),
+            Attribution::Style(false) => Ok(None),
+            Attribution::Style(true) => {
+                let document = Renderer::fetch_style_document(style)?;
+                style_edit::attribution(&document)
+                    .map(Some)
+                    .ok_or_else(|| "the style's sources declare no attribution".to_string())
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(tag = "cmd")]
 enum Command {
+    /// Report what this build supports; needs no `init`.
+    #[serde(rename = "capabilities")]
+    Capabilities,
     #[serde(rename = "init")]
-    Init {
-        width: u32,
-        height: u32,
-        style: String,
-        #[serde(default = "default_pixel_ratio")]
-        pixel_ratio: f64,
-        #[serde(default)]
-        protocol_version: Option<String>,
-    },
+    Init(InitArgs),
+    /// Drop a named session's renderer and everything set up for it.
+    #[serde(rename = "close_session")]
+    CloseSession(CloseSessionArgs),
     #[serde(rename = "reload_style")]
-    ReloadStyle { style: String },
+    ReloadStyle(ReloadStyleArgs),
+    /// Change the map size in place, keeping the loaded style.
+    #[serde(rename = "resize")]
+    Resize(ResizeArgs),
     #[serde(rename = "render")]
-    Render {
-        center: [f64; 2],
-        zoom: f64,
-        #[serde(default)]
-        bearing: f64,
-        #[serde(default)]
-        pitch: f64,
-    },
+    Render(RenderArgs),
+    /// Store named `render` options for `render` with `preset`.
+    #[serde(rename = "set_preset")]
+    SetPreset(SetPresetArgs),
+    /// Store a named camera for `render` with `bookmark`.
+    #[serde(rename = "set_bookmark")]
+    SetBookmark(SetBookmarkArgs),
     #[serde(rename = "render_batch")]
-    RenderBatch { views: Vec<View> },
+    RenderBatch(RenderBatchArgs),
+    /// Render `camera` with a circular magnified inset of `focus` at `position`.
+    #[serde(rename = "render_loupe")]
+    RenderLoupe(RenderLoupeArgs),
+    /// Render `camera` with latitude/longitude grid lines drawn over it.
+    #[serde(rename = "render_with_graticule")]
+    RenderWithGraticule(RenderWithGraticuleArgs),
+    /// Render `cameras` repeatedly and report latency percentiles; no images are returned.
+    #[serde(rename = "profile")]
+    Profile(ProfileArgs),
+    /// Build a UTFGrid for tile `z/x/y` from the inline GeoJSON behind `layers`.
+    #[serde(rename = "render_utfgrid")]
+    RenderUtfGrid(RenderUtfGridArgs),
+    /// Render the `width`×`height` window whose top-left corner is `pixel_origin`
+    /// in the global pixel space of `zoom`.
+    #[serde(rename = "render_window")]
+    RenderWindow(RenderWindowArgs),
+    /// Render exactly the extent and pixel grid of a georeferenced reference raster.
+    #[serde(rename = "render_match_raster")]
+    RenderMatchRaster(RenderMatchRasterArgs),
+    /// Render the view that fits `bounds`, `[[west, south], [east, north]]`, inset by
+    /// `padding` logical pixels.
+    #[serde(rename = "render_bounds")]
+    RenderBounds(RenderBoundsArgs),
+    /// Render slippy-map tile `z`/`x`/`y` facing north, filling the shorter side of
+    /// the init size.
+    #[serde(rename = "render_tile")]
+    RenderTile(RenderTileArgs),
+    /// Render at a cartographic scale 1:`scale_denominator` for printing at `dpi`.
+    #[serde(rename = "render_scale")]
+    RenderScale(RenderScaleArgs),
+    /// Render the same printed extent, `width_mm` by `height_mm`, at each of `dpis`.
+    #[serde(rename = "render_dpi_set")]
+    RenderDpiSet(RenderDpiSetArgs),
+    /// Render a `width`×`height` view of any size, in tiles the GPU can draw stitched
+    /// into one image.
+    #[serde(rename = "render_large")]
+    RenderLarge(RenderLargeArgs),
+    /// Set a layer's opacity paint property (`fill-opacity`, `line-opacity`, ...).
+    #[serde(rename = "set_layer_opacity")]
+    SetLayerOpacity(SetLayerOpacityArgs),
+    /// Show or hide a layer via its `visibility` layout property.
+    #[serde(rename = "set_layer_visibility")]
+    SetLayerVisibility(SetLayerVisibilityArgs),
+    /// Set any paint or layout property of a layer; `value` may be an expression.
+    #[serde(rename = "set_layer_property")]
+    SetLayerProperty(SetLayerPropertyArgs),
+    /// Replace a layer's filter expression; `null` or absent clears it.
+    #[serde(rename = "set_layer_filter")]
+    SetLayerFilter(SetLayerFilterArgs),
+    /// Override a raster or raster-dem source's `tileSize` and reload the style.
+    #[serde(rename = "set_source_tile_size")]
+    SetSourceTileSize(SetSourceTileSizeArgs),
+    /// Add a GeoJSON source to the loaded style, for layers to draw.
+    #[serde(rename = "add_source")]
+    AddSource(AddSourceArgs),
+    /// Remove a source no layer draws from the loaded style.
+    #[serde(rename = "remove_source")]
+    RemoveSource(RemoveSourceArgs),
+    /// Insert style layer JSON below the layer `before`, or on top without it.
+    #[serde(rename = "add_layer")]
+    AddLayer(AddLayerArgs),
+    #[serde(rename = "remove_layer")]
+    RemoveLayer(RemoveLayerArgs),
+    /// Merge `state` into one feature's state, for `["feature-state", key]`
+    /// expressions.
+    #[serde(rename = "set_feature_state")]
+    SetFeatureState(SetFeatureStateArgs),
+    /// Clear one feature's state (or one `key` of it), or every feature's without
+    /// `id`.
+    #[serde(rename = "remove_feature_state")]
+    RemoveFeatureState(RemoveFeatureStateArgs),
+    /// Turn MapLibre Native's debug overlays on or off for later renders.
+    #[serde(rename = "set_debug")]
+    SetDebug(SetDebugArgs),
+    /// Set a hillshade layer's exaggeration and/or illumination direction.
+    #[serde(rename = "set_hillshade")]
+    SetHillshade(SetHillshadeArgs),
+    /// Apply several layer edits atomically, then optionally render once.
+    #[serde(rename = "transaction")]
+    Transaction(TransactionArgs),
+    /// Refetch the sprite without reloading the style. Glyph reloads are rejected.
+    #[serde(rename = "reload_resources")]
+    ReloadResources(ReloadResourcesArgs),
+    /// Render tiles into one tar archive of `{z}/{x}/{y}.png` entries, either listed in
+    /// `tiles` or covering `bounds` over `zooms`.
+    #[serde(rename = "render_tile_archive")]
+    RenderTileArchive(RenderTileArchiveArgs),
+    /// Render and PUT the encoded image to an S3-compatible bucket instead of
+    /// returning it. Requires the `s3` cargo feature.
+    #[serde(rename = "render_to_s3")]
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    RenderToS3(RenderToS3Args),
+    /// Render a view once per group of layers, each with every other layer hidden,
+    /// as transparent images for compositing.
+    #[serde(rename = "render_layer_groups")]
+    RenderLayerGroups(RenderLayerGroupsArgs),
+    /// Render a view with a legend panel docked beside it, as one image.
+    #[serde(rename = "render_with_legend")]
+    RenderWithLegend(RenderWithLegendArgs),
+    /// Render one center at several zooms into a grid, each cell labelled with its
+    /// zoom.
+    #[serde(rename = "render_zoom_ladder")]
+    RenderZoomLadder(RenderZoomLadderArgs),
+    /// Count the features of each layer visible in a camera's viewport.
+    #[serde(rename = "feature_counts")]
+    FeatureCounts(FeatureCountsArgs),
+    /// List the features under logical pixel `x`, `y` of the `camera` view, topmost
+    /// first.
+    #[serde(rename = "query_features")]
+    QueryFeatures(QueryFeaturesArgs),
+    /// Report whether `layer` draws anything at `point`, e.g. a land/water test.
+    #[serde(rename = "point_in_layer")]
+    PointInLayer(PointInLayerArgs),
+    /// Report which sample-text codepoints each font stack's glyph ranges lack.
+    #[serde(rename = "check_glyph_coverage")]
+    CheckGlyphCoverage(CheckGlyphCoverageArgs),
+    /// Pack a directory of icons into sprite sheets (`<output>.png`/`.json`, plus
+    /// `@2x` and other ratios).
+    #[serde(rename = "build_sprite")]
+    BuildSprite(BuildSpriteArgs),
+    /// Highlight the features added, removed and changed between two GeoJSON states.
+    #[serde(rename = "render_geojson_diff")]
+    RenderGeoJsonDiff(RenderGeoJsonDiffArgs),
+    /// Render a camera adjusted to stay within a GeoJSON boundary.
+    #[serde(rename = "render_constrained")]
+    RenderConstrained(RenderConstrainedArgs),
+    /// Play back asset tracks: interpolate each asset's position over `frames` evenly
+    /// spaced times and render each frame.
+    #[serde(rename = "render_track_animation")]
+    RenderTrackAnimation(RenderTrackAnimationArgs),
+    /// Render `frames` cameras moving from `from` to `to`, e.g. for a flyover video.
+    #[serde(rename = "render_path")]
+    RenderPath(RenderPathArgs),
+    /// Reload only `refresh_sources`, keeping every other source's tiles, then render.
+    #[serde(rename = "refresh_and_render")]
+    RefreshAndRender(RefreshAndRenderArgs),
+    /// Check a style document against the bundled style spec without loading it.
+    #[serde(rename = "lint_style")]
+    LintStyle(LintStyleArgs),
+    /// Load a style into a throwaway renderer and report whether MapLibre Native
+    /// accepts it, leaving the session's style alone.
+    #[serde(rename = "validate_style")]
+    ValidateStyle(ValidateStyleArgs),
+    /// List the loaded style's sources and layers.
+    #[serde(rename = "describe_style")]
+    DescribeStyle,
+    /// Return the loaded style JSON, with every edit made since `init`.
+    #[serde(rename = "get_style")]
+    GetStyle,
+    /// Report process memory and render-surface counters.
+    #[serde(rename = "memory_stats")]
+    MemoryStats,
+    /// Report how the renderer would clamp a camera, without rendering.
+    #[serde(rename = "validate_camera")]
+    ValidateCamera(ValidateCameraArgs),
+    /// Report the area and tiles a camera would show at the session's size, without
+    /// rendering.
+    #[serde(rename = "describe_view")]
+    DescribeView(DescribeViewArgs),
+    /// Report the texture sizes and memory an `init` with this size would need, without
+    /// building a renderer.
+    #[serde(rename = "estimate_resources")]
+    EstimateResources(EstimateResourcesArgs),
+    /// Reply to a `resource_request`; `data_len` raw bytes follow the command line.
+    #[serde(rename = "resource_response")]
+    ResourceResponse(ResourceResponseArgs),
+    /// Flush stdout and fsync files written since the last flush.
+    #[serde(rename = "flush")]
+    Flush,
+    /// Run these commands in order and answer with all their responses at once.
+    #[serde(rename = "pipeline")]
+    Pipeline(PipelineArgs),
+    /// Stop a running `render_batch` or `render_path` before its next view.
+    #[serde(rename = "cancel")]
+    Cancel,
     #[serde(rename = "quit")]
     Quit,
 }
 
+impl Command {
+    /// Mark an `init` style read from stdin as delivered.
+    fn take_style_len(&mut self) {
+        if let Command::Init(InitArgs { style_len, .. }) = self {
+            *style_len = None;
+        }
+    }
+
+    /// Whether the command renders or reads the style, and so fails before `init`.
+    fn needs_renderer(&self) -> bool {
+        !matches!(
+            self,
+            Command::Capabilities
+                | Command::Init(_)
+                | Command::CloseSession(_)
+                | Command::SetPreset(_)
+                | Command::SetBookmark(_)
+                | Command::CheckGlyphCoverage(CheckGlyphCoverageArgs {
+                    glyphs: Some(_),
+                    ..
+                })
+                | Command::BuildSprite(_)
+                | Command::LintStyle(_)
+                | Command::ValidateStyle(_)
+                | Command::MemoryStats
+                | Command::ValidateCamera(_)
+                | Command::EstimateResources(_)
+                | Command::ResourceResponse(_)
+                | Command::Flush
+                | Command::Pipeline(_)
+                | Command::Cancel
+                | Command::Quit
+        )
+    }
+}
+
+/// Arguments of `init`.
 #[derive(Debug, Deserialize)]
-struct View {
-    center: [f64; 2],
-    zoom: f64,
+struct InitArgs {
+    width: u32,
+    height: u32,
+    style: String,
+    /// `gzip+base64` when `style` is compressed style JSON.
     #[serde(default)]
-    bearing: f64,
+    style_encoding: StyleEncoding,
+    /// Bytes of style that follow this command's line when `style` is `@stdin`.
     #[serde(default)]
-    pitch: f64,
+    style_len: Option<usize>,
+    #[serde(default = "default_pixel_ratio")]
+    pixel_ratio: f64,
+    /// The client's `MAJOR.MINOR` protocol; `init` fails unless the major version
+    /// matches the daemon's.
+    #[serde(default)]
+    protocol_version: Option<String>,
+    /// Flag, blank, or reject renders that contain only the background.
+    #[serde(default)]
+    empty_tile_behavior: Option<raster::EmptyTileBehavior>,
+    /// Fill used by `empty_tile_behavior: "color"`, as RGBA.
+    #[serde(default = "default_empty_tile_color")]
+    empty_tile_color: [u8; 4],
+    /// Cache lifetime advertised for rendered images; 0 means `no-cache`.
+    #[serde(default)]
+    tile_ttl_seconds: Option<u64>,
+    /// Keep one render surface for the session (default). When false, the renderer
+    /// is rebuilt before every render, trading speed for bounded memory.
+    #[serde(default = "default_surface_reuse")]
+    surface_reuse: bool,
+    /// Caps on symbol `icon-size`/`text-size`, `background`: `"transparent"` or a
+    /// hex colour to draw the map over, and `glyphs`/`sprite` URLs replacing the
+    /// style's. All require an inline or file style.
+    #[serde(flatten)]
+    style_rewrites: style_edit::StyleRewrites,
+    /// Default label antialiasing for renders that don't set `text_aa`.
+    #[serde(default)]
+    text_aa: raster::TextAntialiasing,
+    /// Cap on features per vector tile, guarding render latency against
+    /// pathological data.
+    #[serde(default)]
+    max_features_per_tile: Option<usize>,
+    #[serde(default)]
+    dense_tile_behavior: resources::DenseTileBehavior,
+    /// Format of `render` and `render_batch` output when a render doesn't set one.
+    #[serde(default)]
+    output_format: OutputFormat,
+    /// JPEG quality (1-100) when a render doesn't set one.
+    #[serde(default)]
+    quality: Option<u8>,
+    /// Trade render quality for speed: `draft`, `standard` or `final`.
+    #[serde(default)]
+    render_mode: RenderMode,
+    /// Renderers `render_batch` spreads views over; 0 means one per CPU.
+    #[serde(default = "default_batch_workers")]
+    batch_workers: usize,
+    /// Zooms `render` and `render_batch` accept.
+    #[serde(default)]
+    min_zoom: f64,
+    #[serde(default = "default_max_zoom")]
+    max_zoom: f64,
+    /// `reject` (default) or `clamp` renders outside `min_zoom`..`max_zoom`.
+    #[serde(default)]
+    zoom_out_of_range: camera::ZoomOutOfRange,
+    /// Largest GeoJSON file or download a `render_batch` view may name.
+    #[serde(default = "default_max_geojson_bytes")]
+    max_geojson_bytes: u64,
+    /// Fail a `render` that hasn't finished after this many milliseconds.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// SQLite database MapLibre Native caches HTTP responses in, kept across
+    /// sessions.
+    #[serde(default)]
+    cache_path: Option<String>,
+    /// Largest size of the `cache_path` database.
+    #[serde(default)]
+    cache_size_mb: Option<u64>,
+    /// Refuse every `http(s)://` resource load, as `--offline` does.
+    #[serde(default)]
+    offline: bool,
+    /// Write logs up to this level to stderr, overriding `RUST_LOG`.
+    #[serde(default)]
+    log_level: Option<String>,
+    /// Keep this many plain renders, keyed by camera and output options, to answer
+    /// repeated views without rendering; 0 (default) turns it off.
+    #[serde(default)]
+    render_cache_size: usize,
+    /// `default_center`, `default_zoom`, `default_bearing` and `default_pitch` for
+    /// renders that leave them out.
+    #[serde(flatten)]
+    camera_defaults: camera::Defaults,
+    /// Retries of a remote style or daemon-side download that failed transiently.
+    #[serde(default = "default_max_retries")]
+    max_retries: u32,
+    /// Wait before the first retry, doubling for each one after it.
+    #[serde(default = "default_retry_backoff_ms")]
+    retry_backoff_ms: u64,
+    /// Attribution drawn over every `render`; `true` reads it from this style once,
+    /// here.
+    #[serde(default)]
+    attribution: Option<Attribution>,
+    #[serde(default)]
+    attribution_position: compose::Anchor,
+    /// Logo composited over every `render` that doesn't set its own.
+    #[serde(default)]
+    watermark: Option<compose::Watermark>,
+    /// Round every `render` zoom to a whole number, so raster tiles are drawn 1:1.
+    #[serde(default)]
+    snap_zoom: bool,
+    /// Most output pixels a `render_large` image may have.
+    #[serde(default = "default_max_large_render_pixels")]
+    max_large_render_pixels: u64,
+    /// Drop the renderer after this many milliseconds without a command for the
+    /// session, rebuilding it for the next one.
+    #[serde(default)]
+    idle_timeout_ms: Option<u64>,
 }
 
-#[derive(Debug, Serialize)]
-struct Response {
-    status: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    png_len: Option<usize>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    png_lengths: Option<Vec<usize>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
+/// Arguments of `close_session`.
+#[derive(Debug, Deserialize)]
+struct CloseSessionArgs {
+    session_id: String,
 }
 
-struct Renderer {
-    renderer: Option<ImageRenderer<Static>>,
-    temp_style_file: Option<NamedTempFile>,
+/// Arguments of `reload_style`.
+#[derive(Debug, Deserialize)]
+struct ReloadStyleArgs {
+    style: String,
+    #[serde(default)]
+    style_encoding: StyleEncoding,
 }
 
-impl Renderer {
-    fn new() -> Self {
-        Self {
-            renderer: None,
-            temp_style_file: None,
-        }
-    }
+/// Arguments of `resize`.
+#[derive(Debug, Deserialize)]
+struct ResizeArgs {
+    width: u32,
+    height: u32,
+}
 
-    fn load_style(
-        renderer: &mut ImageRenderer<Static>,
-        style: &str,
-        temp_style_file: &mut Option<NamedTempFile>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        if style.starts_with("http://")
-            || style.starts_with("https://")
-            || style.starts_with("file://")
-        {
-            let url = style.parse().map_err(|_| "Invalid style URL")?;
-            renderer.load_style_from_url(&url);
-            Ok(())
-        } else if style.starts_with("{") {
-            if temp_style_file.is_none() {
-                *temp_style_file = Some(NamedTempFile::new()?);
-            }
-            let temp_file = temp_style_file
-                .as_mut()
-                .ok_or("temporary style file unavailable")?;
-            temp_file.as_file_mut().set_len(0)?;
-            temp_file.as_file_mut().seek(SeekFrom::Start(0))?;
-            temp_file.as_file_mut().write_all(style.as_bytes())?;
-            temp_file.as_file_mut().flush()?;
-            renderer.load_style_from_path(temp_file.path())?;
-            Ok(())
-        } else {
-            renderer.load_style_from_path(style)?;
-            Ok(())
-        }
-    }
+/// Arguments of `render`.
+#[derive(Debug, Deserialize)]
+struct RenderArgs {
+    /// Required unless `bookmark` is given or `init` set a default.
+    #[serde(default)]
+    center: Option<[f64; 2]>,
+    #[serde(default)]
+    zoom: Option<f64>,
+    #[serde(default)]
+    bearing: Option<f64>,
+    #[serde(default)]
+    pitch: Option<f64>,
+    /// Place `center` in the middle of the area inside these insets; a bookmark's
+    /// own padding when absent.
+    #[serde(default)]
+    padding: Option<camera::Padding>,
+    /// Include sprite/glyph/source load failures observed during this render.
+    #[serde(default)]
+    report_resources: bool,
+    /// Include the model-view-projection matrix of the rendered camera.
+    #[serde(default)]
+    report_matrix: bool,
+    /// Include per-channel histograms of the output pixels.
+    #[serde(default)]
+    report_histogram: bool,
+    #[serde(default = "default_histogram_buckets")]
+    histogram_buckets: u32,
+    /// Include a run-length encoded mask of the partially transparent pixels.
+    #[serde(default)]
+    report_edge_mask: bool,
+    /// Report, per layer, whether it drew anything for this camera.
+    #[serde(default)]
+    report_drawn_layers: bool,
+    /// The `init` `output_format` when absent.
+    #[serde(default)]
+    format: Option<OutputFormat>,
+    /// JPEG quality (1-100); the `init` setting, or 90, when absent.
+    #[serde(default)]
+    quality: Option<u8>,
+    /// Only used by `png8`.
+    #[serde(default)]
+    dither: raster::Dither,
+    #[serde(default)]
+    alpha_mode: raster::AlphaMode,
+    /// Adam7-interlace `png` output so browsers can draw it progressively.
+    #[serde(default)]
+    png_interlaced: bool,
+    /// `linear` writes a 16-bit PNG in linear light for compositing pipelines.
+    #[serde(default)]
+    color_encoding: raster::ColorEncoding,
+    /// Shrink the encoding until the payload fits this many bytes.
+    #[serde(default)]
+    target_bytes: Option<usize>,
+    /// Use the @1x or @2x sprite regardless of the pixel ratio.
+    #[serde(default)]
+    force_sprite_scale: Option<u32>,
+    /// Lowest JPEG quality `target_bytes` may fall back to.
+    #[serde(default = "default_min_quality")]
+    min_quality: u8,
+    /// Return these rectangles of the render, each encoded separately, instead of
+    /// the whole image.
+    #[serde(default)]
+    crops: Vec<raster::Crop>,
+    /// Return only this rectangle of the render.
+    #[serde(default)]
+    crop: Option<raster::Region>,
+    /// Multiply the opacity of every layer not in `overlay_layers` by this factor
+    /// for this render only.
+    #[serde(default)]
+    dim_basemap: Option<f64>,
+    #[serde(default)]
+    overlay_layers: Vec<String>,
+    /// Label symbol layers in this language for this render only.
+    #[serde(default)]
+    language: Option<style_edit::Language>,
+    /// Return the best frame available after this many milliseconds instead of
+    /// waiting for every tile; the response carries `complete: false` if cut short.
+    #[serde(default)]
+    deadline_ms: Option<u64>,
+    /// Overrides the `init` `timeout_ms` for this render.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Send `loading` events every `progress_interval_ms` until the map is idle.
+    #[serde(default)]
+    progress: bool,
+    #[serde(default = "default_progress_interval_ms")]
+    progress_interval_ms: u64,
+    /// Label antialiasing for this render; the `init` setting when absent.
+    #[serde(default)]
+    text_aa: Option<raster::TextAntialiasing>,
+    /// Render the camera stored by `set_bookmark` under this name.
+    #[serde(default)]
+    bookmark: Option<String>,
+    /// Draw these fill layers as procedural hatch or dot patterns.
+    #[serde(default)]
+    fill_patterns: Vec<pattern::FillPattern>,
+    /// Start from the options stored by `set_preset` under this name.
+    #[serde(default)]
+    preset: Option<String>,
+    /// Options merged over the preset's.
+    #[serde(default)]
+    overrides: Option<serde_json::Value>,
+    /// Write the image to this file and respond with its path instead of the
+    /// payload.
+    #[serde(default)]
+    output_path: Option<String>,
+    /// Overrides the `init` attribution for this render.
+    #[serde(default)]
+    attribution: Option<Attribution>,
+    #[serde(default)]
+    attribution_position: Option<compose::Anchor>,
+    /// Overrides the `init` watermark for this render.
+    #[serde(default)]
+    watermark: Option<compose::Watermark>,
+    /// Overrides the `init` `snap_zoom` for this render.
+    #[serde(default)]
+    snap_zoom: Option<bool>,
+}
 
-    fn init(
-        &mut self,
-        width: u32,
-        height: u32,
-        style: &str,
-        pixel_ratio: f64,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let width_nz = NonZeroU32::new(width).ok_or("Width must be non-zero")?;
-        let height_nz = NonZeroU32::new(height).ok_or("Height must be non-zero")?;
+/// Arguments of `set_preset`.
+#[derive(Debug, Deserialize)]
+struct SetPresetArgs {
+    name: String,
+    config: serde_json::Value,
+}
 
-        let builder = ImageRendererBuilder::new()
-            .with_size(width_nz, height_nz)
-            .with_pixel_ratio(pixel_ratio as f32);
+/// Arguments of `set_bookmark`.
+#[derive(Debug, Deserialize)]
+struct SetBookmarkArgs {
+    name: String,
+    camera: View,
+}
 
-        let mut renderer = builder.build_static_renderer();
-        Self::load_style(&mut renderer, style, &mut self.temp_style_file)?;
+/// Arguments of `render_batch`.
+#[derive(Debug, Deserialize)]
+struct RenderBatchArgs {
+    views: Vec<View>,
+    /// Write each view's image into this directory instead of sending payloads.
+    #[serde(default)]
+    output_dir: Option<String>,
+    /// File name of each view in `output_dir`; `{index}` and `{ext}` are replaced.
+    #[serde(default = "default_output_name")]
+    output_name: String,
+    /// Send each view as soon as it finishes instead of one response at the end.
+    #[serde(default)]
+    stream: bool,
+}
 
-        self.renderer = Some(renderer);
-        Ok(())
-    }
+/// Arguments of `render_loupe`.
+#[derive(Debug, Deserialize)]
+struct RenderLoupeArgs {
+    camera: View,
+    focus: [f64; 2],
+    /// Linear scale of the inset relative to the base view (2.0 = one zoom level).
+    magnification: f64,
+    /// Loupe radius in logical pixels.
+    radius: f64,
+    /// Loupe centre in logical pixels from the top-left of the image.
+    position: [f64; 2],
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
 
-    fn render(
-        &mut self,
-        center: [f64; 2],
-        zoom: f64,
-        bearing: f64,
-        pitch: f64,
-    ) -> Result<Image, RenderingError> {
-        let renderer = self
-            .renderer
-            .as_mut()
-            .ok_or(RenderingError::StyleNotSpecified)?;
+/// Arguments of `render_with_graticule`.
+#[derive(Debug, Deserialize)]
+struct RenderWithGraticuleArgs {
+    camera: View,
+    /// Degrees between lines; chosen from the zoom when absent.
+    #[serde(default)]
+    interval_degrees: Option<f64>,
+    /// Straight RGBA line colour.
+    #[serde(default = "default_graticule_color")]
+    color: [u8; 4],
+    #[serde(default = "default_graticule_label")]
+    label: bool,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
 
-        let camera = CameraUpdate::new()
-            .center(LatLng {
-                lat: center[1],
-                lng: center[0],
-            })
-            .zoom(zoom)
-            .bearing(bearing)
-            .pitch(pitch);
+/// Arguments of `profile`.
+#[derive(Debug, Deserialize)]
+struct ProfileArgs {
+    cameras: Vec<View>,
+    iterations: u32,
+    /// Leading passes excluded from the warm distribution.
+    #[serde(default = "default_warmup")]
+    warmup: u32,
+}
 
-        let image = renderer.render_static(&camera)?;
+/// Arguments of `render_utfgrid`.
+#[derive(Debug, Deserialize)]
+struct RenderUtfGridArgs {
+    z: u8,
+    x: u32,
+    y: u32,
+    layers: Vec<String>,
+    #[serde(default)]
+    fields: Vec<String>,
+    /// Tile pixels per grid cell.
+    #[serde(default = "default_utfgrid_resolution")]
+    resolution: u32,
+}
 
-        Ok(image)
-    }
+/// Arguments of `render_window`.
+#[derive(Debug, Deserialize)]
+struct RenderWindowArgs {
+    zoom: f64,
+    pixel_origin: [f64; 2],
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
 
-    fn reload_style(&mut self, style: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let renderer = self
-            .renderer
-            .as_mut()
-            .ok_or("Renderer not initialized")?;
+/// Arguments of `render_match_raster`.
+#[derive(Debug, Deserialize)]
+struct RenderMatchRasterArgs {
+    /// `[west, south, east, north]` in degrees.
+    reference_bounds: [f64; 4],
+    reference_width: u32,
+    reference_height: u32,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
 
-        Self::load_style(renderer, style, &mut self.temp_style_file)
-    }
+/// Arguments of `render_bounds`.
+#[derive(Debug, Deserialize)]
+struct RenderBoundsArgs {
+    bounds: [[f64; 2]; 2],
+    #[serde(default)]
+    padding: f64,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
 }
 
-fn encode_png(image: Image) -> Result<Vec<u8>, String> {
-    let img_buffer = image.as_image();
-    let mut png_bytes: Vec<u8> = Vec::new();
-    img_buffer
-        .write_to(
-            &mut std::io::Cursor::new(&mut png_bytes),
-            image::ImageFormat::Png,
-        )
-        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
-    Ok(png_bytes)
+/// Arguments of `render_tile`.
+#[derive(Debug, Deserialize)]
+struct RenderTileArgs {
+    z: u32,
+    x: u32,
+    y: u32,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
 }
 
-fn send_response(resp: &Response) {
-    println!(
-        "{}",
-        serde_json::to_string(resp).unwrap_or_else(|_| {
-            r#"{"status":"error","error":"JSON encode failed"}"#.to_string()
-        })
-    );
-    let _ = io::stdout().flush();
+/// Arguments of `render_scale`.
+#[derive(Debug, Deserialize)]
+struct RenderScaleArgs {
+    center: [f64; 2],
+    scale_denominator: f64,
+    #[serde(default = "default_print_dpi")]
+    dpi: f64,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+    /// The `init` `output_format` when absent. Only PNG records the DPI.
+    #[serde(default)]
+    format: Option<OutputFormat>,
+    /// JPEG quality (1-100); the `init` setting, or 90, when absent.
+    #[serde(default)]
+    quality: Option<u8>,
+    /// Only used by `png8`.
+    #[serde(default)]
+    dither: raster::Dither,
 }
 
-fn send_response_with_payload(resp: &Response, payload: &[u8]) {
-    send_response(resp);
-    let _ = io::stdout().write_all(payload);
-    let _ = io::stdout().flush();
+/// Arguments of `render_dpi_set`.
+#[derive(Debug, Deserialize)]
+struct RenderDpiSetArgs {
+    camera: View,
+    width_mm: f64,
+    height_mm: f64,
+    dpis: Vec<u32>,
 }
 
-fn send_response_with_chunks<'a>(resp: &Response, chunks: impl IntoIterator<Item = &'a [u8]>) {
-    send_response(resp);
-    let mut stdout = io::stdout();
-    for chunk in chunks {
-        let _ = stdout.write_all(chunk);
-    }
-    let _ = stdout.flush();
+/// Arguments of `render_large`.
+#[derive(Debug, Deserialize)]
+struct RenderLargeArgs {
+    center: [f64; 2],
+    zoom: f64,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+    width: u32,
+    height: u32,
+    /// Logical pixels per tile side; the largest the texture limit allows when
+    /// absent.
+    #[serde(default)]
+    tile_size: Option<u32>,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
 }
 
-fn main() {
-    let stdin = io::stdin();
-    let mut renderer = Renderer::new();
+/// Arguments of `set_layer_opacity`.
+#[derive(Debug, Deserialize)]
+struct SetLayerOpacityArgs {
+    layer: String,
+    opacity: f64,
+}
 
-    for line in stdin.lock().lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => break,
-        };
+/// Arguments of `set_layer_visibility`.
+#[derive(Debug, Deserialize)]
+struct SetLayerVisibilityArgs {
+    layer: String,
+    visible: bool,
+}
 
-        if line.trim().is_empty() {
-            continue;
-        }
+/// Arguments of `set_layer_property`.
+#[derive(Debug, Deserialize)]
+struct SetLayerPropertyArgs {
+    layer: String,
+    property: String,
+    value: serde_json::Value,
+    kind: style_edit::PropertyKind,
+}
 
-        let cmd: Command = match serde_json::from_str(&line) {
-            Ok(c) => c,
-            Err(e) => {
-                send_response(&Response {
-                    status: "error".to_string(),
-                    png_len: None,
-                    png_lengths: None,
-                    error: Some(format!("Invalid command: {}", e)),
-                });
-                continue;
-            }
+/// Arguments of `set_layer_filter`.
+#[derive(Debug, Deserialize)]
+struct SetLayerFilterArgs {
+    layer: String,
+    #[serde(default)]
+    filter: serde_json::Value,
+}
+
+/// Arguments of `set_source_tile_size`.
+#[derive(Debug, Deserialize)]
+struct SetSourceTileSizeArgs {
+    source: String,
+    tile_size: u32,
+}
+
+/// Arguments of `add_source`.
+#[derive(Debug, Deserialize)]
+struct AddSourceArgs {
+    id: String,
+    /// GeoJSON, or a URL or path to load it from.
+    geojson: serde_json::Value,
+    /// Overwrite a source that already has this id.
+    #[serde(default)]
+    replace: bool,
+}
+
+/// Arguments of `remove_source`.
+#[derive(Debug, Deserialize)]
+struct RemoveSourceArgs {
+    id: String,
+}
+
+/// Arguments of `add_layer`.
+#[derive(Debug, Deserialize)]
+struct AddLayerArgs {
+    layer: serde_json::Value,
+    #[serde(default)]
+    before: Option<String>,
+}
+
+/// Arguments of `remove_layer`.
+#[derive(Debug, Deserialize)]
+struct RemoveLayerArgs {
+    id: String,
+}
+
+/// Arguments of `set_feature_state`.
+#[derive(Debug, Deserialize)]
+struct SetFeatureStateArgs {
+    source: String,
+    #[serde(default)]
+    source_layer: Option<String>,
+    id: serde_json::Value,
+    state: serde_json::Value,
+}
+
+/// Arguments of `remove_feature_state`.
+#[derive(Debug, Deserialize)]
+struct RemoveFeatureStateArgs {
+    source: String,
+    #[serde(default)]
+    source_layer: Option<String>,
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(default)]
+    key: Option<String>,
+}
+
+/// Arguments of `set_debug`.
+#[derive(Debug, Deserialize)]
+struct SetDebugArgs {
+    #[serde(default)]
+    tile_borders: bool,
+    #[serde(default)]
+    collision: bool,
+    #[serde(default)]
+    overdraw: bool,
+}
+
+/// Arguments of `set_hillshade`.
+#[derive(Debug, Deserialize)]
+struct SetHillshadeArgs {
+    layer: String,
+    #[serde(default)]
+    exaggeration: Option<f64>,
+    #[serde(default)]
+    illumination_direction: Option<f64>,
+}
+
+/// Arguments of `transaction`.
+#[derive(Debug, Deserialize)]
+struct TransactionArgs {
+    operations: Vec<style_edit::Operation>,
+    #[serde(default)]
+    render: Option<View>,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `reload_resources`.
+#[derive(Debug, Deserialize)]
+struct ReloadResourcesArgs {
+    #[serde(default)]
+    sprites: bool,
+    #[serde(default)]
+    glyphs: bool,
+}
+
+/// Arguments of `render_tile_archive`.
+#[derive(Debug, Deserialize)]
+struct RenderTileArchiveArgs {
+    #[serde(default)]
+    tiles: Vec<[u32; 3]>,
+    #[serde(default)]
+    bounds: Option<[f64; 4]>,
+    #[serde(default)]
+    zooms: Option<[u8; 2]>,
+    #[serde(default = "default_tile_size")]
+    tile_size: u32,
+    /// Archive path; sent as the response payload when absent.
+    #[serde(default)]
+    output: Option<String>,
+    #[serde(default)]
+    gzip: bool,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_to_s3`.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+struct RenderToS3Args {
+    camera: View,
+    bucket: String,
+    key: String,
+    #[serde(default)]
+    endpoint: Option<String>,
+    #[serde(default)]
+    region: Option<String>,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_layer_groups`.
+#[derive(Debug, Deserialize)]
+struct RenderLayerGroupsArgs {
+    camera: View,
+    groups: Vec<LayerGroup>,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_with_legend`.
+#[derive(Debug, Deserialize)]
+struct RenderWithLegendArgs {
+    camera: View,
+    legend: legend::Options,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_zoom_ladder`.
+#[derive(Debug, Deserialize)]
+struct RenderZoomLadderArgs {
+    center: [f64; 2],
+    zooms: Vec<f64>,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+    /// Cells per row; as close to square as the zoom count allows when absent.
+    #[serde(default)]
+    columns: Option<usize>,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `feature_counts`.
+#[derive(Debug, Deserialize)]
+struct FeatureCountsArgs {
+    camera: View,
+    #[serde(default)]
+    layers: Option<Vec<String>>,
+    #[serde(default = "default_feature_count_limit")]
+    limit: usize,
+}
+
+/// Arguments of `query_features`.
+#[derive(Debug, Deserialize)]
+struct QueryFeaturesArgs {
+    camera: View,
+    x: u32,
+    y: u32,
+    #[serde(default)]
+    layers: Option<Vec<String>>,
+}
+
+/// Arguments of `point_in_layer`.
+#[derive(Debug, Deserialize)]
+struct PointInLayerArgs {
+    point: [f64; 2],
+    layer: String,
+    /// Zoom at which the layer is evaluated; accuracy follows the tile detail.
+    #[serde(default = "default_probe_zoom")]
+    zoom: f64,
+}
+
+/// Arguments of `check_glyph_coverage`.
+#[derive(Debug, Deserialize)]
+struct CheckGlyphCoverageArgs {
+    font_stacks: Vec<Vec<String>>,
+    sample_texts: Vec<String>,
+    /// Glyph URL template; defaults to the style's `glyphs`.
+    #[serde(default)]
+    glyphs: Option<String>,
+}
+
+/// Arguments of `build_sprite`.
+#[derive(Debug, Deserialize)]
+struct BuildSpriteArgs {
+    icon_dir: String,
+    #[serde(default = "default_sprite_ratios")]
+    pixel_ratios: Vec<u32>,
+    output: String,
+}
+
+/// Arguments of `render_geojson_diff`.
+#[derive(Debug, Deserialize)]
+struct RenderGeoJsonDiffArgs {
+    before: serde_json::Value,
+    after: serde_json::Value,
+    camera: View,
+    #[serde(default = "geojson_diff::ChangeStyle::added")]
+    added_style: geojson_diff::ChangeStyle,
+    #[serde(default = "geojson_diff::ChangeStyle::removed")]
+    removed_style: geojson_diff::ChangeStyle,
+    #[serde(default = "geojson_diff::ChangeStyle::changed")]
+    changed_style: geojson_diff::ChangeStyle,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_constrained`.
+#[derive(Debug, Deserialize)]
+struct RenderConstrainedArgs {
+    camera: View,
+    boundary: serde_json::Value,
+    mode: constrain::Mode,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_track_animation`.
+#[derive(Debug, Deserialize)]
+struct RenderTrackAnimationArgs {
+    camera: View,
+    tracks: Vec<track::Track>,
+    frames: u32,
+    /// Playback rate of `apng` output.
+    #[serde(default = "default_fps")]
+    fps: f64,
+    #[serde(default)]
+    out_of_range: track::OutOfRange,
+    #[serde(default)]
+    marker: track::Marker,
+    #[serde(default)]
+    animation: track::Animation,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `render_path`.
+#[derive(Debug, Deserialize)]
+struct RenderPathArgs {
+    from: View,
+    to: View,
+    frames: u32,
+    #[serde(default)]
+    easing: camera::Easing,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `refresh_and_render`.
+#[derive(Debug, Deserialize)]
+struct RefreshAndRenderArgs {
+    refresh_sources: Vec<String>,
+    camera: View,
+    #[serde(default)]
+    format: OutputFormat,
+    #[serde(default)]
+    dither: raster::Dither,
+}
+
+/// Arguments of `lint_style`.
+#[derive(Debug, Deserialize)]
+struct LintStyleArgs {
+    style: String,
+}
+
+/// Arguments of `validate_style`.
+#[derive(Debug, Deserialize)]
+struct ValidateStyleArgs {
+    style: String,
+}
+
+/// Arguments of `validate_camera`.
+#[derive(Debug, Deserialize)]
+struct ValidateCameraArgs {
+    center: [f64; 2],
+    zoom: f64,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+}
+
+/// Arguments of `describe_view`.
+#[derive(Debug, Deserialize)]
+struct DescribeViewArgs {
+    center: [f64; 2],
+    zoom: f64,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+}
+
+/// Arguments of `estimate_resources`.
+#[derive(Debug, Deserialize)]
+struct EstimateResourcesArgs {
+    width: u32,
+    height: u32,
+    #[serde(default = "default_pixel_ratio")]
+    pixel_ratio: f64,
+    #[serde(default)]
+    render_mode: RenderMode,
+}
+
+/// Arguments of `resource_response`.
+#[derive(Debug, Deserialize)]
+struct ResourceResponseArgs {
+    id: u64,
+    #[serde(default = "default_status_code")]
+    status_code: u16,
+    #[serde(default)]
+    data_len: usize,
+}
+
+/// Arguments of `pipeline`.
+#[derive(Debug, Deserialize)]
+struct PipelineArgs {
+    commands: Vec<serde_json::Value>,
+    /// Run the rest after a command fails instead of stopping there.
+    #[serde(default)]
+    continue_on_error: bool,
+}
+
+fn default_max_retries() -> u32 {
+    resources::DEFAULT_MAX_RETRIES
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    resources::DEFAULT_RETRY_BACKOFF.as_millis() as u64
+}
+
+fn default_empty_tile_color() -> [u8; 4] {
+    [255, 0, 255, 255]
+}
+
+fn default_utfgrid_resolution() -> u32 {
+    4
+}
+
+fn default_probe_zoom() -> f64 {
+    12.0
+}
+
+fn default_print_dpi() -> f64 {
+    300.0
+}
+
+fn default_min_quality() -> u8 {
+    40
+}
+
+fn default_sprite_ratios() -> Vec<u32> {
+    vec![1, 2]
+}
+
+fn default_feature_count_limit() -> usize {
+    100
+}
+
+fn default_tile_size() -> u32 {
+    512
+}
+
+fn default_batch_workers() -> usize {
+    1
+}
+
+fn default_max_zoom() -> f64 {
+    camera::DEFAULT_MAX_ZOOM
+}
+
+fn default_output_name() -> String {
+    "{index}.{ext}".to_string()
+}
+
+fn default_max_geojson_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_progress_interval_ms() -> u64 {
+    100
+}
+
+fn default_histogram_buckets() -> u32 {
+    raster::MAX_HISTOGRAM_BUCKETS
+}
+
+fn default_fps() -> f64 {
+    10.0
+}
+
+fn default_surface_reuse() -> bool {
+    true
+}
+
+fn default_graticule_color() -> [u8; 4] {
+    [34, 34, 34, 160]
+}
+
+fn default_graticule_label() -> bool {
+    true
+}
+
+fn default_warmup() -> u32 {
+    1
+}
+
+/// Feature properties as returned to clients.
+type Properties = serde_json::Map<String, serde_json::Value>;
+
+/// Map size, in logical pixels, of the probe renders used by `point_in_layer` and
+/// the fractional pixel ratio check.
+const PROBE_SIZE: u32 = 8;
+
+/// Source-free style for capability probes; renders without any network access.
+const PROBE_STYLE: &str =
+    r#"{"version":8,"sources":{},"layers":[{"id":"background","type":"background"}]}"#;
+
+/// Loupe outline width in logical pixels.
+const LOUPE_BORDER_WIDTH: f64 = 3.0;
+
+/// JPEG quality `target_bytes` starts its search from, and the default otherwise.
+const MAX_JPEG_QUALITY: u8 = 90;
+
+/// The error for a command asked to load a remote `url` while the daemon is offline,
+/// sent at once instead of starting a load that can only fail.
+fn network_disabled(code: ErrorCode, command: &str, url: &str) -> Response {
+    Response {
+        error_code: Some("network_disabled"),
+        ..Response::error(
+            code,
+            format!(
+                "{} failed: network access is disabled (offline), so {} cannot load",
+                command, url
+            ),
+        )
+    }
+}
+
+/// Check a client's protocol version against `PROTOCOL_VERSION`. Only a different major
+/// version (or one that isn't `MAJOR.MINOR`) is incompatible; a client that sent no
+/// version, or a newer minor version than the daemon's, gets a warning instead.
+fn check_protocol_version(client: Option<&str>) -> Result<Option<String>, String> {
+    let Some(client) = client else {
+        return Ok(Some(format!(
+            "init did not send protocol_version; the daemon speaks protocol {}",
+            PROTOCOL_VERSION
+        )));
+    };
+    let parse = |version: &str| -> Option<(u32, u32)> {
+        let (major, minor) = version.split_once('.').unwrap_or((version, "0"));
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    };
+    match (parse(client), parse(PROTOCOL_VERSION)) {
+        (Some((major, minor)), Some((daemon_major, daemon_minor))) if major == daemon_major => {
+            Ok((minor > daemon_minor).then(|| {
+                format!(
+                    "client protocol {} is newer than the daemon's {}; features added since \
+                     are unavailable",
+                    client, PROTOCOL_VERSION
+                )
+            }))
+        }
+        _ => Err(format!(
+            "Protocol version mismatch: client={}, daemon={} (major versions must match)",
+            client, PROTOCOL_VERSION
+        )),
+    }
+}
+
+/// Check a requested JPEG quality.
+fn check_quality(quality: Option<u8>) -> Result<(), String> {
+    match quality {
+        Some(quality) if !(1..=100).contains(&quality) => Err(format!(
+            "quality must be between 1 and 100, got {}",
+            quality
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Reject pixel ratios that are zero, negative, NaN or infinite.
+fn check_pixel_ratio(pixel_ratio: f64) -> Result<(), String> {
+    if !(pixel_ratio.is_finite() && pixel_ratio > 0.0) {
+        return Err(format!(
+            "pixel_ratio must be a positive number, got {}",
+            pixel_ratio
+        ));
+    }
+    Ok(())
+}
+
+/// Check a requested render timeout.
+fn check_timeout(timeout_ms: Option<u64>) -> Result<(), String> {
+    match timeout_ms {
+        Some(0) => Err("timeout_ms must be greater than 0".to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn check_idle_timeout(idle_timeout_ms: Option<u64>) -> Result<(), String> {
+    match idle_timeout_ms {
+        Some(0) => Err("idle_timeout_ms must be greater than 0".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Reject JPEG output for a transparent background, which JPEG would flatten.
+fn check_background(transparent: bool, format: OutputFormat) -> Result<(), String> {
+    if transparent && format == OutputFormat::Jpeg {
+        return Err("background 'transparent' cannot be combined with jpeg output".to_string());
+    }
+    Ok(())
+}
+
+/// Most cells a `render_zoom_ladder` grid may have; each is a full render.
+const MAX_LADDER_ZOOMS: usize = 16;
+
+/// Print resolution `render_dpi_set` camera zooms refer to: one logical pixel per
+/// CSS pixel.
+const REFERENCE_DPI: f64 = 96.0;
+
+/// Most output pixels one `render_dpi_set` variant may have.
+const MAX_DPI_VARIANT_PIXELS: u64 = 100_000_000;
+
+/// Most output pixels a `render_large` image may have unless `init` sets another cap:
+/// 16384 squared, 1 GiB as RGBA.
+const DEFAULT_MAX_LARGE_RENDER_PIXELS: u64 = 16384 * 16384;
+
+/// Logical pixels each `render_large` tile is rendered past its edges, so lines and
+/// labels there are drawn as in a single view and cropped off.
+const LARGE_RENDER_OVERLAP: u32 = 64;
+
+fn default_max_large_render_pixels() -> u64 {
+    DEFAULT_MAX_LARGE_RENDER_PIXELS
+}
+
+/// Write an encoded image to `path`, creating missing parent directories. The file is
+/// left for `flush` to sync.
+fn write_output(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, bytes).map_err(|e| format!("Writing {} failed: {}", path.display(), e))
+}
+
+/// Whether a style or GeoJSON location is a URL rather than a file path.
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://")
+        || location.starts_with("https://")
+        || location.starts_with("file://")
+}
+
+/// Check a watermark and decode its logo from a URL, `data:` URI, file path or bare
+/// base64.
+fn load_watermark(watermark: &compose::Watermark) -> Result<RgbaImage, String> {
+    use base64::Engine;
+    watermark.check()?;
+    let source = watermark.image.trim();
+    let base64 = |data: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("watermark image is not valid base64: {}", e))
+    };
+    let bytes = if let Some(data) = source.strip_prefix("data:") {
+        let (_, data) = data
+            .split_once(";base64,")
+            .ok_or("watermark data URI must be base64")?;
+        base64(data)?
+    } else if is_url(source) {
+        resources::fetch(source, ResourceKind::Image)?
+    } else if Path::new(source).is_file() {
+        std::fs::read(source).map_err(|e| format!("Cannot read {}: {}", source, e))?
+    } else {
+        base64(source)
+            .map_err(|e| format!("{} is not a file, URL or base64 image ({})", source, e))?
+    };
+    image::load_from_memory(&bytes)
+        .map(|logo| logo.to_rgba8())
+        .map_err(|e| format!("watermark image cannot be decoded: {}", e))
+}
+
+/// Read GeoJSON from a URL or file path, refusing anything over `max_bytes`.
+fn read_geojson(location: &str, max_bytes: u64) -> Result<String, String> {
+    let too_large = |size: u64| {
+        format!(
+            "{} is {} bytes, over max_geojson_bytes ({})",
+            location, size, max_bytes
+        )
+    };
+    let bytes = if is_url(location) {
+        resources::fetch(location, ResourceKind::Source)?
+    } else {
+        // Check the size first so an oversized file is never read in.
+        let size = std::fs::metadata(location)
+            .map_err(|e| format!("Cannot read {}: {}", location, e))?
+            .len();
+        if size > max_bytes {
+            return Err(too_large(size));
+        }
+        std::fs::read(location).map_err(|e| format!("Cannot read {}: {}", location, e))?
+    };
+    if bytes.len() as u64 > max_bytes {
+        return Err(too_large(bytes.len() as u64));
+    }
+    String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8 text", location))
+}
+
+fn default_status_code() -> u16 {
+    200
+}
+
+#[derive(Debug, Deserialize)]
+struct View {
+    center: [f64; 2],
+    zoom: f64,
+    #[serde(default)]
+    bearing: f64,
+    #[serde(default)]
+    pitch: f64,
+    /// `render`, `render_batch` and bookmarks: insets the center is placed in the
+    /// middle of.
+    #[serde(default)]
+    padding: Option<camera::Padding>,
+    /// `render_batch`: new data for GeoJSON sources, keyed by source id, set before
+    /// this view renders. Each is inline GeoJSON, or a URL or path to read it from.
+    #[serde(default)]
+    geojson: Option<HashMap<String, serde_json::Value>>,
+    /// `render_batch`: layer edits applied for this view only and reverted after it
+    /// renders.
+    #[serde(default)]
+    operations: Vec<style_edit::Operation>,
+    /// `render_batch`: map size in logical pixels for this view; defaults to the
+    /// init size.
+    #[serde(default)]
+    width: Option<u32>,
+    #[serde(default)]
+    height: Option<u32>,
+    /// `render_batch`: output format and JPEG quality for this view; the session's
+    /// when absent.
+    #[serde(default)]
+    format: Option<OutputFormat>,
+    #[serde(default)]
+    quality: Option<u8>,
+}
+
+impl View {
+    /// Format and quality a `render_batch` view is encoded with, checked as for
+    /// `render`.
+    fn encoding(
+        &self,
+        format: OutputFormat,
+        quality: u8,
+        transparent: bool,
+    ) -> Result<(OutputFormat, u8), String> {
+        let format = self.format.unwrap_or(format);
+        check_quality(self.quality)?;
+        check_background(transparent, format)?;
+        if self.quality.is_some() && format != OutputFormat::Jpeg {
+            return Err("quality only applies to jpeg output".to_string());
+        }
+        Ok((format, self.quality.unwrap_or(quality)))
+    }
+}
+
+/// Kind of failure behind an error response, stable for clients to match on. The
+/// `error` message says what went wrong; `error_code` narrows some of these further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    /// The command could not be parsed, or is not available in this build.
+    InvalidCommand,
+    /// The command needs a renderer and `init` has not succeeded yet.
+    NotInitialized,
+    /// A value is out of range or conflicts with another option.
+    InvalidParameter,
+    /// `init` could not set up the renderer or load the style.
+    InitFailed,
+    RenderFailed,
+    /// The map rendered but could not be encoded to the output format.
+    EncodeFailed,
+    /// Any other command that failed, such as a style edit or file write.
+    CommandFailed,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Response {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_len: Option<usize>,
+    /// File the image was written to instead of being sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_lengths: Option<Vec<usize>>,
+    /// `pipeline`: every message its commands sent, in order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    responses: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// What kind of error this is; set on every error response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    /// Optional refinement of `code` for errors a client may want to handle
+    /// specially. Only set alongside `code`; clients that ignore it still see the
+    /// right kind of failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<String>>,
+    /// Vector tiles over `max_features_per_tile` loaded by this render.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dense_tiles: Option<Vec<resources::DenseTile>>,
+    /// `flush`: files fsynced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    synced_files: Option<usize>,
+    /// `set_source_tile_size`: the tile size the source now uses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tile_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    histogram: Option<raster::Histogram>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edge_mask: Option<raster::EdgeMask>,
+    /// `render` with `report_matrix`: column-major model-view-projection matrix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matrix: Option<[f64; 16]>,
+    /// `render_track_animation`: the track time of each frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_times: Option<Vec<f64>>,
+    /// `render_zoom_ladder`: cells per row of the grid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    columns: Option<usize>,
+    /// `render_constrained`: the camera actually rendered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    constrained: Option<constrain::Constrained>,
+    /// `render_geojson_diff` feature counts per change class.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<geojson_diff::Summary>,
+    /// `lint_style`: false if any violation is an error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    valid: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    violations: Option<Vec<lint::Violation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_revision: Option<String>,
+    /// Whether a bookmark render was served from the bookmark cache.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resources: Option<diagnostics::ResourceReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    layout: Option<raster::RawLayout>,
+    /// `render`: milliseconds spent rendering and encoding (all crops together).
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    timing: Option<profile::Timing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    complete: Option<bool>,
+    /// Deadline renders: tiles still requested from the client when the frame was read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tiles_pending: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    empty: Option<bool>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    validation: Option<camera::CameraValidation>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    view: Option<camera::ViewDescription>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<profile::ProfileReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utfgrid: Option<utfgrid::UtfGrid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_clamps: Option<Vec<style_edit::SizeClamp>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    cache: Option<cache::CacheHints>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<diagnostics::MemoryStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimate: Option<backend::ResourceEstimate>,
+    /// Sources and layers of the loaded style, from `describe_style`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<style_edit::StyleDescription>,
+    /// The loaded style document, from `get_style`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style_json: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    glyph_coverage: Option<Vec<glyphs::StackCoverage>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sprite: Option<Vec<sprite::SpriteOutput>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feature_counts: Option<Vec<utfgrid::LayerFeatureCount>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<Vec<utfgrid::QueriedFeature>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drawn_layers: Option<Vec<DrawnLayer>>,
+    /// Whether a list was cut short by its `limit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crops: Option<Vec<CropResult>>,
+    /// `render_dpi_set`: one entry per DPI, in request order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dpi_variants: Option<Vec<DpiVariant>>,
+    /// `render_large`: columns and rows of tiles the image was stitched from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tile_grid: Option<[u32; 2]>,
+    /// `render_batch`: the outcome of each view, in request order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    views: Option<Vec<ViewResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<GroupResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    properties: Option<Properties>,
+    /// Center computed by `render_bounds` or `render_tile`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    center: Option<[f64; 2]>,
+    /// Zoom computed by `render_scale`, `render_bounds`, `render_tile` or
+    /// `render_match_raster`, or the whole zoom a `snap_zoom` render used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zoom: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dpi: Option<f64>,
+    /// Line interval `render_with_graticule` drew.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval_degrees: Option<f64>,
+    /// Index of the `transaction` operation that was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_operation: Option<usize>,
+    /// Object key written by `render_to_s3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    /// Bytes uploaded by `render_to_s3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_bytes: Option<usize>,
+    /// Quality of JPEG output, as requested or as chosen by `target_bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<u8>,
+    /// Whether the payload fits `target_bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_met: Option<bool>,
+    /// Sources reloaded by `refresh_and_render`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refreshed_sources: Option<Vec<String>>,
+    /// Resources refreshed by `reload_resources`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reloaded: Option<Vec<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sprite_images: Option<usize>,
+    /// Tiles written by `render_tile_archive`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tile_count: Option<usize>,
+    /// `init`: the GPU backend rendering is done with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend: Option<backend::Backend>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capabilities: Option<Capabilities>,
+    /// `init`: the daemon's protocol version, on success and on a mismatch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol_version: Option<&'static str>,
+    /// `native`, `downscale` or `upscale`, for fractional pixel ratios.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pixel_ratio_path: Option<&'static str>,
+    /// `init`: output pixels per logical pixel; images are the init size times this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pixel_ratio: Option<f64>,
+    /// Raw bytes sent after the JSON line, in order.
+    #[serde(skip)]
+    payloads: Vec<Vec<u8>>,
+}
+
+/// What this build of the daemon supports, for clients to check after connecting.
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    protocol_version: &'static str,
+    /// Values accepted for `format` and `output_format`.
+    formats: Vec<&'static str>,
+    /// Backend the daemon renders with, `metal` or `vulkan`.
+    backend: &'static str,
+    /// Largest render surface side, in device pixels, every driver for the backend
+    /// supports; a given GPU may allow more.
+    max_texture_size: u32,
+    commands: &'static [&'static str],
+}
+
+impl Capabilities {
+    fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            formats: OutputFormat::ALL
+                .iter()
+                .map(|format| format.name())
+                .collect(),
+            backend: backend::NAME,
+            max_texture_size: backend::MAX_TEXTURE_SIZE,
+            commands: COMMANDS,
+        }
+    }
+}
+
+/// A named set of layers rendered on its own by `render_layer_groups`.
+#[derive(Debug, Deserialize)]
+struct LayerGroup {
+    name: String,
+    layers: Vec<String>,
+}
+
+/// Outcome of one `render_layer_groups` group; payloads follow as for `crops`.
+#[derive(Debug, Serialize)]
+struct GroupResult {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Outcome of one `crops` entry. Payloads follow the header for successful crops
+/// only, in request order.
+#[derive(Debug, Serialize)]
+struct CropResult {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// One `render_dpi_set` image; payloads follow the header in the same order.
+#[derive(Debug, Serialize)]
+struct DpiVariant {
+    dpi: u32,
+    width: u32,
+    height: u32,
+    png_len: usize,
+    /// The encoded image, sent after the header rather than in it.
+    #[serde(skip)]
+    png: Vec<u8>,
+}
+
+/// Outcome of one `render_batch` view; payloads follow as for `crops`.
+#[derive(Debug, Serialize)]
+struct ViewResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png_len: Option<usize>,
+    /// File the image was written to, with `output_dir`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// Size of the rendered image in pixels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    timing: Option<profile::Timing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Index of the view's operation that was rejected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_operation: Option<usize>,
+}
+
+impl ViewResult {
+    fn failed(error: impl std::fmt::Display) -> Self {
+        Self {
+            png_len: None,
+            path: None,
+            width: None,
+            height: None,
+            format: None,
+            timing: None,
+            error: Some(error.to_string()),
+            failed_operation: None,
+        }
+    }
+}
+
+/// One view of a `stream` batch, sent with its payload as soon as it finishes.
+#[derive(Serialize)]
+struct BatchViewEvent<'a> {
+    status: &'static str,
+    index: usize,
+    #[serde(flatten)]
+    view: &'a ViewResult,
+}
+
+/// Whether one style layer put any pixels in a render, and if not, why.
+#[derive(Debug, Serialize)]
+struct DrawnLayer {
+    layer: String,
+    drawn: bool,
+    /// `hidden` and `outside_zoom_range` layers are not evaluated at all. Of the
+    /// rest, layers on inline GeoJSON report `no_data_in_view` or `filtered_out`
+    /// (features in view, none drawn); other sources only `nothing_drawn`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self {
+            status: "ok".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Partial result of a command stopped by `cancel`.
+    fn cancelled() -> Self {
+        Self {
+            status: "cancelled".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Header for a single image payload, with its size in pixels. `png_len` is the
+    /// payload byte length whatever the format; it keeps its historical name for
+    /// existing clients.
+    fn image(encoded: &Encoded) -> Self {
+        Self {
+            png_len: Some(encoded.bytes.len()),
+            format: Some(encoded.format.name()),
+            width: Some(encoded.width),
+            height: Some(encoded.height),
+            layout: encoded.layout.clone(),
+            quality: encoded.quality,
+            target_met: encoded.target_met,
+            ..Self::ok()
+        }
+    }
+
+    /// This response with `payload` sent after it.
+    fn with_payload(self, payload: Vec<u8>) -> Self {
+        self.with_payloads(vec![payload])
+    }
+
+    fn with_payloads(self, payloads: Vec<Vec<u8>>) -> Self {
+        Self { payloads, ..self }
+    }
+
+    fn error(code: ErrorCode, error: impl Into<String>) -> Self {
+        Self {
+            status: "error".to_string(),
+            error: Some(error.into()),
+            code: Some(code),
+            ..Default::default()
+        }
+    }
+
+    /// Error for a NaN or infinite camera or bounds value.
+    fn non_finite(error: impl Into<String>) -> Self {
+        Self {
+            error_code: Some("non_finite_parameter"),
+            ..Self::error(ErrorCode::InvalidParameter, error)
+        }
+    }
+
+    fn invalid_camera(error: impl Into<String>) -> Self {
+        Self {
+            error_code: Some("invalid_camera"),
+            ..Self::error(ErrorCode::InvalidParameter, error)
+        }
+    }
+
+    /// Error for a render surface over the backend's texture limit.
+    fn size_too_large(error: impl Into<String>) -> Self {
+        Self {
+            error_code: Some("size_too_large"),
+            ..Self::error(ErrorCode::InvalidParameter, error)
+        }
+    }
+}
+
+/// Settings from the last successful `init`, kept so secondary renderers can be
+/// built with the same size and style.
+#[derive(Debug, Clone, PartialEq)]
+struct InitConfig {
+    width: NonZeroU32,
+    height: NonZeroU32,
+    pixel_ratio: f64,
+    /// Ratio MapLibre Native renders at: `pixel_ratio`, or the next integer when the
+    /// backend can't render fractional ratios and frames are downscaled instead, then
+    /// adjusted for the `render_mode`.
+    render_ratio: f64,
+    render_mode: RenderMode,
+    /// Style as loaded, after `style_rewrites`.
+    style: String,
+    style_rewrites: style_edit::StyleRewrites,
+    surface_reuse: bool,
+    /// Overlays from `set_debug`.
+    debug: DebugFlags,
+}
+
+/// Speed/quality trade-off of renders, set at `init`.
+///
+/// MapLibre Native exposes no draft switch or MSAA setting, so the modes change the
+/// ratio frames are rendered at and resample them to `pixel_ratio`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RenderMode {
+    /// Render at a pixel ratio of at most 1 and scale up: fewer pixels to draw on
+    /// high-DPI output, at the cost of softer lines and text.
+    Draft,
+    #[default]
+    Standard,
+    /// Supersample: render at twice the ratio and scale down, smoothing edges.
+    Final,
+}
+
+impl RenderMode {
+    /// The ratio frames are rendered at for output at `ratio`.
+    fn render_ratio(self, ratio: f64) -> f64 {
+        match self {
+            RenderMode::Draft => ratio.min(1.0),
+            RenderMode::Standard => ratio,
+            RenderMode::Final => ratio * 2.0,
+        }
+    }
+}
+
+/// What `set_feature_state` or `remove_feature_state` does to a feature's state.
+enum FeatureStateChange<'a> {
+    /// Merge these keys into the state.
+    Set(serde_json::Map<String, serde_json::Value>),
+    /// Drop this key, or the whole state.
+    Remove(Option<&'a str>),
+}
+
+/// MapLibre Native debug overlays, set by `set_debug`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct DebugFlags {
+    tile_borders: bool,
+    collision: bool,
+    overdraw: bool,
+}
+
+impl DebugFlags {
+    fn options(self) -> MapDebugOptions {
+        let flags = [
+            (self.tile_borders, MapDebugOptions::TileBorders),
+            (self.collision, MapDebugOptions::Collision),
+            (self.overdraw, MapDebugOptions::Overdraw),
+        ];
+        MapDebugOptions {
+            repr: flags
+                .iter()
+                .filter(|(on, _)| *on)
+                .fold(MapDebugOptions::NoDebug.repr, |repr, (_, flag)| {
+                    repr | flag.repr
+                }),
+        }
+    }
+}
+
+/// A rendered frame at the requested pixel ratio.
+enum Frame {
+    Native(Image),
+    /// Rendered at `render_ratio` and resampled to `pixel_ratio`.
+    Resampled(RgbaImage),
+}
+
+impl Frame {
+    fn new(image: Image, config: Option<&InitConfig>) -> Self {
+        match config {
+            Some(config) if config.render_ratio != config.pixel_ratio => Frame::Resampled(
+                raster::resample(image.as_image(), config.pixel_ratio / config.render_ratio),
+            ),
+            _ => Frame::Native(image),
+        }
+    }
+
+    fn as_image(&self) -> &RgbaImage {
+        match self {
+            Frame::Native(image) => image.as_image(),
+            Frame::Resampled(image) => image,
+        }
+    }
+}
+
+/// A continuously rendering map used for best-effort (deadline) captures, where a
+/// frame can be read back before every tile has loaded.
+struct DeadlineRenderer {
+    renderer: ImageRenderer<Continuous>,
+    idle: Rc<Cell<bool>>,
+    /// Frames drawn so far, for `progress` events.
+    frames: Rc<Cell<u64>>,
+}
+
+/// Interim message sent before the image of a `progress` render.
+#[derive(Serialize)]
+struct LoadingEvent {
+    status: &'static str,
+    elapsed_ms: u64,
+    frames: u64,
+    /// Absent unless the client loads resources (see [`resources::tiles_pending`]).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tiles_pending: Option<usize>,
+}
+
+struct Renderer {
+    renderer: Option<ImageRenderer<Static>>,
+    config: Option<InitConfig>,
+    deadline_renderer: Option<DeadlineRenderer>,
+    renders: u64,
+    surfaces_created: u64,
+    /// Cached result of the fractional pixel ratio probe.
+    fractional_ratios: Option<bool>,
+    /// A render failed on the current surface, which may have left it in a bad state;
+    /// the next render rebuilds it first.
+    tainted: bool,
+}
+
+impl InitConfig {
+    /// Build a static renderer of the session's size and load its style.
+    fn build_renderer(&self) -> Result<ImageRenderer<Static>, Box<dyn std::error::Error>> {
+        let mut renderer = renderer_builder()
+            .with_size(self.width, self.height)
+            .with_pixel_ratio(self.render_ratio as f32)
+            .build_static_renderer();
+        renderer.set_debug_flags(self.debug.options());
+        Renderer::load_style(&mut renderer, &self.style)?;
+        Ok(renderer)
+    }
+
+    /// What a `width` x `height` surface costs at this config's ratios.
+    fn estimate(&self, width: u32, height: u32) -> backend::ResourceEstimate {
+        backend::estimate(width, height, self.pixel_ratio, self.render_ratio)
+    }
+}
+
+/// Whether `style` is a URL rather than inline JSON or a file path.
+fn is_style_url(style: &str) -> bool {
+    style.contains("://") && !style.starts_with('{')
+}
+
+/// A renderer builder using the `init` resource cache, if any.
+fn renderer_builder() -> ImageRendererBuilder {
+    resources::use_loader();
+    match resources::options() {
+        Some(options) => ImageRendererBuilder::new().with_resource_options(options),
+        None => ImageRendererBuilder::new(),
+    }
+}
+
+/// A complete camera. Padding is always set, as MapLibre Native keeps the last padding
+/// it was given.
+fn camera_update(
+    center: [f64; 2],
+    zoom: f64,
+    bearing: f64,
+    pitch: f64,
+    padding: camera::Padding,
+) -> CameraUpdate {
+    CameraUpdate::new()
+        .center(LatLng {
+            lat: center[1],
+            lng: center[0],
+        })
+        .padding(EdgeInsets {
+            top: padding.top,
+            left: padding.left,
+            bottom: padding.bottom,
+            right: padding.right,
+        })
+        .zoom(zoom)
+        .bearing(bearing)
+        .pitch(pitch)
+}
+
+impl Renderer {
+    fn new() -> Self {
+        Self {
+            renderer: None,
+            config: None,
+            deadline_renderer: None,
+            renders: 0,
+            surfaces_created: 0,
+            fractional_ratios: None,
+            tainted: false,
+        }
+    }
+
+    /// Start loading `style` (a URL, inline JSON or a file path); wait on the returned
+    /// request for the result.
+    fn load_style<'a, S>(
+        renderer: &'a mut ImageRenderer<S>,
+        style: &str,
+    ) -> Result<StyleLoadRequest<'a, S>, Box<dyn std::error::Error>> {
+        if is_url(style) {
+            let url = style.parse().map_err(|_| "Invalid style URL")?;
+            Ok(renderer.load_style_from_url(&url))
+        } else if style.starts_with("{") {
+            // Inline styles go straight to MapLibre; nothing is written to disk.
+            Ok(renderer.load_style_from_json_str(style))
+        } else {
+            Ok(renderer.load_style_from_path(style)?)
+        }
+    }
+
+    /// Load `style` into `renderer`, waiting for a remote style URL so a transient
+    /// failure to fetch it (a refused connection or a server error, not a 404 or bad
+    /// JSON) can be retried with backoff per the `init` retry policy. MapLibre Native
+    /// retries tiles itself, but reports a failed style load at once.
+    fn load_style_retrying<S>(
+        renderer: &mut ImageRenderer<S>,
+        style: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !resources::is_remote(style) {
+            Self::load_style(renderer, style)?;
+            return Ok(());
+        }
+        let policy = resources::retry_policy();
+        let mut attempt = 0;
+        loop {
+            match Self::load_style(renderer, style)?.wait() {
+                Ok(()) => return Ok(()),
+                Err(e)
+                    if matches!(e.kind, MapLoadErrorKind::StyleLoad)
+                        && resources::is_transient(&e.message)
+                        && attempt < policy.max_retries =>
+                {
+                    thread::sleep(policy.delay(attempt));
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Load `style` into a throwaway renderer and wait until MapLibre Native has parsed
+    /// it, returning an `error_code` for the kind of failure alongside the message.
+    fn validate_style(style: &str) -> Result<(), (Option<&'static str>, String)> {
+        let size =
+            NonZeroU32::new(PROBE_SIZE).ok_or((None, "probe size must be non-zero".to_string()))?;
+        let mut renderer = renderer_builder()
+            .with_size(size, size)
+            .build_static_renderer();
+        let request = Self::load_style(&mut renderer, style)
+            .map_err(|e| (Some("style_load_error"), e.to_string()))?;
+        request.wait().map_err(|e| {
+            let error_code = match e.kind {
+                MapLoadErrorKind::StyleParse => Some("style_parse_error"),
+                MapLoadErrorKind::StyleLoad => Some("style_load_error"),
+                MapLoadErrorKind::NotFound => Some("style_not_found"),
+                _ => None,
+            };
+            (error_code, e.to_string())
+        })
+    }
+
+    fn init(
+        &mut self,
+        (width, height): (u32, u32),
+        style: &str,
+        pixel_ratio: f64,
+        render_mode: RenderMode,
+        style_rewrites: style_edit::StyleRewrites,
+        surface_reuse: bool,
+    ) -> Result<Option<Vec<style_edit::SizeClamp>>, Box<dyn std::error::Error>> {
+        let width_nz = NonZeroU32::new(width).ok_or("Width must be non-zero")?;
+        let height_nz = NonZeroU32::new(height).ok_or("Height must be non-zero")?;
+        let (style, clamps) = Self::prepare_style(style, &style_rewrites)?;
+        let style = style.as_str();
+
+        let render_ratio = self.render_ratio(pixel_ratio, render_mode)?;
+        let builder = renderer_builder()
+            .with_size(width_nz, height_nz)
+            .with_pixel_ratio(render_ratio as f32);
+
+        let mut renderer = builder.build_static_renderer();
+        Self::load_style_retrying(&mut renderer, style)?;
+
+        self.renderer = Some(renderer);
+        self.surfaces_created += 1;
+        self.deadline_renderer = None;
+        self.tainted = false;
+        self.config = Some(InitConfig {
+            width: width_nz,
+            height: height_nz,
+            pixel_ratio,
+            render_ratio,
+            render_mode,
+            style: style.to_string(),
+            style_rewrites,
+            surface_reuse,
+            debug: DebugFlags::default(),
+        });
+        Ok(clamps)
+    }
+
+    /// Ratio a map of `pixel_ratio` is rendered at: the ratio itself, or the next integer
+    /// when fractional ratios aren't supported, adjusted for `render_mode`.
+    fn render_ratio(
+        &mut self,
+        pixel_ratio: f64,
+        render_mode: RenderMode,
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        let ratio = if pixel_ratio.fract() == 0.0 || self.fractional_ratios_supported()? {
+            pixel_ratio
+        } else {
+            pixel_ratio.ceil()
+        };
+        Ok(render_mode.render_ratio(ratio))
+    }
+
+    /// Whether the linked MapLibre Native renders fractional pixel ratios at their exact
+    /// size, checked once with a tiny probe render.
+    fn fractional_ratios_supported(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        if let Some(supported) = self.fractional_ratios {
+            return Ok(supported);
+        }
+        let size = NonZeroU32::new(PROBE_SIZE).ok_or("probe size must be non-zero")?;
+        let mut probe = ImageRendererBuilder::new()
+            .with_size(size, size)
+            .with_pixel_ratio(1.5f32)
+            .build_static_renderer();
+        Self::load_style(&mut probe, PROBE_STYLE)?;
+        let image = probe.render_static(&camera_update(
+            [0.0, 0.0],
+            0.0,
+            0.0,
+            0.0,
+            camera::Padding::default(),
+        ))?;
+        let expected = PROBE_SIZE * 3 / 2;
+        let supported = image.as_image().dimensions() == (expected, expected);
+        self.fractional_ratios = Some(supported);
+        Ok(supported)
+    }
+
+    /// How a fractional `pixel_ratio` is produced: `native`, `downscale`, or `upscale`
+    /// in `draft` mode. `None` for integer ratios.
+    fn pixel_ratio_path(&self) -> Option<&'static str> {
+        let config = self.config.as_ref()?;
+        (config.pixel_ratio.fract() != 0.0).then_some(
+            if config.render_ratio == config.pixel_ratio {
+                "native"
+            } else if config.render_ratio > config.pixel_ratio {
+                "downscale"
+            } else {
+                "upscale"
+            },
+        )
+    }
+
+    /// Drop the surfaces and their GPU resources, keeping the config to rebuild them
+    /// from. Returns whether there was a surface to drop.
+    fn release(&mut self) -> bool {
+        self.deadline_renderer = None;
+        self.renderer.take().is_some()
+    }
+
+    /// Whether `init` succeeded but the surface has since been released.
+    fn released(&self) -> bool {
+        self.config.is_some() && self.renderer.is_none()
+    }
+
+    /// Drop the static renderer and build a fresh one with the current style, so
+    /// nothing the old surface accumulated can carry over.
+    fn recreate_surface(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        self.renderer = None;
+        let renderer = config.build_renderer()?;
+        self.renderer = Some(renderer);
+        self.surfaces_created += 1;
+        Ok(())
+    }
+
+    /// Apply style rewrites requested at `init`, returning the style to load.
+    fn prepare_style(
+        style: &str,
+        rewrites: &style_edit::StyleRewrites,
+    ) -> Result<(String, Option<Vec<style_edit::SizeClamp>>), String> {
+        if rewrites.is_empty() {
+            return Ok((style.to_string(), None));
+        }
+        let size_caps = &rewrites.size_caps;
+        let mut document = Self::read_style_document(style).map_err(|e| {
+            let rewrite = if !size_caps.is_empty() {
+                "Symbol size caps need"
+            } else if rewrites.background.is_some() {
+                "A background needs"
+            } else if rewrites.language.is_some() {
+                "A language needs"
+            } else {
+                "glyphs and sprite overrides need"
+            };
+            format!("{} the style JSON: {}", rewrite, e)
+        })?;
+        if let Some(background) = &rewrites.background {
+            style_edit::set_background(&mut document, background)?;
+        }
+        style_edit::set_resource_urls(
+            &mut document,
+            rewrites.glyphs.as_ref(),
+            rewrites.sprite.as_ref(),
+        )?;
+        if let Some(language) = &rewrites.language {
+            for layer in document["layers"].as_array_mut().into_iter().flatten() {
+                style_edit::localize_labels(layer, language);
+            }
+        }
+        let clamps =
+            (!size_caps.is_empty()).then(|| style_edit::cap_symbol_sizes(&mut document, size_caps));
+        Ok((document.to_string(), clamps))
+    }
+
+    /// Whether `init` asked for a transparent background, which JPEG can't carry.
+    fn transparent_background(&self) -> bool {
+        self.config.as_ref().is_some_and(|config| {
+            config.style_rewrites.background == Some(style_edit::Background::Transparent)
+        })
+    }
+
+    fn render(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+    ) -> Result<Frame, RenderingError> {
+        self.prepare_surface()?;
+        self.render_current(center, zoom, bearing, pitch, padding)
+    }
+
+    /// Render slippy-map tile `z`/`x`/`y`; see [`camera::tile_view`].
+    fn render_tile(
+        &mut self,
+        tile: [u32; 3],
+    ) -> Result<(Frame, [f64; 2], f64), Box<dyn std::error::Error>> {
+        let size = self.logical_size().ok_or("Renderer not initialized")?;
+        let (center, zoom) = camera::tile_view(tile, size)?;
+        let frame = self.render(center, zoom, 0.0, 0.0, camera::Padding::default())?;
+        Ok((frame, center, zoom))
+    }
+
+    /// Honour `surface_reuse: false` before a render, and replace a surface a failed
+    /// render left behind.
+    fn prepare_surface(&mut self) -> Result<(), RenderingError> {
+        if self.tainted
+            || self
+                .config
+                .as_ref()
+                .is_some_and(|config| !config.surface_reuse)
+        {
+            self.recreate_surface().map_err(|e| {
+                RenderingError::Native(format!("Recreating render surface failed: {}", e))
+            })?;
+            self.tainted = false;
+        }
+        Ok(())
+    }
+
+    /// Render on the current surface as-is.
+    fn render_current(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+    ) -> Result<Frame, RenderingError> {
+        self.renders += 1;
+        let renderer = self
+            .renderer
+            .as_mut()
+            .ok_or(RenderingError::StyleNotSpecified)?;
+
+        let camera = camera_update(center, zoom, bearing, pitch, padding);
+
+        let image = renderer
+            .render_static(&camera)
+            .inspect_err(|_| self.tainted = true)?;
+
+        Ok(Frame::new(image, self.config.as_ref()))
+    }
+
+    /// Render for at most `deadline`, returning whatever has been drawn by then and
+    /// whether the map had finished loading (`complete`).
+    ///
+    /// Static renders only resolve once every tile is in, so this drives a separate
+    /// continuous renderer with the same style and reads its framebuffer back.
+    #[allow(clippy::too_many_arguments)]
+    fn render_best_effort(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+        deadline: Duration,
+        progress: Option<Duration>,
+    ) -> Result<(RgbaImage, bool), Box<dyn std::error::Error>> {
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        if self.deadline_renderer.is_none() {
+            let mut renderer = renderer_builder()
+                .with_size(config.width, config.height)
+                .with_pixel_ratio(config.render_ratio as f32)
+                .build_continuous_renderer();
+            renderer.set_debug_flags(config.debug.options());
+            Self::load_style(&mut renderer, &config.style)?;
+            let idle = Rc::new(Cell::new(false));
+            let flag = Rc::clone(&idle);
+            renderer
+                .map_observer()
+                .set_did_become_idle_callback(move || flag.set(true));
+            let frames = Rc::new(Cell::new(0));
+            let counter = Rc::clone(&frames);
+            renderer
+                .map_observer()
+                .set_finish_rendering_frame_callback(move |_, _| counter.set(counter.get() + 1));
+            self.deadline_renderer = Some(DeadlineRenderer {
+                renderer,
+                idle,
+                frames,
+            });
+        }
+        let deadline_renderer = self
+            .deadline_renderer
+            .as_mut()
+            .ok_or("deadline renderer unavailable")?;
+
+        let started = Instant::now();
+        let run_loop = RunLoopHandle::current();
+        deadline_renderer.idle.set(false);
+        deadline_renderer
+            .renderer
+            .update_camera(&camera_update(center, zoom, bearing, pitch, padding));
+        let first_frame = deadline_renderer.frames.get();
+        let mut last_event = started;
+        while !deadline_renderer.idle.get() && started.elapsed() < deadline {
+            run_loop.tick();
+            deadline_renderer.renderer.render_once();
+            if let Some(interval) = progress {
+                if last_event.elapsed() >= interval {
+                    last_event = Instant::now();
+                    send_message(
+                        &LoadingEvent {
+                            status: "loading",
+                            elapsed_ms: started.elapsed().as_millis() as u64,
+                            frames: deadline_renderer.frames.get() - first_frame,
+                            tiles_pending: resources::tiles_pending(),
+                        },
+                        [],
+                    );
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let complete = deadline_renderer.idle.get();
+
+        let frame = deadline_renderer.renderer.read_still_image();
+        let size = frame.size();
+        let image = RgbaImage::from_raw(size.width, size.height, frame.buffer().to_vec())
+            .ok_or("Invalid image data received from renderer")?;
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        if config.render_ratio != config.pixel_ratio {
+            let factor = config.pixel_ratio / config.render_ratio;
+            return Ok((raster::resample(&image, factor), complete));
+        }
+        Ok((image, complete))
+    }
+
+    /// Parsed style JSON of the session. A URL style is fetched the first time and
+    /// kept in the config as JSON from then on, so edits build on the same document.
+    fn style_document(&mut self) -> Result<serde_json::Value, String> {
+        let config = self.config.as_mut().ok_or("Renderer not initialized")?;
+        if !is_style_url(&config.style) {
+            return Self::read_style_document(&config.style);
+        }
+        let document = Self::fetch_style_document(&config.style)?;
+        config.style = document.to_string();
+        Ok(document)
+    }
+
+    /// Parsed style JSON for any style location, fetching URL styles.
+    fn fetch_style_document(style: &str) -> Result<serde_json::Value, String> {
+        if is_style_url(style) {
+            resources::fetch(style, ResourceKind::Style).and_then(|bytes| {
+                serde_json::from_slice(&bytes).map_err(|e| format!("Invalid style JSON: {}", e))
+            })
+        } else {
+            Self::read_style_document(style)
+        }
+    }
+
+    fn read_style_document(style: &str) -> Result<serde_json::Value, String> {
+        let text = if style.starts_with("{") {
+            style.to_string()
+        } else if style.contains("://") {
+            return Err("style JSON is unavailable for URL styles".to_string());
+        } else {
+            std::fs::read_to_string(style).map_err(|e| format!("Cannot read style: {}", e))?
+        };
+        serde_json::from_str(&text).map_err(|e| format!("Invalid style JSON: {}", e))
+    }
+
+    /// Swap layers on the live renderer for the given layer JSON, keeping their
+    /// position: each is re-added before the layer that follows it in `document`.
+    fn swap_layers(
+        &mut self,
+        document: &serde_json::Value,
+        layers: &[serde_json::Value],
+    ) -> Result<(), String> {
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let order: Vec<&str> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|layer| layer["id"].as_str())
+            .collect();
+        // Parse everything first so an invalid layer leaves the style untouched.
+        let replacements = layers
+            .iter()
+            .map(|layer| {
+                let id = layer["id"].as_str().ok_or("layer has no id")?;
+                AnyLayer::from_json_value(layer)
+                    .map(|replacement| (id, replacement))
+                    .map_err(|e| format!("Invalid layer '{}': {}", id, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for (id, replacement) in replacements {
+            let next = order
+                .iter()
+                .position(|candidate| *candidate == id)
+                .and_then(|index| order.get(index + 1));
+            let mut style = renderer.style();
+            style
+                .remove_layer(id)
+                .ok_or_else(|| format!("layer '{}' not found", id))?;
+            match next {
+                Some(next) => style.add_layer_before(replacement, next),
+                None => style.add_layer(replacement),
+            }
+            .map_err(|e| format!("Cannot update layer '{}': {}", id, e))?;
+        }
+        Ok(())
+    }
+
+    /// Replace the data of GeoJSON sources in the loaded style, keyed by source id.
+    ///
+    /// All data is parsed and every source checked before any is changed. The data
+    /// stays until it is replaced again or the style is reloaded, and is kept in the
+    /// stored style so recreated surfaces and pool workers render it too.
+    fn update_geojson_sources(
+        &mut self,
+        sources: &HashMap<String, serde_json::Value>,
+        max_bytes: u64,
+    ) -> Result<(), String> {
+        let mut document = self.style_document().ok();
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let mut style = renderer.style();
+        let mut updates = Vec::with_capacity(sources.len());
+        for (id, data) in sources {
+            match style.source_mut(id) {
+                Some(SourceRefMut::GeoJson(_)) => {}
+                Some(_) => return Err(format!("source '{}' is not a GeoJSON source", id)),
+                None => {
+                    return Err(format!(
+                        "source '{}' does not exist in the loaded style",
+                        id
+                    ))
+                }
+            }
+            let text = match data {
+                serde_json::Value::String(location) => read_geojson(location, max_bytes)
+                    .map_err(|e| format!("Cannot load GeoJSON for source '{}': {}", id, e))?,
+                data => data.to_string(),
+            };
+            let geojson = GeoJson::from_json_str(&text)
+                .map_err(|e| format!("Invalid GeoJSON for source '{}': {}", id, e))?;
+            if let Some(source) = document
+                .as_mut()
+                .map(|document| &mut document["sources"][id])
+            {
+                source["data"] = serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid GeoJSON for source '{}': {}", id, e))?;
+            }
+            updates.push((id, geojson));
+        }
+        for (id, geojson) in updates {
+            if let Some(SourceRefMut::GeoJson(mut source)) = style.source_mut(id) {
+                source.set_geojson(&geojson);
+            }
+        }
+        if let Some(document) = document {
+            self.store_style(document);
+        }
+        Ok(())
+    }
+
+    /// Drop and re-add the sources `ids` and the layers drawing them, so their data
+    /// and tiles load afresh while every other source keeps its loaded tiles.
+    ///
+    /// GeoJSON `data` URLs are fetched here and inlined; inline data is re-read from
+    /// the style. Remote TileJSON and tile URLs get a new `mlnative_refresh` query
+    /// parameter each time so HTTP caches can't serve stale copies.
+    fn refresh_sources(&mut self, ids: &[String]) -> Result<(), String> {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        let token = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+        let document = self
+            .style_document()
+            .map_err(|e| format!("Source refresh needs the style JSON: {}", e))?;
+        // Prepare everything first so a missing or invalid source leaves the style untouched.
+        let mut refreshes = Vec::new();
+        for id in ids {
+            let mut source = document["sources"]
+                .get(id)
+                .cloned()
+                .ok_or_else(|| format!("source '{}' not found", id))?;
+            if let Some(url) = source["data"].as_str().map(str::to_string) {
+                let bytes = resources::fetch(&url, ResourceKind::Source)?;
+                source["data"] = serde_json::from_slice(&bytes)
+                    .map_err(|e| format!("Invalid GeoJSON from {}: {}", url, e))?;
+            }
+            if let Some(url) = source["url"].as_str() {
+                source["url"] = resources::with_refresh_token(url, token).into();
+            }
+            for tile in source["tiles"].as_array_mut().into_iter().flatten() {
+                if let Some(url) = tile.as_str() {
+                    *tile = resources::with_refresh_token(url, token).into();
+                }
+            }
+            let source = AnySource::from_json_value(id, &source)
+                .map_err(|e| format!("Invalid source '{}': {}", id, e))?;
+            let dependents = Self::source_dependents(&document, id)?;
+            refreshes.push((id, source, dependents));
+        }
+
+        for (id, source, dependents) in refreshes {
+            self.readd_source(id, source, dependents)?;
+        }
+        Ok(())
+    }
+
+    /// Parse the layers of `document` that draw source `id`, each with the id of the
+    /// layer above it in the style, so they can be put back after the source changes.
+    fn source_dependents(
+        document: &serde_json::Value,
+        id: &str,
+    ) -> Result<Vec<(String, AnyLayer, Option<String>)>, String> {
+        let layers: Vec<&serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+        layers
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer["source"] == id)
+            .map(|(index, layer)| {
+                let layer_id = layer["id"].as_str().ok_or("layer has no id")?;
+                let next = layers
+                    .get(index + 1)
+                    .and_then(|next| next["id"].as_str())
+                    .map(str::to_string);
+                AnyLayer::from_json_value(layer)
+                    .map(|parsed| (layer_id.to_string(), parsed, next))
+                    .map_err(|e| format!("Invalid layer '{}': {}", layer_id, e))
+            })
+            .collect()
+    }
+
+    /// Swap source `id` on the live renderer for `source`, taking the layers drawing it
+    /// off first and putting them back in place afterwards.
+    fn readd_source(
+        &mut self,
+        id: &str,
+        source: AnySource,
+        dependents: Vec<(String, AnyLayer, Option<String>)>,
+    ) -> Result<(), String> {
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let mut style = renderer.style();
+        for (layer_id, _, _) in &dependents {
+            style.remove_layer(layer_id);
+        }
+        style.remove_source(id);
+        style
+            .add_source(source)
+            .map_err(|e| format!("Cannot re-add source '{}': {}", id, e))?;
+        // Top-down, so each layer's successor is already back in place.
+        for (layer_id, layer, next) in dependents.into_iter().rev() {
+            match next {
+                Some(next) => style.add_layer_before(layer, &next),
+                None => style.add_layer(layer),
+            }
+            .map_err(|e| format!("Cannot re-add layer '{}': {}", layer_id, e))?;
+        }
+        Ok(())
+    }
+
+    /// Add a GeoJSON source to the live style and the stored one, or with `replace`
+    /// swap out a source of that id, keeping the layers that draw it. `data` is
+    /// GeoJSON, or a URL or path loaded as for `render_batch` views.
+    fn add_geojson_source(
+        &mut self,
+        id: &str,
+        data: &serde_json::Value,
+        replace: bool,
+        max_bytes: u64,
+    ) -> Result<(), String> {
+        let mut document = self
+            .style_document()
+            .map_err(|e| format!("Source edits need the style JSON: {}", e))?;
+        let exists = document["sources"].get(id).is_some();
+        if exists && !replace {
+            return Err(format!(
+                "source '{}' already exists; set replace to overwrite it",
+                id
+            ));
+        }
+        let data = match data {
+            serde_json::Value::String(location) => {
+                let text = read_geojson(location, max_bytes)
+                    .map_err(|e| format!("Cannot load GeoJSON for source '{}': {}", id, e))?;
+                serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid GeoJSON for source '{}': {}", id, e))?
+            }
+            data => data.clone(),
+        };
+        let definition = serde_json::json!({"type": "geojson", "data": data});
+        let source = AnySource::from_json_value(id, &definition)
+            .map_err(|e| format!("Invalid source '{}': {}", id, e))?;
+        if exists {
+            let dependents = Self::source_dependents(&document, id)?;
+            self.readd_source(id, source, dependents)?;
+        } else {
+            let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+            renderer
+                .style()
+                .add_source(source)
+                .map_err(|e| format!("Cannot add source '{}': {}", id, e))?;
+        }
+        let sources = document["sources"]
+            .as_object_mut()
+            .ok_or("style has no sources object")?;
+        sources.insert(id.to_string(), definition);
+        self.store_style(document);
+        Ok(())
+    }
+
+    /// Remove a source no layer draws from the live style and the stored one.
+    fn remove_source(&mut self, id: &str) -> Result<(), String> {
+        let mut document = self
+            .style_document()
+            .map_err(|e| format!("Source edits need the style JSON: {}", e))?;
+        let removed = document["sources"]
+            .as_object_mut()
+            .and_then(|sources| sources.remove(id));
+        if removed.is_none() {
+            return Err(format!("source '{}' not found", id));
+        }
+        let users: Vec<&str> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|layer| layer["source"] == id)
+            .filter_map(|layer| layer["id"].as_str())
+            .collect();
+        if !users.is_empty() {
+            return Err(format!(
+                "source '{}' is drawn by layers {}; remove them first",
+                id,
+                users.join(", ")
+            ));
+        }
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        renderer.style().remove_source(id);
+        self.store_style(document);
+        Ok(())
+    }
+
+    /// Insert a layer into the live style and the stored one, below the layer
+    /// `before` or on top of the others.
+    fn add_layer(&mut self, layer: &serde_json::Value, before: Option<&str>) -> Result<(), String> {
+        let mut document = self
+            .style_document()
+            .map_err(|e| format!("Layer edits need the style JSON: {}", e))?;
+        let id = layer["id"].as_str().ok_or("layer has no id")?;
+        let layers = document["layers"]
+            .as_array()
+            .ok_or("style has no layers array")?;
+        if layers.iter().any(|existing| existing["id"] == id) {
+            return Err(format!("layer '{}' already exists", id));
+        }
+        if let Some(source) = layer.get("source") {
+            let source = source.as_str().ok_or("layer source must be a source id")?;
+            if document["sources"].get(source).is_none() {
+                return Err(format!("source '{}' not found", source));
+            }
+        }
+        let position = match before {
+            Some(before) => layers
+                .iter()
+                .position(|existing| existing["id"] == before)
+                .ok_or_else(|| format!("layer '{}' not found", before))?,
+            None => layers.len(),
+        };
+        let parsed = AnyLayer::from_json_value(layer)
+            .map_err(|e| format!("Invalid layer '{}': {}", id, e))?;
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let mut style = renderer.style();
+        match before {
+            Some(before) => style.add_layer_before(parsed, before),
+            None => style.add_layer(parsed),
+        }
+        .map_err(|e| format!("Cannot add layer '{}': {}", id, e))?;
+        if let Some(layers) = document["layers"].as_array_mut() {
+            layers.insert(position, layer.clone());
+        }
+        self.store_style(document);
+        Ok(())
+    }
+
+    /// Remove a layer from the live style and the stored one.
+    fn remove_layer(&mut self, id: &str) -> Result<(), String> {
+        let mut document = self
+            .style_document()
+            .map_err(|e| format!("Layer edits need the style JSON: {}", e))?;
+        let layers = document["layers"]
+            .as_array_mut()
+            .ok_or("style has no layers array")?;
+        let position = layers
+            .iter()
+            .position(|layer| layer["id"] == id)
+            .ok_or_else(|| format!("layer '{}' not found", id))?;
+        layers.remove(position);
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        renderer.style().remove_layer(id);
+        self.store_style(document);
+        Ok(())
+    }
+
+    /// Keep `document` as the session's style after a live edit, so renderers built
+    /// later load it too.
+    fn store_style(&mut self, document: serde_json::Value) {
+        if let Some(config) = self.config.as_mut() {
+            config.style = document.to_string();
+        }
+        self.deadline_renderer = None;
+    }
+
+    /// Apply layer edits all-or-nothing, natively and in the stored style so
+    /// recreated surfaces and secondary renderers pick them up.
+    ///
+    /// Every edit is made on a copy of the style JSON first and each touched layer is
+    /// swapped once, so several edits to one layer cost a single invalidation. On
+    /// failure the index of the offending operation is returned and nothing changes.
+    fn apply_operations(
+        &mut self,
+        operations: &[style_edit::Operation],
+    ) -> Result<(), (Option<usize>, String)> {
+        let original = self
+            .style_document()
+            .map_err(|e| (None, format!("Layer edits need the style JSON: {}", e)))?;
+        let mut document = original.clone();
+        let mut touched: Vec<String> = Vec::new();
+        for (index, operation) in operations.iter().enumerate() {
+            let id = operation.layer();
+            let layer = document["layers"]
+                .as_array_mut()
+                .and_then(|layers| layers.iter_mut().find(|layer| layer["id"] == id))
+                .ok_or_else(|| (Some(index), format!("layer '{}' not found", id)))?;
+            operation.apply(layer).map_err(|e| (Some(index), e))?;
+            // Catch invalid values here so they can be attributed to an operation.
+            AnyLayer::from_json_value(layer)
+                .map_err(|e| (Some(index), format!("Invalid layer '{}': {}", id, e)))?;
+            if !touched.iter().any(|touched| touched == id) {
+                touched.push(id.to_string());
+            }
+        }
+        self.commit_layers(&original, document, &touched)
+            .map_err(|e| (None, e))
+    }
+
+    /// Swap the `touched` layers of an edited copy of the style onto the live renderer
+    /// and store the copy, or put `original` back if a swap fails.
+    fn commit_layers(
+        &mut self,
+        original: &serde_json::Value,
+        document: serde_json::Value,
+        touched: &[String],
+    ) -> Result<(), String> {
+        let layers_of = |document: &serde_json::Value| -> Vec<serde_json::Value> {
+            document["layers"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|layer| touched.iter().any(|id| layer["id"] == id.as_str()))
+                .cloned()
+                .collect()
+        };
+        if let Err(e) = self.swap_layers(&document, &layers_of(&document)) {
+            let _ = self.swap_layers(original, &layers_of(original));
+            return Err(e);
+        }
+        self.store_style(document);
+        Ok(())
+    }
+
+    /// Set (`Some`) or remove (`None`) feature state for one feature, or with `id`
+    /// `None` remove it for every feature of the source. Removing with a `key` drops
+    /// only that key. Returns how many layers react to the state.
+    ///
+    /// MapLibre Native's feature-state API isn't exposed, so the state is written into
+    /// the style instead: each `["feature-state", key]` in a layer of the source
+    /// becomes a lookup of the feature's id in a table of every feature's state (see
+    /// `style_edit::set_feature_states`), and the layers are swapped as for layer edits.
+    fn set_feature_state(
+        &mut self,
+        source: &str,
+        source_layer: Option<&str>,
+        id: Option<&serde_json::Value>,
+        change: FeatureStateChange,
+    ) -> Result<usize, String> {
+        let original = self
+            .style_document()
+            .map_err(|e| format!("Feature state needs the style JSON: {}", e))?;
+        let source_type = original["sources"][source]["type"]
+            .as_str()
+            .ok_or_else(|| format!("source '{}' not found", source))?;
+        if source_type == "vector" && source_layer.is_none() {
+            return Err(format!(
+                "source_layer is required for vector source '{}'",
+                source
+            ));
+        }
+        let id = id.map(style_edit::feature_state_id).transpose()?;
+        let mut document = original.clone();
+        let layers: Vec<&mut serde_json::Value> = document["layers"]
+            .as_array_mut()
+            .into_iter()
+            .flatten()
+            .filter(|layer| {
+                layer["source"] == source && layer["source-layer"].as_str() == source_layer
+            })
+            .collect();
+        let mut table = layers
+            .iter()
+            .find_map(|layer| style_edit::feature_states(layer))
+            .unwrap_or_default();
+        match (change, id) {
+            (FeatureStateChange::Set(state), Some(id)) => {
+                let entry = table
+                    .entry(id)
+                    .or_insert_with(|| serde_json::Value::Object(Default::default()));
+                if let Some(entry) = entry.as_object_mut() {
+                    entry.extend(state);
+                }
+            }
+            (FeatureStateChange::Set(_), None) => return Err("id is required".to_string()),
+            (FeatureStateChange::Remove(key), Some(id)) => match key {
+                Some(key) => {
+                    if let Some(entry) = table.get_mut(&id).and_then(|e| e.as_object_mut()) {
+                        entry.remove(key);
+                    }
+                }
+                None => {
+                    table.remove(&id);
+                }
+            },
+            (FeatureStateChange::Remove(key), None) => match key {
+                Some(key) => {
+                    for entry in table.values_mut().filter_map(|e| e.as_object_mut()) {
+                        entry.remove(key);
+                    }
+                }
+                None => table.clear(),
+            },
+        }
+        let touched: Vec<String> = layers
+            .into_iter()
+            .filter_map(|layer| {
+                style_edit::set_feature_states(layer, &table)
+                    .then(|| layer["id"].as_str().map(String::from))
+                    .flatten()
+            })
+            .collect();
+        self.commit_layers(&original, document, &touched)?;
+        Ok(touched.len())
+    }
+
+    /// Render `view` with its `operations` applied, then revert them so they affect
+    /// this render only. Failures of an operation carry its index.
+    fn render_with_operations(&mut self, view: &View) -> Result<Frame, (Option<usize>, String)> {
+        let render = |renderer: &mut Self| {
+            renderer
+                .render(
+                    view.center,
+                    view.zoom,
+                    view.bearing,
+                    view.pitch,
+                    view.padding.unwrap_or_default(),
+                )
+                .map_err(|e| (None, e.to_string()))
+        };
+        if view.operations.is_empty() {
+            return render(self);
+        }
+        let original = self
+            .style_document()
+            .map_err(|e| (None, format!("Layer edits need the style JSON: {}", e)))?;
+        let saved_style = self.config.as_ref().map(|config| config.style.clone());
+        self.apply_operations(&view.operations)?;
+        let frame = render(self);
+
+        // Revert even if the render failed, so the edits never leak into the next view.
+        let touched: Vec<serde_json::Value> = original["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|layer| view.operations.iter().any(|op| layer["id"] == op.layer()))
+            .cloned()
+            .collect();
+        self.swap_layers(&original, &touched)
+            .map_err(|e| (None, format!("Reverting layer edits failed: {}", e)))?;
+        if let (Some(config), Some(style)) = (self.config.as_mut(), saved_style) {
+            config.style = style;
+        }
+        self.deadline_renderer = None;
+        frame
+    }
+
+    /// Render with every layer except `overlays` faded by `factor`, then restore.
+    #[allow(clippy::too_many_arguments)]
+    fn render_dimmed(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+        factor: f64,
+        overlays: &[String],
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        if !(0.0..=1.0).contains(&factor) {
+            return Err("dim_basemap must be between 0 and 1".into());
+        }
+        let document = self
+            .style_document()
+            .map_err(|e| format!("dim_basemap needs the style JSON: {}", e))?;
+        let dimmed: Vec<serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|layer| {
+                layer["id"]
+                    .as_str()
+                    .is_some_and(|id| !overlays.iter().any(|overlay| overlay == id))
+            })
+            .cloned()
+            .map(|mut layer| {
+                style_edit::scale_opacity(&mut layer, factor);
+                layer
+            })
+            .collect();
+        self.prepare_surface()?;
+        self.render_with_layers(&document, &dimmed, center, zoom, bearing, pitch, padding)
+    }
+
+    /// Render with symbol labels in `language`, then restore the style's labels.
+    fn render_localized(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+        language: &style_edit::Language,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        let document = self
+            .style_document()
+            .map_err(|e| format!("language needs the style JSON: {}", e))?;
+        let localized: Vec<serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|layer| {
+                let mut layer = layer.clone();
+                style_edit::localize_labels(&mut layer, language).then_some(layer)
+            })
+            .collect();
+        self.prepare_surface()?;
+        self.render_with_layers(&document, &localized, center, zoom, bearing, pitch, padding)
+    }
+
+    /// Render with a temporary source and layers added on top of the style, removing
+    /// them again afterwards.
+    fn render_with_overlay(
+        &mut self,
+        source_id: &str,
+        source: &serde_json::Value,
+        layers: &[serde_json::Value],
+        view: &View,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        self.prepare_surface()?;
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let source = AnySource::from_json_value(source_id, source)
+            .map_err(|e| format!("Invalid source '{}': {}", source_id, e))?;
+        let layers = layers
+            .iter()
+            .map(|layer| {
+                AnyLayer::from_json_value(layer).map_err(|e| format!("Invalid layer: {}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut style = renderer.style();
+        style
+            .add_source(source)
+            .map_err(|e| format!("Cannot add source '{}': {}", source_id, e))?;
+        let mut added = Vec::new();
+        let mut result = Ok(());
+        for layer in layers {
+            match style.add_layer(layer) {
+                Ok(id) => added.push(id),
+                Err(e) => {
+                    result = Err(format!("Cannot add layer: {}", e));
+                    break;
+                }
+            }
+        }
+        let result = match result {
+            Ok(()) => self
+                .render_current(
+                    view.center,
+                    view.zoom,
+                    view.bearing,
+                    view.pitch,
+                    view.padding.unwrap_or_default(),
+                )
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        // Tear down even if adding or rendering failed part-way through.
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let mut style = renderer.style();
+        for id in added {
+            style.remove_layer(id.as_str());
+        }
+        style.remove_source(source_id);
+        Ok(result?)
+    }
+
+    /// Render with each patterned fill layer drawn as its pattern instead of its
+    /// colour. The style is rendered in slices split at the patterned layers, and each
+    /// pattern is painted through a mask render of its layer between the slices, so
+    /// layers above a pattern still cover it.
+    fn render_fill_patterns(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+        patterns: &[pattern::FillPattern],
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let pixel_ratio = self
+            .config
+            .as_ref()
+            .ok_or("Renderer not initialized")?
+            .pixel_ratio;
+        let document = self
+            .style_document()
+            .map_err(|e| format!("fill_patterns need the style JSON: {}", e))?;
+        let layers: Vec<&serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+        let mut patterned = patterns
+            .iter()
+            .map(|pattern| {
+                pattern.validate()?;
+                let index = layers
+                    .iter()
+                    .position(|layer| layer["id"] == pattern.layer.as_str())
+                    .ok_or_else(|| format!("layer '{}' not found", pattern.layer))?;
+                Ok((index, pattern, pattern.mask_layer(layers[index])?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        patterned.sort_by_key(|(index, ..)| *index);
+        if patterned.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err("each layer can only have one pattern".into());
+        }
+        // Every layer outside `keep`, hidden.
+        let only = |keep: std::ops::Range<usize>| -> Vec<serde_json::Value> {
+            layers
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !keep.contains(index))
+                .map(|(_, layer)| {
+                    let mut layer = (*layer).clone();
+                    let _ = style_edit::set_visibility(&mut layer, false);
+                    layer
+                })
+                .collect()
+        };
+
+        self.prepare_surface()?;
+        let mut image = self
+            .render_with_layers(
+                &document,
+                &only(0..patterned[0].0),
+                center,
+                zoom,
+                bearing,
+                pitch,
+                padding,
+            )?
+            .as_image()
+            .clone();
+        for (slot, (index, pattern, mask_layer)) in patterned.iter().enumerate() {
+            let mut replacements = only(*index..*index + 1);
+            replacements.push(mask_layer.clone());
+            let mask = self.render_with_layers(
+                &document,
+                &replacements,
+                center,
+                zoom,
+                bearing,
+                pitch,
+                padding,
+            )?;
+            compose::over(&mut image, &pattern.paint(mask.as_image(), pixel_ratio));
+
+            let next = patterned
+                .get(slot + 1)
+                .map_or(layers.len(), |(next, ..)| *next);
+            if index + 1 < next {
+                let slice = self.render_with_layers(
+                    &document,
+                    &only(index + 1..next),
+                    center,
+                    zoom,
+                    bearing,
+                    pitch,
+                    padding,
+                )?;
+                compose::over(&mut image, slice.as_image());
+            }
+        }
+        Ok(image)
+    }
+
+    /// Render with label text drawn without antialiasing: the map is rendered once
+    /// with symbol layers hidden and once with only symbol layers, whose alpha is
+    /// hardened before they are stamped on top.
+    fn render_crisp_text(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let document = self
+            .style_document()
+            .map_err(|e| format!("text_aa 'none' needs the style JSON: {}", e))?;
+        let layers: Vec<&serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+        let hide = |symbols: bool| -> Vec<serde_json::Value> {
+            layers
+                .iter()
+                .filter(|layer| (layer["type"] == "symbol") == symbols)
+                .map(|layer| {
+                    let mut layer = (*layer).clone();
+                    let _ = style_edit::set_visibility(&mut layer, false);
+                    layer
+                })
+                .collect()
+        };
+        let (without_text, text_only) = (hide(true), hide(false));
+        self.prepare_surface()?;
+        let mut base = self
+            .render_with_layers(
+                &document,
+                &without_text,
+                center,
+                zoom,
+                bearing,
+                pitch,
+                padding,
+            )?
+            .as_image()
+            .clone();
+        let mut text = self
+            .render_with_layers(&document, &text_only, center, zoom, bearing, pitch, padding)?
+            .as_image()
+            .clone();
+        raster::harden_alpha(&mut text);
+        compose::stamp(&mut base, &text);
+        Ok(base)
+    }
+
+    /// Render on the current surface with `replacements` swapped in for the layers of
+    /// the same id, restoring the originals from `document` afterwards.
+    #[allow(clippy::too_many_arguments)]
+    fn render_with_layers(
+        &mut self,
+        document: &serde_json::Value,
+        replacements: &[serde_json::Value],
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        let originals: Vec<serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|layer| replacements.iter().any(|r| r["id"] == layer["id"]))
+            .cloned()
+            .collect();
+        let result = match self.swap_layers(document, replacements) {
+            Ok(()) => self
+                .render_current(center, zoom, bearing, pitch, padding)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        // Restore even if swapping failed part-way through.
+        self.swap_layers(document, &originals)?;
+        Ok(result?)
+    }
+
+    /// Whether each layer draws any pixels at this camera, found by rendering the
+    /// layers one at a time with every other layer hidden.
+    fn drawn_layers(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+    ) -> Result<Vec<DrawnLayer>, Box<dyn std::error::Error>> {
+        let size = self.logical_size().ok_or("Renderer not initialized")?;
+        let document = self
+            .style_document()
+            .map_err(|e| format!("report_drawn_layers needs the style JSON: {}", e))?;
+        let viewport = utfgrid::Viewport {
+            center,
+            zoom,
+            bearing,
+            size,
+        };
+        let (counts, _) = utfgrid::feature_counts(&document, None, &viewport, usize::MAX);
+        let originals: Vec<serde_json::Value> =
+            document["layers"].as_array().cloned().unwrap_or_default();
+        let hide = |layer: &serde_json::Value| {
+            let mut hidden = layer.clone();
+            let _ = style_edit::set_visibility(&mut hidden, false);
+            hidden
+        };
+
+        let evaluate = |renderer: &mut Self| -> Result<Vec<DrawnLayer>, String> {
+            renderer.swap_layers(&document, &originals.iter().map(hide).collect::<Vec<_>>())?;
+            let mut drawn_layers = Vec::with_capacity(originals.len());
+            for layer in &originals {
+                let id = layer["id"].as_str().unwrap_or_default().to_string();
+                let outside_zoom_range = layer["minzoom"].as_f64().is_some_and(|min| zoom < min)
+                    || layer["maxzoom"].as_f64().is_some_and(|max| zoom >= max);
+                let skipped = if layer["layout"]["visibility"] == "none" {
+                    Some("hidden")
+                } else if outside_zoom_range {
+                    Some("outside_zoom_range")
+                } else {
+                    None
+                };
+                if skipped.is_some() {
+                    drawn_layers.push(DrawnLayer {
+                        layer: id,
+                        drawn: false,
+                        reason: skipped,
+                    });
+                    continue;
+                }
+                renderer.swap_layers(&document, std::slice::from_ref(layer))?;
+                let frame = renderer
+                    .render_current(center, zoom, bearing, pitch, padding)
+                    .map_err(|e| e.to_string());
+                renderer.swap_layers(&document, &[hide(layer)])?;
+                let drawn = frame?.as_image().pixels().any(|pixel| pixel[3] > 0);
+                let count = counts
+                    .iter()
+                    .find(|count| count.layer == id)
+                    .and_then(|count| count.count);
+                drawn_layers.push(DrawnLayer {
+                    layer: id,
+                    drawn,
+                    reason: match (drawn, count) {
+                        (true, _) => None,
+                        (false, Some(0)) => Some("no_data_in_view"),
+                        (false, Some(_)) => Some("filtered_out"),
+                        (false, None) => Some("nothing_drawn"),
+                    },
+                });
+            }
+            Ok(drawn_layers)
+        };
+        let result = evaluate(self);
+        // Restore every layer even if evaluation stopped part-way through.
+        self.swap_layers(&document, &originals)?;
+        Ok(result?)
+    }
+
+    /// Render with the `scale` sprite variant in place of the one MapLibre picked for
+    /// the pixel ratio, then put the usual variant back.
+    fn render_with_sprite_scale(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        pitch: f64,
+        padding: camera::Padding,
+        scale: u32,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        if !matches!(scale, 1 | 2) {
+            return Err("force_sprite_scale must be 1 or 2".into());
+        }
+        let selected = self.sprite_scale().ok_or("Renderer not initialized")?;
+        if scale == selected {
+            return Ok(self.render(center, zoom, bearing, pitch, padding)?);
+        }
+        let document = self
+            .style_document()
+            .map_err(|e| format!("force_sprite_scale needs the style JSON: {}", e))?;
+        let forced = sprite::load_icons(&document["sprite"], scale)?;
+        let usual = sprite::load_icons(&document["sprite"], selected)?;
+
+        self.prepare_surface()?;
+        self.add_sprite_icons(&forced)?;
+        let result = self.render_current(center, zoom, bearing, pitch, padding);
+        self.add_sprite_icons(&usual)?;
+        Ok(result?)
+    }
+
+    /// Sprite variant MapLibre loads for the render ratio: @2x above 1, else @1x.
+    fn sprite_scale(&self) -> Option<u32> {
+        let config = self.config.as_ref()?;
+        Some(if config.render_ratio > 1.0 { 2 } else { 1 })
+    }
+
+    /// Refetch the style's sprite and replace its images in place, keeping the
+    /// loaded style and tiles. Returns the number of images updated.
+    fn reload_sprites(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let scale = self.sprite_scale().ok_or("Renderer not initialized")?;
+        let document = self
+            .style_document()
+            .map_err(|e| format!("sprite reload needs the style JSON: {}", e))?;
+        let icons = sprite::load_icons(&document["sprite"], scale)?;
+        self.add_sprite_icons(&icons)?;
+        Ok(icons.len())
+    }
+
+    /// Add (or replace) style images.
+    fn add_sprite_icons(&mut self, icons: &[sprite::SpriteIcon]) -> Result<(), String> {
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let mut style = renderer.style();
+        for icon in icons {
+            style
+                .add_image(&icon.id, &icon.image, icon.pixel_ratio, icon.sdf)
+                .map_err(|e| format!("Cannot add sprite image '{}': {}", icon.id, e))?;
+        }
+        Ok(())
+    }
+
+    /// Render `view` and a legend panel for it, docked into one image. Also returns
+    /// whether legend entries were left out for lack of room.
+    fn render_with_legend(
+        &mut self,
+        view: &View,
+        options: &legend::Options,
+    ) -> Result<(RgbaImage, bool), Box<dyn std::error::Error>> {
+        let document = self
+            .style_document()
+            .map_err(|e| format!("render_with_legend needs the style JSON: {}", e))?;
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        let width = NonZeroU32::new(options.width).ok_or("legend width must be non-zero")?;
+        let (panel_style, truncated) = legend::style(
+            &document,
+            options,
+            [options.width as f64, config.height.get() as f64],
+        )?;
+
+        let mut panel_renderer = renderer_builder()
+            .with_size(width, config.height)
+            .with_pixel_ratio(config.render_ratio as f32)
+            .build_static_renderer();
+        Self::load_style(&mut panel_renderer, &panel_style.to_string())?;
+        let panel = panel_renderer.render_static(&camera_update(
+            [0.0, 0.0],
+            legend::LAYOUT_ZOOM,
+            0.0,
+            0.0,
+            camera::Padding::default(),
+        ))?;
+        let panel = Frame::new(panel, Some(config));
+
+        let map = self.render(
+            view.center,
+            view.zoom,
+            view.bearing,
+            view.pitch,
+            camera::Padding::default(),
+        )?;
+        let left = options.position == legend::Position::Left;
+        Ok((
+            compose::dock(map.as_image(), panel.as_image(), left),
+            truncated,
+        ))
+    }
+
+    /// Render `center` at each of `zooms`, in the order given, and arrange the
+    /// frames in a grid `columns` wide with each cell labelled with its zoom.
+    ///
+    /// The renders share one renderer, so zooms within one whole zoom level reuse
+    /// the tiles already in its cache.
+    fn render_zoom_ladder(
+        &mut self,
+        center: [f64; 2],
+        zooms: &[f64],
+        bearing: f64,
+        pitch: f64,
+        columns: usize,
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let pixel_ratio = self.pixel_ratio().ok_or("Renderer not initialized")?;
+        let scale = (2.0 * pixel_ratio).round().max(1.0) as u32;
+        let mut cells = Vec::with_capacity(zooms.len());
+        for &zoom in zooms {
+            let mut cell = self
+                .render(center, zoom, bearing, pitch, camera::Padding::default())?
+                .as_image()
+                .clone();
+            compose::label(
+                &mut cell,
+                &format!("z{}", zoom),
+                [scale * 2, scale * 2],
+                scale,
+            );
+            cells.push(cell);
+        }
+        Ok(compose::grid(&cells, columns, scale * 2))
+    }
+
+    /// Render `view` showing only `layers` of `document`; everything else, including
+    /// backgrounds, is hidden so the rest of the image is transparent.
+    fn render_layer_group(
+        &mut self,
+        document: &serde_json::Value,
+        layers: &[String],
+        view: &View,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        let all: Vec<&serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+        if let Some(missing) = layers
+            .iter()
+            .find(|id| !all.iter().any(|layer| layer["id"] == id.as_str()))
+        {
+            return Err(format!("layer '{}' not found", missing).into());
+        }
+        let hidden: Vec<serde_json::Value> = all
+            .into_iter()
+            .filter(|layer| !layers.iter().any(|id| layer["id"] == id.as_str()))
+            .cloned()
+            .map(|mut layer| {
+                let _ = style_edit::set_visibility(&mut layer, false);
+                layer
+            })
+            .collect();
+        self.render_with_layers(
+            document,
+            &hidden,
+            view.center,
+            view.zoom,
+            view.bearing,
+            view.pitch,
+            camera::Padding::default(),
+        )
+    }
+
+    /// Whether `layer` draws anything at `point` when viewed at `zoom`.
+    ///
+    /// Renders a tiny map centred on the point with every other layer hidden and
+    /// checks the centre pixel, so it works for any source type. Properties are only
+    /// available for layers backed by inline GeoJSON.
+    fn point_in_layer(
+        &mut self,
+        point: [f64; 2],
+        layer: &str,
+        zoom: f64,
+    ) -> Result<(bool, Option<Properties>), Box<dyn std::error::Error>> {
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        let (init_width, init_height) = (config.width.get(), config.height.get());
+        let document = self
+            .style_document()
+            .map_err(|e| format!("point_in_layer needs the style JSON: {}", e))?;
+        if !document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|candidate| candidate["id"] == layer)
+        {
+            return Err(format!("layer '{}' not found", layer).into());
+        }
+        let hidden: Vec<serde_json::Value> = document["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|candidate| candidate["id"] != layer)
+            .cloned()
+            .map(|mut candidate| {
+                let _ = style_edit::set_visibility(&mut candidate, false);
+                candidate
+            })
+            .collect();
+
+        self.prepare_surface()?;
+        self.resize(PROBE_SIZE, PROBE_SIZE)?;
+        let result = self.render_with_layers(
+            &document,
+            &hidden,
+            point,
+            zoom,
+            0.0,
+            0.0,
+            camera::Padding::default(),
+        );
+        self.resize(init_width, init_height)?;
+        let image = result?;
+        let image = image.as_image();
+        let hit = image.get_pixel(image.width() / 2, image.height() / 2)[3] > 0;
+
+        let properties = if hit {
+            utfgrid::properties_at(&document, layer, point, zoom, PROBE_SIZE as f64 / 2.0)
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+        Ok((hit, properties))
+    }
+
+    /// Render a window of the zoom level's global pixel space, centered on `center`
+    /// (see [`camera::window_center`]), temporarily resizing the map to the window.
+    fn render_window(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        width: u32,
+        height: u32,
+    ) -> Result<Frame, Box<dyn std::error::Error>> {
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        let (init_width, init_height) = (config.width.get(), config.height.get());
+        self.prepare_surface()?;
+        self.resize(width, height)?;
+        let result = self.render_current(center, zoom, 0.0, 0.0, camera::Padding::default());
+        self.resize(init_width, init_height)?;
+        Ok(result?)
+    }
+
+    /// Configs rendering a `size_mm` print at each of `dpis`: the print's logical
+    /// size at [`REFERENCE_DPI`], at a pixel ratio of `dpi` / [`REFERENCE_DPI`].
+    /// Their surface sizes are left to the caller to check.
+    fn dpi_variants(
+        &mut self,
+        camera: &View,
+        size_mm: [f64; 2],
+        dpis: &[u32],
+    ) -> Result<Vec<(u32, InitConfig)>, String> {
+        let config = self.config.clone().ok_or("Renderer not initialized")?;
+        if !size_mm.iter().all(|&mm| mm.is_finite() && mm > 0.0) {
+            return Err("width_mm and height_mm must be positive".to_string());
+        }
+        if dpis.is_empty() || dpis.contains(&0) {
+            return Err("dpis must be a non-empty list of positive DPIs".to_string());
+        }
+        if let Some(dpi) = dpis
+            .iter()
+            .enumerate()
+            .find_map(|(i, dpi)| dpis[..i].contains(dpi).then_some(dpi))
+        {
+            return Err(format!("dpi {} is listed twice", dpi));
+        }
+        let [width, height] = size_mm.map(|mm| (mm / 25.4 * REFERENCE_DPI).round().max(1.0) as u32);
+        let (width_nz, height_nz) = NonZeroU32::new(width)
+            .zip(NonZeroU32::new(height))
+            .ok_or("width and height must be non-zero")?;
+        if let Some(padding) = camera.padding {
+            padding.check([width, height].map(f64::from))?;
+        }
+        let mut variants = Vec::with_capacity(dpis.len());
+        for &dpi in dpis {
+            let pixel_ratio = f64::from(dpi) / REFERENCE_DPI;
+            let render_ratio = self
+                .render_ratio(pixel_ratio, config.render_mode)
+                .map_err(|e| e.to_string())?;
+            variants.push((
+                dpi,
+                InitConfig {
+                    width: width_nz,
+                    height: height_nz,
+                    pixel_ratio,
+                    render_ratio,
+                    ..config.clone()
+                },
+            ));
+        }
+        Ok(variants)
+    }
+
+    /// Render `camera` with each of the [`Renderer::dpi_variants`] configs, as PNGs
+    /// with a `pHYs` chunk.
+    ///
+    /// Each variant is rendered by its own renderer, built from its config like a pool
+    /// worker's. `camera.zoom` is kept, so the extent is the same at every DPI and
+    /// lines and symbols keep their printed size.
+    fn render_dpi_set(
+        &mut self,
+        camera: &View,
+        variants: &[(u32, InitConfig)],
+    ) -> Result<Vec<DpiVariant>, Box<dyn std::error::Error>> {
+        let mut images = Vec::with_capacity(variants.len());
+        for (dpi, variant) in variants {
+            self.renders += 1;
+            let mut renderer = variant.build_renderer()?;
+            let image = renderer.render_static(&camera_update(
+                camera.center,
+                camera.zoom,
+                camera.bearing,
+                camera.pitch,
+                camera.padding.unwrap_or_default(),
+            ))?;
+            let frame = Frame::new(image, Some(variant));
+            let image = frame.as_image();
+            let bytes = raster::encode_png_with_dpi(image, f64::from(*dpi))?;
+            images.push(DpiVariant {
+                dpi: *dpi,
+                width: image.width(),
+                height: image.height(),
+                png_len: bytes.len(),
+                png: bytes,
+            });
+        }
+        Ok(images)
+    }
+
+    /// Render an unpitched `width`×`height` view as tiles of at most
+    /// `tile_size` logical pixels, each rendered with [`LARGE_RENDER_OVERLAP`] to
+    /// spare, and stitch them into one image. Returns it with the tile grid's columns
+    /// and rows.
+    fn render_large(
+        &mut self,
+        center: [f64; 2],
+        zoom: f64,
+        bearing: f64,
+        [width, height]: [u32; 2],
+        tile_size: Option<u32>,
+    ) -> Result<(RgbaImage, [u32; 2]), Box<dyn std::error::Error>> {
+        let config = self.config.as_ref().ok_or("Renderer not initialized")?;
+        let (init_width, init_height) = (config.width.get(), config.height.get());
+        let ratio = config.pixel_ratio;
+        let largest = (f64::from(backend::MAX_TEXTURE_SIZE) / config.render_ratio) as u32;
+        let max_tile = largest.saturating_sub(2 * LARGE_RENDER_OVERLAP);
+        let tile = tile_size.unwrap_or(max_tile);
+        if tile == 0 || tile > max_tile {
+            return Err(format!(
+                "tile_size must be between 1 and {} at this pixel ratio and render mode",
+                max_tile
+            )
+            .into());
+        }
+        let tiles = camera::large_tiles(
+            center,
+            zoom,
+            bearing,
+            [width, height],
+            tile,
+            LARGE_RENDER_OVERLAP,
+        )?;
+        let device = |logical: u32| (f64::from(logical) * ratio).round() as u32;
+        let mut image = RgbaImage::new(device(width), device(height));
+        let inset = device(LARGE_RENDER_OVERLAP);
+
+        self.prepare_surface()?;
+        let mut render_tiles = || -> Result<(), Box<dyn std::error::Error>> {
+            for tile in &tiles {
+                self.resize(
+                    tile.size[0] + 2 * LARGE_RENDER_OVERLAP,
+                    tile.size[1] + 2 * LARGE_RENDER_OVERLAP,
+                )?;
+                let frame = self.render_current(
+                    tile.center,
+                    zoom,
+                    bearing,
+                    0.0,
+                    camera::Padding::default(),
+                )?;
+                let [left, top] = tile.origin.map(device);
+                let [right, bottom] =
+                    [0, 1].map(|axis| device(tile.origin[axis] + tile.size[axis]));
+                let piece = image::imageops::crop_imm(
+                    frame.as_image(),
+                    inset,
+                    inset,
+                    right - left,
+                    bottom - top,
+                );
+                image::imageops::replace(&mut image, &*piece, i64::from(left), i64::from(top));
+            }
+            Ok(())
+        };
+        let result = render_tiles();
+        self.resize(init_width, init_height)?;
+        result?;
+        Ok((image, [width.div_ceil(tile), height.div_ceil(tile)]))
+    }
+
+    /// Change the session's map size without rebuilding the renderer or reloading the
+    /// style.
+    fn set_size(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let (Some(width_nz), Some(height_nz)) = (NonZeroU32::new(width), NonZeroU32::new(height))
+        else {
+            return Err("width and height must be non-zero".to_string());
+        };
+        self.resize(width, height)?;
+        if let Some(config) = self.config.as_mut() {
+            config.width = width_nz;
+            config.height = height_nz;
+        }
+        self.deadline_renderer = None;
+        Ok(())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        self.renderer
+            .as_mut()
+            .ok_or("Renderer not initialized")?
+            .set_map_size(Size { width, height });
+        Ok(())
+    }
+
+    /// Init map size in logical pixels.
+    fn logical_size(&self) -> Option<[f64; 2]> {
+        self.config
+            .as_ref()
+            .map(|config| [config.width.get() as f64, config.height.get() as f64])
+    }
+
+    /// Check a `width` x `height` surface against the texture limit, at the session's
+    /// ratios. Passes before `init`, which checks its own size.
+    fn check_size(&self, width: u32, height: u32) -> Result<(), String> {
+        self.config
+            .as_ref()
+            .map_or(Ok(()), |config| config.estimate(width, height).check())
+    }
+
+    fn pixel_ratio(&self) -> Option<f64> {
+        self.config.as_ref().map(|config| config.pixel_ratio)
+    }
+
+    /// Check `render_loupe` parameters: the loupe must fit in the map.
+    fn check_loupe(&self, magnification: f64, radius: f64) -> Result<(), String> {
+        let [width, height] = self.logical_size().ok_or("Renderer not initialized")?;
+        if !(magnification.is_finite() && magnification > 0.0) {
+            return Err("magnification must be a positive number".to_string());
+        }
+        let max_radius = width.min(height) / 2.0;
+        if !(radius > 0.0 && radius <= max_radius) {
+            return Err(format!("radius must be in (0, {}]", max_radius));
+        }
+        Ok(())
+    }
+
+    /// Render the base view, then a deeper view of `focus`, and composite the
+    /// latter as a bordered circular loupe. See [`Renderer::check_loupe`].
+    fn render_loupe(
+        &mut self,
+        camera: &View,
+        focus: [f64; 2],
+        magnification: f64,
+        radius: f64,
+        position: [f64; 2],
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let scale = self.pixel_ratio().ok_or("Renderer not initialized")?;
+
+        let mut base = self
+            .render(
+                camera.center,
+                camera.zoom,
+                camera.bearing,
+                camera.pitch,
+                camera::Padding::default(),
+            )?
+            .as_image()
+            .clone();
+        let detail = self.render(
+            focus,
+            camera.zoom + magnification.log2(),
+            camera.bearing,
+            camera.pitch,
+            camera::Padding::default(),
+        )?;
+        compose::loupe(
+            &mut base,
+            detail.as_image(),
+            [position[0] * scale, position[1] * scale],
+            radius * scale,
+            LOUPE_BORDER_WIDTH * scale,
+        );
+        Ok(base)
+    }
+
+    /// Render `camera` and draw graticule lines `interval` degrees apart over it.
+    fn render_with_graticule(
+        &mut self,
+        camera: &View,
+        interval: f64,
+        color: [u8; 4],
+        label: bool,
+    ) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+        let size = self.logical_size().ok_or("Renderer not initialized")?;
+        let mut image = self
+            .render(
+                camera.center,
+                camera.zoom,
+                camera.bearing,
+                camera.pitch,
+                camera::Padding::default(),
+            )?
+            .as_image()
+            .clone();
+        let values = camera::CameraValues {
+            center: camera.center,
+            zoom: camera.zoom,
+            bearing: camera.bearing,
+            pitch: camera.pitch,
+        };
+        graticule::draw(&mut image, values, size, interval, color, label);
+        Ok(image)
+    }
+
+    fn reload_style(
+        &mut self,
+        style: &str,
+    ) -> Result<Option<Vec<style_edit::SizeClamp>>, Box<dyn std::error::Error>> {
+        let renderer = self.renderer.as_mut().ok_or("Renderer not initialized")?;
+        let config = self.config.as_mut().ok_or("Renderer not initialized")?;
+        let (style, clamps) = Self::prepare_style(style, &config.style_rewrites)?;
+
+        if let Err(e) = Self::load_style_retrying(renderer, &style) {
+            // Keep rendering the previous style rather than one that didn't load.
+            let _ = Self::load_style(renderer, &config.style);
+            return Err(e);
+        }
+        self.deadline_renderer = None;
+        config.style = style;
+        Ok(clamps)
+    }
+
+    /// Draw `debug` overlays in every later render, until the next `init`.
+    fn set_debug(&mut self, debug: DebugFlags) -> Result<(), String> {
+        let config = self.config.as_mut().ok_or("Renderer not initialized")?;
+        config.debug = debug;
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.set_debug_flags(debug.options());
+        }
+        self.deadline_renderer = None;
+        Ok(())
+    }
+
+    /// Override a source's tile size in the style document and reload it in place.
+    /// Runtime changes such as feature state are lost, as with `reload_style`.
+    fn set_source_tile_size(
+        &mut self,
+        source: &str,
+        tile_size: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut document = self
+            .style_document()
+            .map_err(|e| format!("Source edits need the style JSON: {}", e))?;
+        style_edit::set_source_tile_size(&mut document, source, tile_size)?;
+        self.reload_style(&document.to_string())?;
+        Ok(())
+    }
+}
+
+fn encode_png(img_buffer: &RgbaImage) -> Result<Vec<u8>, String> {
+    let mut png_bytes: Vec<u8> = Vec::new();
+    img_buffer
+        .write_to(
+            &mut std::io::Cursor::new(&mut png_bytes),
+            image::ImageFormat::Png,
+        )
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(png_bytes)
+}
+
+/// Histogram of `image` as `format` stores it: JPEG's RGB flattened over white, and
+/// RGBA for everything else (`png8` before quantization).
+fn output_histogram(
+    image: &RgbaImage,
+    format: OutputFormat,
+    buckets: u32,
+) -> Result<raster::Histogram, String> {
+    match format {
+        OutputFormat::Jpeg => raster::histogram(&raster::flatten_over_white(image), 3, buckets),
+        _ => raster::histogram(image.as_raw(), 4, buckets),
+    }
+}
+
+/// An encoded image payload plus the header fields that describe it.
+#[derive(Clone)]
+struct Encoded {
+    bytes: Vec<u8>,
+    format: OutputFormat,
+    width: u32,
+    height: u32,
+    layout: Option<raster::RawLayout>,
+    /// JPEG quality, and whether the `target_bytes` budget was met.
+    quality: Option<u8>,
+    target_met: Option<bool>,
+}
+
+fn encode_output(
+    image: &RgbaImage,
+    format: OutputFormat,
+    dither: raster::Dither,
+) -> Result<Encoded, String> {
+    encode_image(image, format, dither, MAX_JPEG_QUALITY)
+}
+
+/// Encode `image` as `format`, with `quality` (1-100) for JPEG.
+fn encode_image(
+    image: &RgbaImage,
+    format: OutputFormat,
+    dither: raster::Dither,
+    quality: u8,
+) -> Result<Encoded, String> {
+    let (width, height) = image.dimensions();
+    let (bytes, layout) = match format {
+        OutputFormat::Png => (encode_png(image)?, None),
+        OutputFormat::Png8 => (raster::encode_png8(image, dither)?, None),
+        OutputFormat::Rgba => {
+            let (bytes, layout) = raster::raw_rgba(image);
+            (bytes, Some(layout))
+        }
+        OutputFormat::Jpeg => (raster::encode_jpeg(image, quality)?, None),
+        OutputFormat::Webp => (raster::encode_webp(image)?, None),
+    };
+    Ok(Encoded {
+        bytes,
+        format,
+        width,
+        height,
+        layout,
+        quality: (format == OutputFormat::Jpeg).then_some(quality),
+        target_met: None,
+    })
+}
+
+/// Encode `image` within `target` bytes: the requested format first, then a PNG8
+/// fallback for PNG, then a binary search for the highest JPEG quality that fits,
+/// down to `min_quality`. If nothing fits, the `min_quality` JPEG is returned.
+fn encode_to_target(
+    image: &RgbaImage,
+    format: OutputFormat,
+    dither: raster::Dither,
+    target: usize,
+    min_quality: u8,
+) -> Result<Encoded, String> {
+    let fits = |encoded: Encoded| {
+        let met = encoded.bytes.len() <= target;
+        met.then_some(Encoded {
+            target_met: Some(true),
+            ..encoded
+        })
+    };
+    if let Some(encoded) = fits(encode_output(image, format, dither)?) {
+        return Ok(encoded);
+    }
+    if format == OutputFormat::Png {
+        if let Some(encoded) = fits(encode_output(image, OutputFormat::Png8, dither)?) {
+            return Ok(encoded);
+        }
+    }
+
+    let min_quality = min_quality.clamp(1, MAX_JPEG_QUALITY);
+    let (mut low, mut high) = (min_quality, MAX_JPEG_QUALITY);
+    let mut best: Option<(u8, Vec<u8>)> = None;
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let bytes = raster::encode_jpeg(image, quality)?;
+        if bytes.len() <= target {
+            best = Some((quality, bytes));
+            low = quality + 1;
+        } else if quality == min_quality {
+            break;
+        } else {
+            high = quality - 1;
+        }
+    }
+    let (quality, bytes, target_met) = match best {
+        Some((quality, bytes)) => (quality, bytes, true),
+        None => (min_quality, raster::encode_jpeg(image, min_quality)?, false),
+    };
+    Ok(Encoded {
+        bytes,
+        format: OutputFormat::Jpeg,
+        width: image.width(),
+        height: image.height(),
+        layout: None,
+        quality: Some(quality),
+        target_met: Some(target_met),
+    })
+}
+
+/// Render each `[z, x, y]` tile as a `tile_size` window and append it to `archive`.
+fn write_tile_archive<W: Write>(
+    renderer: &mut Renderer,
+    tiles: &[[u32; 3]],
+    tile_size: u32,
+    format: OutputFormat,
+    dither: raster::Dither,
+    archive: &mut archive::Archive<W>,
+) -> Result<(), String> {
+    // Tile zoom z spans tile_size * 2^z pixels; MapLibre's zoom 0 world is 512 wide.
+    let zoom_offset = (f64::from(tile_size) / camera::WORLD_TILE_SIZE).log2();
+    for &[z, x, y] in tiles {
+        let name = format!("{}/{}/{}", z, x, y);
+        let origin = [
+            f64::from(x) * f64::from(tile_size),
+            f64::from(y) * f64::from(tile_size),
+        ];
+        let zoom = f64::from(z) + zoom_offset;
+        let encoded = camera::window_center(zoom, origin, [tile_size, tile_size])
+            .and_then(|center| {
+                renderer
+                    .render_window(center, zoom, tile_size, tile_size)
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(|e| format!("tile {}: {}", name, e))
+            .and_then(|image| encode_output(image.as_image(), format, dither))?;
+        archive
+            .append(&format!("{}.{}", name, format.extension()), &encoded.bytes)
+            .map_err(|e| format!("Writing tile {} failed: {}", name, e))?;
+    }
+    Ok(())
+}
+
+thread_local! {
+    /// `id` of the command being handled on this thread, echoed in every message it
+    /// sends. Only ever set on the main thread, so loader requests never carry one.
+    static REQUEST_ID: RefCell<Option<serde_json::Value>> = const { RefCell::new(None) };
+}
+
+/// Fields any command may carry: an `id` for client-side correlation, and the
+/// `session_id` of the session it applies to.
+#[derive(Default, Deserialize)]
+struct Envelope {
+    /// The command's name, for logs.
+    #[serde(default)]
+    cmd: String,
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+fn envelope(line: &str) -> Envelope {
+    serde_json::from_str(line).unwrap_or_default()
+}
+
+/// A command of a `pipeline`, read as the reader thread reads a line.
+fn pipeline_input(raw: serde_json::Value) -> Input {
+    match serde_json::from_value(raw.clone()) {
+        Ok(Command::Render(RenderArgs {
+            preset: Some(_), ..
+        })) => Input::PresetRender(raw),
+        Ok(cmd) => Input::Command(
+            Box::new(cmd),
+            serde_json::from_value(raw).unwrap_or_default(),
+        ),
+        Err(e) => Input::Invalid(
+            format!("Invalid command: {}", e),
+            raw.get("id").filter(|id| !id.is_null()).cloned(),
+        ),
+    }
+}
+
+/// Answer a finished `pipeline` with its commands' messages, their payloads following
+/// in order.
+fn send_pipeline_response(run: pipeline::Run) {
+    REQUEST_ID.with(|current| *current.borrow_mut() = run.id.clone());
+    let messages = run.finish();
+    let response = Response {
+        png_lengths: Some(
+            messages
+                .iter()
+                .map(|message| message.payload.len())
+                .collect(),
+        ),
+        responses: Some(
+            messages
+                .iter()
+                .map(|message| message.header.clone())
+                .collect(),
+        ),
+        ..Response::ok()
+    };
+    send_response(
+        &response.with_payloads(
+            messages
+                .into_iter()
+                .map(|message| message.payload)
+                .collect(),
+        ),
+    );
+}
+
+/// Write one JSON line plus any raw payloads while holding the stdout lock, so
+/// messages from the resource loader thread never interleave with responses.
+fn send_message<'a, T: Serialize>(message: &T, payloads: impl IntoIterator<Item = &'a [u8]>) {
+    let mut header = serde_json::to_string(message)
+        .unwrap_or_else(|_| r#"{"status":"error","error":"JSON encode failed"}"#.to_string());
+    if let Some(id) = REQUEST_ID.with(|id| id.borrow().clone()) {
+        // Lead with the id, keeping the message's own field order after it.
+        if header.starts_with('{') && header.len() > 2 {
+            header.replace_range(..1, &format!("{{\"id\":{},", id));
+        }
+    }
+    if pipeline::capturing() {
+        pipeline::capture(&header, payloads);
+        return;
+    }
+    if shutdown::output_closed() {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let written = writeln!(stdout, "{}", header)
+        .and_then(|()| {
+            payloads
+                .into_iter()
+                .try_for_each(|payload| stdout.write_all(payload))
+        })
+        .and_then(|()| stdout.flush());
+    if let Err(e) = written {
+        shutdown::output_failed(&e);
+    }
+}
+
+fn send_response(resp: &Response) {
+    send_message(resp, resp.payloads.iter().map(Vec::as_slice));
+}
+
+/// fsync each file and the directories holding them, so the files and their
+/// directory entries survive a crash.
+fn sync_files(files: Vec<PathBuf>) -> Result<(), String> {
+    let mut directories = Vec::new();
+    for path in &files {
+        std::fs::File::open(path)
+            .and_then(|file| file.sync_all())
+            .map_err(|e| format!("Cannot sync {}: {}", path.display(), e))?;
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if !directories.contains(&directory) {
+            directories.push(directory);
+        }
+    }
+    for directory in directories {
+        // Directories can't be opened for syncing on every platform; the file data
+        // itself is already durable.
+        if let Ok(handle) = std::fs::File::open(&directory) {
+            let _ = handle.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Daemon-wide settings taken from the command line.
+#[derive(Debug)]
+struct Options {
+    client_resources: bool,
+    /// Fail `http(s)://` resource requests immediately.
+    offline: bool,
+    /// Byte separating commands on stdin.
+    delimiter: u8,
+    /// Longest command accepted on stdin, in bytes, so one runaway line can't exhaust
+    /// memory.
+    max_command_bytes: usize,
+    /// Render a probe and exit; see [`backend::check`].
+    probe_backend: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            client_resources: false,
+            offline: false,
+            delimiter: b'\n',
+            max_command_bytes: DEFAULT_MAX_COMMAND_BYTES,
+            probe_backend: false,
+        }
+    }
+}
+
+/// Default `--max-command-bytes`: room for large inline styles and GeoJSON.
+const DEFAULT_MAX_COMMAND_BYTES: usize = 8 * 1024 * 1024;
+
+fn parse_max_command_bytes(value: Option<&str>) -> Result<usize, String> {
+    match value.map(str::parse) {
+        Some(Ok(bytes)) if bytes > 0 => Ok(bytes),
+        _ => Err(format!(
+            "--max-command-bytes expects a positive number of bytes, got {:?}",
+            value
+        )),
+    }
+}
+
+fn parse_delimiter(value: Option<&str>) -> Result<u8, String> {
+    match value {
+        Some("nul") | Some("\\0") => Ok(0),
+        Some("newline") | Some("\\n") => Ok(b'\n'),
+        Some(other) if other.len() == 1 && other.is_ascii() => Ok(other.as_bytes()[0]),
+        other => Err(format!(
+            "--delimiter expects 'nul', 'newline' or a single ASCII character, got {:?}",
+            other
+        )),
+    }
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--resource-loader" => match args.next().as_deref() {
+                    Some("client") => options.client_resources = true,
+                    Some("default") => options.client_resources = false,
+                    other => {
+                        return Err(format!(
+                            "--resource-loader expects 'client' or 'default', got {:?}",
+                            other
+                        ))
+                    }
+                },
+                "--offline" => options.offline = true,
+                "--probe-backend" => options.probe_backend = true,
+                "--delimiter" => options.delimiter = parse_delimiter(args.next().as_deref())?,
+                "--max-command-bytes" => {
+                    options.max_command_bytes = parse_max_command_bytes(args.next().as_deref())?
+                }
+                other => return Err(format!("Unknown argument: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Output options a cached render was encoded with.
+type RenderOptions = (
+    OutputFormat,
+    u8,
+    raster::Dither,
+    raster::AlphaMode,
+    raster::TextAntialiasing,
+    bool,
+    raster::ColorEncoding,
+);
+
+/// `render_cache_size` entry key: the camera's center, zoom, bearing and pitch as bits,
+/// and the output options.
+type RenderKey = ([u64; 5], RenderOptions);
+
+/// A plain render of a bookmark or camera, reused until the style or sources change.
+struct CachedRender {
+    options: RenderOptions,
+    encoded: Encoded,
+    empty: Option<bool>,
+}
+
+/// A renderer and the settings `init` gave it. Commands pick one by `session_id`;
+/// those without one share an unnamed session.
+struct Session {
+    renderer: Renderer,
+    empty_tiles: Option<raster::EmptyTilePolicy>,
+    tile_ttl: Option<u64>,
+    output_format: OutputFormat,
+    output_quality: u8,
+    batch_workers: usize,
+    batch_pool: Option<pool::Pool>,
+    text_aa: raster::TextAntialiasing,
+    dense_tile_behavior: resources::DenseTileBehavior,
+    /// The `init` feature cap and retry policy, installed for each of the session's
+    /// commands as the loader reads them from process-wide state.
+    tile_guard: Option<resources::TileGuard>,
+    retry: resources::RetryPolicy,
+    zoom_range: camera::ZoomRange,
+    max_geojson_bytes: u64,
+    render_timeout: Option<u64>,
+    camera_defaults: camera::Defaults,
+    bookmarks: HashMap<String, View>,
+    bookmark_renders: HashMap<String, CachedRender>,
+    render_cache: cache::Lru<RenderKey, CachedRender>,
+    attribution: Option<String>,
+    attribution_position: compose::Anchor,
+    /// The `init` watermark and its decoded logo.
+    watermark: Option<(compose::Watermark, RgbaImage)>,
+    snap_zoom: bool,
+    max_large_render_pixels: u64,
+    idle_timeout: Option<Duration>,
+    /// When the session's last command finished.
+    last_used: Instant,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            renderer: Renderer::new(),
+            empty_tiles: None,
+            tile_ttl: None,
+            output_format: OutputFormat::default(),
+            output_quality: MAX_JPEG_QUALITY,
+            batch_workers: default_batch_workers(),
+            batch_pool: None,
+            text_aa: raster::TextAntialiasing::default(),
+            dense_tile_behavior: resources::DenseTileBehavior::default(),
+            tile_guard: None,
+            retry: resources::RetryPolicy::DEFAULT,
+            zoom_range: camera::ZoomRange::default(),
+            max_geojson_bytes: default_max_geojson_bytes(),
+            render_timeout: None,
+            camera_defaults: camera::Defaults::default(),
+            bookmarks: HashMap::new(),
+            bookmark_renders: HashMap::new(),
+            render_cache: cache::Lru::new(0),
+            attribution: None,
+            attribution_position: compose::Anchor::default(),
+            watermark: None,
+            snap_zoom: false,
+            max_large_render_pixels: DEFAULT_MAX_LARGE_RENDER_PIXELS,
+            idle_timeout: None,
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Free the renderer and worker pool once the session has idled past its
+    /// `idle_timeout_ms`. Returns whether anything was freed.
+    fn release_if_idle(&mut self, now: Instant) -> bool {
+        if self
+            .idle_timeout
+            .is_none_or(|timeout| now.duration_since(self.last_used) < timeout)
+        {
+            return false;
+        }
+        self.batch_pool = None;
+        self.renderer.release()
+    }
+}
+
+enum Input {
+    /// A command and its `id` and `session_id`.
+    Command(Box<Command>, Envelope),
+    /// A `render` naming a preset, as raw JSON to resolve against the presets set so far.
+    PresetRender(serde_json::Value),
+    Invalid(String, Option<serde_json::Value>),
+    /// A frame rejected unparsed, with its `error_code`: invalid UTF-8 or over
+    /// `--max-command-bytes`. The reader has already moved past it.
+    Rejected(&'static str, String),
+}
+
+/// Read the next frame up to and including `delimiter` into `frame`, buffering at most
+/// `limit` bytes of it. Returns the bytes consumed and whether the frame fit; the rest
+/// of a frame over the limit is read and discarded, so the next read starts at the
+/// following command.
+fn read_frame(
+    input: &mut impl BufRead,
+    delimiter: u8,
+    limit: usize,
+    frame: &mut Vec<u8>,
+) -> io::Result<(u64, bool)> {
+    let mut read = 0u64;
+    let mut fits = true;
+    loop {
+        let buf = match input.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf.is_empty() {
+            return Ok((read, fits));
+        }
+        let (chunk, done) = match buf.iter().position(|&byte| byte == delimiter) {
+            Some(end) => (&buf[..=end], true),
+            None => (buf, false),
+        };
+        // The delimiter doesn't count towards the limit.
+        let content = chunk.len() - usize::from(done);
+        if fits && frame.len() + content > limit {
+            fits = false;
+            frame.clear();
+        }
+        if fits {
+            frame.extend_from_slice(chunk);
+        }
+        let consumed = chunk.len();
+        input.consume(consumed);
+        read += consumed as u64;
+        if done {
+            return Ok((read, fits));
+        }
+    }
+}
+
+/// Read the `len` raw bytes that follow a command, or skip them without buffering
+/// when they are over `limit`, returning `None`.
+fn read_payload(input: &mut impl Read, len: usize, limit: usize) -> io::Result<Option<Vec<u8>>> {
+    if len > limit {
+        let skipped = io::copy(&mut input.take(len as u64), &mut io::sink())?;
+        if skipped < len as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(None);
+    }
+    let mut data = vec![0; len];
+    input.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+/// Read commands on a dedicated thread so `resource_response` replies can be
+/// delivered while the main thread is blocked inside a render.
+fn spawn_reader(
+    tx: mpsc::Sender<Input>,
+    delimiter: u8,
+    max_command_bytes: usize,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut frame = Vec::new();
+        // Byte offset of the current frame in the input stream, for error reports.
+        let mut offset = 0u64;
+        loop {
+            frame.clear();
+            let frame_start = offset;
+            match read_frame(&mut stdin, delimiter, max_command_bytes, &mut frame) {
+                Ok((0, _)) | Err(_) => break,
+                Ok((read, true)) => offset += read,
+                Ok((read, false)) => {
+                    offset += read;
+                    log::warn!(
+                        "skipped a {}-byte command at byte offset {}",
+                        read,
+                        frame_start
+                    );
+                    let error = format!(
+                        "Command too large: {} bytes at byte {} exceed --max-command-bytes ({})",
+                        read, frame_start, max_command_bytes
+                    );
+                    if tx
+                        .send(Input::Rejected("command_too_large", error))
+                        .is_err()
+                    {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            if frame.last() == Some(&delimiter) {
+                frame.pop();
+            }
+
+            let line = match std::str::from_utf8(&frame) {
+                Ok(line) => line,
+                Err(e) => {
+                    let position = frame_start + e.valid_up_to() as u64;
+                    log::warn!("invalid UTF-8 in command at byte offset {}", position);
+                    let error = format!("Invalid command: not valid UTF-8 at byte {}", position);
+                    if tx.send(Input::Rejected("invalid_encoding", error)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let input = match serde_json::from_str(line) {
+                Ok(Command::ResourceResponse(ResourceResponseArgs {
+                    id,
+                    status_code,
+                    data_len,
+                })) => {
+                    let payload_start = offset;
+                    let Ok(data) = read_payload(&mut stdin, data_len, max_command_bytes) else {
+                        break;
+                    };
+                    offset += data_len as u64;
+                    let Some(data) = data else {
+                        // Fail the request rather than leave it waiting for the timeout.
+                        resources::deliver(
+                            id,
+                            resources::ResourceReply {
+                                status_code: 413,
+                                data: Vec::new(),
+                            },
+                        );
+                        let error = format!(
+                            "Resource response too large: {} bytes at byte {} exceed \
+                             --max-command-bytes ({})",
+                            data_len, payload_start, max_command_bytes
+                        );
+                        if tx
+                            .send(Input::Rejected("command_too_large", error))
+                            .is_err()
+                        {
+                            break;
+                        }
+                        continue;
+                    };
+                    resources::deliver(id, resources::ResourceReply { status_code, data });
+                    continue;
+                }
+                Ok(Command::Render(RenderArgs {
+                    preset: Some(_), ..
+                })) => match serde_json::from_str(line) {
+                    Ok(raw) => Input::PresetRender(raw),
+                    Err(e) => Input::Invalid(format!("Invalid command: {}", e), envelope(line).id),
+                },
+                Ok(Command::Cancel) => {
+                    // Flag it now: the main loop may be busy with the command to cancel.
+                    cancel::request();
+                    Input::Command(Box::new(Command::Cancel), envelope(line))
+                }
+                Ok(mut cmd) => {
+                    // Read a style sent after the line now, so the next frame starts
+                    // after it whatever `init` makes of it.
+                    if let Command::Init(InitArgs {
+                        style,
+                        style_len: Some(len),
+                        ..
+                    }) = &mut cmd
+                    {
+                        let blob_start = offset;
+                        let Ok(blob) = read_payload(&mut stdin, *len, max_command_bytes) else {
+                            break;
+                        };
+                        offset += *len as u64;
+                        let Some(blob) = blob else {
+                            let error = format!(
+                                "Style too large: {} bytes at byte {} exceed \
+                                 --max-command-bytes ({})",
+                                len, blob_start, max_command_bytes
+                            );
+                            if tx
+                                .send(Input::Rejected("command_too_large", error))
+                                .is_err()
+                            {
+                                break;
+                            }
+                            continue;
+                        };
+                        if style == STYLE_FROM_STDIN {
+                            match String::from_utf8(blob) {
+                                Ok(text) => *style = text,
+                                Err(e) => {
+                                    let position = blob_start + e.utf8_error().valid_up_to() as u64;
+                                    log::warn!(
+                                        "invalid UTF-8 in style at byte offset {}",
+                                        position
+                                    );
+                                    let error = format!(
+                                        "Invalid command: style is not valid UTF-8 at byte {}",
+                                        position
+                                    );
+                                    if tx.send(Input::Rejected("invalid_encoding", error)).is_err()
+                                    {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            }
+                            cmd.take_style_len();
+                        }
+                    }
+                    Input::Command(Box::new(cmd), envelope(line))
+                }
+                Err(e) => Input::Invalid(format!("Invalid command: {}", e), envelope(line).id),
+            };
+
+            if tx.send(input).is_err() {
+                break;
+            }
+        }
+        resources::fail_pending();
+    })
+}
+
+/// Whether the daemon keeps reading commands after one.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Everything the command loop keeps between commands.
+struct Daemon {
+    sessions: HashMap<String, Session>,
+    /// The backend probed at startup, or why none can render.
+    backend_check: Result<(backend::Backend, Option<String>), String>,
+    /// Set by the first `init` that finds a usable backend.
+    render_backend: Option<backend::Backend>,
+    /// Files written by commands but not yet fsynced, for `flush`.
+    unsynced_files: Vec<PathBuf>,
+    presets: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+    pipeline: Option<pipeline::Run>,
+}
+
+impl Daemon {
+    fn new(backend_check: Result<(backend::Backend, Option<String>), String>) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            backend_check,
+            render_backend: None,
+            unsynced_files: Vec::new(),
+            presets: HashMap::new(),
+            pipeline: None,
+        }
+    }
+
+    /// The next command of a running `pipeline`, answering it once none are left.
+    fn pipeline_input(&mut self) -> Option<Input> {
+        match self.pipeline.as_mut().and_then(pipeline::Run::next_command) {
+            Some(raw) => Some(pipeline_input(raw)),
+            None => {
+                if let Some(run) = self.pipeline.take() {
+                    send_pipeline_response(run);
+                }
+                None
+            }
+        }
+    }
+
+    /// Release the renderers of sessions idle past their `idle_timeout_ms`.
+    fn release_idle_sessions(&mut self) {
+        let now = Instant::now();
+        for (name, session) in &mut self.sessions {
+            if session.release_if_idle(now) {
+                log::info!(
+                    "released the idle renderer of {}",
+                    if name.is_empty() {
+                        "the unnamed session".to_string()
+                    } else {
+                        format!("session '{}'", name)
+                    }
+                );
+            }
+        }
+    }
+
+    /// Resolve a line read from stdin (or a pipeline) into a command and handle it.
+    fn handle_input(&mut self, input: Input) -> Flow {
+        let (id, session_id) = match &input {
+            Input::Command(_, envelope) => (envelope.id.clone(), envelope.session_id.clone()),
+            Input::Invalid(_, id) => (id.clone(), None),
+            Input::PresetRender(raw) => (
+                raw.get("id").filter(|id| !id.is_null()).cloned(),
+                raw.get("session_id")
+                    .and_then(|session_id| session_id.as_str())
+                    .map(String::from),
+            ),
+            Input::Rejected(..) => (None, None),
+        };
+        match &input {
+            Input::Command(_, envelope) => log::info!(
+                "{} received{}{}",
+                envelope.cmd,
+                id.as_ref()
+                    .map_or(String::new(), |id| format!(" with id {}", id)),
+                session_id
+                    .as_ref()
+                    .map_or(String::new(), |name| format!(" for session '{}'", name))
+            ),
+            Input::PresetRender(_) => log::info!("render with preset received"),
+            Input::Invalid(error, _) => log::warn!("{}", error),
+            Input::Rejected(..) => {}
+        }
+        REQUEST_ID.with(|current| *current.borrow_mut() = id);
+        let cmd = match input {
+            Input::Command(cmd, _) => *cmd,
+            Input::PresetRender(raw) => {
+                let resolved = preset::resolve(&self.presets, &raw).and_then(|resolved| {
+                    serde_json::from_value(resolved)
+                        .map_err(|e| format!("invalid options after merging: {}", e))
+                });
+                match resolved {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        send_response(&Response::error(
+                            ErrorCode::InvalidCommand,
+                            format!("Render failed: {}", e),
+                        ));
+                        return Flow::Continue;
+                    }
+                }
+            }
+            Input::Invalid(error, _) => {
+                send_response(&Response::error(ErrorCode::InvalidCommand, error));
+                return Flow::Continue;
+            }
+            Input::Rejected(error_code, error) => {
+                send_response(&Response {
+                    error_code: Some(error_code),
+                    ..Response::error(ErrorCode::InvalidCommand, error)
+                });
+                return Flow::Continue;
+            }
+        };
+
+        let name = session_id.clone().unwrap_or_default();
+        let flow = self.handle_command(cmd, session_id);
+        // Idle time counts from the end of the session's last command.
+        if let Some(session) = self.sessions.get_mut(&name) {
+            session.last_used = Instant::now();
+        }
+        flow
+    }
+
+    /// Handle one command in the session `session_id` names, sending its response.
+    fn handle_command(&mut self, cmd: Command, session_id: Option<String>) -> Flow {
+        let quit = matches!(cmd, Command::Quit);
+        match self.respond(cmd, session_id) {
+            Some(response) => send_response(&response),
+            None if quit => return Flow::Quit,
+            None => {}
+        }
+        Flow::Continue
+    }
+
+    /// The response to one command in the session `session_id` names, or `None` for
+    /// commands answered later (`pipeline`) or not at all.
+    fn respond(&mut self, cmd: Command, session_id: Option<String>) -> Option<Response> {
+        let Daemon {
+            sessions,
+            backend_check,
+            render_backend,
+            unsynced_files,
+            presets,
+            pipeline,
+        } = self;
+        if let Command::CloseSession(CloseSessionArgs { session_id }) = &cmd {
+            return Some(match sessions.remove(session_id) {
+                Some(_) => Response::ok(),
+                None => Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Close session failed: no session named '{}'", session_id),
+                ),
+            });
+        }
+        // Only `init` starts a named session, so a mistyped name can't start one.
+        let session_id = session_id.unwrap_or_default();
+        if !session_id.is_empty()
+            && !sessions.contains_key(&session_id)
+            && !matches!(cmd, Command::Init(_))
+        {
+            return Some(Response::error(
+                ErrorCode::NotInitialized,
+                format!(
+                    "Session '{}' not initialized; send init with its session_id first",
+                    session_id
+                ),
+            ));
+        }
+        let session = sessions
+            .entry(session_id.clone())
+            .or_insert_with(Session::new);
+
+        // Anything that can change the style, its sources or the map size makes cached
+        // bookmark renders stale.
+        let sets_geojson = match &cmd {
+            Command::RenderBatch(args) => args.views.iter().any(|view| view.geojson.is_some()),
+            _ => false,
+        };
+        if sets_geojson
+            || matches!(
+                cmd,
+                Command::Init(_)
+                    | Command::ReloadStyle(_)
+                    | Command::Resize(_)
+                    | Command::SetLayerOpacity(_)
+                    | Command::SetLayerVisibility(_)
+                    | Command::SetLayerProperty(_)
+                    | Command::SetLayerFilter(_)
+                    | Command::SetHillshade(_)
+                    | Command::SetDebug(_)
+                    | Command::SetFeatureState(_)
+                    | Command::RemoveFeatureState(_)
+                    | Command::SetSourceTileSize(_)
+                    | Command::AddSource(_)
+                    | Command::RemoveSource(_)
+                    | Command::AddLayer(_)
+                    | Command::RemoveLayer(_)
+                    | Command::Transaction(_)
+                    | Command::ReloadResources(_)
+                    | Command::RefreshAndRender(_)
+            )
+        {
+            session.bookmark_renders.clear();
+            session.render_cache.clear();
+        }
+        if session.renderer.config.is_none() && cmd.needs_renderer() {
+            return Some(Response::error(
+                ErrorCode::NotInitialized,
+                "Renderer not initialized; send init first",
+            ));
+        }
+        if cmd.needs_renderer() && session.renderer.released() {
+            if let Err(e) = session.renderer.recreate_surface() {
+                return Some(Response::error(
+                    ErrorCode::CommandFailed,
+                    format!("Restoring the idle renderer failed: {}", e),
+                ));
+            }
+            log::info!("restored the renderer released while idle");
+        }
+        resources::set_tile_guard(session.tile_guard);
+        resources::set_retry(session.retry);
+
+        let response = match cmd {
+            Command::Capabilities => Response {
+                capabilities: Some(Capabilities::current()),
+                ..Response::ok()
+            },
+            Command::Init(args) => session.init(args, backend_check, render_backend),
+            Command::Render(args) => session.render(args, unsynced_files),
+            Command::SetFeatureState(args) => session.set_feature_state(args),
+            Command::RemoveFeatureState(args) => session.remove_feature_state(args),
+            Command::SetDebug(args) => session.set_debug(args),
+            Command::SetSourceTileSize(args) => session.set_source_tile_size(args),
+            Command::AddSource(args) => session.add_source(args),
+            Command::RemoveSource(args) => session.remove_source(args),
+            Command::AddLayer(args) => session.add_layer(args),
+            Command::RemoveLayer(args) => session.remove_layer(args),
+            Command::Resize(args) => session.resize(args),
+            // Handled before a session is picked.
+            Command::CloseSession(_) => return None,
+            Command::Pipeline(args) => return start_pipeline(args, pipeline, session_id),
+            Command::ReloadStyle(args) => session.reload_style(args),
+            Command::SetPreset(args) => set_preset(args, presets),
+            Command::SetBookmark(args) => session.set_bookmark(args),
+            Command::RenderBatch(args) => session.render_batch(args, unsynced_files),
+            Command::RenderWithGraticule(args) => session.render_with_graticule(args),
+            Command::RenderLoupe(args) => session.render_loupe(args),
+            Command::Profile(args) => session.profile(args),
+            Command::RenderUtfGrid(args) => session.render_utfgrid(args),
+            Command::RenderWindow(args) => session.render_window(args),
+            Command::RenderMatchRaster(args) => session.render_match_raster(args),
+            Command::RenderBounds(args) => session.render_bounds(args),
+            Command::RenderTile(args) => session.render_tile(args),
+            Command::RenderScale(args) => session.render_scale(args),
+            Command::RenderDpiSet(args) => session.render_dpi_set(args),
+            Command::RenderLarge(args) => session.render_large(args),
+            Command::SetLayerOpacity(args) => session.set_layer_opacity(args),
+            Command::SetLayerVisibility(args) => session.set_layer_visibility(args),
+            Command::SetLayerProperty(args) => session.set_layer_property(args),
+            Command::SetLayerFilter(args) => session.set_layer_filter(args),
+            Command::SetHillshade(args) => session.set_hillshade(args),
+            Command::Transaction(args) => session.transaction(args),
+            #[cfg(feature = "s3")]
+            Command::RenderToS3(args) => session.render_to_s3(args),
+            #[cfg(not(feature = "s3"))]
+            Command::RenderToS3(_) => Response::error(
+                ErrorCode::InvalidCommand,
+                "render_to_s3 requires a build with the s3 feature".to_string(),
+            ),
+            Command::RenderTileArchive(args) => session.render_tile_archive(args, unsynced_files),
+            Command::ReloadResources(args) => session.reload_resources(args),
+            Command::RenderLayerGroups(args) => session.render_layer_groups(args),
+            Command::RenderWithLegend(args) => session.render_with_legend(args),
+            Command::RenderZoomLadder(args) => session.render_zoom_ladder(args),
+            Command::FeatureCounts(args) => session.feature_counts(args),
+            Command::QueryFeatures(args) => session.query_features(args),
+            Command::PointInLayer(args) => session.point_in_layer(args),
+            Command::RenderGeoJsonDiff(args) => session.render_geojson_diff(args),
+            Command::RenderConstrained(args) => session.render_constrained(args),
+            Command::RefreshAndRender(args) => session.refresh_and_render(args),
+            Command::RenderTrackAnimation(args) => session.render_track_animation(args),
+            Command::RenderPath(args) => session.render_path(args),
+            Command::LintStyle(args) => lint_style(args),
+            Command::CheckGlyphCoverage(args) => session.check_glyph_coverage(args),
+            Command::BuildSprite(args) => build_sprite(args, unsynced_files),
+            Command::DescribeStyle => session.describe_style(),
+            Command::GetStyle => session.get_style(),
+            Command::ValidateStyle(args) => validate_style(args),
+            Command::MemoryStats => session.memory_stats(),
+            Command::ValidateCamera(args) => validate_camera(args),
+            Command::DescribeView(args) => session.describe_view(args),
+            Command::EstimateResources(args) => estimate_resources(args),
+            // Consumed by the reader thread; never forwarded.
+            Command::ResourceResponse(_) => return None,
+            // The reader flagged it on arrival; the command it stopped has responded.
+            Command::Cancel => {
+                cancel::clear();
+                Response::ok()
+            }
+            Command::Flush => flush(unsynced_files),
+            Command::Quit => return None,
+        };
+        Some(response)
+    }
+}
+
+/// Handlers for the commands that use a session, each returning the response
+/// `handle_command` sends.
+impl Session {
+    fn init(
+        &mut self,
+        args: InitArgs,
+        backend_check: &Result<(backend::Backend, Option<String>), String>,
+        render_backend: &mut Option<backend::Backend>,
+    ) -> Response {
+        let Session {
+            renderer,
+            empty_tiles,
+            tile_ttl,
+            output_format,
+            output_quality,
+            batch_workers,
+            batch_pool,
+            text_aa,
+            dense_tile_behavior,
+            tile_guard,
+            retry,
+            zoom_range,
+            max_geojson_bytes,
+            render_timeout,
+            camera_defaults,
+            render_cache,
+            attribution,
+            attribution_position,
+            watermark,
+            snap_zoom,
+            max_large_render_pixels,
+            idle_timeout,
+            ..
+        } = self;
+        let InitArgs {
+            width,
+            height,
+            style,
+            style_encoding,
+            style_len,
+            pixel_ratio,
+            protocol_version,
+            empty_tile_behavior,
+            empty_tile_color,
+            tile_ttl_seconds,
+            surface_reuse,
+            style_rewrites,
+            text_aa: session_text_aa,
+            max_features_per_tile,
+            dense_tile_behavior: session_dense_tile_behavior,
+            output_format: session_output_format,
+            quality: session_quality,
+            render_mode,
+            batch_workers: session_batch_workers,
+            min_zoom,
+            max_zoom,
+            zoom_out_of_range,
+            max_geojson_bytes: session_max_geojson_bytes,
+            timeout_ms,
+            cache_path,
+            cache_size_mb,
+            offline,
+            log_level,
+            render_cache_size,
+            camera_defaults: session_camera_defaults,
+            max_retries,
+            retry_backoff_ms,
+            attribution: session_attribution,
+            attribution_position: session_attribution_position,
+            watermark: session_watermark,
+            snap_zoom: session_snap_zoom,
+            max_large_render_pixels: session_max_large_render_pixels,
+            idle_timeout_ms,
+        } = args;
+        let protocol_warning = match check_protocol_version(protocol_version.as_deref()) {
+            Ok(warning) => warning,
+            Err(e) => {
+                return Response {
+                    error_code: Some("protocol_mismatch"),
+                    protocol_version: Some(PROTOCOL_VERSION),
+                    ..Response::error(ErrorCode::InitFailed, e)
+                };
+            }
+        };
+        let stdin_style = if style_len.is_some() {
+            Err(format!(
+                "style_len only applies to style \"{}\"",
+                STYLE_FROM_STDIN
+            ))
+        } else if style == STYLE_FROM_STDIN {
+            Err(format!(
+                "style \"{}\" needs style_len and must be sent on its own line",
+                STYLE_FROM_STDIN
+            ))
+        } else {
+            Ok(())
+        };
+        let style = match stdin_style.and_then(|()| style_encoding.decode(style)) {
+            Ok(style) => style,
+            Err(e) => {
+                return Response::error(ErrorCode::InvalidParameter, format!("Init failed: {}", e));
+            }
+        };
+        let transparent = style_rewrites.background == Some(style_edit::Background::Transparent);
+        let checked = check_pixel_ratio(pixel_ratio)
+            .and_then(|()| check_quality(session_quality))
+            .and_then(|()| check_timeout(timeout_ms))
+            .and_then(|()| check_idle_timeout(idle_timeout_ms))
+            .and_then(|()| check_background(transparent, session_output_format))
+            .and_then(|()| camera::ZoomRange::new(min_zoom, max_zoom, zoom_out_of_range))
+            .and_then(|range| {
+                let level = log_level
+                    .as_deref()
+                    .map(diagnostics::parse_level)
+                    .transpose()?;
+                resources::check_offline(offline)?;
+                let session_tile_guard = resources::tile_guard(
+                    max_features_per_tile,
+                    session_dense_tile_behavior,
+                    offline,
+                )?;
+                let cache = resources::cache(cache_path.as_deref(), cache_size_mb, offline)?;
+                let session_retry = resources::retry(max_retries, retry_backoff_ms)?;
+                Ok((range, level, session_tile_guard, cache, session_retry))
+            });
+        let (session_zoom_range, level, session_tile_guard, cache, session_retry) = match checked {
+            Ok(checked) => checked,
+            Err(e) => {
+                return Response::error(ErrorCode::InvalidParameter, format!("Init failed: {}", e));
+            }
+        };
+        if (offline || resources::offline()) && resources::is_remote(&style) {
+            return network_disabled(ErrorCode::InitFailed, "Init", &style);
+        }
+        let session_attribution =
+            match session_attribution.map_or(Ok(None), |given| given.resolve(&style)) {
+                Ok(text) => text,
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::InvalidParameter,
+                        format!("Init failed: attribution: {}", e),
+                    );
+                }
+            };
+        let session_watermark = match session_watermark
+            .map(|given| load_watermark(&given).map(|logo| (given, logo)))
+            .transpose()
+        {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                return Response::error(ErrorCode::InvalidParameter, format!("Init failed: {}", e));
+            }
+        };
+        let mut backend_warning = None;
+        if render_backend.is_none() {
+            match backend_check {
+                Ok((backend, warning)) => {
+                    *render_backend = Some(backend.clone());
+                    backend_warning = warning.clone();
+                }
+                Err(e) => {
+                    return Response {
+                        error_code: Some("backend_unavailable"),
+                        ..Response::error(ErrorCode::InitFailed, format!("Init failed: {}", e))
+                    };
+                }
+            }
+        }
+        // Past the texture limit the native layer fails obscurely or clamps.
+        let size_check = renderer
+            .render_ratio(pixel_ratio, render_mode)
+            .map(|ratio| backend::estimate(width, height, pixel_ratio, ratio).check());
+        match size_check {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                return Response::size_too_large(format!("Init failed: {}", e));
+            }
+            Err(e) => {
+                return Response::error(ErrorCode::InitFailed, format!("Init failed: {}", e));
+            }
+        }
+        // Loading the style needs the new loader settings; put the old ones back if it
+        // fails, so a rejected init leaves the daemon as it was.
+        let previous = resources::loader_settings();
+        resources::set_offline(offline);
+        resources::set_cache(cache);
+        resources::set_retry(session_retry);
+        match renderer.init(
+            (width, height),
+            &style,
+            pixel_ratio,
+            render_mode,
+            style_rewrites,
+            surface_reuse,
+        ) {
+            Ok(size_clamps) => {
+                if let Some(level) = level {
+                    diagnostics::set_level(level);
+                }
+                resources::set_tile_guard(session_tile_guard);
+                *tile_guard = session_tile_guard;
+                *retry = session_retry;
+                *dense_tile_behavior = session_dense_tile_behavior;
+                *empty_tiles = empty_tile_behavior.map(|behavior| raster::EmptyTilePolicy {
+                    behavior,
+                    color: empty_tile_color,
+                });
+                *tile_ttl = tile_ttl_seconds;
+                *output_format = session_output_format;
+                *output_quality = session_quality.unwrap_or(MAX_JPEG_QUALITY);
+                *batch_workers = match session_batch_workers {
+                    0 => thread::available_parallelism().map_or(1, |n| n.get()),
+                    n => n,
+                };
+                *batch_pool = None;
+                *zoom_range = session_zoom_range;
+                *max_geojson_bytes = session_max_geojson_bytes;
+                *render_timeout = timeout_ms;
+                *camera_defaults = session_camera_defaults;
+                *render_cache = cache::Lru::new(render_cache_size);
+                *attribution = session_attribution;
+                *attribution_position = session_attribution_position;
+                *watermark = session_watermark;
+                *snap_zoom = session_snap_zoom;
+                *max_large_render_pixels = session_max_large_render_pixels;
+                *idle_timeout = idle_timeout_ms.map(Duration::from_millis);
+                let (mode, warning) = session_text_aa.supported();
+                *text_aa = mode;
+                let warnings: Vec<String> = protocol_warning
+                    .into_iter()
+                    .chain(backend_warning)
+                    .chain(warning)
+                    .collect();
+                Response {
+                    protocol_version: Some(PROTOCOL_VERSION),
+                    size_clamps,
+                    warnings: (!warnings.is_empty()).then_some(warnings),
+                    backend: render_backend.clone(),
+                    pixel_ratio_path: renderer.pixel_ratio_path(),
+                    pixel_ratio: Some(pixel_ratio),
+                    ..Response::ok()
+                }
+            }
+            Err(e) => {
+                resources::restore_loader_settings(previous);
+                Response::error(ErrorCode::InitFailed, format!("Init failed: {}", e))
+            }
+        }
+    }
+
+    fn render(&mut self, args: RenderArgs, unsynced_files: &mut Vec<PathBuf>) -> Response {
+        let Session {
+            renderer,
+            empty_tiles,
+            tile_ttl,
+            output_format,
+            output_quality,
+            text_aa,
+            dense_tile_behavior,
+            zoom_range,
+            render_timeout,
+            camera_defaults,
+            bookmarks,
+            bookmark_renders,
+            render_cache,
+            attribution,
+            attribution_position,
+            watermark,
+            snap_zoom,
+            ..
+        } = self;
+        let RenderArgs {
+            center,
+            zoom,
+            bearing,
+            pitch,
+            padding,
+            report_resources,
+            report_matrix,
+            report_histogram,
+            histogram_buckets,
+            report_edge_mask,
+            report_drawn_layers,
+            format,
+            quality,
+            dither,
+            alpha_mode,
+            png_interlaced,
+            color_encoding,
+            target_bytes,
+            min_quality,
+            force_sprite_scale,
+            crops,
+            crop,
+            dim_basemap,
+            overlay_layers,
+            language,
+            deadline_ms,
+            timeout_ms,
+            progress,
+            progress_interval_ms,
+            text_aa: requested_text_aa,
+            bookmark,
+            fill_patterns,
+            preset: _,
+            overrides,
+            output_path,
+            attribution: requested_attribution,
+            attribution_position: requested_attribution_position,
+            watermark: requested_watermark,
+            snap_zoom: requested_snap_zoom,
+        } = args;
+        // Presets are resolved before dispatch, so overrides left here have none.
+        if overrides.is_some() {
+            return Response::error(
+                ErrorCode::InvalidCommand,
+                "Render failed: overrides need a preset",
+            );
+        }
+        // Padding given here overrides the bookmark's, so such renders aren't cached.
+        let padding_override = padding.is_some();
+        let (center, zoom, bearing, pitch, padding) = match (&bookmark, center, zoom) {
+            (Some(name), None, None) => match bookmarks.get(name) {
+                Some(view) => (
+                    view.center,
+                    view.zoom,
+                    view.bearing,
+                    view.pitch,
+                    padding.or(view.padding),
+                ),
+                None => {
+                    return Response::error(
+                        ErrorCode::InvalidParameter,
+                        format!("Render failed: no bookmark named '{}'", name),
+                    );
+                }
+            },
+            (None, center, zoom) => match camera_defaults.complete(center, zoom, bearing, pitch) {
+                Ok(view) => (view.center, view.zoom, view.bearing, view.pitch, padding),
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::InvalidCommand,
+                        format!("Render failed: {}", e),
+                    );
+                }
+            },
+            _ => {
+                return Response::error(
+                    ErrorCode::InvalidCommand,
+                    "Render failed: give either center and zoom or a bookmark",
+                );
+            }
+        };
+        if let Err(e) = camera::check_finite(camera::CameraValues {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        }) {
+            return Response::non_finite(format!("Render failed: {}", e));
+        }
+        let (center, pitch) = match camera::check_range(camera::CameraValues {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        }) {
+            Ok(checked) => (checked.center, checked.pitch),
+            Err(e) => {
+                return Response::invalid_camera(format!("Render failed: {}", e));
+            }
+        };
+        if let Some(padding) = padding {
+            if let Err(e) = renderer
+                .logical_size()
+                .map_or(Ok(()), |size| padding.check(size))
+            {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Render failed: {}", e),
+                );
+            }
+        }
+        let padding = padding.unwrap_or_default();
+        let timeout_ms = timeout_ms.or(*render_timeout);
+        if let Err(e) = check_timeout(timeout_ms) {
+            return Response::error(ErrorCode::InvalidParameter, format!("Render failed: {}", e));
+        }
+        let snap_zoom = requested_snap_zoom.unwrap_or(*snap_zoom);
+        let zoom = if snap_zoom { zoom.round() } else { zoom };
+        let zoom = match zoom_range.apply(zoom) {
+            Ok(zoom) => zoom,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Render failed: {}", e),
+                );
+            }
+        };
+        let format = format.unwrap_or(*output_format);
+        let quality_check = check_quality(quality)
+            .and_then(|()| check_background(renderer.transparent_background(), format))
+            .and_then(|()| match quality {
+                Some(_) if format != OutputFormat::Jpeg => {
+                    Err("quality only applies to jpeg output".to_string())
+                }
+                Some(_) if target_bytes.is_some() => Err(
+                    "quality cannot be combined with target_bytes; set min_quality instead"
+                        .to_string(),
+                ),
+                _ => Ok(()),
+            });
+        if let Err(e) = quality_check {
+            return Response::error(ErrorCode::InvalidParameter, format!("Render failed: {}", e));
+        }
+        let quality = quality.unwrap_or(*output_quality);
+        if report_histogram && !(1..=raster::MAX_HISTOGRAM_BUCKETS).contains(&histogram_buckets) {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!(
+                    "Render failed: histogram_buckets must be between 1 and {}",
+                    raster::MAX_HISTOGRAM_BUCKETS
+                ),
+            );
+        }
+        let matrix = report_matrix
+            .then(|| {
+                let validation = camera::validate(camera::CameraValues {
+                    center,
+                    zoom,
+                    bearing,
+                    pitch,
+                });
+                Some(camera::projection_matrix(
+                    validation.ok()?.camera,
+                    renderer.logical_size()?,
+                    padding,
+                ))
+            })
+            .flatten();
+        if report_resources {
+            diagnostics::begin_capture();
+        }
+        // Only report tiles loaded by this render.
+        resources::take_dense_tiles();
+        // Progress renders wait for idle on the continuous renderer with no deadline.
+        let best_effort = deadline_ms
+            .map(Duration::from_millis)
+            .or(progress.then_some(Duration::MAX));
+        let progress = progress.then(|| Duration::from_millis(progress_interval_ms));
+        let (text_aa, text_aa_warning) = match requested_text_aa {
+            Some(mode) => mode.supported(),
+            None => (*text_aa, None),
+        };
+        let crisp_text = text_aa == raster::TextAntialiasing::Off;
+        // Only plain renders of a bookmark, or of a camera with `render_cache_size`, are
+        // cached; every other option either varies between calls or reports on this
+        // particular render.
+        if output_path.is_some() && !crops.is_empty() {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Render failed: output_path cannot be combined with crops",
+            );
+        }
+        if crop.is_some() && !crops.is_empty() {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Render failed: crop cannot be combined with crops",
+            );
+        }
+        let plain = !report_resources
+            && output_path.is_none()
+            && crops.is_empty()
+            && crop.is_none()
+            && target_bytes.is_none()
+            && dim_basemap.is_none()
+            && language.is_none()
+            && !padding_override
+            && best_effort.is_none()
+            && force_sprite_scale.is_none()
+            && fill_patterns.is_empty()
+            && !report_histogram
+            && !report_edge_mask
+            && !report_drawn_layers
+            && requested_attribution.is_none()
+            && requested_attribution_position.is_none()
+            && requested_watermark.is_none()
+            && requested_snap_zoom.is_none();
+        let attribution = match requested_attribution {
+            Some(given) => match renderer
+                .config
+                .as_ref()
+                .ok_or_else(|| "Renderer not initialized".to_string())
+                .and_then(|config| given.resolve(&config.style))
+            {
+                Ok(text) => text,
+                Err(e) => {
+                    return Response::error(
+                        ErrorCode::InvalidParameter,
+                        format!("Render failed: attribution: {}", e),
+                    );
+                }
+            },
+            None => attribution.clone(),
+        };
+        let attribution_corner = requested_attribution_position.unwrap_or(*attribution_position);
+        let pixel_ratio = renderer.pixel_ratio().unwrap_or(1.0);
+        let attribution_scale = (2.0 * pixel_ratio).round().max(1.0) as u32;
+        let requested_watermark = match requested_watermark
+            .map(|given| load_watermark(&given).map(|logo| (given, logo)))
+            .transpose()
+        {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Render failed: {}", e),
+                );
+            }
+        };
+        let watermark = requested_watermark.as_ref().or(watermark.as_ref());
+        let watermark_margin = |margin: f64| (margin * pixel_ratio).round() as u32;
+        let cacheable = bookmark.filter(|_| plain);
+        let options = (
+            format,
+            quality,
+            dither,
+            alpha_mode,
+            text_aa,
+            png_interlaced,
+            color_encoding,
+        );
+        let render_key = (plain && cacheable.is_none() && render_cache.enabled()).then(|| {
+            (
+                [center[0], center[1], zoom, bearing, pitch].map(f64::to_bits),
+                options,
+            )
+        });
+        let cached = match (&cacheable, &render_key) {
+            (Some(name), _) => bookmark_renders
+                .get(name)
+                .filter(|cached| cached.options == options),
+            (None, Some(key)) => render_cache.get(key),
+            (None, None) => None,
+        };
+        if let Some(cached) = cached {
+            return Response {
+                empty: cached.empty,
+                cache: tile_ttl.map(cache::hints),
+                pixel_ratio_path: renderer.pixel_ratio_path(),
+                cached: Some(true),
+                matrix,
+                zoom: snap_zoom.then_some(zoom),
+                warnings: text_aa_warning.map(|warning| vec![warning]),
+                ..Response::image(&cached.encoded)
+            }
+            .with_payload(cached.encoded.bytes.clone());
+        }
+        let render_started = Instant::now();
+        let mut timed_out = false;
+        let mut tiles_pending = None;
+        let result = match (best_effort, dim_basemap, force_sprite_scale) {
+            _ if !fill_patterns.is_empty()
+                && (best_effort.is_some()
+                    || dim_basemap.is_some()
+                    || force_sprite_scale.is_some()
+                    || crisp_text) =>
+            {
+                Err((ErrorCode::InvalidParameter, "Render failed: fill_patterns cannot be combined with deadline_ms, progress, dim_basemap, force_sprite_scale or text_aa 'none'".to_string()))
+            }
+            _ if crisp_text
+                && (best_effort.is_some()
+                    || dim_basemap.is_some()
+                    || force_sprite_scale.is_some()) =>
+            {
+                Err((ErrorCode::InvalidParameter, "Render failed: text_aa 'none' cannot be combined with deadline_ms, progress, dim_basemap or force_sprite_scale".to_string()))
+            }
+            _ if language.is_some()
+                && (best_effort.is_some()
+                    || timeout_ms.is_some()
+                    || dim_basemap.is_some()
+                    || force_sprite_scale.is_some()
+                    || !fill_patterns.is_empty()
+                    || crisp_text) =>
+            {
+                Err((ErrorCode::InvalidParameter, "Render failed: language cannot be combined with deadline_ms, progress, timeout_ms, dim_basemap, force_sprite_scale, fill_patterns or text_aa 'none'".to_string()))
+            }
+            (Some(_), Some(_), _) => Err((
+                ErrorCode::InvalidParameter,
+                "Render failed: dim_basemap cannot be combined with deadline_ms or progress"
+                    .to_string(),
+            )),
+            (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => Err((
+                ErrorCode::InvalidParameter,
+                "Render failed: force_sprite_scale cannot be combined with deadline_ms, progress or dim_basemap"
+                    .to_string(),
+            )),
+            (None, Some(factor), None) => renderer
+                .render_dimmed(center, zoom, bearing, pitch, padding, factor, &overlay_layers)
+                .map(|image| (image.as_image().clone(), None))
+                .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+            (Some(deadline), None, None) => renderer
+                .render_best_effort(center, zoom, bearing, pitch, padding, deadline, progress)
+                .map(|(image, complete)| {
+                    tiles_pending = resources::tiles_pending();
+                    (image, Some(complete))
+                })
+                .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+            (None, None, Some(scale)) => renderer
+                .render_with_sprite_scale(center, zoom, bearing, pitch, padding, scale)
+                .map(|image| (image.as_image().clone(), None))
+                .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+            (None, None, None) if !fill_patterns.is_empty() => renderer
+                .render_fill_patterns(center, zoom, bearing, pitch, padding, &fill_patterns)
+                .map(|image| (image, None))
+                .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+            (None, None, None) if crisp_text => renderer
+                .render_crisp_text(center, zoom, bearing, pitch, padding)
+                .map(|image| (image, None))
+                .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+            (None, None, None) => match timeout_ms {
+                // A static render can't be interrupted, so timed renders run on
+                // the continuous renderer, which stops drawing at the timeout.
+                Some(ms) => renderer
+                    .render_best_effort(
+                        center,
+                        zoom,
+                        bearing,
+                        pitch, padding,
+                        Duration::from_millis(ms),
+                        None,
+                    )
+                    .map(|(image, complete)| {
+                        timed_out = !complete;
+                        (image, None)
+                    })
+                    .map_err(|e| {
+                        (ErrorCode::RenderFailed, format!("Render failed: {}", e))
+                    }),
+                None => match &language {
+                    Some(language) => renderer
+                        .render_localized(center, zoom, bearing, pitch, padding, language)
+                        .map(|image| (image.as_image().clone(), None))
+                        .map_err(|e| {
+                            (ErrorCode::RenderFailed, format!("Render failed: {}", e))
+                        }),
+                    None => renderer
+                        .render(center, zoom, bearing, pitch, padding)
+                        .map(|image| (image.as_image().clone(), None))
+                        .map_err(|e| {
+                            (ErrorCode::RenderFailed, format!("Render failed: {}", e))
+                        }),
+                },
+            },
+        };
+        let render_time = render_started.elapsed();
+        match &result {
+            Ok(_) => log::info!(
+                "render at {:?} zoom {} took {} ms",
+                center,
+                zoom,
+                render_time.as_millis()
+            ),
+            Err((_, e)) => log::warn!("{} after {} ms", e, render_time.as_millis()),
+        }
+        let warnings = text_aa_warning.map(|warning| vec![warning]);
+        let resources = report_resources.then(diagnostics::finish_capture);
+        let dense_tiles = Some(resources::take_dense_tiles()).filter(|t| !t.is_empty());
+        if timed_out {
+            return Response {
+                error_code: Some("render_timeout"),
+                resources,
+                ..Response::error(
+                    ErrorCode::RenderFailed,
+                    format!(
+                        "Render failed: not finished within timeout_ms ({} ms)",
+                        timeout_ms.unwrap_or_default()
+                    ),
+                )
+            };
+        }
+        // Evaluated after the capture above so its renders aren't reported.
+        let (result, drawn_layers) = match result {
+            Ok(rendered) if report_drawn_layers => {
+                match renderer.drawn_layers(center, zoom, bearing, pitch, padding) {
+                    Ok(layers) => (Ok(rendered), Some(layers)),
+                    Err(e) => (
+                        Err((ErrorCode::RenderFailed, format!("Render failed: {}", e))),
+                        None,
+                    ),
+                }
+            }
+            result => (result, None),
+        };
+        if let (Some(tiles), resources::DenseTileBehavior::Error) =
+            (&dense_tiles, dense_tile_behavior)
+        {
+            return Response {
+                error_code: Some("tile_too_dense"),
+                dense_tiles: dense_tiles.clone(),
+                resources,
+                ..Response::error(
+                    ErrorCode::RenderFailed,
+                    format!(
+                        "Render failed: {} vector tile(s) exceed max_features_per_tile",
+                        tiles.len()
+                    ),
+                )
+            };
+        }
+        let result = result.and_then(|(mut image, complete)| {
+            if let Some(region) = &crop {
+                image = raster::crop(&image, region)
+                    .map_err(|e| (ErrorCode::InvalidParameter, format!("Render failed: {}", e)))?;
+            }
+            let empty = empty_tiles
+                .map(|policy| policy.apply(&mut image))
+                .transpose()
+                .map_err(|e| (ErrorCode::RenderFailed, e))?;
+            if let Some((given, logo)) = watermark {
+                compose::watermark(
+                    &mut image,
+                    logo,
+                    given.position,
+                    given.opacity,
+                    watermark_margin(given.margin),
+                );
+            }
+            if let Some(text) = &attribution {
+                compose::attribution(&mut image, text, attribution_corner, attribution_scale);
+            }
+            if alpha_mode == raster::AlphaMode::Premultiplied {
+                raster::premultiply(&mut image);
+            }
+            Ok((image, complete, empty))
+        });
+        let premultiplied = alpha_mode == raster::AlphaMode::Premultiplied;
+        let linear = color_encoding == raster::ColorEncoding::Linear;
+        let encode = |image: &RgbaImage| {
+            let encoded = match target_bytes {
+                Some(target) => encode_to_target(image, format, dither, target, min_quality),
+                None if linear => raster::encode_png16_linear(image).map(|bytes| Encoded {
+                    bytes,
+                    format,
+                    width: image.width(),
+                    height: image.height(),
+                    layout: None,
+                    quality: None,
+                    target_met: None,
+                }),
+                None if png_interlaced => {
+                    raster::encode_png_interlaced(image).map(|bytes| Encoded {
+                        bytes,
+                        format,
+                        width: image.width(),
+                        height: image.height(),
+                        layout: None,
+                        quality: None,
+                        target_met: None,
+                    })
+                }
+                None => encode_image(image, format, dither, quality),
+            };
+            encoded.map(|mut encoded| {
+                if let Some(layout) = encoded.layout.as_mut() {
+                    layout.premultiplied_alpha = premultiplied;
+                }
+                encoded
+            })
+        };
+        let cache_hints = |complete: Option<bool>| {
+            // A cut-short frame must not be cached as the real tile.
+            tile_ttl.map(|ttl| cache::hints(if complete == Some(false) { 0 } else { ttl }))
+        };
+        let result = result.and_then(|rendered| match target_bytes {
+            _ if linear && format != OutputFormat::Png => {
+                Err((ErrorCode::InvalidParameter, "color_encoding 'linear' only applies to png output".to_string()))
+            }
+            _ if linear && (target_bytes.is_some() || png_interlaced || premultiplied) => {
+                Err((ErrorCode::InvalidParameter, "color_encoding 'linear' cannot be combined with target_bytes, png_interlaced or premultiplied alpha".to_string()))
+            }
+            Some(_) if !crops.is_empty() => {
+                Err((ErrorCode::InvalidParameter, "target_bytes cannot be combined with crops".to_string()))
+            }
+            Some(_) if format == OutputFormat::Rgba => {
+                Err((ErrorCode::InvalidParameter, "target_bytes does not apply to rgba output".to_string()))
+            }
+            Some(_) if png_interlaced => {
+                Err((ErrorCode::InvalidParameter, "png_interlaced cannot be combined with target_bytes".to_string()))
+            }
+            None if png_interlaced && format != OutputFormat::Png => {
+                Err((ErrorCode::InvalidParameter, "png_interlaced only applies to png output".to_string()))
+            }
+            _ => Ok(rendered),
+        });
+        let encode_started = Instant::now();
+        match result {
+            Ok((image, complete, empty)) if !crops.is_empty() => {
+                let histogram = report_histogram
+                    .then(|| output_histogram(&image, format, histogram_buckets).ok())
+                    .flatten();
+                let edge_mask = report_edge_mask.then(|| raster::edge_mask(&image));
+                let mut payloads = Vec::new();
+                let encode_started = Instant::now();
+                let results = crops
+                    .iter()
+                    .map(|crop| {
+                        match raster::crop(&image, &crop.region)
+                            .and_then(|cropped| encode(&cropped))
+                        {
+                            Ok(encoded) => {
+                                let result = CropResult {
+                                    id: crop.id.clone(),
+                                    png_len: Some(encoded.bytes.len()),
+                                    error: None,
+                                };
+                                payloads.push(encoded.bytes);
+                                result
+                            }
+                            Err(e) => CropResult {
+                                id: crop.id.clone(),
+                                png_len: None,
+                                error: Some(e),
+                            },
+                        }
+                    })
+                    .collect();
+                let timing = profile::Timing::new(render_time, encode_started.elapsed());
+                Response {
+                    crops: Some(results),
+                    format: Some(format.name()),
+                    resources,
+                    complete,
+                    tiles_pending,
+                    empty,
+                    cache: cache_hints(complete),
+                    pixel_ratio_path: renderer.pixel_ratio_path(),
+                    matrix,
+                    zoom: snap_zoom.then_some(zoom),
+                    timing: Some(timing),
+                    histogram,
+                    edge_mask,
+                    drawn_layers,
+                    warnings,
+                    dense_tiles,
+                    ..Response::ok()
+                }
+                .with_payloads(payloads)
+            }
+            Ok((image, complete, empty)) => match encode(&image) {
+                Ok(encoded) => {
+                    let timing = profile::Timing::new(render_time, encode_started.elapsed());
+                    let histogram = report_histogram
+                        .then(|| output_histogram(&image, encoded.format, histogram_buckets).ok())
+                        .flatten();
+                    let edge_mask = report_edge_mask.then(|| raster::edge_mask(&image));
+                    let caching = cacheable.is_some() || render_key.is_some();
+                    let response = Response {
+                        resources,
+                        complete,
+                        tiles_pending,
+                        empty,
+                        cache: cache_hints(complete),
+                        pixel_ratio_path: renderer.pixel_ratio_path(),
+                        cached: caching.then_some(false),
+                        matrix,
+                        zoom: snap_zoom.then_some(zoom),
+                        histogram,
+                        edge_mask,
+                        drawn_layers,
+                        warnings,
+                        dense_tiles,
+                        timing: Some(timing),
+                        ..Response::image(&encoded)
+                    };
+                    let response = match output_path {
+                        Some(path) => match write_output(Path::new(&path), &encoded.bytes) {
+                            Ok(()) => {
+                                unsynced_files.push(PathBuf::from(&path));
+                                Response {
+                                    png_len: None,
+                                    path: Some(path),
+                                    ..response
+                                }
+                            }
+                            Err(e) => Response::error(
+                                ErrorCode::CommandFailed,
+                                format!("Render failed: {}", e),
+                            ),
+                        },
+                        None if caching => response.with_payload(encoded.bytes.clone()),
+                        None => return response.with_payload(encoded.bytes),
+                    };
+                    let render = CachedRender {
+                        options,
+                        encoded,
+                        empty,
+                    };
+                    if let Some(name) = cacheable {
+                        bookmark_renders.insert(name, render);
+                    } else if let Some(key) = render_key {
+                        render_cache.insert(key, render);
+                    }
+                    response
+                }
+                Err(e) => Response::error(ErrorCode::EncodeFailed, e),
+            },
+            Err((code, e)) => Response {
+                resources,
+                ..Response::error(code, e)
+            },
+        }
+    }
+
+    fn set_feature_state(&mut self, args: SetFeatureStateArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetFeatureStateArgs {
+            source,
+            source_layer,
+            id,
+            state,
+        } = args;
+        let serde_json::Value::Object(state) = state else {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Set feature state failed: state must be an object",
+            );
+        };
+        match renderer.set_feature_state(
+            &source,
+            source_layer.as_deref(),
+            Some(&id),
+            FeatureStateChange::Set(state),
+        ) {
+            Ok(layers) => Response {
+                warnings: (layers == 0).then(|| {
+                    vec![format!(
+                        "no layer of source '{}' uses feature-state, so the state has no effect",
+                        source
+                    )]
+                }),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set feature state failed: {}", e),
+            ),
+        }
+    }
+
+    fn remove_feature_state(&mut self, args: RemoveFeatureStateArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RemoveFeatureStateArgs {
+            source,
+            source_layer,
+            id,
+            key,
+        } = args;
+        match renderer.set_feature_state(
+            &source,
+            source_layer.as_deref(),
+            id.as_ref(),
+            FeatureStateChange::Remove(key.as_deref()),
+        ) {
+            Ok(_) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Remove feature state failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_debug(&mut self, args: SetDebugArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetDebugArgs {
+            tile_borders,
+            collision,
+            overdraw,
+        } = args;
+        match renderer.set_debug(DebugFlags {
+            tile_borders,
+            collision,
+            overdraw,
+        }) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(ErrorCode::CommandFailed, format!("Set debug failed: {}", e)),
+        }
+    }
+
+    fn set_source_tile_size(&mut self, args: SetSourceTileSizeArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetSourceTileSizeArgs { source, tile_size } = args;
+        match renderer.set_source_tile_size(&source, tile_size) {
+            Ok(()) => Response {
+                tile_size: Some(tile_size),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set source tile size failed: {}", e),
+            ),
+        }
+    }
+
+    fn add_source(&mut self, args: AddSourceArgs) -> Response {
+        let Session {
+            renderer,
+            max_geojson_bytes,
+            ..
+        } = self;
+        let AddSourceArgs {
+            id,
+            geojson,
+            replace,
+        } = args;
+        match renderer.add_geojson_source(&id, &geojson, replace, *max_geojson_bytes) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Add source failed: {}", e),
+            ),
+        }
+    }
+
+    fn remove_source(&mut self, args: RemoveSourceArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RemoveSourceArgs { id } = args;
+        match renderer.remove_source(&id) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Remove source failed: {}", e),
+            ),
+        }
+    }
+
+    fn add_layer(&mut self, args: AddLayerArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let AddLayerArgs { layer, before } = args;
+        match renderer.add_layer(&layer, before.as_deref()) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(ErrorCode::CommandFailed, format!("Add layer failed: {}", e)),
+        }
+    }
+
+    fn remove_layer(&mut self, args: RemoveLayerArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RemoveLayerArgs { id } = args;
+        match renderer.remove_layer(&id) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Remove layer failed: {}", e),
+            ),
+        }
+    }
+
+    fn resize(&mut self, args: ResizeArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let ResizeArgs { width, height } = args;
+        {
+            if let Err(e) = renderer.check_size(width, height) {
+                return Response::size_too_large(format!("Resize failed: {}", e));
+            }
+            match renderer.set_size(width, height) {
+                Ok(()) => Response::ok(),
+                Err(e) => {
+                    Response::error(ErrorCode::CommandFailed, format!("Resize failed: {}", e))
+                }
+            }
+        }
+    }
+
+    fn reload_style(&mut self, args: ReloadStyleArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let ReloadStyleArgs {
+            style,
+            style_encoding,
+        } = args;
+        let style = match style_encoding.decode(style) {
+            Ok(style) => style,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Reload style failed: {}", e),
+                );
+            }
+        };
+        if resources::offline() && resources::is_remote(&style) {
+            return network_disabled(ErrorCode::CommandFailed, "Reload style", &style);
+        }
+        match renderer.reload_style(&style) {
+            Ok(size_clamps) => Response {
+                size_clamps,
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Reload style failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_bookmark(&mut self, args: SetBookmarkArgs) -> Response {
+        let Session {
+            bookmarks,
+            bookmark_renders,
+            ..
+        } = self;
+        let SetBookmarkArgs { name, camera } = args;
+        bookmark_renders.remove(&name);
+        bookmarks.insert(name, camera);
+        Response::ok()
+    }
+
+    fn render_batch(
+        &mut self,
+        args: RenderBatchArgs,
+        unsynced_files: &mut Vec<PathBuf>,
+    ) -> Response {
+        let Session {
+            renderer,
+            tile_ttl,
+            output_format,
+            output_quality,
+            batch_workers,
+            batch_pool,
+            zoom_range,
+            max_geojson_bytes,
+            ..
+        } = self;
+        let RenderBatchArgs {
+            mut views,
+            output_dir,
+            output_name,
+            stream,
+        } = args;
+        if output_dir.is_some() && !output_name.contains("{index}") {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Batch render failed: output_name must contain {index}",
+            );
+        }
+        let non_finite = views.iter().enumerate().find_map(|(i, view)| {
+            camera::check_finite(camera::CameraValues {
+                center: view.center,
+                zoom: view.zoom,
+                bearing: view.bearing,
+                pitch: view.pitch,
+            })
+            .err()
+            .map(|e| format!("Batch render failed: view {}: {}", i, e))
+        });
+        if let Some(error) = non_finite {
+            return Response::non_finite(error);
+        }
+        let invalid =
+            views.iter_mut().enumerate().find_map(|(i, view)| {
+                match camera::check_range(camera::CameraValues {
+                    center: view.center,
+                    zoom: view.zoom,
+                    bearing: view.bearing,
+                    pitch: view.pitch,
+                }) {
+                    Ok(checked) => {
+                        view.center = checked.center;
+                        view.pitch = checked.pitch;
+                        None
+                    }
+                    Err(e) => Some(format!("Batch render failed: view {}: {}", i, e)),
+                }
+            });
+        if let Some(error) = invalid {
+            return Response::invalid_camera(error);
+        }
+        let out_of_range =
+            views
+                .iter_mut()
+                .enumerate()
+                .find_map(|(i, view)| match zoom_range.apply(view.zoom) {
+                    Ok(zoom) => {
+                        view.zoom = zoom;
+                        None
+                    }
+                    Err(e) => Some(format!("Batch render failed: view {}: {}", i, e)),
+                });
+        if let Some(error) = out_of_range {
+            return Response::error(ErrorCode::InvalidParameter, error);
+        }
+        let init_size = renderer
+            .config
+            .as_ref()
+            .map(|config| (config.width.get(), config.height.get()));
+        let bad_padding = views.iter().enumerate().find_map(|(i, view)| {
+            let (width, height) = init_size?;
+            let size = [view.width.unwrap_or(width), view.height.unwrap_or(height)];
+            view.padding?
+                .check(size.map(f64::from))
+                .err()
+                .map(|e| format!("Batch render failed: view {}: {}", i, e))
+        });
+        if let Some(error) = bad_padding {
+            return Response::error(ErrorCode::InvalidParameter, error);
+        }
+        let too_large = views.iter().enumerate().find_map(|(i, view)| {
+            let (width, height) = init_size?;
+            renderer
+                .check_size(view.width.unwrap_or(width), view.height.unwrap_or(height))
+                .err()
+                .map(|e| format!("Batch render failed: view {}: {}", i, e))
+        });
+        if let Some(error) = too_large {
+            return Response::size_too_large(error);
+        }
+        let mut current_size = init_size;
+        let transparent = renderer.transparent_background();
+        let encoding = |view: &View| view.encoding(*output_format, *output_quality, transparent);
+        let mut outcomes: Vec<Option<ViewResult>> = Vec::new();
+        outcomes.resize_with(views.len(), || None);
+        let mut payloads: Vec<Option<Vec<u8>>> = vec![None; views.len()];
+        // Record a finished view, or with `stream` send it straight away.
+        let mut finish = |index: usize, result: Result<(Encoded, profile::Timing), ViewResult>| {
+            let (outcome, payload) = match result {
+                Ok((encoded, timing)) => {
+                    let outcome = ViewResult {
+                        png_len: Some(encoded.bytes.len()),
+                        path: None,
+                        width: Some(encoded.width),
+                        height: Some(encoded.height),
+                        format: Some(encoded.format.name()),
+                        timing: Some(timing),
+                        error: None,
+                        failed_operation: None,
+                    };
+                    match &output_dir {
+                        None => (outcome, Some(encoded.bytes)),
+                        Some(dir) => {
+                            let name = output_name
+                                .replace("{index}", &index.to_string())
+                                .replace("{ext}", encoded.format.extension());
+                            let path = Path::new(dir).join(name);
+                            match write_output(&path, &encoded.bytes) {
+                                Ok(()) => {
+                                    let outcome = ViewResult {
+                                        png_len: None,
+                                        path: Some(path.display().to_string()),
+                                        ..outcome
+                                    };
+                                    unsynced_files.push(path);
+                                    (outcome, None)
+                                }
+                                Err(e) => (ViewResult::failed(e), None),
+                            }
+                        }
+                    }
+                }
+                Err(outcome) => (outcome, None),
+            };
+            if stream {
+                send_message(
+                    &BatchViewEvent {
+                        status: "view",
+                        index,
+                        view: &outcome,
+                    },
+                    payload.as_deref(),
+                );
+            } else {
+                payloads[index] = payload;
+            }
+            outcomes[index] = Some(outcome);
+        };
+        let mut error_response: Option<Response> = None;
+
+        // Views that change the style or data depend on the views before them,
+        // so only plain batches are spread over the worker pool.
+        let pooled = *batch_workers > 1
+            && renderer
+                .config
+                .as_ref()
+                .is_some_and(|config| config.surface_reuse)
+            && views.iter().all(|view| {
+                view.geojson.is_none()
+                    && view.operations.is_empty()
+                    && view.width.is_none()
+                    && view.height.is_none()
+            });
+        if let Some(config) = renderer.config.as_ref().filter(|_| pooled) {
+            if !batch_pool
+                .as_ref()
+                .is_some_and(|pool| pool.serves(config, *batch_workers))
+            {
+                // Join the old workers before starting new ones.
+                drop(batch_pool.take());
+            }
+            let pool =
+                batch_pool.get_or_insert_with(|| pool::Pool::new(config.clone(), *batch_workers));
+            let each = |index, result: pool::ViewOutcome| {
+                finish(index, result.map_err(ViewResult::failed))
+            };
+            if let Err(e) = pool.render(&views, encoding, each) {
+                error_response = Some(Response::error(
+                    ErrorCode::RenderFailed,
+                    format!("Batch render failed: {}", e),
+                ));
+            }
+        }
+        // A failing view only loses its own image; later views still render.
+        for (index, view) in views.iter().enumerate().filter(|_| !pooled) {
+            if cancel::requested() {
+                finish(index, Err(ViewResult::failed(cancel::CANCELLED)));
+                continue;
+            }
+            if view.width == Some(0) || view.height == Some(0) {
+                finish(
+                    index,
+                    Err(ViewResult::failed("width and height must be non-zero")),
+                );
+                continue;
+            }
+            let (format, quality) = match encoding(view) {
+                Ok(encoding) => encoding,
+                Err(e) => {
+                    finish(index, Err(ViewResult::failed(e)));
+                    continue;
+                }
+            };
+            if let Some(sources) = &view.geojson {
+                if let Err(e) = renderer.update_geojson_sources(sources, *max_geojson_bytes) {
+                    finish(index, Err(ViewResult::failed(e)));
+                    continue;
+                }
+            }
+            // Only resize between views whose sizes differ.
+            let size = init_size.map(|(width, height)| {
+                (view.width.unwrap_or(width), view.height.unwrap_or(height))
+            });
+            if size != current_size {
+                if let Some((width, height)) = size {
+                    if let Err(e) = renderer.resize(width, height) {
+                        finish(index, Err(ViewResult::failed(e)));
+                        continue;
+                    }
+                }
+                current_size = size;
+            }
+            let started = Instant::now();
+            finish(
+                index,
+                match renderer.render_with_operations(view) {
+                    Ok(image) => {
+                        let rendered = Instant::now();
+                        encode_image(image.as_image(), format, raster::Dither::default(), quality)
+                            .map(|encoded| {
+                                let timing =
+                                    profile::Timing::new(rendered - started, rendered.elapsed());
+                                (encoded, timing)
+                            })
+                            .map_err(ViewResult::failed)
+                    }
+                    Err((failed_operation, e)) => Err(ViewResult {
+                        failed_operation,
+                        ..ViewResult::failed(e)
+                    }),
+                },
+            );
+        }
+        if current_size != init_size {
+            if let Some((width, height)) = init_size {
+                if let Err(e) = renderer.resize(width, height) {
+                    error_response = Some(Response::error(
+                        ErrorCode::RenderFailed,
+                        format!("Batch render failed: {}", e),
+                    ));
+                }
+            }
+        }
+
+        let outcomes: Vec<ViewResult> = outcomes.into_iter().flatten().collect();
+        let payloads: Vec<Vec<u8>> = payloads.into_iter().flatten().collect();
+        let cancelled = outcomes
+            .iter()
+            .any(|outcome| outcome.error.as_deref() == Some(cancel::CANCELLED));
+        let done = || {
+            if cancelled {
+                Response::cancelled()
+            } else {
+                Response::ok()
+            }
+        };
+        // The batch only fails as a whole when no view rendered.
+        if outcomes.iter().all(|outcome| outcome.error.is_some())
+            && error_response.is_none()
+            && !cancelled
+        {
+            error_response = outcomes.iter().enumerate().find_map(|(i, outcome)| {
+                outcome.error.as_ref().map(|e| Response {
+                    failed_operation: outcome.failed_operation,
+                    ..Response::error(
+                        ErrorCode::RenderFailed,
+                        format!("Batch render failed: view {}: {}", i, e),
+                    )
+                })
+            });
+        }
+
+        // Streamed views were sent already, so the summary leaves them out.
+        if let Some(resp) = error_response {
+            Response {
+                views: (!outcomes.is_empty() && !stream).then_some(outcomes),
+                ..resp
+            }
+        } else if stream {
+            Response {
+                format: Some(output_format.name()),
+                cache: tile_ttl.map(cache::hints),
+                ..done()
+            }
+        } else {
+            Response {
+                png_lengths: Some(payloads.iter().map(Vec::len).collect()),
+                views: Some(outcomes),
+                format: Some(output_format.name()),
+                cache: tile_ttl.map(cache::hints),
+                ..done()
+            }
+            .with_payloads(payloads)
+        }
+    }
+
+    fn render_with_graticule(&mut self, args: RenderWithGraticuleArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderWithGraticuleArgs {
+            camera,
+            interval_degrees,
+            color,
+            label,
+            format,
+            dither,
+        } = args;
+        if let Err(e) = camera::check_finite(camera::CameraValues {
+            center: camera.center,
+            zoom: camera.zoom,
+            bearing: camera.bearing,
+            pitch: camera.pitch,
+        }) {
+            return Response::non_finite(format!("Graticule render failed: {}", e));
+        }
+        let interval = match graticule::interval(interval_degrees, camera.zoom) {
+            Ok(interval) => interval,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Graticule render failed: {}", e),
+                );
+            }
+        };
+        match renderer
+            .render_with_graticule(&camera, interval, color, label)
+            .map_err(|e| e.to_string())
+            .and_then(|image| {
+                encode_output(&image, format, dither).map(|encoded| (encoded, interval))
+            }) {
+            Ok((encoded, interval)) => Response {
+                interval_degrees: Some(interval),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Graticule render failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_loupe(&mut self, args: RenderLoupeArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderLoupeArgs {
+            camera,
+            focus,
+            magnification,
+            radius,
+            position,
+            format,
+            dither,
+        } = args;
+        if let Err(e) = renderer.check_loupe(magnification, radius) {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!("Loupe render failed: {}", e),
+            );
+        }
+        match renderer
+            .render_loupe(&camera, focus, magnification, radius, position)
+            .map_err(|e| {
+                (
+                    ErrorCode::RenderFailed,
+                    format!("Loupe render failed: {}", e),
+                )
+            })
+            .and_then(|image| {
+                encode_output(&image, format, dither).map_err(|e| (ErrorCode::EncodeFailed, e))
+            }) {
+            Ok(encoded) => Response::image(&encoded).with_payload(encoded.bytes),
+            Err((code, e)) => Response::error(code, e),
+        }
+    }
+
+    fn profile(&mut self, args: ProfileArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let ProfileArgs {
+            cameras,
+            iterations,
+            warmup,
+        } = args;
+        let mut passes = Vec::with_capacity(iterations as usize);
+        let mut failure = None;
+        'passes: for _ in 0..iterations {
+            let mut timings = Vec::with_capacity(cameras.len());
+            for view in &cameras {
+                let started = Instant::now();
+                if let Err(e) = renderer.render(
+                    view.center,
+                    view.zoom,
+                    view.bearing,
+                    view.pitch,
+                    camera::Padding::default(),
+                ) {
+                    failure = Some(format!("Profile failed: {}", e));
+                    break 'passes;
+                }
+                timings.push(started.elapsed());
+            }
+            passes.push(timings);
+        }
+        match failure {
+            Some(e) => Response::error(ErrorCode::RenderFailed, e),
+            None => Response {
+                profile: Some(profile::report(&passes, warmup)),
+                ..Response::ok()
+            },
+        }
+    }
+
+    fn render_utfgrid(&mut self, args: RenderUtfGridArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderUtfGridArgs {
+            z,
+            x,
+            y,
+            layers,
+            fields,
+            resolution,
+        } = args;
+        if let Err(e) = utfgrid::check_resolution(resolution) {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!("UTFGrid failed: {}", e),
+            );
+        }
+        match renderer
+            .style_document()
+            .and_then(|style| utfgrid::build(&style, z, x, y, &layers, &fields, resolution))
+        {
+            Ok(grid) => Response {
+                utfgrid: Some(grid),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(ErrorCode::RenderFailed, format!("UTFGrid failed: {}", e)),
+        }
+    }
+
+    fn render_window(&mut self, args: RenderWindowArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderWindowArgs {
+            zoom,
+            pixel_origin,
+            width,
+            height,
+            format,
+            dither,
+        } = args;
+        let center = match camera::window_center(zoom, pixel_origin, [width, height]) {
+            Ok(center) => center,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Window render failed: {}", e),
+                );
+            }
+        };
+        if let Err(e) = renderer.check_size(width, height) {
+            return Response::size_too_large(format!("Window render failed: {}", e));
+        }
+        match renderer
+            .render_window(center, zoom, width, height)
+            .map_err(|e| {
+                (
+                    ErrorCode::RenderFailed,
+                    format!("Window render failed: {}", e),
+                )
+            })
+            .and_then(|image| {
+                encode_output(image.as_image(), format, dither)
+                    .map_err(|e| (ErrorCode::EncodeFailed, e))
+            }) {
+            Ok(encoded) => Response::image(&encoded).with_payload(encoded.bytes),
+            Err((code, e)) => Response::error(code, e),
+        }
+    }
+
+    fn render_match_raster(&mut self, args: RenderMatchRasterArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderMatchRasterArgs {
+            reference_bounds,
+            reference_width,
+            reference_height,
+            format,
+            dither,
+        } = args;
+        if let Err(e) = camera::check_finite_bounds(reference_bounds) {
+            return Response::non_finite(format!("Raster match render failed: {}", e));
+        }
+        let window = if reference_width == 0 || reference_height == 0 {
+            Err("reference_width and reference_height must be non-zero".to_string())
+        } else {
+            renderer
+                .pixel_ratio()
+                .ok_or_else(|| "Renderer not initialized".to_string())
+                .and_then(|ratio| {
+                    let width = f64::from(reference_width) / ratio;
+                    let height = f64::from(reference_height) / ratio;
+                    if width.fract() != 0.0 || height.fract() != 0.0 {
+                        return Err(format!(
+                            "reference dimensions must be multiples of pixel_ratio {}",
+                            ratio
+                        ));
+                    }
+                    let (zoom, origin) =
+                        camera::window_for_bounds(reference_bounds, width, height)?;
+                    let size = [width as u32, height as u32];
+                    camera::window_center(zoom, origin, size).map(|center| (zoom, center, size))
+                })
+        };
+        let (zoom, center, [width, height]) = match window {
+            Ok(window) => window,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Raster match render failed: {}", e),
+                );
+            }
+        };
+        if let Err(e) = renderer.check_size(width, height) {
+            return Response::size_too_large(format!("Raster match render failed: {}", e));
+        }
+        match renderer
+            .render_window(center, zoom, width, height)
+            .map_err(|e| e.to_string())
+            .and_then(|image| encode_output(image.as_image(), format, dither))
+        {
+            Ok(encoded) => Response {
+                zoom: Some(zoom),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Raster match render failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_bounds(&mut self, args: RenderBoundsArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderBoundsArgs {
+            bounds,
+            padding,
+            format,
+            dither,
+        } = args;
+        let [[west, south], [east, north]] = bounds;
+        if let Err(e) = camera::check_finite_bounds([west, south, east, north]).and_then(|()| {
+            padding
+                .is_finite()
+                .then_some(())
+                .ok_or_else(|| "padding must be a finite number".to_string())
+        }) {
+            return Response::non_finite(format!("Bounds render failed: {}", e));
+        }
+        let size = renderer.logical_size().unwrap_or_default();
+        let (center, zoom) = match camera::fit_bounds(bounds, size, padding) {
+            Ok(camera) => camera,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Bounds render failed: {}", e),
+                );
+            }
+        };
+        match renderer
+            .render(center, zoom, 0.0, 0.0, camera::Padding::default())
+            .map_err(|e| e.to_string())
+            .and_then(|image| {
+                encode_output(image.as_image(), format, dither)
+                    .map(|encoded| (encoded, center, zoom))
+            }) {
+            Ok((encoded, center, zoom)) => Response {
+                center: Some(center),
+                zoom: Some(zoom),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Bounds render failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_tile(&mut self, args: RenderTileArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderTileArgs {
+            z,
+            x,
+            y,
+            format,
+            dither,
+        } = args;
+        match renderer
+            .render_tile([z, x, y])
+            .map_err(|e| e.to_string())
+            .and_then(|(image, center, zoom)| {
+                encode_output(image.as_image(), format, dither)
+                    .map(|encoded| (encoded, center, zoom))
+            }) {
+            Ok((encoded, center, zoom)) => Response {
+                center: Some(center),
+                zoom: Some(zoom),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Tile render failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_scale(&mut self, args: RenderScaleArgs) -> Response {
+        let Session {
+            renderer,
+            output_format,
+            output_quality,
+            zoom_range,
+            ..
+        } = self;
+        let RenderScaleArgs {
+            center,
+            scale_denominator,
+            dpi,
+            bearing,
+            pitch,
+            format,
+            quality,
+            dither,
+        } = args;
+        if !(scale_denominator > 0.0 && dpi > 0.0) {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Scale render failed: scale_denominator and dpi must be positive",
+            );
+        }
+        let ratio = renderer.pixel_ratio().unwrap_or(1.0);
+        let zoom = camera::zoom_for_scale(scale_denominator, dpi, ratio, center[1]);
+        let values = camera::CameraValues {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        };
+        if let Err(e) = camera::check_finite(values) {
+            return Response::non_finite(format!("Scale render failed: {}", e));
+        }
+        let (center, pitch) = match camera::check_range(values) {
+            Ok(checked) => (checked.center, checked.pitch),
+            Err(e) => {
+                return Response::invalid_camera(format!("Scale render failed: {}", e));
+            }
+        };
+        let format = format.unwrap_or(*output_format);
+        let checked = zoom_range.apply(zoom).and_then(|zoom| {
+            check_quality(quality)?;
+            check_background(renderer.transparent_background(), format)?;
+            if quality.is_some() && format != OutputFormat::Jpeg {
+                return Err("quality only applies to jpeg output".to_string());
+            }
+            Ok(zoom)
+        });
+        let zoom = match checked {
+            Ok(zoom) => zoom,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Scale render failed: {}", e),
+                );
+            }
+        };
+        let quality = quality.unwrap_or(*output_quality);
+        match renderer
+            .render(center, zoom, bearing, pitch, camera::Padding::default())
+            .map_err(|e| (ErrorCode::RenderFailed, e.to_string()))
+            .and_then(|image| {
+                let image = image.as_image();
+                match format {
+                    OutputFormat::Png => {
+                        raster::encode_png_with_dpi(image, dpi).map(|bytes| Encoded {
+                            bytes,
+                            format,
+                            width: image.width(),
+                            height: image.height(),
+                            layout: None,
+                            quality: None,
+                            target_met: None,
+                        })
+                    }
+                    _ => encode_image(image, format, dither, quality),
+                }
+                .map_err(|e| (ErrorCode::EncodeFailed, e))
+            }) {
+            Ok(encoded) => Response {
+                zoom: Some(zoom),
+                dpi: Some(dpi),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err((code, e)) => Response::error(code, format!("Scale render failed: {}", e)),
+        }
+    }
+
+    fn render_dpi_set(&mut self, args: RenderDpiSetArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderDpiSetArgs {
+            camera,
+            width_mm,
+            height_mm,
+            dpis,
+        } = args;
+        if let Err(e) = camera::check_finite(camera::CameraValues {
+            center: camera.center,
+            zoom: camera.zoom,
+            bearing: camera.bearing,
+            pitch: camera.pitch,
+        }) {
+            return Response::non_finite(format!("DPI set render failed: {}", e));
+        }
+        let variants = match renderer.dpi_variants(&camera, [width_mm, height_mm], &dpis) {
+            Ok(variants) => variants,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("DPI set render failed: {}", e),
+                );
+            }
+        };
+        for (dpi, variant) in &variants {
+            let estimate = variant.estimate(variant.width.get(), variant.height.get());
+            if let Err(e) = estimate.check() {
+                return Response::size_too_large(format!(
+                    "DPI set render failed: {} dpi: {}",
+                    dpi, e
+                ));
+            }
+            let pixels = u64::from(estimate.output_width) * u64::from(estimate.output_height);
+            if pixels > MAX_DPI_VARIANT_PIXELS {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!(
+                        "DPI set render failed: {} dpi would be {} pixels, over the {} pixel \
+                         limit per variant",
+                        dpi, pixels, MAX_DPI_VARIANT_PIXELS
+                    ),
+                );
+            }
+        }
+        match renderer.render_dpi_set(&camera, &variants) {
+            Ok(mut variants) => {
+                let payloads: Vec<Vec<u8>> = variants
+                    .iter_mut()
+                    .map(|variant| std::mem::take(&mut variant.png))
+                    .collect();
+                Response {
+                    png_lengths: Some(payloads.iter().map(Vec::len).collect()),
+                    format: Some(OutputFormat::Png.name()),
+                    dpi_variants: Some(variants),
+                    ..Response::ok()
+                }
+                .with_payloads(payloads)
+            }
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("DPI set render failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_large(&mut self, args: RenderLargeArgs) -> Response {
+        let Session {
+            renderer,
+            max_large_render_pixels,
+            ..
+        } = self;
+        let RenderLargeArgs {
+            center,
+            zoom,
+            bearing,
+            pitch,
+            width,
+            height,
+            tile_size,
+            format,
+            dither,
+        } = args;
+        let values = camera::CameraValues {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        };
+        if let Err(e) = camera::check_finite(values) {
+            return Response::non_finite(format!("Large render failed: {}", e));
+        }
+        let ratio = renderer.pixel_ratio().unwrap_or(1.0);
+        let pixels =
+            (f64::from(width) * ratio).round() as u64 * (f64::from(height) * ratio).round() as u64;
+        let checked = camera::check_range(values).and_then(|_| {
+            if width == 0 || height == 0 {
+                Err("width and height must be non-zero".to_string())
+            } else if pitch != 0.0 {
+                Err(
+                    "pitched views cannot be tiled, as each tile would get its own \
+                     perspective; use pitch 0"
+                        .to_string(),
+                )
+            } else if pixels > *max_large_render_pixels {
+                Err(format!(
+                    "{}x{} at pixel ratio {} is {} pixels, over max_large_render_pixels \
+                     ({})",
+                    width, height, ratio, pixels, max_large_render_pixels
+                ))
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = checked {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!("Large render failed: {}", e),
+            );
+        }
+        match renderer
+            .render_large(center, zoom, bearing, [width, height], tile_size)
+            .map_err(|e| {
+                (
+                    ErrorCode::RenderFailed,
+                    format!("Large render failed: {}", e),
+                )
+            })
+            .and_then(|(image, grid)| {
+                encode_output(&image, format, dither)
+                    .map(|encoded| (encoded, grid))
+                    .map_err(|e| (ErrorCode::EncodeFailed, e))
+            }) {
+            Ok((encoded, grid)) => Response {
+                tile_grid: Some(grid),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err((code, e)) => Response::error(code, e),
+        }
+    }
+
+    fn set_layer_opacity(&mut self, args: SetLayerOpacityArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetLayerOpacityArgs { layer, opacity } = args;
+        match renderer
+            .apply_operations(&[style_edit::Operation::LayerOpacity { layer, opacity }])
+            .map_err(|(_, e)| e)
+        {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set layer opacity failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_layer_visibility(&mut self, args: SetLayerVisibilityArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetLayerVisibilityArgs { layer, visible } = args;
+        match renderer
+            .apply_operations(&[style_edit::Operation::LayerVisibility { layer, visible }])
+            .map_err(|(_, e)| e)
+        {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set layer visibility failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_layer_property(&mut self, args: SetLayerPropertyArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetLayerPropertyArgs {
+            layer,
+            property,
+            value,
+            kind,
+        } = args;
+        match renderer
+            .apply_operations(&[kind.operation(layer, property, value)])
+            .map_err(|(_, e)| e)
+        {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set layer property failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_layer_filter(&mut self, args: SetLayerFilterArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetLayerFilterArgs { layer, filter } = args;
+        match renderer
+            .apply_operations(&[style_edit::Operation::Filter { layer, filter }])
+            .map_err(|(_, e)| e)
+        {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set layer filter failed: {}", e),
+            ),
+        }
+    }
+
+    fn set_hillshade(&mut self, args: SetHillshadeArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let SetHillshadeArgs {
+            layer,
+            exaggeration,
+            illumination_direction,
+        } = args;
+        match renderer
+            .apply_operations(&[style_edit::Operation::Hillshade {
+                layer,
+                exaggeration,
+                illumination_direction,
+            }])
+            .map_err(|(_, e)| e)
+        {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Set hillshade failed: {}", e),
+            ),
+        }
+    }
+
+    fn transaction(&mut self, args: TransactionArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let TransactionArgs {
+            operations,
+            render,
+            format,
+            dither,
+        } = args;
+        match renderer.apply_operations(&operations) {
+            Err((failed_operation, e)) => Response {
+                failed_operation,
+                ..Response::error(
+                    ErrorCode::CommandFailed,
+                    format!("Transaction failed: {}", e),
+                )
+            },
+            Ok(()) => match render {
+                None => Response::ok(),
+                Some(view) => match renderer
+                    .render(
+                        view.center,
+                        view.zoom,
+                        view.bearing,
+                        view.pitch,
+                        camera::Padding::default(),
+                    )
+                    .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e)))
+                    .and_then(|image| {
+                        encode_output(image.as_image(), format, dither)
+                            .map_err(|e| (ErrorCode::EncodeFailed, e))
+                    }) {
+                    Ok(encoded) => Response::image(&encoded).with_payload(encoded.bytes),
+                    Err((code, e)) => Response::error(code, e),
+                },
+            },
+        }
+    }
+
+    #[cfg(feature = "s3")]
+    fn render_to_s3(&mut self, args: RenderToS3Args) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderToS3Args {
+            camera,
+            bucket,
+            key,
+            endpoint,
+            region,
+            format,
+            dither,
+        } = args;
+        let target = s3::Target {
+            bucket: &bucket,
+            key: &key,
+            endpoint: endpoint.as_deref(),
+            region: region.as_deref(),
         };
+        let content_type = match format {
+            OutputFormat::Png | OutputFormat::Png8 => "image/png",
+            OutputFormat::Rgba => "application/octet-stream",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        };
+        match renderer
+            .render(
+                camera.center,
+                camera.zoom,
+                camera.bearing,
+                camera.pitch,
+                camera::Padding::default(),
+            )
+            .map_err(|e| (ErrorCode::RenderFailed, format!("Render failed: {}", e)))
+            .and_then(|image| {
+                encode_output(image.as_image(), format, dither)
+                    .map_err(|e| (ErrorCode::EncodeFailed, e))
+            })
+            .and_then(|encoded| {
+                let len = encoded.bytes.len();
+                s3::put_object(&target, encoded.bytes, content_type)
+                    .map(|_| len)
+                    .map_err(|e| (ErrorCode::CommandFailed, e))
+            }) {
+            Ok(len) => Response {
+                format: Some(format.name()),
+                key: Some(key),
+                uploaded_bytes: Some(len),
+                ..Response::ok()
+            },
+            Err((code, e)) => Response::error(code, e),
+        }
+    }
 
-        match cmd {
-            Command::Init {
-                width,
-                height,
-                style,
-                pixel_ratio,
-                protocol_version,
-            } => {
-                if let Some(ref version) = protocol_version {
-                    if version != PROTOCOL_VERSION {
-                        send_response(&Response {
-                            status: "error".to_string(),
-                            png_len: None,
-                            png_lengths: None,
-                            error: Some(format!(
-                                "Protocol version mismatch: client={}, daemon={}",
-                                version, PROTOCOL_VERSION
-                            )),
-                        });
-                        continue;
-                    }
+    fn render_tile_archive(
+        &mut self,
+        args: RenderTileArchiveArgs,
+        unsynced_files: &mut Vec<PathBuf>,
+    ) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderTileArchiveArgs {
+            tiles,
+            bounds,
+            zooms,
+            tile_size,
+            output,
+            gzip,
+            format,
+            dither,
+        } = args;
+        if let Some(Err(e)) = bounds.map(camera::check_finite_bounds) {
+            return Response::non_finite(format!("Tile archive failed: {}", e));
+        }
+        let tiles = match (tiles.is_empty(), bounds, zooms) {
+            (false, None, None) => Ok(tiles),
+            (true, Some(bounds), Some(zooms)) => archive::coverage(bounds, zooms),
+            _ => Err("give either tiles or bounds with zooms".to_string()),
+        };
+        let tiles = tiles.and_then(|mut tiles| {
+            if !matches!(tile_size, 256 | 512) {
+                return Err("tile_size must be 256 or 512".to_string());
+            }
+            if tile_size == 256 && tiles.iter().any(|tile| tile[0] == 0) {
+                return Err("256-pixel tiles start at zoom 1".to_string());
+            }
+            tiles.sort_unstable();
+            tiles.dedup();
+            Ok(tiles)
+        });
+        let tiles = match tiles {
+            Ok(tiles) => tiles,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Tile archive failed: {}", e),
+                );
+            }
+        };
+        let result = match &output {
+            Some(path) => std::fs::File::create(path)
+                .map_err(|e| format!("Cannot create {}: {}", path, e))
+                .and_then(|file| {
+                    let mut archive = archive::Archive::new(io::BufWriter::new(file), gzip);
+                    write_tile_archive(renderer, &tiles, tile_size, format, dither, &mut archive)?;
+                    archive
+                        .finish()
+                        .and_then(|mut writer| writer.flush())
+                        .map_err(|e| format!("Writing {} failed: {}", path, e))?;
+                    unsynced_files.push(PathBuf::from(path));
+                    Ok(None)
+                }),
+            None => {
+                let mut archive = archive::Archive::new(Vec::new(), gzip);
+                write_tile_archive(renderer, &tiles, tile_size, format, dither, &mut archive)
+                    .and_then(|()| archive.finish().map_err(|e| e.to_string()))
+                    .map(Some)
+            }
+        };
+        match result {
+            Ok(bytes) => {
+                let response = Response {
+                    png_len: bytes.as_ref().map(Vec::len),
+                    format: Some(format.name()),
+                    tile_count: Some(tiles.len()),
+                    ..Response::ok()
+                };
+                match bytes {
+                    Some(bytes) => response.with_payload(bytes),
+                    None => response,
                 }
-                match renderer.init(width, height, &style, pixel_ratio) {
-                    Ok(_) => send_response(&Response {
-                        status: "ok".to_string(),
-                        png_len: None,
-                        png_lengths: None,
-                        error: None,
-                    }),
-                    Err(e) => send_response(&Response {
-                        status: "error".to_string(),
-                        png_len: None,
-                        png_lengths: None,
-                        error: Some(format!("Init failed: {}", e)),
-                    }),
+            }
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Tile archive failed: {}", e),
+            ),
+        }
+    }
+
+    fn reload_resources(&mut self, args: ReloadResourcesArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let ReloadResourcesArgs { sprites, glyphs } = args;
+        if glyphs {
+            return Response::error(
+                ErrorCode::InvalidCommand,
+                "Resource reload failed: MapLibre Native does not expose glyph cache \
+                 invalidation; use reload_style",
+            );
+        }
+        let result = if sprites {
+            renderer.reload_sprites().map(Some)
+        } else {
+            Ok(None)
+        };
+        match result {
+            Ok(sprite_images) => Response {
+                reloaded: Some(sprite_images.iter().map(|_| "sprites").collect()),
+                sprite_images,
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Resource reload failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_layer_groups(&mut self, args: RenderLayerGroupsArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderLayerGroupsArgs {
+            camera,
+            groups,
+            format,
+            dither,
+        } = args;
+        let document = renderer
+            .style_document()
+            .map_err(|e| format!("Layer group render needs the style JSON: {}", e));
+        let prepared = document.and_then(|document| {
+            renderer
+                .prepare_surface()
+                .map(|()| document)
+                .map_err(|e| format!("Layer group render failed: {}", e))
+        });
+        match prepared {
+            Ok(document) => {
+                let mut payloads = Vec::new();
+                let results = groups
+                    .into_iter()
+                    .map(|group| {
+                        match renderer
+                            .render_layer_group(&document, &group.layers, &camera)
+                            .map_err(|e| e.to_string())
+                            .and_then(|image| encode_output(image.as_image(), format, dither))
+                        {
+                            Ok(encoded) => {
+                                let result = GroupResult {
+                                    name: group.name,
+                                    png_len: Some(encoded.bytes.len()),
+                                    error: None,
+                                };
+                                payloads.push(encoded.bytes);
+                                result
+                            }
+                            Err(e) => GroupResult {
+                                name: group.name,
+                                png_len: None,
+                                error: Some(e),
+                            },
+                        }
+                    })
+                    .collect();
+                Response {
+                    groups: Some(results),
+                    format: Some(format.name()),
+                    ..Response::ok()
                 }
+                .with_payloads(payloads)
+            }
+            Err(e) => Response::error(ErrorCode::RenderFailed, e),
+        }
+    }
+
+    fn render_with_legend(&mut self, args: RenderWithLegendArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderWithLegendArgs {
+            camera,
+            legend,
+            format,
+            dither,
+        } = args;
+        if legend.width == 0 {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                "Legend render failed: legend width must be non-zero",
+            );
+        }
+        match renderer
+            .render_with_legend(&camera, &legend)
+            .map_err(|e| {
+                (
+                    ErrorCode::RenderFailed,
+                    format!("Legend render failed: {}", e),
+                )
+            })
+            .and_then(|(image, truncated)| {
+                encode_output(&image, format, dither)
+                    .map(|encoded| (encoded, truncated))
+                    .map_err(|e| (ErrorCode::EncodeFailed, e))
+            }) {
+            Ok((encoded, truncated)) => Response {
+                truncated: Some(truncated),
+                ..Response::image(&encoded)
             }
-            Command::Render {
+            .with_payload(encoded.bytes),
+            Err((code, e)) => Response::error(code, e),
+        }
+    }
+
+    fn render_zoom_ladder(&mut self, args: RenderZoomLadderArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderZoomLadderArgs {
+            center,
+            zooms,
+            bearing,
+            pitch,
+            columns,
+            format,
+            dither,
+        } = args;
+        let non_finite = zooms.iter().find_map(|&zoom| {
+            camera::check_finite(camera::CameraValues {
                 center,
                 zoom,
                 bearing,
                 pitch,
-            } => match renderer.render(center, zoom, bearing, pitch) {
-                Ok(image) => match encode_png(image) {
-                    Ok(png_bytes) => send_response_with_payload(
-                        &Response {
-                            status: "ok".to_string(),
-                            png_len: Some(png_bytes.len()),
-                            png_lengths: None,
-                            error: None,
-                        },
-                        &png_bytes,
-                    ),
-                    Err(e) => send_response(&Response {
-                        status: "error".to_string(),
-                        png_len: None,
-                        png_lengths: None,
-                        error: Some(e),
-                    }),
-                },
-                Err(e) => send_response(&Response {
-                    status: "error".to_string(),
-                    png_len: None,
-                    png_lengths: None,
-                    error: Some(format!("Render failed: {}", e)),
-                }),
+            })
+            .err()
+        });
+        if let Some(e) = non_finite {
+            return Response::non_finite(format!("Zoom ladder render failed: {}", e));
+        }
+        let columns = columns.unwrap_or_else(|| {
+            (1..=zooms.len())
+                .find(|columns| columns * columns >= zooms.len())
+                .unwrap_or(1)
+        });
+        let checked = if zooms.is_empty() || zooms.len() > MAX_LADDER_ZOOMS {
+            Err(format!("give between 1 and {} zooms", MAX_LADDER_ZOOMS))
+        } else if columns == 0 {
+            Err("columns must be at least 1".to_string())
+        } else {
+            Ok(())
+        };
+        if let Err(e) = checked {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!("Zoom ladder render failed: {}", e),
+            );
+        }
+        let ladder = renderer
+            .render_zoom_ladder(center, &zooms, bearing, pitch, columns)
+            .map_err(|e| e.to_string())
+            .and_then(|image| encode_output(&image, format, dither));
+        match ladder {
+            Ok(encoded) => Response {
+                columns: Some(columns.min(zooms.len())),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Zoom ladder render failed: {}", e),
+            ),
+        }
+    }
+
+    fn feature_counts(&mut self, args: FeatureCountsArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let FeatureCountsArgs {
+            camera,
+            layers,
+            limit,
+        } = args;
+        let size = renderer
+            .config
+            .as_ref()
+            .map(|config| [config.width.get() as f64, config.height.get() as f64])
+            .ok_or_else(|| "Renderer not initialized".to_string());
+        match size.and_then(|size| {
+            renderer.style_document().map(|style| {
+                let viewport = utfgrid::Viewport {
+                    center: camera.center,
+                    zoom: camera.zoom,
+                    bearing: camera.bearing,
+                    size,
+                };
+                utfgrid::feature_counts(&style, layers.as_deref(), &viewport, limit)
+            })
+        }) {
+            Ok((counts, truncated)) => Response {
+                feature_counts: Some(counts),
+                truncated: Some(truncated),
+                ..Response::ok()
             },
-            Command::ReloadStyle { style } => match renderer.reload_style(&style) {
-                Ok(_) => send_response(&Response {
-                    status: "ok".to_string(),
-                    png_len: None,
-                    png_lengths: None,
-                    error: None,
-                }),
-                Err(e) => send_response(&Response {
-                    status: "error".to_string(),
-                    png_len: None,
-                    png_lengths: None,
-                    error: Some(format!("Reload style failed: {}", e)),
-                }),
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Feature count failed: {}", e),
+            ),
+        }
+    }
+
+    fn query_features(&mut self, args: QueryFeaturesArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let QueryFeaturesArgs {
+            camera,
+            x,
+            y,
+            layers,
+        } = args;
+        let (width, height) = renderer
+            .config
+            .as_ref()
+            .map_or((0, 0), |config| (config.width.get(), config.height.get()));
+        if x >= width || y >= height {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!(
+                    "Feature query failed: pixel {}, {} is outside the {}x{} map",
+                    x, y, width, height
+                ),
+            );
+        }
+        let viewport = utfgrid::Viewport {
+            center: camera.center,
+            zoom: camera.zoom,
+            bearing: camera.bearing,
+            size: [width as f64, height as f64],
+        };
+        // Query the middle of the pixel.
+        let pixel = [x as f64 + 0.5, y as f64 + 0.5];
+        match renderer
+            .style_document()
+            .and_then(|style| utfgrid::features_at(&style, layers.as_deref(), &viewport, pixel))
+        {
+            Ok(features) => Response {
+                features: Some(features),
+                ..Response::ok()
             },
-            Command::RenderBatch { views } => {
-                let mut png_batches = Vec::with_capacity(views.len());
-                let mut png_lengths = Vec::with_capacity(views.len());
-                let mut error_response: Option<Response> = None;
-
-                for view in views {
-                    match renderer.render(view.center, view.zoom, view.bearing, view.pitch) {
-                        Ok(image) => match encode_png(image) {
-                            Ok(png_bytes) => {
-                                png_lengths.push(png_bytes.len());
-                                png_batches.push(png_bytes);
-                            }
-                            Err(_) => {
-                                error_response = Some(Response {
-                                    status: "error".to_string(),
-                                    png_len: None,
-                                    png_lengths: None,
-                                    error: Some("PNG encoding failed".to_string()),
-                                });
-                                break;
-                            }
-                        },
-                        Err(e) => {
-                            error_response = Some(Response {
-                                status: "error".to_string(),
-                                png_len: None,
-                                png_lengths: None,
-                                error: Some(format!("Batch render failed: {}", e)),
-                            });
-                            break;
-                        }
-                    }
-                }
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Feature query failed: {}", e),
+            ),
+        }
+    }
+
+    fn point_in_layer(&mut self, args: PointInLayerArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let PointInLayerArgs { point, layer, zoom } = args;
+        match renderer.point_in_layer(point, &layer, zoom) {
+            Ok((hit, properties)) => Response {
+                hit: Some(hit),
+                properties,
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Point query failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_geojson_diff(&mut self, args: RenderGeoJsonDiffArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderGeoJsonDiffArgs {
+            before,
+            after,
+            camera,
+            added_style,
+            removed_style,
+            changed_style,
+            format,
+            dither,
+        } = args;
+        let (features, summary) = match geojson_diff::diff(&before, &after) {
+            Ok(diff) => diff,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("GeoJSON diff failed: {}", e),
+                );
+            }
+        };
+        let source = serde_json::json!({
+            "type": "geojson",
+            "data": {"type": "FeatureCollection", "features": features},
+        });
+        let layers = geojson_diff::layers([
+            ("added", &added_style),
+            ("removed", &removed_style),
+            ("changed", &changed_style),
+        ]);
+        let result = renderer
+            .render_with_overlay(geojson_diff::SOURCE_ID, &source, &layers, &camera)
+            .map_err(|e| format!("Render failed: {}", e))
+            .and_then(|image| encode_output(image.as_image(), format, dither))
+            .map(|encoded| (encoded, summary));
+        match result {
+            Ok((encoded, summary)) => Response {
+                diff: Some(summary),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("GeoJSON diff failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_constrained(&mut self, args: RenderConstrainedArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderConstrainedArgs {
+            camera,
+            boundary,
+            mode,
+            format,
+            dither,
+        } = args;
+        let values = camera::CameraValues {
+            center: camera.center,
+            zoom: camera.zoom,
+            bearing: camera.bearing,
+            pitch: camera.pitch,
+        };
+        let size = renderer.logical_size().unwrap_or_default();
+        let constrained = match constrain::constrain(values, &boundary, mode, size) {
+            Ok(constrained) => constrained,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Constrained render failed: {}", e),
+                );
+            }
+        };
+        let values = constrained.camera;
+        let result = renderer
+            .render(
+                values.center,
+                values.zoom,
+                values.bearing,
+                values.pitch,
+                camera::Padding::default(),
+            )
+            .map_err(|e| format!("Render failed: {}", e))
+            .and_then(|image| encode_output(image.as_image(), format, dither))
+            .map(|encoded| (encoded, constrained));
+        match result {
+            Ok((encoded, constrained)) => Response {
+                constrained: Some(constrained),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Constrained render failed: {}", e),
+            ),
+        }
+    }
+
+    fn refresh_and_render(&mut self, args: RefreshAndRenderArgs) -> Response {
+        let Session {
+            renderer, tile_ttl, ..
+        } = self;
+        let RefreshAndRenderArgs {
+            refresh_sources,
+            camera,
+            format,
+            dither,
+        } = args;
+        let result = renderer
+            .refresh_sources(&refresh_sources)
+            .and_then(|()| {
+                renderer
+                    .render(
+                        camera.center,
+                        camera.zoom,
+                        camera.bearing,
+                        camera.pitch,
+                        camera::Padding::default(),
+                    )
+                    .map_err(|e| format!("Render failed: {}", e))
+            })
+            .and_then(|image| encode_output(image.as_image(), format, dither));
+        match result {
+            Ok(encoded) => Response {
+                refreshed_sources: Some(refresh_sources),
+                cache: tile_ttl.map(cache::hints),
+                ..Response::image(&encoded)
+            }
+            .with_payload(encoded.bytes),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Refresh and render failed: {}", e),
+            ),
+        }
+    }
 
-                if let Some(resp) = error_response {
-                    send_response(&resp);
+    fn render_track_animation(&mut self, args: RenderTrackAnimationArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderTrackAnimationArgs {
+            camera,
+            mut tracks,
+            frames,
+            fps,
+            out_of_range,
+            marker,
+            animation,
+            format,
+            dither,
+        } = args;
+        let times = track::frame_times(&mut tracks, frames).and_then(|times| {
+            if !(fps.is_finite() && fps > 0.0) {
+                return Err("fps must be positive".to_string());
+            }
+            if animation == track::Animation::Apng && format != OutputFormat::Png {
+                return Err("apng animations are always png".to_string());
+            }
+            Ok(times)
+        });
+        let times = match times {
+            Ok(times) => times,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Track animation failed: {}", e),
+                );
+            }
+        };
+        let layers = [track::layer(&marker)];
+        let result = times
+            .iter()
+            .map(|&time| {
+                let source = serde_json::json!({
+                    "type": "geojson",
+                    "data": track::features(&tracks, time, out_of_range),
+                });
+                renderer
+                    .render_with_overlay(track::SOURCE_ID, &source, &layers, &camera)
+                    .map_err(|e| format!("Render failed: {}", e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|images| (times, images));
+        let result = result.and_then(|(times, images)| match animation {
+            track::Animation::Frames => images
+                .iter()
+                .map(|image| encode_output(image.as_image(), format, dither))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|encoded| {
+                    let response = Response {
+                        png_lengths: Some(
+                            encoded.iter().map(|encoded| encoded.bytes.len()).collect(),
+                        ),
+                        format: Some(format.name()),
+                        frame_times: Some(times),
+                        ..Response::ok()
+                    };
+                    let payloads = encoded.into_iter().map(|e| e.bytes).collect();
+                    (response, payloads)
+                }),
+            track::Animation::Apng => {
+                let frames: Vec<&RgbaImage> = images.iter().map(|image| image.as_image()).collect();
+                raster::encode_apng(&frames, fps).map(|bytes| {
+                    let response = Response {
+                        png_len: Some(bytes.len()),
+                        format: Some("apng"),
+                        frame_times: Some(times),
+                        ..Response::ok()
+                    };
+                    (response, vec![bytes])
+                })
+            }
+        });
+        match result {
+            Ok((response, payloads)) => response.with_payloads(payloads),
+            Err(e) => Response::error(
+                ErrorCode::RenderFailed,
+                format!("Track animation failed: {}", e),
+            ),
+        }
+    }
+
+    fn render_path(&mut self, args: RenderPathArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let RenderPathArgs {
+            from,
+            to,
+            frames,
+            easing,
+            format,
+            dither,
+        } = args;
+        let camera = |view: &View| camera::CameraValues {
+            center: view.center,
+            zoom: view.zoom,
+            bearing: view.bearing,
+            pitch: view.pitch,
+        };
+        if let Some(e) = [&from, &to]
+            .into_iter()
+            .find_map(|view| camera::check_finite(camera(view)).err())
+        {
+            return Response::non_finite(format!("Path render failed: {}", e));
+        }
+        let cameras = match camera::path(camera(&from), camera(&to), frames, easing) {
+            Ok(cameras) => cameras,
+            Err(e) => {
+                return Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Path render failed: {}", e),
+                );
+            }
+        };
+        // A cancel keeps the frames rendered so far.
+        let mut cancelled = false;
+        let encoded = cameras
+            .iter()
+            .take_while(|_| {
+                cancelled = cancel::requested();
+                !cancelled
+            })
+            .map(|camera| {
+                let image = renderer
+                    .render(
+                        camera.center,
+                        camera.zoom,
+                        camera.bearing,
+                        camera.pitch,
+                        camera::Padding::default(),
+                    )
+                    .map_err(|e| (ErrorCode::RenderFailed, e.to_string()))?;
+                encode_output(image.as_image(), format, dither)
+                    .map_err(|e| (ErrorCode::EncodeFailed, e))
+            })
+            .collect::<Result<Vec<_>, _>>();
+        match encoded {
+            Ok(encoded) => Response {
+                png_lengths: Some(encoded.iter().map(|encoded| encoded.bytes.len()).collect()),
+                format: Some(format.name()),
+                ..if cancelled {
+                    Response::cancelled()
                 } else {
-                    send_response_with_chunks(
-                        &Response {
-                            status: "ok".to_string(),
-                            png_len: None,
-                            png_lengths: Some(png_lengths),
-                            error: None,
-                        },
-                        png_batches.iter().map(Vec::as_slice),
-                    );
+                    Response::ok()
                 }
             }
-            Command::Quit => break,
+            .with_payloads(encoded.into_iter().map(|encoded| encoded.bytes).collect()),
+            Err((code, e)) => Response::error(code, format!("Path render failed: {}", e)),
+        }
+    }
+
+    fn check_glyph_coverage(&mut self, args: CheckGlyphCoverageArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let CheckGlyphCoverageArgs {
+            font_stacks,
+            sample_texts,
+            glyphs,
+        } = args;
+        let glyphs_url = match glyphs {
+            Some(url) => Ok(url),
+            None => renderer.style_document().and_then(|style| {
+                style["glyphs"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| "style has no glyphs URL".to_string())
+            }),
+        };
+        match glyphs_url {
+            Ok(url) => Response {
+                glyph_coverage: Some(glyphs::check_coverage(&url, &font_stacks, &sample_texts)),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Glyph coverage check failed: {}", e),
+            ),
+        }
+    }
+
+    fn describe_style(&mut self) -> Response {
+        let renderer = &mut self.renderer;
+        match renderer.style_document() {
+            Ok(document) => Response {
+                style: Some(style_edit::describe(&document)),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::CommandFailed,
+                format!("Describe style failed: {}", e),
+            ),
+        }
+    }
+
+    fn get_style(&mut self) -> Response {
+        let renderer = &mut self.renderer;
+        match renderer.style_document() {
+            Ok(document) => Response {
+                style_json: Some(document),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(ErrorCode::CommandFailed, format!("Get style failed: {}", e)),
+        }
+    }
+
+    fn memory_stats(&mut self) -> Response {
+        let renderer = &mut self.renderer;
+        Response {
+            memory: Some(diagnostics::memory_stats(
+                renderer.renders,
+                renderer.surfaces_created,
+                !renderer.released(),
+            )),
+            ..Response::ok()
+        }
+    }
+
+    fn describe_view(&mut self, args: DescribeViewArgs) -> Response {
+        let renderer = &mut self.renderer;
+        let DescribeViewArgs {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        } = args;
+        let result = camera::validate(camera::CameraValues {
+            center,
+            zoom,
+            bearing,
+            pitch,
+        })
+        .and_then(|validation| {
+            let size = renderer.logical_size().ok_or("Renderer not initialized")?;
+            let pixel_ratio = renderer.pixel_ratio().ok_or("Renderer not initialized")?;
+            Ok(camera::describe_view(validation.camera, size, pixel_ratio))
+        });
+        match result {
+            Ok(view) => Response {
+                view: Some(view),
+                ..Response::ok()
+            },
+            Err(e) => Response::error(
+                ErrorCode::InvalidParameter,
+                format!("Describe view failed: {}", e),
+            ),
+        }
+    }
+}
+
+/// Start running a `pipeline`, which is answered once its last command has run.
+fn start_pipeline(
+    args: PipelineArgs,
+    pipeline: &mut Option<pipeline::Run>,
+    session_id: String,
+) -> Option<Response> {
+    let PipelineArgs {
+        commands,
+        continue_on_error,
+    } = args;
+    let id = REQUEST_ID.with(|current| current.borrow().clone());
+    let session_id = (!session_id.is_empty()).then_some(session_id);
+    match pipeline::Run::start(commands, continue_on_error, id, session_id) {
+        Ok(run) => {
+            *pipeline = Some(run);
+            None
+        }
+        Err(e) => Some(Response::error(
+            ErrorCode::InvalidParameter,
+            format!("Pipeline failed: {}", e),
+        )),
+    }
+}
+
+fn set_preset(
+    args: SetPresetArgs,
+    presets: &mut HashMap<String, serde_json::Map<String, serde_json::Value>>,
+) -> Response {
+    let SetPresetArgs { name, config } = args;
+    let stored = preset::validate(&config).and_then(|config| {
+        // Catch option errors now rather than at every render using it.
+        let mut command = config.clone();
+        command.insert("cmd".to_string(), "render".into());
+        serde_json::from_value::<Command>(command.into())
+            .map(|_| config)
+            .map_err(|e| format!("invalid render options: {}", e))
+    });
+    match stored {
+        Ok(config) => {
+            presets.insert(name, config);
+            Response::ok()
+        }
+        Err(e) => Response::error(
+            ErrorCode::CommandFailed,
+            format!("Set preset failed: {}", e),
+        ),
+    }
+}
+
+fn lint_style(args: LintStyleArgs) -> Response {
+    let LintStyleArgs { style } = args;
+    match Renderer::fetch_style_document(&style) {
+        Ok(document) => {
+            let report = lint::lint(&document);
+            let valid = !report
+                .violations
+                .iter()
+                .any(|violation| violation.severity == lint::Severity::Error);
+            Response {
+                valid: Some(valid),
+                violations: Some(report.violations),
+                spec_revision: Some(report.spec_revision),
+                ..Response::ok()
+            }
+        }
+        Err(e) => Response::error(
+            ErrorCode::CommandFailed,
+            format!("Lint style failed: {}", e),
+        ),
+    }
+}
+
+fn build_sprite(args: BuildSpriteArgs, unsynced_files: &mut Vec<PathBuf>) -> Response {
+    let BuildSpriteArgs {
+        icon_dir,
+        pixel_ratios,
+        output,
+    } = args;
+    match sprite::build(&icon_dir, &pixel_ratios, &output) {
+        Ok(outputs) => {
+            for output in &outputs {
+                unsynced_files.push(PathBuf::from(&output.png));
+                unsynced_files.push(PathBuf::from(&output.json));
+            }
+            Response {
+                sprite: Some(outputs),
+                ..Response::ok()
+            }
+        }
+        Err(e) => Response::error(
+            ErrorCode::CommandFailed,
+            format!("Sprite build failed: {}", e),
+        ),
+    }
+}
+
+fn validate_style(args: ValidateStyleArgs) -> Response {
+    let ValidateStyleArgs { style } = args;
+    match Renderer::validate_style(&style) {
+        Ok(()) => Response::ok(),
+        Err((error_code, e)) => Response {
+            error_code,
+            ..Response::error(
+                ErrorCode::CommandFailed,
+                format!("Style validation failed: {}", e),
+            )
+        },
+    }
+}
+
+fn validate_camera(args: ValidateCameraArgs) -> Response {
+    let ValidateCameraArgs {
+        center,
+        zoom,
+        bearing,
+        pitch,
+    } = args;
+    match camera::validate(camera::CameraValues {
+        center,
+        zoom,
+        bearing,
+        pitch,
+    }) {
+        Ok(validation) => Response {
+            validation: Some(validation),
+            ..Response::ok()
+        },
+        Err(e) => Response::error(
+            ErrorCode::InvalidParameter,
+            format!("Invalid camera: {}", e),
+        ),
+    }
+}
+
+fn estimate_resources(args: EstimateResourcesArgs) -> Response {
+    let EstimateResourcesArgs {
+        width,
+        height,
+        pixel_ratio,
+        render_mode,
+    } = args;
+    {
+        let checked = check_pixel_ratio(pixel_ratio).and_then(|()| {
+            if width == 0 || height == 0 {
+                return Err("width and height must be non-zero".to_string());
+            }
+            Ok(())
+        });
+        if let Err(e) = checked {
+            return Response::error(
+                ErrorCode::InvalidParameter,
+                format!("Resource estimate failed: {}", e),
+            );
+        }
+        let estimate = backend::estimate(
+            width,
+            height,
+            pixel_ratio,
+            render_mode.render_ratio(pixel_ratio),
+        );
+        // Too large a surface is an error, but still carries the estimate.
+        let base = match estimate.check() {
+            Ok(()) => Response::ok(),
+            Err(e) => Response {
+                error_code: Some("texture_too_large"),
+                ..Response::error(
+                    ErrorCode::InvalidParameter,
+                    format!("Resource estimate failed: {}", e),
+                )
+            },
+        };
+        Response {
+            estimate: Some(estimate),
+            ..base
+        }
+    }
+}
+
+fn flush(unsynced_files: &mut Vec<PathBuf>) -> Response {
+    if let Err(e) = io::stdout().lock().flush() {
+        shutdown::output_failed(&e);
+    }
+    let synced = unsynced_files.len();
+    match sync_files(std::mem::take(unsynced_files)) {
+        Ok(()) => Response {
+            synced_files: Some(synced),
+            ..Response::ok()
+        },
+        Err(e) => Response::error(ErrorCode::CommandFailed, format!("Flush failed: {}", e)),
+    }
+}
+
+fn main() {
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("mlnative-render: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if options.probe_backend {
+        // The daemon reports the probe's last line on stderr, so errors always get there.
+        diagnostics::install();
+        if !log::log_enabled!(log::Level::Error) {
+            diagnostics::set_level(log::LevelFilter::Error);
+        }
+        std::process::exit(backend::run_probe());
+    }
+    // Before any thread starts: a software fallback is selected through the
+    // environment, which is only safe to change while single-threaded.
+    let backend_check = backend::check();
+    diagnostics::install();
+    shutdown::install();
+    resources::install_loader(options.client_resources, options.offline);
+
+    let (tx, rx) = mpsc::channel();
+    spawn_reader(tx, options.delimiter, options.max_command_bytes);
+    let mut daemon = Daemon::new(backend_check);
+
+    loop {
+        // Signals take effect between commands, never mid-render.
+        if shutdown::requested() {
+            break;
+        }
+        // Checked on every pass, so a busy session can't keep idle ones loaded.
+        daemon.release_idle_sessions();
+        let input = match daemon.pipeline_input() {
+            Some(input) => input,
+            None => match rx.recv_timeout(shutdown::POLL_INTERVAL) {
+                Ok(input) => input,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        if let Flow::Quit = daemon.handle_input(input) {
+            break;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A daemon whose backend was never probed, as unit tests can't render.
+    fn daemon() -> Daemon {
+        Daemon::new(Err("not probed in unit tests".to_string()))
+    }
+
+    /// Handle `command` as if read from stdin, returning what the daemon sent back.
+    fn handle(daemon: &mut Daemon, command: serde_json::Value) -> (Flow, Vec<pipeline::Message>) {
+        let capture = pipeline::Run::start(Vec::new(), false, None, None).unwrap();
+        let flow = daemon.handle_input(pipeline_input(command));
+        (flow, capture.finish())
+    }
+
+    /// The single response `command` gets, which must not end the loop.
+    fn respond(daemon: &mut Daemon, command: serde_json::Value) -> serde_json::Value {
+        let (flow, mut messages) = handle(daemon, command);
+        assert!(matches!(flow, Flow::Continue));
+        assert_eq!(messages.len(), 1);
+        messages.remove(0).header
+    }
+
+    #[test]
+    fn oversized_payloads_are_skipped_unbuffered() {
+        let mut input = io::Cursor::new(b"abcdefgh{}".to_vec());
+        assert_eq!(
+            read_payload(&mut input, 3, 4).unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(read_payload(&mut input, 5, 4).unwrap(), None);
+        // The next command starts right after the skipped bytes.
+        assert_eq!(input.position(), 8);
+        assert!(read_payload(&mut input, 5, 4).is_err());
+    }
+
+    #[test]
+    fn quit_ends_the_loop_without_a_response() {
+        let (flow, messages) = handle(&mut daemon(), serde_json::json!({"cmd": "quit"}));
+        assert!(matches!(flow, Flow::Quit));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn render_before_init_is_not_initialized() {
+        let response = respond(
+            &mut daemon(),
+            serde_json::json!({"cmd": "render", "id": 7, "center": [0, 0], "zoom": 1}),
+        );
+        assert_eq!(response["status"], "error");
+        assert_eq!(response["code"], "not_initialized");
+        assert_eq!(response["id"], 7);
+    }
+
+    #[test]
+    fn unknown_command_is_invalid() {
+        let response = respond(&mut daemon(), serde_json::json!({"cmd": "draw"}));
+        assert_eq!(response["code"], "invalid_command");
+    }
+
+    #[test]
+    fn init_rejects_a_bad_pixel_ratio_before_starting_a_renderer() {
+        let mut daemon = daemon();
+        let response = respond(
+            &mut daemon,
+            serde_json::json!({
+                "cmd": "init", "width": 64, "height": 64, "style": "{}", "pixel_ratio": 0
+            }),
+        );
+        assert_eq!(response["code"], "invalid_parameter");
+        assert!(daemon.render_backend.is_none());
+    }
+
+    #[test]
+    fn init_rejects_another_major_protocol_version() {
+        let response = respond(
+            &mut daemon(),
+            serde_json::json!({
+                "cmd": "init", "width": 64, "height": 64, "style": "{}",
+                "protocol_version": "99.0"
+            }),
+        );
+        assert_eq!(response["error_code"], "protocol_mismatch");
+        assert_eq!(response["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn commands_naming_an_unknown_session_are_not_initialized() {
+        let mut daemon = daemon();
+        let response = respond(
+            &mut daemon,
+            serde_json::json!({"cmd": "close_session", "session_id": "tiles"}),
+        );
+        assert_eq!(response["code"], "invalid_parameter");
+        let response = respond(
+            &mut daemon,
+            serde_json::json!({"cmd": "reload_style", "style": "{}", "session_id": "tiles"}),
+        );
+        assert_eq!(response["code"], "not_initialized");
+        assert!(!daemon.sessions.contains_key("tiles"));
+    }
+
+    #[test]
+    fn estimate_resources_scales_the_surface_by_the_pixel_ratio() {
+        let response = respond(
+            &mut daemon(),
+            serde_json::json!({"cmd": "estimate_resources", "width": 300, "height": 200, "pixel_ratio": 2}),
+        );
+        assert_eq!(response["status"], "ok");
+        let estimate = &response["estimate"];
+        assert_eq!(estimate["texture_width"], 600);
+        assert_eq!(estimate["texture_height"], 400);
+        assert_eq!(estimate["framebuffer_bytes"], 600 * 400 * 4);
+    }
+
+    #[test]
+    fn estimate_resources_rejects_a_surface_over_the_texture_limit() {
+        let side = backend::MAX_TEXTURE_SIZE;
+        let response = respond(
+            &mut daemon(),
+            serde_json::json!({"cmd": "estimate_resources", "width": side, "height": side, "pixel_ratio": 2}),
+        );
+        assert_eq!(response["error_code"], "texture_too_large");
+        assert_eq!(response["estimate"]["fits"], false);
+        let response = respond(
+            &mut daemon(),
+            serde_json::json!({"cmd": "estimate_resources", "width": side, "height": side}),
+        );
+        assert_eq!(response["estimate"]["fits"], true);
+    }
+
+    /// A daemon rendering with the backend the system picks, skipping the probe.
+    fn rendering_daemon() -> Daemon {
+        Daemon::new(Ok((
+            backend::Backend {
+                name: backend::NAME,
+                mode: "default",
+            },
+            None,
+        )))
+    }
+
+    /// A background-only style that renders without network access.
+    const BLUE_STYLE: &str = r##"{"version":8,"sources":{},"layers":[
+        {"id":"background","type":"background","paint":{"background-color":"#336699"}}]}"##;
+
+    #[test]
+    #[ignore = "renders with MapLibre Native, which needs a Vulkan or Metal driver"]
+    fn init_starts_a_renderer() {
+        let mut daemon = rendering_daemon();
+        let response = respond(
+            &mut daemon,
+            serde_json::json!({"cmd": "init", "width": 64, "height": 32, "style": BLUE_STYLE}),
+        );
+        assert_eq!(response["status"], "ok", "{}", response);
+        assert_eq!(response["backend"]["name"], backend::NAME);
+        assert_eq!(response["protocol_version"], PROTOCOL_VERSION);
+        assert!(daemon.render_backend.is_some());
+        assert!(daemon.sessions[""].renderer.config.is_some());
+    }
+
+    #[test]
+    #[ignore = "renders with MapLibre Native, which needs a Vulkan or Metal driver"]
+    fn render_sends_the_image_after_its_header() {
+        let mut daemon = rendering_daemon();
+        respond(
+            &mut daemon,
+            serde_json::json!({"cmd": "init", "width": 64, "height": 32, "style": BLUE_STYLE}),
+        );
+        let (flow, mut messages) = handle(
+            &mut daemon,
+            serde_json::json!({"cmd": "render", "id": 3, "center": [0, 0], "zoom": 1}),
+        );
+        assert!(matches!(flow, Flow::Continue));
+        assert_eq!(messages.len(), 1);
+        let message = messages.remove(0);
+        assert_eq!(message.header["status"], "ok", "{}", message.header);
+        assert_eq!(message.header["id"], 3);
+        assert_eq!(message.header["format"], "png");
+        assert_eq!(message.header["png_len"], message.payload.len());
+        let image = image::load_from_memory(&message.payload)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (64, 32));
+        assert_eq!(image.get_pixel(32, 16).0, [0x33, 0x66, 0x99, 255]);
+    }
+}
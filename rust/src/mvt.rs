@@ -0,0 +1,242 @@
+//! Just enough Mapbox Vector Tile (protobuf) handling to count and cap the features
+//! in a tile for `max_features_per_tile`, without decoding geometry.
+
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+/// `Tile.layers`.
+const TILE_LAYERS: u64 = 3;
+/// `Layer.features`.
+const LAYER_FEATURES: u64 = 2;
+const WIRE_VARINT: u64 = 0;
+const WIRE_64BIT: u64 = 1;
+const WIRE_LENGTH: u64 = 2;
+const WIRE_32BIT: u64 = 5;
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// One protobuf field: its number, and the byte ranges of the whole field and of
+/// its payload when length-delimited.
+struct Field {
+    number: u64,
+    raw: std::ops::Range<usize>,
+    payload: Option<std::ops::Range<usize>>,
+}
+
+fn fields(data: &[u8]) -> Option<Vec<Field>> {
+    let mut pos = 0;
+    let mut fields = Vec::new();
+    while pos < data.len() {
+        let start = pos;
+        let key = read_varint(data, &mut pos)?;
+        let payload = match key & 7 {
+            WIRE_VARINT => {
+                read_varint(data, &mut pos)?;
+                None
+            }
+            WIRE_64BIT => {
+                pos += 8;
+                None
+            }
+            WIRE_32BIT => {
+                pos += 4;
+                None
+            }
+            WIRE_LENGTH => {
+                let length = usize::try_from(read_varint(data, &mut pos)?).ok()?;
+                let payload = pos..pos.checked_add(length)?;
+                pos = payload.end;
+                Some(payload)
+            }
+            _ => return None,
+        };
+        if pos > data.len() {
+            return None;
+        }
+        fields.push(Field {
+            number: key >> 3,
+            raw: start..pos,
+            payload,
+        });
+    }
+    Some(fields)
+}
+
+/// The tile bytes, gunzipped if needed, or `None` if they are an image or otherwise
+/// not a vector tile.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    const IMAGE_MAGIC: [&[u8]; 4] = [b"\x89PNG", b"\xff\xd8\xff", b"RIFF", b"GIF8"];
+    if IMAGE_MAGIC.iter().any(|magic| data.starts_with(magic)) {
+        return None;
+    }
+    let tile = if data.starts_with(&[0x1f, 0x8b]) {
+        let mut inflated = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut inflated).ok()?;
+        inflated
+    } else {
+        data.to_vec()
+    };
+    fields(&tile)?;
+    Some(tile)
+}
+
+/// Total features across all layers of a decoded tile.
+pub fn count_features(tile: &[u8]) -> usize {
+    fields(tile)
+        .into_iter()
+        .flatten()
+        .filter(|field| field.number == TILE_LAYERS)
+        .filter_map(|layer| fields(&tile[layer.payload?]))
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|field| field.number == LAYER_FEATURES)
+                .count()
+        })
+        .sum()
+}
+
+/// Re-encode a decoded tile keeping only the first `max` features, in layer order.
+pub fn truncate(tile: &[u8], max: usize) -> Vec<u8> {
+    let mut budget = max;
+    let mut out = Vec::with_capacity(tile.len());
+    for field in fields(tile).into_iter().flatten() {
+        let layer = field
+            .payload
+            .clone()
+            .filter(|_| field.number == TILE_LAYERS)
+            .and_then(|payload| fields(&tile[payload.clone()]).map(|inner| (payload, inner)));
+        let Some((payload, inner)) = layer else {
+            out.extend_from_slice(&tile[field.raw]);
+            continue;
+        };
+        let layer_bytes = &tile[payload];
+        let mut kept = Vec::with_capacity(layer_bytes.len());
+        for inner_field in inner {
+            if inner_field.number == LAYER_FEATURES {
+                if budget == 0 {
+                    continue;
+                }
+                budget -= 1;
+            }
+            kept.extend_from_slice(&layer_bytes[inner_field.raw]);
+        }
+        write_varint(&mut out, (TILE_LAYERS << 3) | WIRE_LENGTH);
+        write_varint(&mut out, kept.len() as u64);
+        out.extend_from_slice(&kept);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn length_field(number: u64, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, (number << 3) | WIRE_LENGTH);
+        write_varint(&mut out, payload.len() as u64);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A layer named `name` with `features` features, each holding only an id.
+    fn layer(name: &str, features: u64) -> Vec<u8> {
+        let mut layer = vec![(15 << 3) | WIRE_VARINT as u8, 2];
+        layer.extend(length_field(1, name.as_bytes()));
+        for id in 0..features {
+            layer.extend(length_field(
+                LAYER_FEATURES,
+                &[(1 << 3) | WIRE_VARINT as u8, id as u8],
+            ));
+        }
+        length_field(TILE_LAYERS, &layer)
+    }
+
+    fn tile() -> Vec<u8> {
+        [layer("water", 2), layer("roads", 3)].concat()
+    }
+
+    fn layer_names(tile: &[u8]) -> Vec<String> {
+        fields(tile)
+            .unwrap()
+            .into_iter()
+            .map(|layer| {
+                let inner = &tile[layer.payload.unwrap()];
+                let name = fields(inner)
+                    .unwrap()
+                    .into_iter()
+                    .find(|f| f.number == 1)
+                    .unwrap();
+                String::from_utf8(inner[name.payload.unwrap()].to_vec()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut pos = 0;
+            assert_eq!(read_varint(&out, &mut pos), Some(value));
+            assert_eq!(pos, out.len());
+        }
+        let mut out = Vec::new();
+        write_varint(&mut out, 300);
+        assert_eq!(out, [0xac, 0x02]);
+        assert_eq!(read_varint(&[0x80], &mut 0), None);
+    }
+
+    #[test]
+    fn features_are_counted_across_layers() {
+        assert_eq!(count_features(&tile()), 5);
+        assert_eq!(count_features(&[]), 0);
+    }
+
+    #[test]
+    fn truncation_keeps_the_first_features_in_layer_order() {
+        let tile = tile();
+        let truncated = truncate(&tile, 3);
+        assert_eq!(count_features(&truncated), 3);
+        assert_eq!(truncated, [layer("water", 2), layer("roads", 1)].concat());
+        assert_eq!(layer_names(&truncate(&tile, 0)), ["water", "roads"]);
+        assert_eq!(truncate(&tile, 10), tile);
+    }
+
+    #[test]
+    fn decode_inflates_gzip_and_rejects_images() {
+        let tile = tile();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&tile).unwrap();
+        assert_eq!(decode(&gzip.finish().unwrap()), Some(tile.clone()));
+        assert_eq!(decode(&tile), Some(tile));
+        assert_eq!(decode(b"\x89PNG\r\n\x1a\n"), None);
+        // Wire type 7 does not exist.
+        assert_eq!(decode(&[0x0f]), None);
+        // A length running past the end.
+        assert_eq!(decode(&[0x1a, 0x05, 0x00]), None);
+    }
+}
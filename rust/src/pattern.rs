@@ -0,0 +1,213 @@
+//! Procedural fill patterns (hatching and dots) for print cartography.
+//!
+//! A patterned fill layer is rendered alone as a solid mask, and the pattern is drawn
+//! through the mask's coverage, so it follows whatever geometry, filters and zoom
+//! rules the layer has without needing sprite images.
+
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// Parallel lines at `angle`.
+    Hatch,
+    /// Lines at `angle` and `angle + 90`.
+    CrossHatch,
+    /// A square grid of dots rotated by `angle`.
+    Dots,
+}
+
+/// A pattern replacing the solid colour of one fill layer.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FillPattern {
+    pub layer: String,
+    pub kind: Kind,
+    /// Distance between lines or dots, in logical pixels.
+    #[serde(default = "default_spacing")]
+    pub spacing: f64,
+    /// Degrees counter-clockwise from horizontal.
+    #[serde(default = "default_angle")]
+    pub angle: f64,
+    /// RGBA.
+    #[serde(default = "default_color")]
+    pub color: [u8; 4],
+    /// Line width or dot diameter, in logical pixels.
+    #[serde(default = "default_width")]
+    pub width: f64,
+}
+
+fn default_spacing() -> f64 {
+    8.0
+}
+
+fn default_angle() -> f64 {
+    45.0
+}
+
+fn default_color() -> [u8; 4] {
+    [0, 0, 0, 255]
+}
+
+fn default_width() -> f64 {
+    1.0
+}
+
+impl FillPattern {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.spacing.is_finite() && self.spacing > 0.0) {
+            return Err(format!(
+                "pattern for '{}': spacing must be positive",
+                self.layer
+            ));
+        }
+        if !(self.width.is_finite() && self.width > 0.0 && self.width < self.spacing) {
+            return Err(format!(
+                "pattern for '{}': width must be positive and less than spacing",
+                self.layer
+            ));
+        }
+        if !self.angle.is_finite() {
+            return Err(format!(
+                "pattern for '{}': angle must be finite",
+                self.layer
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copy of `layer` that draws its full coverage in opaque black, for the mask pass.
+    pub fn mask_layer(&self, layer: &Value) -> Result<Value, String> {
+        if layer["type"] != "fill" {
+            return Err(format!("layer '{}' is not a fill layer", self.layer));
+        }
+        let mut mask = layer.clone();
+        let paint = mask
+            .as_object_mut()
+            .ok_or_else(|| format!("layer '{}' is not an object", self.layer))?
+            .entry("paint")
+            .or_insert_with(|| json!({}));
+        let paint = paint
+            .as_object_mut()
+            .ok_or_else(|| format!("layer '{}' has an invalid paint", self.layer))?;
+        paint.remove("fill-pattern");
+        paint.remove("fill-outline-color");
+        paint.insert("fill-color".to_string(), json!("#000000"));
+        paint.insert("fill-opacity".to_string(), json!(1));
+        Ok(mask)
+    }
+
+    /// Fraction of the pixel centred at `point` (logical pixels) covered by the pattern,
+    /// with a one-pixel antialiased edge.
+    fn coverage(&self, point: [f64; 2], pixel: f64) -> f64 {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        // Image y grows downwards, so flip it to keep angles counter-clockwise.
+        let (x, y) = (point[0], -point[1]);
+        let along = x * cos + y * sin;
+        let across = -x * sin + y * cos;
+        let offset = |d: f64| (d - self.spacing * (d / self.spacing).round()).abs();
+        let edge = |distance: f64, half: f64| ((half - distance) / pixel + 0.5).clamp(0.0, 1.0);
+        let half = self.width / 2.0;
+        match self.kind {
+            Kind::Hatch => edge(offset(across), half),
+            Kind::CrossHatch => edge(offset(across), half).max(edge(offset(along), half)),
+            Kind::Dots => edge(offset(along).hypot(offset(across)), half),
+        }
+    }
+
+    /// The pattern clipped to the alpha of `mask`, at `pixel_ratio` image pixels per
+    /// logical pixel.
+    pub fn paint(&self, mask: &RgbaImage, pixel_ratio: f64) -> RgbaImage {
+        let pixel = 1.0 / pixel_ratio;
+        let [r, g, b, a] = self.color;
+        RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+            let mask_alpha = f64::from(mask.get_pixel(x, y)[3]) / 255.0;
+            if mask_alpha == 0.0 {
+                return Rgba([0, 0, 0, 0]);
+            }
+            let center = [(f64::from(x) + 0.5) * pixel, (f64::from(y) + 0.5) * pixel];
+            let alpha = f64::from(a) * mask_alpha * self.coverage(center, pixel);
+            Rgba([r, g, b, alpha.round() as u8])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(kind: Kind, angle: f64) -> FillPattern {
+        FillPattern {
+            layer: "parks".to_string(),
+            kind,
+            spacing: 8.0,
+            angle,
+            color: [255, 0, 0, 200],
+            width: 2.0,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_spacing_and_width() {
+        assert!(pattern(Kind::Hatch, 45.0).validate().is_ok());
+        let with = |spacing, width, angle| FillPattern {
+            spacing,
+            width,
+            angle,
+            ..pattern(Kind::Hatch, 0.0)
+        };
+        assert!(with(0.0, 1.0, 0.0).validate().is_err());
+        assert!(with(8.0, 8.0, 0.0).validate().is_err());
+        assert!(with(8.0, 0.0, 0.0).validate().is_err());
+        assert!(with(8.0, 1.0, f64::NAN).validate().is_err());
+    }
+
+    #[test]
+    fn mask_layer_draws_solid_black() {
+        let layer = json!({"id": "parks", "type": "fill", "paint": {
+            "fill-color": "green", "fill-opacity": 0.3, "fill-pattern": "grass", "fill-outline-color": "red"
+        }});
+        let mask = pattern(Kind::Hatch, 0.0).mask_layer(&layer).unwrap();
+        assert_eq!(
+            mask["paint"],
+            json!({"fill-color": "#000000", "fill-opacity": 1})
+        );
+        let line = json!({"id": "parks", "type": "line"});
+        assert!(pattern(Kind::Hatch, 0.0).mask_layer(&line).is_err());
+    }
+
+    #[test]
+    fn coverage_follows_each_kind() {
+        let hatch = pattern(Kind::Hatch, 0.0);
+        assert_eq!(hatch.coverage([3.0, 0.0], 1.0), 1.0);
+        assert_eq!(hatch.coverage([0.0, 4.0], 1.0), 0.0);
+        // Half a pixel past the line edge is half covered.
+        assert_eq!(hatch.coverage([0.0, 1.0], 1.0), 0.5);
+
+        let vertical = pattern(Kind::Hatch, 90.0);
+        assert!(vertical.coverage([0.0, 3.0], 1.0) > 0.99);
+        assert!(vertical.coverage([4.0, 0.0], 1.0) < 0.01);
+
+        let cross = pattern(Kind::CrossHatch, 0.0);
+        assert_eq!(cross.coverage([4.0, 0.0], 1.0), 1.0);
+        assert_eq!(cross.coverage([0.0, 4.0], 1.0), 1.0);
+        assert_eq!(cross.coverage([4.0, 4.0], 1.0), 0.0);
+
+        let dots = pattern(Kind::Dots, 0.0);
+        assert_eq!(dots.coverage([8.0, 8.0], 1.0), 1.0);
+        assert_eq!(dots.coverage([4.0, 0.0], 1.0), 0.0);
+    }
+
+    #[test]
+    fn paint_is_clipped_to_the_mask() {
+        let mut mask = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        mask.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        let painted = pattern(Kind::Hatch, 0.0).paint(&mask, 2.0);
+        // Row 0 is on the line at y = 0; the transparent mask pixel stays empty.
+        assert_eq!(*painted.get_pixel(0, 0), Rgba([255, 0, 0, 200]));
+        assert_eq!(*painted.get_pixel(1, 0), Rgba([0, 0, 0, 0]));
+        // Row 3 is 1.75 logical pixels from the line, clear of its half width.
+        assert_eq!(painted.get_pixel(0, 3)[3], 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! `pipeline`: run several commands in one round-trip and answer with all their
+//! responses.
+//!
+//! The main loop feeds the commands back through its usual input path one at a time,
+//! so each behaves exactly as if it had been sent on its own. While a pipeline runs,
+//! the messages they send are captured here instead of written, and go out together
+//! once it ends.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Commands a pipeline may not contain: itself, and those the reader thread or the
+/// process lifetime depend on.
+pub const EXCLUDED: [&str; 4] = ["pipeline", "quit", "cancel", "resource_response"];
+
+thread_local! {
+    /// Messages sent by the running pipeline's commands. Only the main thread runs
+    /// commands, so loader requests are never captured.
+    static CAPTURED: RefCell<Option<Vec<Message>>> = const { RefCell::new(None) };
+}
+
+/// One captured message: its JSON header and the payload bytes that followed it.
+pub struct Message {
+    pub header: serde_json::Value,
+    pub payload: Vec<u8>,
+}
+
+/// Whether messages are being captured for a pipeline.
+pub fn capturing() -> bool {
+    CAPTURED.with(|captured| captured.borrow().is_some())
+}
+
+/// Keep a message for the running pipeline.
+pub fn capture<'a>(header: &str, payloads: impl IntoIterator<Item = &'a [u8]>) {
+    let message = Message {
+        header: serde_json::from_str(header).unwrap_or(serde_json::Value::Null),
+        payload: payloads.into_iter().flatten().copied().collect(),
+    };
+    CAPTURED.with(|captured| {
+        if let Some(messages) = captured.borrow_mut().as_mut() {
+            messages.push(message);
+        }
+    });
+}
+
+/// A pipeline in progress.
+pub struct Run {
+    /// The pipeline's own `id`, for its final response.
+    pub id: Option<serde_json::Value>,
+    /// Given to commands that don't name a session of their own.
+    session_id: Option<String>,
+    commands: VecDeque<serde_json::Value>,
+    continue_on_error: bool,
+}
+
+impl Run {
+    /// Check `commands` and start capturing their messages.
+    pub fn start(
+        commands: Vec<serde_json::Value>,
+        continue_on_error: bool,
+        id: Option<serde_json::Value>,
+        session_id: Option<String>,
+    ) -> Result<Self, String> {
+        for (index, command) in commands.iter().enumerate() {
+            let name = command["cmd"]
+                .as_str()
+                .ok_or_else(|| format!("command {} is not an object with a cmd", index))?;
+            if EXCLUDED.contains(&name) {
+                return Err(format!(
+                    "command {} is {}, which a pipeline cannot run",
+                    index, name
+                ));
+            }
+        }
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+        Ok(Self {
+            id,
+            session_id,
+            commands: commands.into(),
+            continue_on_error,
+        })
+    }
+
+    /// The next command to run; `None` once all have run, or after one failed or was
+    /// cancelled unless `continue_on_error` is set.
+    pub fn next_command(&mut self) -> Option<serde_json::Value> {
+        let failed = CAPTURED.with(|captured| {
+            captured
+                .borrow()
+                .as_ref()
+                .and_then(|messages| messages.last())
+                .is_some_and(|message| {
+                    matches!(
+                        message.header["status"].as_str(),
+                        Some("error" | "cancelled")
+                    )
+                })
+        });
+        if failed && !self.continue_on_error {
+            self.commands.clear();
+        }
+        let mut command = self.commands.pop_front()?;
+        if let (Some(session_id), Some(object)) = (&self.session_id, command.as_object_mut()) {
+            object
+                .entry("session_id")
+                .or_insert_with(|| session_id.clone().into());
+        }
+        Some(command)
+    }
+
+    /// Stop capturing, returning the messages the commands sent in order.
+    pub fn finish(self) -> Vec<Message> {
+        CAPTURED
+            .with(|captured| captured.borrow_mut().take())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn respond(status: &str, payload: &[u8]) {
+        capture(&json!({"status": status}).to_string(), [payload]);
+    }
+
+    #[test]
+    fn excluded_and_malformed_commands_are_rejected() {
+        let start = |commands| Run::start(commands, false, None, None).err();
+        assert_eq!(
+            start(vec![json!({"cmd": "render"}), json!({"cmd": "quit"})]).as_deref(),
+            Some("command 1 is quit, which a pipeline cannot run")
+        );
+        assert_eq!(
+            start(vec![json!("render")]).as_deref(),
+            Some("command 0 is not an object with a cmd")
+        );
+        assert!(!capturing());
+    }
+
+    #[test]
+    fn commands_inherit_the_pipeline_session() {
+        let commands = vec![
+            json!({"cmd": "render"}),
+            json!({"cmd": "render", "session_id": "other"}),
+        ];
+        let mut run = Run::start(commands, false, Some(json!(3)), Some("main".into())).unwrap();
+        assert!(capturing());
+        assert_eq!(run.next_command().unwrap()["session_id"], "main");
+        respond("ok", b"png");
+        assert_eq!(run.next_command().unwrap()["session_id"], "other");
+        respond("ok", b"");
+        assert!(run.next_command().is_none());
+
+        assert_eq!(run.id, Some(json!(3)));
+        let messages = run.finish();
+        assert!(!capturing());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload, b"png");
+        assert_eq!(messages[1].header["status"], "ok");
+    }
+
+    #[test]
+    fn a_failure_stops_the_rest_unless_continuing() {
+        for (continue_on_error, expected) in [(false, 1), (true, 3)] {
+            let commands = vec![json!({"cmd": "render"}); 3];
+            let mut run = Run::start(commands, continue_on_error, None, None).unwrap();
+            let mut ran = 0;
+            while run.next_command().is_some() {
+                ran += 1;
+                respond("error", b"");
+            }
+            assert_eq!(ran, expected);
+            run.finish();
+        }
+    }
+
+    #[test]
+    fn messages_outside_a_pipeline_are_not_kept() {
+        respond("ok", b"ignored");
+        let mut run = Run::start(vec![], false, None, None).unwrap();
+        assert!(run.next_command().is_none());
+        assert!(run.finish().is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! Worker threads with renderers of their own, so `render_batch` can render views in
+//! parallel.
+//!
+//! MapLibre Native renderers are bound to the thread that built them, so each worker
+//! builds one from the session's `InitConfig` and loads the same style. Workers take
+//! views from a shared queue and encode them too; results come back as they finish,
+//! tagged with their view index.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::{
+    camera, camera_update, encode_image, profile, raster, Encoded, Frame, InitConfig, OutputFormat,
+    View,
+};
+
+/// A view's encoded image and how long it took, or why it failed.
+pub type ViewOutcome = Result<(Encoded, profile::Timing), String>;
+
+struct Job {
+    index: usize,
+    center: [f64; 2],
+    zoom: f64,
+    bearing: f64,
+    pitch: f64,
+    padding: camera::Padding,
+    format: OutputFormat,
+    quality: u8,
+    reply: mpsc::Sender<(usize, ViewOutcome)>,
+}
+
+pub struct Pool {
+    /// Session the workers' renderers were built for.
+    config: InitConfig,
+    jobs: Option<mpsc::Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Pool {
+    pub fn new(config: InitConfig, size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let config = config.clone();
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || work(&config, &receiver))
+            })
+            .collect();
+        Self {
+            config,
+            jobs: Some(sender),
+            workers,
+        }
+    }
+
+    /// Whether this pool renders as a new one for `config` and `size` would.
+    pub fn serves(&self, config: &InitConfig, size: usize) -> bool {
+        self.workers.len() == size && self.config == *config
+    }
+
+    /// Render and encode every view in the format and quality `encoding` picks for
+    /// it, passing each view's index and image or error to `each` as it finishes, in
+    /// completion order. Views `encoding` rejects are not rendered. Fails only if the
+    /// views can't be handed to the workers.
+    pub fn render(
+        &self,
+        views: &[View],
+        encoding: impl Fn(&View) -> Result<(OutputFormat, u8), String>,
+        mut each: impl FnMut(usize, ViewOutcome),
+    ) -> Result<(), String> {
+        let jobs = self.jobs.as_ref().ok_or("render pool is shut down")?;
+        let (reply, results) = mpsc::channel();
+        let mut pending = vec![false; views.len()];
+        for (index, view) in views.iter().enumerate() {
+            let (format, quality) = match encoding(view) {
+                Ok(encoding) => encoding,
+                Err(e) => {
+                    each(index, Err(e));
+                    continue;
+                }
+            };
+            jobs.send(Job {
+                index,
+                center: view.center,
+                zoom: view.zoom,
+                bearing: view.bearing,
+                pitch: view.pitch,
+                padding: view.padding.unwrap_or_default(),
+                format,
+                quality,
+                reply: reply.clone(),
+            })
+            .map_err(|_| "render workers have exited".to_string())?;
+            pending[index] = true;
+        }
+        drop(reply);
+
+        for (index, result) in results {
+            pending[index] = false;
+            each(index, result);
+        }
+        for (index, _) in pending.iter().enumerate().filter(|(_, pending)| **pending) {
+            each(index, Err("render worker exited".to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Closing the queue ends each worker's loop.
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(config: &InitConfig, jobs: &Mutex<mpsc::Receiver<Job>>) {
+    let build = || {
+        config
+            .build_renderer()
+            .map_err(|e| format!("Starting render worker failed: {}", e))
+    };
+    let mut renderer = build();
+    let mut tainted = false;
+    loop {
+        let job = match jobs.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+        if crate::cancel::requested() {
+            let _ = job
+                .reply
+                .send((job.index, Err(crate::cancel::CANCELLED.to_string())));
+            continue;
+        }
+        if tainted {
+            // The last render failed and may have left the surface in a bad state.
+            drop(renderer);
+            renderer = build();
+            tainted = false;
+        }
+        let started = Instant::now();
+        let result = match renderer.as_mut() {
+            Ok(renderer) => renderer
+                .render_static(&camera_update(
+                    job.center,
+                    job.zoom,
+                    job.bearing,
+                    job.pitch,
+                    job.padding,
+                ))
+                .map_err(|e| {
+                    tainted = true;
+                    e.to_string()
+                })
+                .and_then(|image| {
+                    let frame = Frame::new(image, Some(config));
+                    let rendered = Instant::now();
+                    encode_image(
+                        frame.as_image(),
+                        job.format,
+                        raster::Dither::default(),
+                        job.quality,
+                    )
+                    .map(|encoded| {
+                        let timing = profile::Timing::new(rendered - started, rendered.elapsed());
+                        (encoded, timing)
+                    })
+                }),
+            Err(e) => Err(e.clone()),
+        };
+        let _ = job.reply.send((job.index, result));
+    }
+}
@@ -0,0 +1,127 @@
+//! Named `render` configurations for `set_preset`, applied by `render` with `preset`.
+//!
+//! Presets are stored as the JSON of `render` options and resolved on the main thread,
+//! in command order, so a `render` always sees the presets set before it.
+
+use serde_json::{Map, Value};
+
+/// Keys of a `render` command that aren't render options: the command envelope and
+/// the preset selection.
+const CONTROL_KEYS: [&str; 4] = ["cmd", "id", "preset", "overrides"];
+
+/// Merge `top` over `base`: objects merge key by key, `null` removes a key (restoring
+/// its default), and anything else replaces the base value.
+fn merge(base: &mut Map<String, Value>, top: &Map<String, Value>) {
+    for (key, value) in top {
+        match (base.get_mut(key), value) {
+            (_, Value::Null) => {
+                base.remove(key);
+            }
+            (Some(Value::Object(base)), Value::Object(top)) => merge(base, top),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Check a preset configuration before storing it.
+pub fn validate(config: &Value) -> Result<Map<String, Value>, String> {
+    let config = config
+        .as_object()
+        .ok_or("preset config must be an object of render options")?;
+    if let Some(key) = CONTROL_KEYS.iter().find(|key| config.contains_key(**key)) {
+        return Err(format!("preset config cannot contain '{}'", key));
+    }
+    Ok(config.clone())
+}
+
+/// The `render` command for `command` (raw JSON with a `preset`): the preset's
+/// options, then the command's own options, then `overrides`, each merged over the
+/// last.
+pub fn resolve(
+    presets: &std::collections::HashMap<String, Map<String, Value>>,
+    command: &Value,
+) -> Result<Value, String> {
+    let name = command["preset"]
+        .as_str()
+        .ok_or("preset must be a string")?;
+    let mut resolved = presets
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("no preset named '{}'", name))?;
+    let mut own = command.as_object().cloned().unwrap_or_default();
+    own.retain(|key, _| !CONTROL_KEYS.contains(&key.as_str()));
+    merge(&mut resolved, &own);
+    match &command["overrides"] {
+        Value::Null => {}
+        Value::Object(overrides) => merge(&mut resolved, overrides),
+        _ => return Err("overrides must be an object".to_string()),
+    }
+    resolved.insert("cmd".to_string(), Value::from("render"));
+    Ok(Value::Object(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn presets() -> HashMap<String, Map<String, Value>> {
+        let thumbnail = validate(&json!({
+            "width": 256,
+            "height": 256,
+            "format": "webp",
+            "center": [0, 0],
+            "watermark": {"image": "logo.png", "opacity": 0.5}
+        }))
+        .unwrap();
+        HashMap::from([("thumbnail".to_string(), thumbnail)])
+    }
+
+    #[test]
+    fn commands_and_overrides_merge_over_the_preset() {
+        let command = json!({
+            "cmd": "render",
+            "id": 7,
+            "preset": "thumbnail",
+            "center": [10, 20],
+            "overrides": {"format": null, "watermark": {"opacity": 1.0}}
+        });
+        assert_eq!(
+            resolve(&presets(), &command).unwrap(),
+            json!({
+                "cmd": "render",
+                "width": 256,
+                "height": 256,
+                "center": [10, 20],
+                "watermark": {"image": "logo.png", "opacity": 1.0}
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_presets_and_bad_overrides_are_rejected() {
+        let presets = presets();
+        let resolve = |command: Value| resolve(&presets, &command).unwrap_err();
+        assert_eq!(
+            resolve(json!({"preset": "poster"})),
+            "no preset named 'poster'"
+        );
+        assert_eq!(resolve(json!({"preset": 1})), "preset must be a string");
+        assert_eq!(
+            resolve(json!({"preset": "thumbnail", "overrides": [1]})),
+            "overrides must be an object"
+        );
+    }
+
+    #[test]
+    fn configs_cannot_hold_control_keys() {
+        assert!(validate(&json!([1, 2])).is_err());
+        assert_eq!(
+            validate(&json!({"width": 1, "preset": "other"})).unwrap_err(),
+            "preset config cannot contain 'preset'"
+        );
+    }
+}
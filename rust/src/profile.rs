@@ -0,0 +1,163 @@
+use serde::Serialize;
+use std::time::Duration;
+
+/// Upper bounds (inclusive, in milliseconds) of the histogram buckets; a final
+/// open-ended bucket catches everything slower.
+const BUCKET_BOUNDS_MS: [f64; 12] = [
+    1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0,
+];
+
+#[derive(Debug, Serialize)]
+pub struct TimingSummary {
+    pub count: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistogramBucket {
+    /// Inclusive upper bound; `null` for the overflow bucket.
+    pub le_ms: Option<f64>,
+    pub count: usize,
+}
+
+/// Latency distribution of a `profile` run.
+///
+/// `cold` covers the first pass over the cameras (empty tile cache); `warm` and
+/// `histogram` cover the passes after the warmup ones.
+#[derive(Debug, Serialize)]
+pub struct ProfileReport {
+    pub iterations: u32,
+    pub warmup: u32,
+    pub cold: Option<TimingSummary>,
+    pub warm: Option<TimingSummary>,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+/// Time one image spent being rendered and then encoded.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Timing {
+    pub render_ms: f64,
+    pub encode_ms: f64,
+}
+
+impl Timing {
+    pub fn new(render: Duration, encode: Duration) -> Self {
+        Self {
+            render_ms: to_ms(&render),
+            encode_ms: to_ms(&encode),
+        }
+    }
+}
+
+fn to_ms(duration: &Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Nearest-rank percentile of an ascending slice.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn summarize(samples: &[Duration]) -> Option<TimingSummary> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<f64> = samples.iter().map(to_ms).collect();
+    sorted.sort_by(f64::total_cmp);
+    Some(TimingSummary {
+        count: sorted.len(),
+        mean_ms: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        p50_ms: percentile(&sorted, 50.0),
+        p90_ms: percentile(&sorted, 90.0),
+        p99_ms: percentile(&sorted, 99.0),
+        max_ms: sorted[sorted.len() - 1],
+    })
+}
+
+fn histogram(samples: &[Duration]) -> Vec<HistogramBucket> {
+    let mut buckets: Vec<HistogramBucket> = BUCKET_BOUNDS_MS
+        .iter()
+        .map(|&bound| HistogramBucket {
+            le_ms: Some(bound),
+            count: 0,
+        })
+        .chain(std::iter::once(HistogramBucket {
+            le_ms: None,
+            count: 0,
+        }))
+        .collect();
+    for ms in samples.iter().map(to_ms) {
+        let index = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        buckets[index].count += 1;
+    }
+    buckets
+}
+
+/// Build the report from per-iteration timings (one `Vec` per pass over the cameras).
+pub fn report(passes: &[Vec<Duration>], warmup: u32) -> ProfileReport {
+    let warm: Vec<Duration> = passes
+        .iter()
+        .skip(warmup as usize)
+        .flatten()
+        .copied()
+        .collect();
+    ProfileReport {
+        iterations: passes.len() as u32,
+        warmup,
+        cold: passes.first().and_then(|pass| summarize(pass)),
+        warm: summarize(&warm),
+        histogram: histogram(&warm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|&v| Duration::from_millis(v)).collect()
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 50.0), 5.0);
+        assert_eq!(percentile(&sorted, 90.0), 9.0);
+        assert_eq!(percentile(&sorted, 99.0), 10.0);
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&[7.0], 50.0), 7.0);
+    }
+
+    #[test]
+    fn warmup_passes_count_only_towards_cold() {
+        let passes = [ms(&[300, 100]), ms(&[4, 2]), ms(&[1, 9000])];
+        let report = report(&passes, 1);
+        assert_eq!(report.iterations, 3);
+        let cold = report.cold.unwrap();
+        assert_eq!((cold.count, cold.mean_ms, cold.max_ms), (2, 200.0, 300.0));
+        let warm = report.warm.unwrap();
+        assert_eq!(warm.count, 4);
+        assert_eq!(warm.p50_ms, 2.0);
+        assert_eq!(warm.max_ms, 9000.0);
+        let counts: Vec<_> = report.histogram.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(report.histogram.last().unwrap().le_ms, None);
+    }
+
+    #[test]
+    fn all_warmup_leaves_warm_empty() {
+        let report = report(&[ms(&[5])], 1);
+        assert!(report.cold.is_some());
+        assert!(report.warm.is_none());
+        assert!(report.histogram.iter().all(|b| b.count == 0));
+        assert!(super::report(&[], 0).cold.is_none());
+    }
+}
@@ -0,0 +1,800 @@
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+/// Memory layout of a raw pixel payload.
+///
+/// Guarantees: rows are `stride` bytes apart with no padding between the last row
+/// and the end of the buffer, so the payload length is always `stride * height`.
+/// MapLibre Native hands back straight (unpremultiplied) RGBA8, one byte per channel.
+#[derive(Debug, Clone, Serialize)]
+pub struct RawLayout {
+    pub stride: u32,
+    pub channel_order: &'static str,
+    pub premultiplied_alpha: bool,
+}
+
+/// Copy the rendered pixels out as tightly packed RGBA8 rows.
+pub fn raw_rgba(image: &RgbaImage) -> (Vec<u8>, RawLayout) {
+    let layout = RawLayout {
+        stride: image.width() * 4,
+        channel_order: "rgba",
+        premultiplied_alpha: false,
+    };
+    (image.as_raw().clone(), layout)
+}
+
+/// Alpha representation of the output pixels.
+///
+/// MapLibre Native reads frames back as straight (unassociated) alpha, so only
+/// `premultiplied` needs a conversion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaMode {
+    #[default]
+    Straight,
+    Premultiplied,
+}
+
+/// Multiply each colour channel by alpha in place, rounding to nearest.
+pub fn premultiply(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let alpha = u16::from(pixel[3]);
+        for c in 0..3 {
+            pixel[c] = ((u16::from(pixel[c]) * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Divide each colour channel by alpha in place, undoing `premultiply`.
+pub fn unpremultiply(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let alpha = u16::from(pixel[3]);
+        if alpha == 0 {
+            continue;
+        }
+        for c in 0..3 {
+            pixel[c] = ((u16::from(pixel[c]) * 255 + alpha / 2) / alpha).min(255) as u8;
+        }
+    }
+}
+
+/// How label text is antialiased.
+///
+/// MapLibre Native draws text from signed distance fields, which always produces
+/// grayscale coverage; `none` is applied after rendering by hardening the alpha of
+/// the symbol layers. Subpixel (LCD) antialiasing is not available.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextAntialiasing {
+    #[default]
+    Grayscale,
+    Subpixel,
+    #[serde(rename = "none")]
+    Off,
+}
+
+impl TextAntialiasing {
+    /// The mode actually used, with a warning if `self` had to fall back.
+    pub fn supported(self) -> (Self, Option<String>) {
+        match self {
+            TextAntialiasing::Subpixel => (
+                TextAntialiasing::Grayscale,
+                Some(
+                    "text_aa 'subpixel' is not supported by MapLibre Native; using 'grayscale'"
+                        .to_string(),
+                ),
+            ),
+            mode => (mode, None),
+        }
+    }
+}
+
+/// Snap every pixel to fully opaque or fully transparent at half coverage, removing
+/// antialiased edges. Colour is kept for pixels that stay visible.
+pub fn harden_alpha(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        if pixel[3] >= 128 {
+            pixel[3] = 255;
+        } else {
+            pixel.0 = [0; 4];
+        }
+    }
+}
+
+/// Resample by `factor` with Lanczos3, filtering in premultiplied alpha so
+/// transparent pixels don't bleed dark fringes into edges.
+pub fn resample(image: &RgbaImage, factor: f64) -> RgbaImage {
+    let width = ((image.width() as f64 * factor).round() as u32).max(1);
+    let height = ((image.height() as f64 * factor).round() as u32).max(1);
+    let mut premultiplied = image.clone();
+    premultiply(&mut premultiplied);
+    let mut scaled = image::imageops::resize(
+        &premultiplied,
+        width,
+        height,
+        image::imageops::FilterType::Lanczos3,
+    );
+    unpremultiply(&mut scaled);
+    scaled
+}
+
+/// Dithering applied when quantizing to a 256-colour palette.
+///
+/// Every mode is deterministic: the palette comes from NeuQuant over the whole image
+/// and error diffusion always walks rows top-to-bottom, left-to-right.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    None,
+    #[default]
+    Ordered,
+    FloydSteinberg,
+}
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+/// Amplitude of the ordered-dither threshold offset, in 8-bit channel units.
+const ORDERED_SPREAD: f32 = 32.0;
+const NEUQUANT_SAMPLE_FACTOR: i32 = 10;
+
+fn quantize(image: &RgbaImage, dither: Dither) -> (NeuQuant, Vec<u8>) {
+    let quant = NeuQuant::new(NEUQUANT_SAMPLE_FACTOR, 256, image.as_raw());
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut indices = Vec::with_capacity(width * height);
+
+    match dither {
+        Dither::None => {
+            indices.extend(image.pixels().map(|p| quant.index_of(&p.0) as u8));
+        }
+        Dither::Ordered => {
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let threshold = BAYER_4X4[y as usize % 4][x as usize % 4] / 16.0 - 0.5;
+                let mut rgba = pixel.0;
+                for channel in &mut rgba[..3] {
+                    *channel = (f32::from(*channel) + threshold * ORDERED_SPREAD)
+                        .round()
+                        .clamp(0.0, 255.0) as u8;
+                }
+                indices.push(quant.index_of(&rgba) as u8);
+            }
+        }
+        Dither::FloydSteinberg => {
+            // Carried error for the current and next row, RGB only; alpha is not dithered.
+            let mut current = vec![[0.0f32; 3]; width + 2];
+            let mut next = vec![[0.0f32; 3]; width + 2];
+            for y in 0..height {
+                for x in 0..width {
+                    let pixel = image.get_pixel(x as u32, y as u32).0;
+                    let mut wanted = [0.0f32; 3];
+                    let mut rgba = pixel;
+                    for c in 0..3 {
+                        wanted[c] = (f32::from(pixel[c]) + current[x + 1][c]).clamp(0.0, 255.0);
+                        rgba[c] = wanted[c].round() as u8;
+                    }
+                    let index = quant.index_of(&rgba);
+                    indices.push(index as u8);
+
+                    let chosen = quant.lookup(index).unwrap_or(rgba);
+                    for c in 0..3 {
+                        let error = wanted[c] - f32::from(chosen[c]);
+                        current[x + 2][c] += error * 7.0 / 16.0;
+                        next[x][c] += error * 3.0 / 16.0;
+                        next[x + 1][c] += error * 5.0 / 16.0;
+                        next[x + 2][c] += error / 16.0;
+                    }
+                }
+                std::mem::swap(&mut current, &mut next);
+                next.iter_mut().for_each(|e| *e = [0.0; 3]);
+            }
+        }
+    }
+
+    (quant, indices)
+}
+
+/// Encode as an 8-bit indexed PNG with a `tRNS` chunk for palette alpha.
+pub fn encode_png8(image: &RgbaImage, dither: Dither) -> Result<Vec<u8>, String> {
+    let (quant, indices) = quantize(image, dither);
+    let palette_rgba = quant.color_map_rgba();
+    let palette: Vec<u8> = palette_rgba
+        .chunks_exact(4)
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    let trns: Vec<u8> = palette_rgba.chunks_exact(4).map(|c| c[3]).collect();
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette);
+    encoder.set_trns(trns);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Failed to encode PNG8: {}", e))?;
+    writer
+        .write_image_data(&indices)
+        .map_err(|e| format!("Failed to encode PNG8: {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("Failed to encode PNG8: {}", e))?;
+    Ok(bytes)
+}
+
+/// Encode as an RGBA PNG with a `pHYs` chunk recording the print resolution.
+pub fn encode_png_with_dpi(image: &RgbaImage, dpi: f64) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels_per_meter = (dpi / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .write_image_data(image.as_raw())
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(bytes)
+}
+
+/// Transfer function of the colour channels in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorEncoding {
+    #[default]
+    Srgb,
+    /// Linear light, written as 16 bits per channel so dark tones keep their
+    /// precision.
+    Linear,
+}
+
+/// Decode an sRGB channel value to linear light scaled to 16 bits.
+fn srgb_to_linear16(value: u8) -> u16 {
+    let c = f64::from(value) / 255.0;
+    let linear = if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    };
+    (linear * 65535.0).round() as u16
+}
+
+/// Encode as a 16-bit RGBA PNG in linear light, tagged with a gamma of 1.0. Alpha
+/// is already linear and is only widened.
+pub fn encode_png16_linear(image: &RgbaImage) -> Result<Vec<u8>, String> {
+    let table: Vec<u16> = (0..=255).map(srgb_to_linear16).collect();
+    let samples: Vec<u8> = image
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            [
+                table[usize::from(r)],
+                table[usize::from(g)],
+                table[usize::from(b)],
+                u16::from(a) * 257,
+            ]
+        })
+        .flat_map(u16::to_be_bytes)
+        .collect();
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .write_image_data(&samples)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .finish()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(bytes)
+}
+
+/// Adam7 passes as `(x0, y0, dx, dy)`.
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Encode as an Adam7-interlaced RGBA PNG, which browsers draw progressively as it
+/// downloads. The `png` crate only writes non-interlaced image data, so the passes
+/// are filtered (Paeth) and deflated here and written as a single `IDAT`.
+pub fn encode_png_interlaced(image: &RgbaImage) -> Result<Vec<u8>, String> {
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let (width, height) = image.dimensions();
+    let mut filtered = Vec::new();
+    for (x0, y0, dx, dy) in ADAM7 {
+        if x0 >= width || y0 >= height {
+            continue;
+        }
+        let pass_width = (width - x0).div_ceil(dx);
+        let row_bytes = pass_width as usize * 4;
+        let mut previous = vec![0u8; row_bytes];
+        for y in (y0..height).step_by(dy as usize) {
+            let row: Vec<u8> = (x0..width)
+                .step_by(dx as usize)
+                .flat_map(|x| image.get_pixel(x, y).0)
+                .collect();
+            filtered.push(4);
+            filtered.extend((0..row_bytes).map(|i| {
+                let (left, upper_left) = if i >= 4 {
+                    (row[i - 4], previous[i - 4])
+                } else {
+                    (0, 0)
+                };
+                row[i].wrapping_sub(paeth(left, previous[i], upper_left))
+            }));
+            previous = row;
+        }
+    }
+    let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+    deflate
+        .write_all(&filtered)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    let data = deflate
+        .finish()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+
+    let mut info = png::Info::with_size(width, height);
+    info.color_type = png::ColorType::Rgba;
+    info.bit_depth = png::BitDepth::Eight;
+    info.interlaced = true;
+    let mut bytes = Vec::new();
+    let mut writer = png::Encoder::with_info(&mut bytes, info)
+        .and_then(|encoder| encoder.write_header())
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    writer
+        .write_chunk(png::chunk::IDAT, &data)
+        .and_then(|()| writer.finish())
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(bytes)
+}
+
+/// Encode `frames` as a looping animated PNG shown at `fps` frames per second.
+pub fn encode_apng(frames: &[&RgbaImage], fps: f64) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or("no frames to encode")?;
+    let delay_ms = (1000.0 / fps).round().clamp(1.0, f64::from(u16::MAX)) as u16;
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, first.width(), first.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .and_then(|()| encoder.set_frame_delay(delay_ms, 1000))
+        .map_err(|e| format!("Failed to encode APNG: {}", e))?;
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("Failed to encode APNG: {}", e))?;
+    for frame in frames {
+        writer
+            .write_image_data(frame.as_raw())
+            .map_err(|e| format!("Failed to encode APNG: {}", e))?;
+    }
+    writer
+        .finish()
+        .map_err(|e| format!("Failed to encode APNG: {}", e))?;
+    Ok(bytes)
+}
+
+/// Packed RGB8 of `image` composited over white, as JPEG output stores it.
+pub fn flatten_over_white(image: &RgbaImage) -> Vec<u8> {
+    image
+        .pixels()
+        .flat_map(|pixel| {
+            let alpha = u16::from(pixel[3]);
+            let over_white =
+                move |c: u8| ((u16::from(c) * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+            [
+                over_white(pixel[0]),
+                over_white(pixel[1]),
+                over_white(pixel[2]),
+            ]
+        })
+        .collect()
+}
+
+/// Encode as baseline JPEG at `quality` (1-100). JPEG has no alpha channel, so
+/// translucent pixels are flattened over white.
+pub fn encode_jpeg(image: &RgbaImage, quality: u8) -> Result<Vec<u8>, String> {
+    let rgb = flatten_over_white(image);
+    let mut bytes = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, quality)
+        .encode(
+            &rgb,
+            image.width(),
+            image.height(),
+            image::ExtendedColorType::Rgb8,
+        )
+        .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    Ok(bytes)
+}
+
+/// Encode as lossless WebP, keeping alpha. The `image` crate has no lossy WebP
+/// encoder, so there is no quality setting; expect roughly PNG-sized output.
+pub fn encode_webp(image: &RgbaImage) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    image::codecs::webp::WebPEncoder::new_lossless(&mut bytes)
+        .encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ExtendedColorType::Rgba8,
+        )
+        .map_err(|e| format!("Failed to encode WebP: {}", e))?;
+    Ok(bytes)
+}
+
+/// Run-length encoded mask of the partially transparent (antialiased edge) pixels.
+#[derive(Debug, Serialize)]
+pub struct EdgeMask {
+    pub width: u32,
+    pub height: u32,
+    /// Alternating run lengths over the pixels in row-major order, starting with a
+    /// run of non-edge pixels (0 if the first pixel is an edge).
+    pub runs: Vec<u32>,
+    pub edge_pixels: u64,
+}
+
+/// Mask of the pixels with alpha strictly between 0 and 255.
+pub fn edge_mask(image: &RgbaImage) -> EdgeMask {
+    let mut runs = vec![0u32];
+    let mut edge_pixels = 0;
+    for pixel in image.pixels() {
+        let edge = pixel[3] != 0 && pixel[3] != 255;
+        // Even-indexed runs are non-edge, odd-indexed runs are edge.
+        if edge != (runs.len() % 2 == 0) {
+            runs.push(0);
+        }
+        if let Some(run) = runs.last_mut() {
+            *run += 1;
+        }
+        edge_pixels += u64::from(edge);
+    }
+    EdgeMask {
+        width: image.width(),
+        height: image.height(),
+        runs,
+        edge_pixels,
+    }
+}
+
+/// Most buckets `report_histogram` may ask for: one per 8-bit value.
+pub const MAX_HISTOGRAM_BUCKETS: u32 = 256;
+
+/// Per-channel pixel counts, each channel split into `buckets` equal value ranges.
+#[derive(Debug, Serialize)]
+pub struct Histogram {
+    pub buckets: u32,
+    pub r: Vec<u64>,
+    pub g: Vec<u64>,
+    pub b: Vec<u64>,
+    /// Absent for RGB buffers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a: Option<Vec<u64>>,
+}
+
+/// Histogram of packed 8-bit pixels with `channels` of 3 (RGB) or 4 (RGBA).
+pub fn histogram(pixels: &[u8], channels: usize, buckets: u32) -> Result<Histogram, String> {
+    if !(1..=MAX_HISTOGRAM_BUCKETS).contains(&buckets) {
+        return Err(format!(
+            "histogram_buckets must be between 1 and {}",
+            MAX_HISTOGRAM_BUCKETS
+        ));
+    }
+    if !(channels == 3 || channels == 4) {
+        return Err(format!("cannot histogram {}-channel pixels", channels));
+    }
+    let mut counts = vec![vec![0u64; buckets as usize]; channels];
+    for pixel in pixels.chunks_exact(channels) {
+        for (channel, &value) in pixel.iter().enumerate() {
+            counts[channel][(u32::from(value) * buckets / 256) as usize] += 1;
+        }
+    }
+    let a = (channels == 4).then(|| counts.pop()).flatten();
+    let b = counts.pop().unwrap_or_default();
+    let g = counts.pop().unwrap_or_default();
+    let r = counts.pop().unwrap_or_default();
+    Ok(Histogram {
+        buckets,
+        r,
+        g,
+        b,
+        a,
+    })
+}
+
+/// What to do with a render that contains no data, i.e. only the background.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyTileBehavior {
+    /// Replace the image with fully transparent pixels.
+    Transparent,
+    /// Fill the image with the configured sentinel colour.
+    Color,
+    /// Fail the render.
+    Error,
+}
+
+/// `Init`-time policy for empty renders.
+#[derive(Debug, Clone, Copy)]
+pub struct EmptyTilePolicy {
+    pub behavior: EmptyTileBehavior,
+    pub color: [u8; 4],
+}
+
+/// A render is empty when every pixel matches the first one: nothing was drawn
+/// over the background (or the clear colour, when the style has no background).
+pub fn is_empty(image: &RgbaImage) -> bool {
+    let mut pixels = image.pixels();
+    match pixels.next() {
+        Some(first) => pixels.all(|p| p == first),
+        None => true,
+    }
+}
+
+impl EmptyTilePolicy {
+    /// Apply the policy in place, returning whether the image was empty.
+    pub fn apply(&self, image: &mut RgbaImage) -> Result<bool, String> {
+        if !is_empty(image) {
+            return Ok(false);
+        }
+        let fill = match self.behavior {
+            EmptyTileBehavior::Transparent => [0, 0, 0, 0],
+            EmptyTileBehavior::Color => self.color,
+            EmptyTileBehavior::Error => return Err("Render contains no data".to_string()),
+        };
+        for pixel in image.pixels_mut() {
+            pixel.0 = fill;
+        }
+        Ok(true)
+    }
+}
+
+/// A rectangle of a render, in output (physical) pixels.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    #[serde(alias = "width")]
+    pub w: u32,
+    #[serde(alias = "height")]
+    pub h: u32,
+}
+
+/// A named rectangle to cut from a render.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Crop {
+    pub id: String,
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+/// Copy `crop` out of `image`, rejecting empty rectangles or ones that leave the image.
+pub fn crop(image: &RgbaImage, crop: &Region) -> Result<RgbaImage, String> {
+    let fits = crop.w > 0
+        && crop.h > 0
+        && crop
+            .x
+            .checked_add(crop.w)
+            .is_some_and(|right| right <= image.width())
+        && crop
+            .y
+            .checked_add(crop.h)
+            .is_some_and(|bottom| bottom <= image.height());
+    if !fits {
+        return Err(format!(
+            "crop {}x{}+{}+{} is outside the {}x{} render",
+            crop.w,
+            crop.h,
+            crop.x,
+            crop.y,
+            image.width(),
+            image.height()
+        ));
+    }
+    Ok(image::imageops::crop_imm(image, crop.x, crop.y, crop.w, crop.h).to_image())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Composite premultiplied `src` over opaque `dst`: `src + dst * (1 - a)`.
+    fn over_premultiplied(src: Rgba<u8>, dst: [u8; 3]) -> [u8; 3] {
+        let inverse = 255 - u16::from(src[3]);
+        std::array::from_fn(|c| {
+            (u16::from(src[c]) + (u16::from(dst[c]) * inverse + 127) / 255) as u8
+        })
+    }
+
+    #[test]
+    fn interlaced_png_round_trips() {
+        // Odd sizes leave some Adam7 passes empty or partial.
+        let image =
+            RgbaImage::from_fn(13, 5, |x, y| Rgba([(x * 19) as u8, (y * 50) as u8, 7, 200]));
+        let bytes = encode_png_interlaced(&image).unwrap();
+        let decoder = png::Decoder::new(std::io::Cursor::new(&bytes));
+        let mut reader = decoder.read_info().unwrap();
+        assert!(reader.info().interlaced);
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, image.into_raw());
+    }
+
+    #[test]
+    fn linear_png_is_16_bit_linear_light() {
+        let image = RgbaImage::from_fn(3, 1, |x, _| {
+            let v = [0, 128, 255][x as usize];
+            Rgba([v, v, v, 128])
+        });
+        let bytes = encode_png16_linear(&image).unwrap();
+        let decoder = png::Decoder::new(std::io::Cursor::new(&bytes));
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().bit_depth, png::BitDepth::Sixteen);
+        assert_eq!(reader.info().gamma(), Some(png::ScaledFloat::new(1.0)));
+        let mut samples = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut samples).unwrap();
+        let channel = |pixel: usize, c: usize| {
+            let i = (pixel * 4 + c) * 2;
+            u16::from_be_bytes([samples[i], samples[i + 1]])
+        };
+        assert_eq!(channel(0, 0), 0);
+        // sRGB mid-grey is about 21.6% linear light.
+        assert_eq!(channel(1, 0), 14146);
+        assert_eq!(channel(2, 0), 65535);
+        assert_eq!(channel(1, 3), 128 * 257);
+    }
+
+    #[test]
+    fn webp_is_lossless_with_alpha() {
+        let image = RgbaImage::from_fn(9, 4, |x, y| {
+            Rgba([(x * 28) as u8, (y * 60) as u8, 90, (x * y) as u8])
+        });
+        let bytes = encode_webp(&image).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::WebP)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn raw_stride_times_height_is_the_payload_length() {
+        // An odd width, where a padded layout would show.
+        let image = RgbaImage::from_fn(7, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let (bytes, layout) = raw_rgba(&image);
+        assert_eq!(layout.stride, 7 * 4);
+        assert_eq!(layout.stride as usize * 3, bytes.len());
+        assert_eq!(layout.channel_order, "rgba");
+        assert!(!layout.premultiplied_alpha);
+        // Row 2 starts `2 * stride` bytes in.
+        let row = 2 * layout.stride as usize;
+        assert_eq!(&bytes[row..row + 4], &[0, 2, 0, 255]);
+    }
+
+    #[test]
+    fn png8_is_identical_across_runs_for_every_dither() {
+        // A gradient with partial alpha, so every mode has rounding to do.
+        let image = RgbaImage::from_fn(48, 32, |x, y| {
+            Rgba([
+                (x * 5) as u8,
+                (y * 8) as u8,
+                ((x + y) * 3) as u8,
+                128 + (x * 2) as u8,
+            ])
+        });
+        for dither in [Dither::None, Dither::Ordered, Dither::FloydSteinberg] {
+            let first = encode_png8(&image, dither).unwrap();
+            let second = encode_png8(&image.clone(), dither).unwrap();
+            assert_eq!(first, second, "{:?} output differs between runs", dither);
+
+            let decoder = png::Decoder::new(std::io::Cursor::new(first));
+            let reader = decoder.read_info().unwrap();
+            let info = reader.info();
+            assert_eq!(info.color_type, png::ColorType::Indexed);
+            assert!(info
+                .palette
+                .as_ref()
+                .is_some_and(|palette| !palette.is_empty()));
+        }
+    }
+
+    #[test]
+    fn edge_mask_runs_alternate_from_non_edge() {
+        let alphas = [128, 0, 255, 255, 10, 20, 0];
+        let image = RgbaImage::from_fn(7, 1, |x, _| Rgba([0, 0, 0, alphas[x as usize]]));
+        let mask = edge_mask(&image);
+        assert_eq!(mask.runs, [0, 1, 3, 2, 1]);
+        assert_eq!(mask.edge_pixels, 3);
+    }
+
+    #[test]
+    fn histogram_buckets_rgb_and_rgba() {
+        let rgba = [0, 127, 128, 255, 255, 0, 64, 255];
+        let halves = histogram(&rgba, 4, 2).unwrap();
+        assert_eq!(halves.r, [1, 1]);
+        assert_eq!(halves.g, [2, 0]);
+        assert_eq!(halves.b, [1, 1]);
+        assert_eq!(halves.a, Some(vec![0, 2]));
+
+        let rgb = histogram(&rgba[..6], 3, 256).unwrap();
+        assert_eq!((rgb.r[0], rgb.r[255], rgb.b[255]), (1, 1, 0));
+        assert!(rgb.a.is_none());
+        assert!(histogram(&rgba, 4, 0).is_err());
+    }
+
+    #[test]
+    fn premultiplied_red_edge_composites_without_halo() {
+        // Antialiased edge of a red shape on a transparent background.
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 128]));
+        premultiply(&mut image);
+        let edge = *image.get_pixel(0, 0);
+        assert_eq!(edge, Rgba([128, 0, 0, 128]));
+
+        // Over white, red must stay saturated; a dark halo would lower it.
+        assert_eq!(over_premultiplied(edge, [255, 255, 255]), [255, 127, 127]);
+        // Over black, the edge is exactly half-intensity red.
+        assert_eq!(over_premultiplied(edge, [0, 0, 0]), [128, 0, 0]);
+    }
+
+    #[test]
+    fn premultiply_keeps_opaque_and_clears_transparent() {
+        let mut image = RgbaImage::from_raw(2, 1, vec![10, 20, 30, 255, 200, 100, 50, 0]).unwrap();
+        premultiply(&mut image);
+        assert_eq!(image.as_raw(), &vec![10, 20, 30, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn harden_alpha_leaves_only_opaque_or_clear_pixels() {
+        let mut image = RgbaImage::from_raw(
+            3,
+            1,
+            vec![50, 60, 70, 200, 50, 60, 70, 128, 50, 60, 70, 127],
+        )
+        .unwrap();
+        harden_alpha(&mut image);
+        assert_eq!(
+            image.as_raw(),
+            &vec![50, 60, 70, 255, 50, 60, 70, 255, 0, 0, 0, 0]
+        );
+    }
+}
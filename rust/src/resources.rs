@@ -0,0 +1,748 @@
+use maplibre_native::{
+    register_file_source_callback, FsErrorReason, FsResponse, ResourceKind, ResourceOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const CLIENT_RESOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Retries of a failed network load unless `init` sets `max_retries`.
+pub const DEFAULT_MAX_RETRIES: u32 = 2;
+/// Wait before the first retry unless `init` sets `retry_backoff_ms`; it doubles for
+/// each retry after that.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RETRIES: u32 = 10;
+
+/// Resource bytes (or a failure status) supplied by the client.
+pub struct ResourceReply {
+    pub status_code: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct ResourceRequest<'a> {
+    status: &'static str,
+    id: u64,
+    url: &'a str,
+    kind: &'static str,
+}
+
+/// Forwards MapLibre Native resource requests to the client over stdout and
+/// waits for the matching `resource_response` command.
+struct ClientResourceBroker {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, Sender<ResourceReply>>>,
+    /// Tile requests waiting for the client.
+    pending_tiles: AtomicUsize,
+}
+
+static BROKER: OnceLock<ClientResourceBroker> = OnceLock::new();
+static OFFLINE: OnceLock<bool> = OnceLock::new();
+/// Set by `init` with `offline`; `--offline` refuses remote loads regardless.
+static INIT_OFFLINE: AtomicBool = AtomicBool::new(false);
+/// Whether the `file://`-only loader replaced MapLibre Native's network loader.
+static LOCAL_LOADER: OnceLock<()> = OnceLock::new();
+/// Whether a renderer has been built, after which the loader can no longer change.
+static LOADER_IN_USE: AtomicBool = AtomicBool::new(false);
+static TILE_GUARD: Mutex<Option<TileGuard>> = Mutex::new(None);
+static DENSE_TILES: Mutex<Vec<DenseTile>> = Mutex::new(Vec::new());
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+static RETRY: Mutex<RetryPolicy> = Mutex::new(RetryPolicy::DEFAULT);
+
+/// How transient failures of network loads are retried, set at `init` and installed
+/// for each command of the session.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT: Self = Self {
+        max_retries: DEFAULT_MAX_RETRIES,
+        backoff: DEFAULT_RETRY_BACKOFF,
+    };
+
+    /// Wait before retry `attempt` (0 for the first), doubling each time.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << attempt.min(16))
+    }
+}
+
+/// What happens to a vector tile with more than `max_features_per_tile` features.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DenseTileBehavior {
+    /// Keep the first features, in layer order, up to the limit.
+    #[default]
+    Drop,
+    /// Fail the tile, and the render that needed it.
+    Error,
+}
+
+/// The feature cap for vector tiles, from [`tile_guard`].
+#[derive(Debug, Clone, Copy)]
+pub struct TileGuard {
+    max_features: usize,
+    behavior: DenseTileBehavior,
+}
+
+/// MapLibre Native's SQLite cache of HTTP responses, set at `init`.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+}
+
+/// A vector tile that exceeded the feature limit.
+#[derive(Debug, Clone, Serialize)]
+pub struct DenseTile {
+    pub url: String,
+    pub features: usize,
+}
+
+impl ClientResourceBroker {
+    fn request(&self, url: &str, kind: ResourceKind) -> FsResponse {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
+        self.lock_pending().insert(id, tx);
+        let tile = matches!(kind, ResourceKind::Tile);
+        if tile {
+            self.pending_tiles.fetch_add(1, Ordering::Relaxed);
+        }
+
+        crate::send_message(
+            &ResourceRequest {
+                status: "resource_request",
+                id,
+                url,
+                kind: kind_name(kind),
+            },
+            [],
+        );
+        // Nobody can answer a request that was never delivered.
+        if crate::shutdown::output_closed() {
+            self.lock_pending().remove(&id);
+            if tile {
+                self.pending_tiles.fetch_sub(1, Ordering::Relaxed);
+            }
+            return FsResponse::Error {
+                reason: FsErrorReason::Connection,
+                message: format!("Cannot request {} from client: stdout is closed", url),
+            };
+        }
+
+        let reply = rx.recv_timeout(CLIENT_RESOURCE_TIMEOUT);
+        self.lock_pending().remove(&id);
+        if tile {
+            self.pending_tiles.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        match reply {
+            Ok(reply) => reply_to_fs_response(url, reply),
+            Err(_) => FsResponse::Error {
+                reason: FsErrorReason::Connection,
+                message: format!("No resource_response from client for {}", url),
+            },
+        }
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Sender<ResourceReply>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn kind_name(kind: ResourceKind) -> &'static str {
+    match kind {
+        ResourceKind::Style => "style",
+        ResourceKind::Source => "source",
+        ResourceKind::Tile => "tile",
+        ResourceKind::Glyphs => "glyphs",
+        ResourceKind::SpriteImage => "sprite_image",
+        ResourceKind::SpriteJSON => "sprite_json",
+        ResourceKind::Image => "image",
+        _ => "unknown",
+    }
+}
+
+fn reply_to_fs_response(url: &str, reply: ResourceReply) -> FsResponse {
+    match reply.status_code {
+        204 => FsResponse::NoContent,
+        200..=299 if reply.data.is_empty() => FsResponse::NoContent,
+        200..=299 => FsResponse::Ok(reply.data),
+        404 => FsResponse::Error {
+            reason: FsErrorReason::NotFound,
+            message: format!("Client reported 404 for {}", url),
+        },
+        429 => FsResponse::Error {
+            reason: FsErrorReason::RateLimit,
+            message: format!("Client reported 429 for {}", url),
+        },
+        500..=599 => FsResponse::Error {
+            reason: FsErrorReason::Server,
+            message: format!("Client reported {} for {}", reply.status_code, url),
+        },
+        code => FsResponse::Error {
+            reason: FsErrorReason::Other,
+            message: format!("Client reported {} for {}", code, url),
+        },
+    }
+}
+
+pub fn is_remote(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// `url` with a `mlnative_refresh` query parameter, so HTTP caches between here and
+/// the server can't answer a source refresh with stale data. Local URLs are unchanged.
+pub fn with_refresh_token(url: &str, token: u64) -> String {
+    if !is_remote(url) {
+        return url.to_string();
+    }
+    let (base, fragment) = url
+        .split_once('#')
+        .map_or((url, None), |(b, f)| (b, Some(f)));
+    let separator = if base.contains('?') { '&' } else { '?' };
+    let mut refreshed = format!("{}{}mlnative_refresh={}", base, separator, token);
+    if let Some(fragment) = fragment {
+        refreshed.push('#');
+        refreshed.push_str(fragment);
+    }
+    refreshed
+}
+
+/// Whether remote loads are refused, by `--offline` or the current `init`.
+pub fn offline() -> bool {
+    OFFLINE.get() == Some(&true) || INIT_OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Tiles requested from the client and not yet answered, or `None` without
+/// `--resource-loader client`: the other loaders don't report what is in flight.
+pub fn tiles_pending() -> Option<usize> {
+    BROKER
+        .get()
+        .map(|broker| broker.pending_tiles.load(Ordering::Relaxed))
+}
+
+/// Whether the daemon loads resources itself rather than MapLibre Native's network
+/// loader.
+fn daemon_loader() -> bool {
+    BROKER.get().is_some() || LOCAL_LOADER.get().is_some()
+}
+
+fn offline_rejection(url: &str) -> FsResponse {
+    let flag = if OFFLINE.get() == Some(&true) {
+        "--offline"
+    } else {
+        "offline"
+    };
+    FsResponse::Error {
+        reason: FsErrorReason::Connection,
+        message: format!("Network access disabled ({}): {}", flag, url),
+    }
+}
+
+/// Serve `file://` URLs from disk; anything else is unavailable offline.
+fn load_local(url: &str) -> FsResponse {
+    if is_remote(url) {
+        return offline_rejection(url);
+    }
+    let Some(path) = url.strip_prefix("file://") else {
+        return FsResponse::Error {
+            reason: FsErrorReason::Other,
+            message: format!("Unsupported URL scheme in offline mode: {}", url),
+        };
+    };
+    match std::fs::read(path) {
+        Ok(data) if data.is_empty() => FsResponse::NoContent,
+        Ok(data) => FsResponse::Ok(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => FsResponse::Error {
+            reason: FsErrorReason::NotFound,
+            message: format!("File not found: {}", path),
+        },
+        Err(e) => FsResponse::Error {
+            reason: FsErrorReason::Other,
+            message: format!("Cannot read {}: {}", path, e),
+        },
+    }
+}
+
+/// Install the resource loader selected on the command line.
+///
+/// `client` routes every resource MapLibre Native needs through the client. `offline`
+/// rejects `http(s)://` requests immediately instead of fetching them; without
+/// `client` it also replaces the default loader with one that only reads `file://`.
+/// Must run before the first renderer is built; the file source is process-global.
+pub fn install_loader(client: bool, offline: bool) {
+    let _ = OFFLINE.set(offline);
+    if client {
+        let broker = BROKER.get_or_init(|| ClientResourceBroker {
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            pending_tiles: AtomicUsize::new(0),
+        });
+        register_file_source_callback(move |url, kind| {
+            if self::offline() && is_remote(url) {
+                offline_rejection(url)
+            } else {
+                guard_tile(url, kind, broker.request(url, kind))
+            }
+        });
+    } else if offline {
+        install_local_loader();
+    }
+}
+
+fn install_local_loader() {
+    if LOCAL_LOADER.set(()).is_ok() {
+        register_file_source_callback(|url, kind| guard_tile(url, kind, load_local(url)));
+    }
+}
+
+/// Note that a renderer is being built with the installed loader.
+pub fn use_loader() {
+    LOADER_IN_USE.store(true, Ordering::Relaxed);
+}
+
+/// Check that `init`'s `offline` can take effect, without changing anything yet.
+///
+/// Without `--resource-loader client` or `--offline`, MapLibre Native's network loader
+/// has to be replaced with the `file://` one, which is only possible before the first
+/// renderer is built and can't be undone.
+pub fn check_offline(offline: bool) -> Result<(), String> {
+    if offline && !daemon_loader() && LOADER_IN_USE.load(Ordering::Relaxed) {
+        return Err(
+            "offline must be set on the first init, or start the daemon with \
+             --offline or --resource-loader client"
+                .to_string(),
+        );
+    }
+    if !offline
+        && BROKER.get().is_none()
+        && LOCAL_LOADER.get().is_some()
+        && OFFLINE.get() != Some(&true)
+    {
+        return Err(
+            "an earlier init set offline, which replaced the network loader; restart the \
+             daemon to load remote resources"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Refuse remote loads from now on, or allow them again, once [`check_offline`] passed.
+pub fn set_offline(offline: bool) {
+    if offline && !daemon_loader() {
+        install_local_loader();
+    }
+    INIT_OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// The cap on features per vector tile, or `None` for no cap, for an `init` with
+/// `offline` as given.
+///
+/// Tiles can only be inspected when the daemon loads them itself, i.e. with
+/// `--resource-loader client` or `--offline`; MapLibre Native's own network loader
+/// never hands tile bytes to the daemon.
+pub fn tile_guard(
+    max_features: Option<usize>,
+    behavior: DenseTileBehavior,
+    offline: bool,
+) -> Result<Option<TileGuard>, String> {
+    if max_features.is_some() && !offline && !daemon_loader() {
+        return Err(
+            "max_features_per_tile needs --resource-loader client or offline, \
+             so the daemon sees tile data"
+                .to_string(),
+        );
+    }
+    Ok(max_features.map(|max_features| TileGuard {
+        max_features,
+        behavior,
+    }))
+}
+
+/// Apply `guard` to the vector tiles loaded from now on.
+pub fn set_tile_guard(guard: Option<TileGuard>) {
+    *TILE_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = guard;
+}
+
+/// A cache database at `path`, capped at `size_mb`, or MapLibre Native's default cache
+/// with `None`, for an `init` with `offline` as given. Creates the database's directory.
+///
+/// The cache belongs to MapLibre Native's own network loader, so it can't be combined
+/// with `--resource-loader client` or `--offline`, which replace that loader.
+pub fn cache(
+    path: Option<&str>,
+    size_mb: Option<u64>,
+    offline: bool,
+) -> Result<Option<Cache>, String> {
+    match (path, size_mb) {
+        (_, Some(0)) => Err("cache_size_mb must be greater than 0".to_string()),
+        (None, Some(_)) => Err("cache_size_mb needs cache_path".to_string()),
+        (None, None) => Ok(None),
+        (Some(_), _) if offline || daemon_loader() => Err(
+            "cache_path needs the default resource loader; --resource-loader client \
+             and offline bypass the cache"
+                .to_string(),
+        ),
+        (Some(path), size_mb) => {
+            let path = PathBuf::from(path);
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(|e| {
+                    format!("Cannot create cache directory {}: {}", dir.display(), e)
+                })?;
+            }
+            Ok(Some(Cache {
+                path,
+                max_bytes: size_mb.map(|mb| mb * 1024 * 1024),
+            }))
+        }
+    }
+}
+
+/// Keep HTTP responses in `cache` for renderers built from now on.
+pub fn set_cache(cache: Option<Cache>) {
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = cache;
+}
+
+/// Retry failed network loads up to `max_retries` times, waiting `backoff_ms` before the
+/// first retry and twice as long before each one after it.
+pub fn retry(max_retries: u32, backoff_ms: u64) -> Result<RetryPolicy, String> {
+    if max_retries > MAX_RETRIES {
+        return Err(format!("max_retries must be at most {}", MAX_RETRIES));
+    }
+    Ok(RetryPolicy {
+        max_retries,
+        backoff: Duration::from_millis(backoff_ms),
+    })
+}
+
+/// Retry network loads from now on with `policy`.
+pub fn set_retry(policy: RetryPolicy) {
+    *RETRY.lock().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+/// The offline flag, cache and retry policy an `init` replaces, so a failed `init`
+/// can put them back.
+pub struct LoaderSettings {
+    offline: bool,
+    cache: Option<Cache>,
+    retry: RetryPolicy,
+}
+
+/// The settings in effect now.
+pub fn loader_settings() -> LoaderSettings {
+    LoaderSettings {
+        offline: INIT_OFFLINE.load(Ordering::Relaxed),
+        cache: CACHE.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        retry: *RETRY.lock().unwrap_or_else(|e| e.into_inner()),
+    }
+}
+
+/// Put back settings from [`loader_settings`]. A `file://` loader installed in the
+/// meantime stays, as it can't be removed.
+pub fn restore_loader_settings(settings: LoaderSettings) {
+    INIT_OFFLINE.store(settings.offline, Ordering::Relaxed);
+    set_cache(settings.cache);
+    set_retry(settings.retry);
+}
+
+/// The retry policy of the current session; none offline, where remote loads are
+/// refused rather than failing.
+pub fn retry_policy() -> RetryPolicy {
+    let policy = *RETRY.lock().unwrap_or_else(|e| e.into_inner());
+    if offline() {
+        return RetryPolicy {
+            max_retries: 0,
+            ..policy
+        };
+    }
+    policy
+}
+
+/// Whether a failed load is worth retrying, judged by the HTTP status in its message:
+/// timeouts, rate limiting and server errors are, other statuses (404 and the like)
+/// are not. Failures without a status, such as refused connections, are retried.
+pub fn is_transient(message: &str) -> bool {
+    let status = ["HTTP status code ", "Client reported "]
+        .iter()
+        .find_map(|prefix| {
+            let rest = &message[message.find(prefix)? + prefix.len()..];
+            rest.split(|c: char| !c.is_ascii_digit())
+                .next()?
+                .parse()
+                .ok()
+        });
+    status.is_none_or(is_transient_status)
+}
+
+fn is_transient_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500..=599)
+}
+
+/// Resource options for a new renderer, if `init` set a cache.
+pub fn options() -> Option<ResourceOptions> {
+    let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner()).clone()?;
+    let options = ResourceOptions::default().with_cache_path(cache.path);
+    Some(match cache.max_bytes {
+        Some(max_bytes) => options.with_maximum_cache_size(max_bytes),
+        None => options,
+    })
+}
+
+/// Dense tiles seen since the last call.
+pub fn take_dense_tiles() -> Vec<DenseTile> {
+    std::mem::take(&mut *DENSE_TILES.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Apply the tile guard to a loaded resource.
+fn guard_tile(url: &str, kind: ResourceKind, response: FsResponse) -> FsResponse {
+    let guard = *TILE_GUARD.lock().unwrap_or_else(|e| e.into_inner());
+    let (Some(guard), ResourceKind::Tile, FsResponse::Ok(data)) = (guard, kind, &response) else {
+        return response;
+    };
+    let Some(tile) = crate::mvt::decode(data) else {
+        return response;
+    };
+    let features = crate::mvt::count_features(&tile);
+    if features <= guard.max_features {
+        return response;
+    }
+    DENSE_TILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(DenseTile {
+            url: url.to_string(),
+            features,
+        });
+    match guard.behavior {
+        DenseTileBehavior::Drop => FsResponse::Ok(crate::mvt::truncate(&tile, guard.max_features)),
+        DenseTileBehavior::Error => FsResponse::Error {
+            reason: FsErrorReason::Other,
+            message: format!(
+                "Tile {} has {} features, over max_features_per_tile ({})",
+                url, features, guard.max_features
+            ),
+        },
+    }
+}
+
+/// Deliver a `resource_response` to the waiting request.
+///
+/// Replies for unknown or already timed-out ids are dropped.
+pub fn deliver(id: u64, reply: ResourceReply) {
+    let Some(broker) = BROKER.get() else {
+        return;
+    };
+    if let Some(tx) = broker.lock_pending().remove(&id) {
+        let _ = tx.send(reply);
+    }
+}
+
+/// Fail every pending request, e.g. once stdin is closed and no reply can arrive.
+pub fn fail_pending() {
+    if let Some(broker) = BROKER.get() {
+        broker.lock_pending().clear();
+    }
+}
+
+/// Fetch a resource for the daemon's own use (outside a render), honouring the
+/// installed loader: forwarded to the client in client mode, `--offline` rejects
+/// remote URLs, and `file://` is read from disk.
+pub fn fetch(url: &str, kind: ResourceKind) -> Result<Vec<u8>, String> {
+    let response = if let Some(broker) = BROKER.get() {
+        if offline() && is_remote(url) {
+            offline_rejection(url)
+        } else {
+            broker.request(url, kind)
+        }
+    } else if is_remote(url) && !offline() {
+        return fetch_http(url).inspect_err(|e| log::warn!("{}", e));
+    } else {
+        load_local(url)
+    };
+    match response {
+        FsResponse::Ok(data) => Ok(data),
+        FsResponse::NoContent => Ok(Vec::new()),
+        FsResponse::Error { message, .. } => {
+            log::warn!("{}", message);
+            Err(message)
+        }
+    }
+}
+
+/// GET `url`, retrying transient failures per the `init` retry policy.
+#[cfg(feature = "http")]
+fn fetch_http(url: &str) -> Result<Vec<u8>, String> {
+    let policy = retry_policy();
+    let mut attempt = 0;
+    loop {
+        match fetch_http_once(url) {
+            Err((true, _)) if attempt < policy.max_retries => {
+                std::thread::sleep(policy.delay(attempt));
+                attempt += 1;
+            }
+            result => return result.map_err(|(_, message)| message),
+        }
+    }
+}
+
+/// One GET of `url`; a failure says whether it is worth retrying.
+#[cfg(feature = "http")]
+fn fetch_http_once(url: &str) -> Result<Vec<u8>, (bool, String)> {
+    let response = reqwest::blocking::Client::new()
+        .get(url)
+        .timeout(CLIENT_RESOURCE_TIMEOUT)
+        .send()
+        .map_err(|e| (true, format!("Request to {} failed: {}", url, e)))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::NO_CONTENT {
+        return Ok(Vec::new());
+    }
+    if !status.is_success() {
+        return Err((
+            is_transient_status(status.as_u16()),
+            format!("{} returned {}", url, status),
+        ));
+    }
+    response
+        .bytes()
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| (true, format!("Reading {} failed: {}", url, e)))
+}
+
+/// Without an HTTP client, remote URLs can only be loaded by MapLibre Native itself
+/// or, with `--resource-loader client`, by the client.
+#[cfg(not(feature = "http"))]
+fn fetch_http(url: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "Cannot fetch {}: this build has no HTTP client (enable the `http` feature, or \
+         serve it with --resource-loader client)",
+        url
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(status_code: u16, data: &[u8]) -> FsResponse {
+        reply_to_fs_response(
+            "https://example.com/tile",
+            ResourceReply {
+                status_code,
+                data: data.to_vec(),
+            },
+        )
+    }
+
+    #[test]
+    fn client_statuses_map_to_file_source_responses() {
+        assert!(matches!(reply(200, b"tile"), FsResponse::Ok(data) if data == b"tile"));
+        assert!(matches!(reply(200, b""), FsResponse::NoContent));
+        assert!(matches!(reply(204, b"ignored"), FsResponse::NoContent));
+        assert!(matches!(
+            reply(404, b""),
+            FsResponse::Error {
+                reason: FsErrorReason::NotFound,
+                ..
+            }
+        ));
+        assert!(matches!(
+            reply(429, b""),
+            FsResponse::Error {
+                reason: FsErrorReason::RateLimit,
+                ..
+            }
+        ));
+        assert!(matches!(
+            reply(503, b""),
+            FsResponse::Error {
+                reason: FsErrorReason::Server,
+                ..
+            }
+        ));
+        // An oversized resource_response is delivered as 413.
+        assert!(matches!(
+            reply(413, b""),
+            FsResponse::Error { reason: FsErrorReason::Other, message }
+                if message == "Client reported 413 for https://example.com/tile"
+        ));
+    }
+
+    #[test]
+    fn refresh_tokens_go_before_the_fragment() {
+        assert_eq!(
+            with_refresh_token("https://a/t.json", 3),
+            "https://a/t.json?mlnative_refresh=3"
+        );
+        assert_eq!(
+            with_refresh_token("http://a/t.json?key=k#frag", 4),
+            "http://a/t.json?key=k&mlnative_refresh=4#frag"
+        );
+        assert_eq!(with_refresh_token("file:///t.json", 5), "file:///t.json");
+    }
+
+    #[test]
+    fn only_timeouts_rate_limits_and_server_errors_are_transient() {
+        assert!(is_transient("HTTP status code 503 for https://a"));
+        assert!(is_transient("Client reported 429 for https://a"));
+        assert!(is_transient("Connection refused"));
+        assert!(!is_transient("HTTP status code 404 for https://a"));
+        assert!(!is_transient("Client reported 403 for https://a"));
+    }
+
+    #[test]
+    fn retry_delays_double() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        };
+        let delays: Vec<_> = (0..3)
+            .map(|attempt| policy.delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400]);
+        assert!(retry(MAX_RETRIES + 1, 0).is_err());
+    }
+
+    #[test]
+    fn cache_sizes_need_a_path() {
+        assert_eq!(
+            cache(Some("cache.db"), Some(0), false).unwrap_err(),
+            "cache_size_mb must be greater than 0"
+        );
+        assert_eq!(
+            cache(None, Some(10), false).unwrap_err(),
+            "cache_size_mb needs cache_path"
+        );
+    }
+
+    #[test]
+    fn local_loads_read_file_urls_only() {
+        let path = std::env::temp_dir().join(format!("mlnative-resource-{}", std::process::id()));
+        std::fs::write(&path, b"{}").unwrap();
+        let url = format!("file://{}", path.display());
+        assert!(matches!(load_local(&url), FsResponse::Ok(data) if data == b"{}"));
+        std::fs::write(&path, b"").unwrap();
+        assert!(matches!(load_local(&url), FsResponse::NoContent));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            load_local(&url),
+            FsResponse::Error {
+                reason: FsErrorReason::NotFound,
+                ..
+            }
+        ));
+        assert!(matches!(
+            load_local("asset://style.json"),
+            FsResponse::Error { .. }
+        ));
+    }
+}
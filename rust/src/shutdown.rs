@@ -0,0 +1,64 @@
+//! Clean exit on SIGTERM and SIGINT, and once stdout can't be written.
+//!
+//! The handler only records the signal; the main loop checks for it between commands,
+//! so a command in progress finishes and its response is written before the daemon
+//! exits as it would on `quit`. A second signal exits at once, for a daemon stuck in a
+//! long render.
+//!
+//! A client that dies or closes its end of stdout leaves nobody to answer, so a failed
+//! write is treated the same way: nothing more is written, and the daemon exits with
+//! status 0 after the command in progress.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How often the main loop checks for a signal while waiting for a command.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set once a write to stdout failed.
+static OUTPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(signal: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // Only async-signal-safe calls are allowed here.
+        unsafe { libc::_exit(128 + signal) }
+    }
+}
+
+/// Install the handler for SIGTERM and SIGINT.
+pub fn install() {
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: `handle` only touches an atomic and calls `_exit`, both
+        // async-signal-safe.
+        unsafe {
+            libc::signal(signal, handler);
+        }
+    }
+}
+
+/// Whether a signal, or a failed write to stdout, asked the daemon to exit.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst) || output_closed()
+}
+
+/// Record that writing to stdout failed with `error`, so the daemon stops writing and
+/// exits.
+pub fn output_failed(error: &io::Error) {
+    if OUTPUT_CLOSED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    if error.kind() == io::ErrorKind::BrokenPipe {
+        log::info!("stdout was closed by the client; exiting");
+    } else {
+        log::warn!("writing to stdout failed ({}); exiting", error);
+    }
+}
+
+/// Whether stdout can no longer be written.
+pub fn output_closed() -> bool {
+    OUTPUT_CLOSED.load(Ordering::SeqCst)
+}
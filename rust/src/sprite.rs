@@ -0,0 +1,377 @@
+//! Sprite atlas generation for `build_sprite`.
+//!
+//! Icons are packed onto shelves, tallest first, and written as the `sprite.png` /
+//! `sprite.json` pair (plus `@2x` variants) that a style's `sprite` URL points at.
+//! Existing sprites can also be loaded at a chosen scale for `force_sprite_scale`.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use maplibre_native::ResourceKind;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::resources;
+
+/// Transparent gap between packed icons, so linear sampling never bleeds.
+const PADDING: u32 = 1;
+
+/// Files written for one pixel ratio.
+#[derive(Debug, Serialize)]
+pub struct SpriteOutput {
+    pub pixel_ratio: u32,
+    pub png: String,
+    pub json: String,
+    pub width: u32,
+    pub height: u32,
+    pub icons: usize,
+}
+
+/// Icon sources under `dir`, keyed by file stem.
+fn sources(dir: &Path) -> Result<BTreeMap<String, PathBuf>, String> {
+    let mut found = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries =
+            std::fs::read_dir(&dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            if !extension.eq_ignore_ascii_case("png") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if let Some(existing) = found.insert(name.to_string(), path.clone()) {
+                return Err(format!(
+                    "icon name '{}' is used by both {} and {}",
+                    name,
+                    existing.display(),
+                    path.display()
+                ));
+            }
+        }
+    }
+    if found.is_empty() {
+        return Err(format!("no PNG icons in {}", dir.display()));
+    }
+    Ok(found)
+}
+
+/// Scale one icon to `pixel_ratio`. Sources are treated as @1x artwork.
+fn scale_icon(path: &Path, pixel_ratio: u32) -> Result<RgbaImage, String> {
+    let icon = image::open(path)
+        .map_err(|e| format!("Cannot decode {}: {}", path.display(), e))?
+        .into_rgba8();
+    Ok(match pixel_ratio {
+        1 => icon,
+        ratio => imageops::resize(
+            &icon,
+            icon.width() * ratio,
+            icon.height() * ratio,
+            FilterType::CatmullRom,
+        ),
+    })
+}
+
+/// Shelf-pack `icons`, returning each icon's origin and the atlas size.
+fn pack(icons: &[(&String, RgbaImage)]) -> (Vec<(u32, u32)>, u32, u32) {
+    let area: u64 = icons
+        .iter()
+        .map(|(_, icon)| u64::from((icon.width() + PADDING) * (icon.height() + PADDING)))
+        .sum();
+    let widest = icons
+        .iter()
+        .map(|(_, icon)| icon.width())
+        .max()
+        .unwrap_or(0);
+    let shelf_width = ((area as f64).sqrt().ceil() as u32).max(widest + PADDING);
+
+    let mut order: Vec<usize> = (0..icons.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(icons[i].1.height()));
+    let mut origins = vec![(0, 0); icons.len()];
+    let (mut x, mut y, mut shelf_height, mut width) = (0, 0, 0, 0);
+    for i in order {
+        let icon = &icons[i].1;
+        if x + icon.width() > shelf_width {
+            x = 0;
+            y += shelf_height + PADDING;
+            shelf_height = 0;
+        }
+        origins[i] = (x, y);
+        x += icon.width() + PADDING;
+        shelf_height = shelf_height.max(icon.height());
+        width = width.max(x - PADDING);
+    }
+    (origins, width.max(1), (y + shelf_height).max(1))
+}
+
+fn build_one(
+    sources: &BTreeMap<String, PathBuf>,
+    pixel_ratio: u32,
+    output: &str,
+) -> Result<SpriteOutput, String> {
+    let icons = sources
+        .iter()
+        .map(|(name, path)| scale_icon(path, pixel_ratio).map(|icon| (name, icon)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (origins, width, height) = pack(&icons);
+
+    let mut atlas = RgbaImage::new(width, height);
+    let mut index = Map::new();
+    for ((name, icon), &(x, y)) in icons.iter().zip(&origins) {
+        imageops::replace(&mut atlas, icon, i64::from(x), i64::from(y));
+        index.insert(
+            name.to_string(),
+            json!({
+                "x": x,
+                "y": y,
+                "width": icon.width(),
+                "height": icon.height(),
+                "pixelRatio": pixel_ratio,
+            }),
+        );
+    }
+
+    let suffix = if pixel_ratio == 1 {
+        String::new()
+    } else {
+        format!("@{}x", pixel_ratio)
+    };
+    let png = format!("{}{}.png", output, suffix);
+    let json = format!("{}{}.json", output, suffix);
+    atlas
+        .save_with_format(&png, image::ImageFormat::Png)
+        .map_err(|e| format!("Cannot write {}: {}", png, e))?;
+    let text = serde_json::to_string_pretty(&Value::Object(index)).map_err(|e| e.to_string())?;
+    std::fs::write(&json, text).map_err(|e| format!("Cannot write {}: {}", json, e))?;
+    Ok(SpriteOutput {
+        pixel_ratio,
+        png,
+        json,
+        width,
+        height,
+        icons: icons.len(),
+    })
+}
+
+/// Build sprite sheets from the icons in `dir` at each of `pixel_ratios`, writing
+/// `<output>.png`/`.json` for @1x and `<output>@Nx.png`/`.json` otherwise.
+pub fn build(dir: &str, pixel_ratios: &[u32], output: &str) -> Result<Vec<SpriteOutput>, String> {
+    if pixel_ratios.is_empty() || pixel_ratios.iter().any(|ratio| !(1..=4).contains(ratio)) {
+        return Err("pixel ratios must be between 1 and 4".to_string());
+    }
+    let sources = sources(Path::new(dir))?;
+    pixel_ratios
+        .iter()
+        .map(|&ratio| build_one(&sources, ratio, output))
+        .collect()
+}
+
+/// One image cut from a style sprite, ready for `StyleRef::add_image`.
+pub struct SpriteIcon {
+    pub id: String,
+    pub image: DynamicImage,
+    pub pixel_ratio: f32,
+    pub sdf: bool,
+}
+
+/// `url` with MapLibre's scale suffix and `extension`, inserted before any query.
+fn variant_url(url: &str, scale: u32, extension: &str) -> String {
+    let suffix = if scale > 1 {
+        format!("@{}x{}", scale, extension)
+    } else {
+        extension.to_string()
+    };
+    match url.split_once('?') {
+        Some((path, query)) => format!("{}{}?{}", path, suffix, query),
+        None => format!("{}{}", url, suffix),
+    }
+}
+
+/// Fetch the `scale` variant of a style's `sprite` (a URL, or an array of
+/// `{id, url}` where non-default ids prefix their icons as `id:name`) and cut it
+/// into icons.
+pub fn load_icons(sprite: &Value, scale: u32) -> Result<Vec<SpriteIcon>, String> {
+    let sheets: Vec<(&str, &str)> = match sprite {
+        Value::String(url) => vec![("default", url.as_str())],
+        Value::Array(sheets) => sheets
+            .iter()
+            .map(|sheet| sheet["id"].as_str().zip(sheet["url"].as_str()))
+            .collect::<Option<_>>()
+            .ok_or("sprite entries need an id and url")?,
+        _ => return Err("style has no sprite".to_string()),
+    };
+    let mut icons = Vec::new();
+    for (sheet_id, url) in sheets {
+        let json_url = variant_url(url, scale, ".json");
+        let index: Map<String, Value> = resources::fetch(&json_url, ResourceKind::SpriteJSON)
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Cannot load {}: {}", json_url, e))?;
+        let png_url = variant_url(url, scale, ".png");
+        let atlas = resources::fetch(&png_url, ResourceKind::SpriteImage)
+            .and_then(|bytes| image::load_from_memory(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Cannot load {}: {}", png_url, e))?;
+        for (name, entry) in &index {
+            let rect = ["x", "y", "width", "height"].map(|key| entry[key].as_u64().unwrap_or(0));
+            let [x, y, width, height] = rect.map(|v| v as u32);
+            if width == 0 || height == 0 || x + width > atlas.width() || y + height > atlas.height()
+            {
+                continue;
+            }
+            icons.push(SpriteIcon {
+                id: if sheet_id == "default" {
+                    name.clone()
+                } else {
+                    format!("{}:{}", sheet_id, name)
+                },
+                image: atlas.crop_imm(x, y, width, height),
+                pixel_ratio: entry["pixelRatio"].as_f64().unwrap_or(1.0) as f32,
+                sdf: entry["sdf"].as_bool().unwrap_or(false),
+            });
+        }
+    }
+    Ok(icons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A fresh directory under the system temp dir for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mlnative-sprite-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_icon(path: &Path, width: u32, height: u32, color: [u8; 4]) {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+            .save(path)
+            .unwrap();
+    }
+
+    #[test]
+    fn packed_icons_never_overlap() {
+        let names: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+        let icons: Vec<_> = names
+            .iter()
+            .zip([(10, 4), (3, 12), (8, 8), (1, 1), (20, 2), (5, 5), (6, 9)])
+            .map(|(name, (w, h))| (name, RgbaImage::new(w, h)))
+            .collect();
+        let (origins, width, height) = pack(&icons);
+        let rects: Vec<[u32; 4]> = icons
+            .iter()
+            .zip(&origins)
+            .map(|((_, icon), &(x, y))| [x, y, x + icon.width(), y + icon.height()])
+            .collect();
+        for (i, a) in rects.iter().enumerate() {
+            assert!(
+                a[2] <= width && a[3] <= height,
+                "{:?} outside {}x{}",
+                a,
+                width,
+                height
+            );
+            for b in &rects[i + 1..] {
+                // Padded rects must not touch.
+                let apart = a[2] + PADDING <= b[0]
+                    || b[2] + PADDING <= a[0]
+                    || a[3] + PADDING <= b[1]
+                    || b[3] + PADDING <= a[1];
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        assert_eq!(pack(&[]), (vec![], 1, 1));
+    }
+
+    #[test]
+    fn build_writes_an_atlas_per_pixel_ratio() {
+        let dir = scratch("build");
+        std::fs::create_dir(dir.join("nested")).unwrap();
+        write_icon(&dir.join("park.png"), 4, 6, [0, 255, 0, 255]);
+        write_icon(&dir.join("nested/cafe.png"), 5, 5, [255, 0, 0, 255]);
+        std::fs::write(dir.join("README.txt"), "not an icon").unwrap();
+        let output = dir.join("out/sprite").to_string_lossy().into_owned();
+        std::fs::create_dir(dir.join("out")).unwrap();
+
+        let written = build(dir.to_str().unwrap(), &[1, 2], &output).unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(written[1].png, format!("{}@2x.png", output));
+        assert!(written.iter().all(|sheet| sheet.icons == 2));
+
+        let index: Value =
+            serde_json::from_str(&std::fs::read_to_string(&written[1].json).unwrap()).unwrap();
+        assert_eq!(index["park"]["width"], 8);
+        assert_eq!(index["park"]["height"], 12);
+        assert_eq!(index["cafe"]["pixelRatio"], 2);
+        let atlas = image::open(&written[1].png).unwrap().into_rgba8();
+        assert_eq!(atlas.dimensions(), (written[1].width, written[1].height));
+        let [x, y] = ["x", "y"].map(|key| index["cafe"][key].as_u64().unwrap() as u32);
+        assert_eq!(*atlas.get_pixel(x, y), Rgba([255, 0, 0, 255]));
+
+        // The @2x sheet can be read back the way `force_sprite_scale` loads it.
+        let url = format!("file://{}", output);
+        let icons = load_icons(&json!([{"id": "poi", "url": url}]), 2).unwrap();
+        let mut ids: Vec<_> = icons.iter().map(|icon| icon.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["poi:cafe", "poi:park"]);
+        assert!(icons
+            .iter()
+            .all(|icon| icon.pixel_ratio == 2.0 && !icon.sdf));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn build_rejects_bad_inputs() {
+        let dir = scratch("reject");
+        let path = dir.to_str().unwrap();
+        assert!(build(path, &[], "out").is_err());
+        assert!(build(path, &[5], "out").is_err());
+        assert_eq!(
+            build(path, &[1], "out").unwrap_err(),
+            format!("no PNG icons in {}", path)
+        );
+
+        std::fs::create_dir(dir.join("a")).unwrap();
+        write_icon(&dir.join("pin.png"), 2, 2, [0; 4]);
+        write_icon(&dir.join("a/pin.png"), 2, 2, [0; 4]);
+        assert!(build(path, &[1], "out")
+            .unwrap_err()
+            .starts_with("icon name 'pin' is used by both"));
+
+        // Only PNGs are icons; other files are skipped.
+        std::fs::remove_file(dir.join("a/pin.png")).unwrap();
+        std::fs::write(dir.join("a/star.svg"), "<svg/>").unwrap();
+        let output = dir.join("out");
+        let written = build(path, &[1], output.to_str().unwrap()).unwrap();
+        assert_eq!(written[0].icons, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn variant_urls_keep_the_query() {
+        assert_eq!(
+            variant_url("https://a/sprite", 1, ".json"),
+            "https://a/sprite.json"
+        );
+        assert_eq!(
+            variant_url("https://a/sprite?key=1", 2, ".png"),
+            "https://a/sprite@2x.png?key=1"
+        );
+        assert!(load_icons(&json!(null), 1).is_err());
+        assert!(load_icons(&json!([{"id": "x"}]), 1).is_err());
+    }
+}
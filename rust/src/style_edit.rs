@@ -0,0 +1,1132 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::lint;
+
+/// Id of the layer `Background::Color` inserts beneath the style's own layers.
+pub const BACKGROUND_LAYER_ID: &str = "mlnative-background";
+
+/// Upper bounds applied to symbol layer sizes before the style is loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct SizeCaps {
+    pub max_icon_size: Option<f64>,
+    pub max_text_size: Option<f64>,
+}
+
+impl SizeCaps {
+    pub fn is_empty(&self) -> bool {
+        self.max_icon_size.is_none() && self.max_text_size.is_none()
+    }
+}
+
+/// Rewrites `init` asks for, applied to every style it and `reload_style` load.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct StyleRewrites {
+    #[serde(flatten)]
+    pub size_caps: SizeCaps,
+    #[serde(default)]
+    pub background: Option<Background>,
+    /// Replace the style's `glyphs` URL template.
+    #[serde(default)]
+    pub glyphs: Option<GlyphsUrl>,
+    /// Replace the style's `sprite` URL.
+    #[serde(default)]
+    pub sprite: Option<SpriteUrl>,
+    /// Label symbol layers in this language where features have a name in it.
+    #[serde(default)]
+    pub language: Option<Language>,
+}
+
+impl StyleRewrites {
+    pub fn is_empty(&self) -> bool {
+        self.size_caps.is_empty()
+            && self.background.is_none()
+            && self.glyphs.is_none()
+            && self.sprite.is_none()
+            && self.language.is_none()
+    }
+}
+
+/// A glyphs URL template with the `{fontstack}` and `{range}` placeholders MapLibre
+/// fills in for each glyph request.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct GlyphsUrl(pub String);
+
+impl TryFrom<String> for GlyphsUrl {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        check_url("glyphs", &value)?;
+        for placeholder in ["{fontstack}", "{range}"] {
+            if !value.contains(placeholder) {
+                return Err(format!(
+                    "glyphs must contain {}, got '{}'",
+                    placeholder, value
+                ));
+            }
+        }
+        Ok(GlyphsUrl(value))
+    }
+}
+
+/// A sprite URL, without the `.json`/`.png` extension MapLibre appends.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SpriteUrl(pub String);
+
+impl TryFrom<String> for SpriteUrl {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        check_url("sprite", &value)?;
+        Ok(SpriteUrl(value))
+    }
+}
+
+/// A language code such as `de` or `zh-Hans`, naming the `name:<code>` feature
+/// property labels should prefer.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Language(pub String);
+
+impl TryFrom<String> for Language {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let mut subtags = value.split('-');
+        let valid = subtags.next().is_some_and(|primary| {
+            (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic())
+        }) && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+        if valid {
+            Ok(Language(value))
+        } else {
+            Err(format!(
+                "language must be a language code such as 'de' or 'zh-Hans', got '{}'",
+                value
+            ))
+        }
+    }
+}
+
+impl Language {
+    /// The label expression: the name in this language, else the default name.
+    fn label(&self) -> Value {
+        json!([
+            "coalesce",
+            ["get", format!("name:{}", self.0)],
+            ["get", "name"]
+        ])
+    }
+}
+
+/// Check `value` is an absolute URL: a scheme such as `https` or a custom one served by
+/// the client resource loader, then `://` and no whitespace.
+fn check_url(field: &str, value: &str) -> Result<(), String> {
+    let valid = value.split_once("://").is_some_and(|(scheme, rest)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            && !rest.is_empty()
+            && !rest.chars().any(char::is_whitespace)
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} must be an absolute URL, got '{}'",
+            field, value
+        ))
+    }
+}
+
+/// What the map is drawn over, set at `init`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Background {
+    /// Hide the style's background layers, leaving alpha 0 wherever nothing else draws.
+    Transparent,
+    /// A hex colour beneath every layer; it shows only where the style draws nothing,
+    /// e.g. a style with no background layer.
+    Color(String),
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        if value == "transparent" {
+            Ok(Background::Transparent)
+        } else if lint::is_hex_color(&value) {
+            Ok(Background::Color(value))
+        } else {
+            Err(format!(
+                "background must be \"transparent\" or a hex colour, got '{}'",
+                value
+            ))
+        }
+    }
+}
+
+/// A symbol size property that was capped.
+///
+/// `exceeded` means a literal value (or the style-spec default) was above the cap;
+/// otherwise the property is an expression whose result is now bounded at render time.
+#[derive(Debug, Serialize)]
+pub struct SizeClamp {
+    pub layer: String,
+    pub property: &'static str,
+    pub exceeded: bool,
+}
+
+/// Visit the values a property can evaluate to.
+///
+/// Zoom expressions must stay at the top level, so `interpolate`/`step` and legacy
+/// `stops` functions are descended into and each output is visited, rather than
+/// the whole expression being wrapped.
+fn for_each_output(value: &mut Value, f: &mut dyn FnMut(&mut Value)) {
+    match value {
+        Value::Array(items) => match items.first().and_then(Value::as_str) {
+            Some("interpolate") | Some("interpolate-hcl") | Some("interpolate-lab") => {
+                for output in items.iter_mut().skip(4).step_by(2) {
+                    for_each_output(output, f);
+                }
+            }
+            Some("step") => {
+                for output in items.iter_mut().skip(2).step_by(2) {
+                    for_each_output(output, f);
+                }
+            }
+            _ => f(value),
+        },
+        Value::Object(function) => {
+            if let Some(stops) = function.get_mut("stops").and_then(Value::as_array_mut) {
+                for stop in stops {
+                    if let Some(output) = stop.get_mut(1) {
+                        for_each_output(output, f);
+                    }
+                }
+            }
+        }
+        _ => f(value),
+    }
+}
+
+/// Whether `key` is a feature name property: `name`, `name:<code>`, or OpenMapTiles'
+/// `name_<code>`.
+fn is_name_key(key: &str) -> bool {
+    key == "name" || key.starts_with("name:") || key.starts_with("name_")
+}
+
+/// Whether `value` is a label expression from `Language::label`, so localizing an
+/// already localized style swaps the language instead of nesting fallbacks.
+fn is_localized_label(value: &Value) -> bool {
+    match value.as_array().map(Vec::as_slice) {
+        Some([op, Value::Array(get), fallback]) if op == "coalesce" => {
+            matches!(get.as_slice(), [get, Value::String(key)] if get == "get" && key.starts_with("name:"))
+                && *fallback == json!(["get", "name"])
+        }
+        _ => false,
+    }
+}
+
+/// Replace the name lookups in an expression with the localized label.
+fn localize_expression(value: &mut Value, language: &Language) -> bool {
+    if is_localized_label(value) {
+        *value = language.label();
+        return true;
+    }
+    let Value::Array(items) = value else {
+        return false;
+    };
+    match items.as_slice() {
+        [op, ..] if op == "literal" => false,
+        [op, Value::String(key)] if op == "get" && is_name_key(key) => {
+            *value = language.label();
+            true
+        }
+        _ => {
+            let mut changed = false;
+            for item in items {
+                changed |= localize_expression(item, language);
+            }
+            changed
+        }
+    }
+}
+
+/// Localize a `text-field` value: an expression, a legacy `{token}` string, or a
+/// `stops` function of either.
+fn localize_text_field(value: &mut Value, language: &Language) -> bool {
+    match value {
+        // A token string can't hold a fallback, so one that mentions a name, such as
+        // "{name:latin}\n{name:nonlatin}", becomes the localized label as a whole.
+        Value::String(text) if text.contains("{name") => {
+            *value = language.label();
+            true
+        }
+        Value::Array(_) => localize_expression(value, language),
+        Value::Object(function) => {
+            let mut changed = false;
+            let stops = function.get_mut("stops").and_then(Value::as_array_mut);
+            for output in stops
+                .into_iter()
+                .flatten()
+                .filter_map(|stop| stop.get_mut(1))
+            {
+                changed |= localize_text_field(output, language);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+/// Make a symbol layer's `text-field` prefer `name:<language>`, falling back to
+/// `name`, returning whether it changed.
+pub fn localize_labels(layer: &mut Value, language: &Language) -> bool {
+    if layer["type"] != "symbol" {
+        return false;
+    }
+    layer
+        .get_mut("layout")
+        .and_then(|layout| layout.get_mut("text-field"))
+        .is_some_and(|field| localize_text_field(field, language))
+}
+
+/// Bound a size property value, returning whether a literal was over the cap.
+fn cap_value(value: &mut Value, max: f64) -> bool {
+    let mut exceeded = false;
+    for_each_output(value, &mut |output| match output {
+        Value::Number(n) if n.as_f64().is_some_and(|v| v > max) => {
+            exceeded = true;
+            *output = json!(max);
+        }
+        Value::Array(_) => *output = json!(["min", output.take(), max]),
+        _ => {}
+    });
+    exceeded
+}
+
+/// Paint properties controlling a layer type's opacity.
+fn opacity_properties(layer_type: &str) -> &'static [&'static str] {
+    match layer_type {
+        "background" => &["background-opacity"],
+        "fill" => &["fill-opacity"],
+        "fill-extrusion" => &["fill-extrusion-opacity"],
+        "line" => &["line-opacity"],
+        "circle" => &["circle-opacity", "circle-stroke-opacity"],
+        "symbol" => &["icon-opacity", "text-opacity"],
+        "raster" => &["raster-opacity"],
+        "heatmap" => &["heatmap-opacity"],
+        "color-relief" => &["color-relief-opacity"],
+        _ => &[],
+    }
+}
+
+fn paint_mut(layer: &mut Value) -> Option<&mut serde_json::Map<String, Value>> {
+    layer
+        .as_object_mut()?
+        .entry("paint")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+}
+
+/// Replace a layer's opacity with `opacity`, dispatching on the layer type.
+pub fn set_opacity(layer: &mut Value, opacity: f64) -> Result<(), String> {
+    let layer_type = layer["type"].as_str().unwrap_or_default().to_string();
+    let properties = opacity_properties(&layer_type);
+    if properties.is_empty() {
+        return Err(format!("{} layers have no opacity property", layer_type));
+    }
+    let paint = paint_mut(layer).ok_or("layer is not an object")?;
+    for property in properties {
+        paint.insert(property.to_string(), json!(opacity));
+    }
+    Ok(())
+}
+
+/// Multiply a layer's existing opacity by `factor`. Layer types without an opacity
+/// property are left alone.
+pub fn scale_opacity(layer: &mut Value, factor: f64) {
+    let properties = opacity_properties(layer["type"].as_str().unwrap_or_default());
+    let Some(paint) = paint_mut(layer) else {
+        return;
+    };
+    for property in properties {
+        let value = paint.entry(property.to_string()).or_insert(json!(1.0));
+        for_each_output(value, &mut |output| match output {
+            Value::Number(n) => *output = json!(n.as_f64().unwrap_or(1.0) * factor),
+            _ => *output = json!(["*", output.take(), factor]),
+        });
+    }
+}
+
+/// Cap `icon-size` and `text-size` on every symbol layer of a style document.
+pub fn cap_symbol_sizes(style: &mut Value, caps: &SizeCaps) -> Vec<SizeClamp> {
+    let mut clamps = Vec::new();
+    let Some(layers) = style.get_mut("layers").and_then(Value::as_array_mut) else {
+        return clamps;
+    };
+    for layer in layers {
+        if layer["type"] != "symbol" {
+            continue;
+        }
+        let id = layer["id"].as_str().unwrap_or_default().to_string();
+        let layout = layer
+            .as_object_mut()
+            .map(|layer| layer.entry("layout").or_insert_with(|| json!({})));
+        let Some(layout) = layout.and_then(Value::as_object_mut) else {
+            continue;
+        };
+        // Style-spec defaults: icon-size 1, text-size 16.
+        for (property, max, default) in [
+            ("icon-size", caps.max_icon_size, 1.0),
+            ("text-size", caps.max_text_size, 16.0),
+        ] {
+            let Some(max) = max else {
+                continue;
+            };
+            match layout.get_mut(property) {
+                Some(value) => {
+                    let literal = value.is_number();
+                    let exceeded = cap_value(value, max);
+                    if exceeded || !literal {
+                        clamps.push(SizeClamp {
+                            layer: id.clone(),
+                            property,
+                            exceeded,
+                        });
+                    }
+                }
+                None if default > max => {
+                    layout.insert(property.to_string(), json!(max));
+                    clamps.push(SizeClamp {
+                        layer: id.clone(),
+                        property,
+                        exceeded: true,
+                    });
+                }
+                None => {}
+            }
+        }
+    }
+    clamps
+}
+
+/// Apply `background` to a style document.
+pub fn set_background(style: &mut Value, background: &Background) -> Result<(), String> {
+    let layers = style
+        .get_mut("layers")
+        .and_then(Value::as_array_mut)
+        .ok_or("style has no layers array")?;
+    match background {
+        Background::Transparent => {
+            for layer in layers
+                .iter_mut()
+                .filter(|layer| layer["type"] == "background")
+            {
+                set_visibility(layer, false)?;
+            }
+        }
+        Background::Color(color) => layers.insert(
+            0,
+            json!({
+                "id": BACKGROUND_LAYER_ID,
+                "type": "background",
+                "paint": {"background-color": color},
+            }),
+        ),
+    }
+    Ok(())
+}
+
+/// Apply the `glyphs` and `sprite` overrides to a style document.
+pub fn set_resource_urls(
+    style: &mut Value,
+    glyphs: Option<&GlyphsUrl>,
+    sprite: Option<&SpriteUrl>,
+) -> Result<(), String> {
+    let style = style.as_object_mut().ok_or("style is not a JSON object")?;
+    if let Some(GlyphsUrl(url)) = glyphs {
+        style.insert("glyphs".to_string(), json!(url));
+    }
+    if let Some(SpriteUrl(url)) = sprite {
+        style.insert("sprite".to_string(), json!(url));
+    }
+    Ok(())
+}
+
+/// Key a feature id is stored under in a feature-state table, matching
+/// `["to-string", ["id"]]`.
+pub fn feature_state_id(id: &Value) -> Result<String, String> {
+    match id {
+        Value::String(id) => Ok(id.clone()),
+        Value::Number(id) => Ok(id.to_string()),
+        _ => Err(format!("feature id must be a string or number, got {}", id)),
+    }
+}
+
+/// The expression standing in for `["feature-state", key]`: `key` of the feature's
+/// entry in `table`, or `null` like unset feature state. Features without an entry
+/// look in an empty object, since `get` fails on anything but an object.
+fn feature_state_lookup(key: &Value, table: &serde_json::Map<String, Value>) -> Value {
+    let entry = json!(["get", ["to-string", ["id"]], ["literal", table]]);
+    json!(["get", key, ["object", entry, ["literal", {}]]])
+}
+
+/// The key and table of a lookup made by `feature_state_lookup`.
+fn as_feature_state_lookup(value: &Value) -> Option<(&Value, &serde_json::Map<String, Value>)> {
+    let [get, key, entry] = value.as_array()?.as_slice() else {
+        return None;
+    };
+    let [object, lookup, _] = entry.as_array()?.as_slice() else {
+        return None;
+    };
+    let [get_entry, id, table] = lookup.as_array()?.as_slice() else {
+        return None;
+    };
+    let [literal, table] = table.as_array()?.as_slice() else {
+        return None;
+    };
+    (get == "get"
+        && object == "object"
+        && get_entry == "get"
+        && *id == json!(["to-string", ["id"]])
+        && literal == "literal")
+        .then_some((key, table.as_object()?))
+}
+
+/// The feature-state table last written into a layer's paint properties, if any.
+pub fn feature_states(layer: &Value) -> Option<serde_json::Map<String, Value>> {
+    fn find(value: &Value) -> Option<&serde_json::Map<String, Value>> {
+        if let Some((_, table)) = as_feature_state_lookup(value) {
+            return Some(table);
+        }
+        value.as_array()?.iter().find_map(find)
+    }
+    layer["paint"].as_object()?.values().find_map(find).cloned()
+}
+
+/// Point every `["feature-state", key]` (or earlier lookup) in a layer's paint
+/// properties at `table`, returning whether the layer uses feature state at all.
+pub fn set_feature_states(layer: &mut Value, table: &serde_json::Map<String, Value>) -> bool {
+    fn rewrite(value: &mut Value, table: &serde_json::Map<String, Value>) -> bool {
+        let key = match value.as_array().map(Vec::as_slice) {
+            Some([op, key]) if op == "feature-state" => Some(key.clone()),
+            _ => as_feature_state_lookup(value).map(|(key, _)| key.clone()),
+        };
+        if let Some(key) = key {
+            *value = feature_state_lookup(&key, table);
+            return true;
+        }
+        let mut found = false;
+        if let Value::Array(items) = value {
+            for item in items {
+                found |= rewrite(item, table);
+            }
+        }
+        found
+    }
+    let mut found = false;
+    if let Some(paint) = layer.get_mut("paint").and_then(Value::as_object_mut) {
+        for value in paint.values_mut() {
+            found |= rewrite(value, table);
+        }
+    }
+    found
+}
+
+/// A style's name, sources and layers, as `describe_style` reports them.
+#[derive(Debug, Serialize)]
+pub struct StyleDescription {
+    pub name: Option<String>,
+    /// Sources in id order.
+    pub sources: Vec<SourceDescription>,
+    /// Layers in drawing order, bottom first.
+    pub layers: Vec<LayerDescription>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceDescription {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayerDescription {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// `None` for layers without a source, such as `background`.
+    pub source: Option<String>,
+    #[serde(rename = "source-layer", skip_serializing_if = "Option::is_none")]
+    pub source_layer: Option<String>,
+}
+
+/// List a style document's sources and layers.
+pub fn describe(style: &Value) -> StyleDescription {
+    let text = |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+    StyleDescription {
+        name: text(style, "name"),
+        sources: style
+            .get("sources")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .map(|(id, source)| SourceDescription {
+                id: id.clone(),
+                kind: text(source, "type").unwrap_or_default(),
+            })
+            .collect(),
+        layers: style
+            .get("layers")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|layer| LayerDescription {
+                id: text(layer, "id").unwrap_or_default(),
+                kind: text(layer, "type").unwrap_or_default(),
+                source: text(layer, "source"),
+                source_layer: text(layer, "source-layer"),
+            })
+            .collect(),
+    }
+}
+
+/// The `attribution` of a style's sources as plain text, in id order and without
+/// repeats, joined by ` | `; `None` when no source declares one.
+pub fn attribution(style: &Value) -> Option<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for source in style
+        .get("sources")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|sources| sources.values())
+    {
+        let Some(html) = source.get("attribution").and_then(Value::as_str) else {
+            continue;
+        };
+        let text = plain_text(html);
+        if !text.is_empty() && !parts.contains(&text) {
+            parts.push(text);
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join(" | "))
+}
+
+/// `html` with its tags dropped, common entities decoded and whitespace collapsed.
+fn plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for character in html.chars() {
+        match character {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(character),
+            _ => {}
+        }
+    }
+    let text = [
+        ("&copy;", "©"),
+        ("&nbsp;", " "),
+        ("&lt;", "<"),
+        ("&gt;", ">"),
+        ("&quot;", "\""),
+        ("&#39;", "'"),
+        ("&amp;", "&"),
+    ]
+    .iter()
+    .fold(text, |text, (entity, character)| {
+        text.replace(entity, character)
+    });
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A runtime edit to one style layer, as used by `set_layer_opacity`,
+/// `set_layer_visibility`, `set_layer_property`, `set_layer_filter`, `set_hillshade`
+/// and `transaction`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op")]
+pub enum Operation {
+    #[serde(rename = "set_layer_opacity")]
+    LayerOpacity { layer: String, opacity: f64 },
+    #[serde(rename = "set_layer_visibility")]
+    LayerVisibility { layer: String, visible: bool },
+    #[serde(rename = "set_paint_property")]
+    PaintProperty {
+        layer: String,
+        name: String,
+        value: Value,
+    },
+    #[serde(rename = "set_layout_property")]
+    LayoutProperty {
+        layer: String,
+        name: String,
+        value: Value,
+    },
+    /// Replace the layer's filter expression; `null` or absent removes it.
+    #[serde(rename = "set_filter")]
+    Filter {
+        layer: String,
+        #[serde(default)]
+        filter: Value,
+    },
+    /// Terrain-relief tuning of a hillshade layer; unset values are left as they are.
+    #[serde(rename = "set_hillshade")]
+    Hillshade {
+        layer: String,
+        #[serde(default)]
+        exaggeration: Option<f64>,
+        #[serde(default)]
+        illumination_direction: Option<f64>,
+    },
+}
+
+/// Which property group of a layer `set_layer_property` edits.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PropertyKind {
+    Paint,
+    Layout,
+}
+
+impl PropertyKind {
+    /// The edit setting property `name` of `layer` to `value`.
+    pub fn operation(self, layer: String, name: String, value: Value) -> Operation {
+        match self {
+            PropertyKind::Paint => Operation::PaintProperty { layer, name, value },
+            PropertyKind::Layout => Operation::LayoutProperty { layer, name, value },
+        }
+    }
+}
+
+fn set_property(layer: &mut Value, group: &str, name: &str, value: &Value) -> Result<(), String> {
+    let properties = layer
+        .as_object_mut()
+        .ok_or("layer is not an object")?
+        .entry(group)
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| format!("layer {} is not an object", group))?;
+    if value.is_null() {
+        properties.remove(name);
+    } else {
+        properties.insert(name.to_string(), value.clone());
+    }
+    Ok(())
+}
+
+impl Operation {
+    pub fn layer(&self) -> &str {
+        match self {
+            Operation::LayerOpacity { layer, .. }
+            | Operation::LayerVisibility { layer, .. }
+            | Operation::PaintProperty { layer, .. }
+            | Operation::LayoutProperty { layer, .. }
+            | Operation::Filter { layer, .. }
+            | Operation::Hillshade { layer, .. } => layer,
+        }
+    }
+
+    /// Apply the edit to the layer's JSON. A `null` property value restores the default.
+    pub fn apply(&self, layer: &mut Value) -> Result<(), String> {
+        match self {
+            Operation::LayerOpacity { opacity, .. } => {
+                if !(0.0..=1.0).contains(opacity) {
+                    return Err("opacity must be between 0 and 1".to_string());
+                }
+                set_opacity(layer, *opacity)
+            }
+            Operation::LayerVisibility { visible, .. } => set_visibility(layer, *visible),
+            Operation::PaintProperty { name, value, .. } => {
+                set_property(layer, "paint", name, value)
+            }
+            Operation::LayoutProperty { name, value, .. } => {
+                set_property(layer, "layout", name, value)
+            }
+            Operation::Filter { filter, .. } => {
+                let layer = layer.as_object_mut().ok_or("layer is not an object")?;
+                if filter.is_null() {
+                    layer.remove("filter");
+                } else {
+                    layer.insert("filter".to_string(), filter.clone());
+                }
+                Ok(())
+            }
+            Operation::Hillshade {
+                exaggeration,
+                illumination_direction,
+                ..
+            } => set_hillshade(layer, *exaggeration, *illumination_direction),
+        }
+    }
+}
+
+/// Set `hillshade-exaggeration` and `hillshade-illumination-direction`, within the
+/// ranges the style spec allows.
+fn set_hillshade(
+    layer: &mut Value,
+    exaggeration: Option<f64>,
+    illumination_direction: Option<f64>,
+) -> Result<(), String> {
+    if layer["type"] != "hillshade" {
+        return Err(format!(
+            "layer '{}' is a {} layer, not hillshade",
+            layer["id"].as_str().unwrap_or_default(),
+            layer["type"].as_str().unwrap_or("typeless")
+        ));
+    }
+    if exaggeration.is_none() && illumination_direction.is_none() {
+        return Err("give exaggeration, illumination_direction or both".to_string());
+    }
+    if let Some(exaggeration) = exaggeration {
+        if !(0.0..=1.0).contains(&exaggeration) {
+            return Err("exaggeration must be between 0 and 1".to_string());
+        }
+        set_property(
+            layer,
+            "paint",
+            "hillshade-exaggeration",
+            &json!(exaggeration),
+        )?;
+    }
+    if let Some(direction) = illumination_direction {
+        if !(0.0..=359.0).contains(&direction) {
+            return Err("illumination_direction must be between 0 and 359".to_string());
+        }
+        set_property(
+            layer,
+            "paint",
+            "hillshade-illumination-direction",
+            &json!(direction),
+        )?;
+    }
+    Ok(())
+}
+
+/// Show or hide a layer via its `visibility` layout property.
+pub fn set_visibility(layer: &mut Value, visible: bool) -> Result<(), String> {
+    let visibility = if visible { "visible" } else { "none" };
+    set_property(layer, "layout", "visibility", &json!(visibility))
+}
+
+/// Override the `tileSize` of a raster or raster-dem source, e.g. to draw 256px
+/// tiles correctly from a style that declares 512.
+pub fn set_source_tile_size(style: &mut Value, source: &str, tile_size: u32) -> Result<(), String> {
+    if !tile_size.is_power_of_two() {
+        return Err(format!(
+            "tile_size must be a power of two, got {}",
+            tile_size
+        ));
+    }
+    let definition = style["sources"]
+        .get_mut(source)
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| format!("source '{}' not found", source))?;
+    match definition.get("type").and_then(Value::as_str) {
+        Some("raster" | "raster-dem") => {
+            definition.insert("tileSize".to_string(), json!(tile_size));
+            Ok(())
+        }
+        other => Err(format!(
+            "source '{}' is a {} source, not raster or raster-dem",
+            source,
+            other.unwrap_or("typeless")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(id: &str, layout: Value) -> Value {
+        json!({"id": id, "type": "symbol", "source": "places", "layout": layout})
+    }
+
+    #[test]
+    fn symbol_sizes_are_capped_through_zoom_expressions() {
+        let mut style = json!({"layers": [
+            symbol("big", json!({"text-size": 30})),
+            symbol("small", json!({"text-size": 10})),
+            symbol("zoomed", json!({"text-size": ["interpolate", ["linear"], ["zoom"], 0, 10, 10, ["get", "size"]]})),
+            symbol("default", json!({})),
+            {"id": "fill", "type": "fill", "source": "places", "paint": {}}
+        ]});
+        let caps = SizeCaps {
+            max_icon_size: Some(0.5),
+            max_text_size: Some(20.0),
+        };
+        let clamps: Vec<_> = cap_symbol_sizes(&mut style, &caps)
+            .into_iter()
+            .map(|c| (c.layer, c.property, c.exceeded))
+            .collect();
+        assert_eq!(
+            clamps,
+            [
+                ("big".to_string(), "icon-size", true),
+                ("big".to_string(), "text-size", true),
+                ("small".to_string(), "icon-size", true),
+                ("zoomed".to_string(), "icon-size", true),
+                ("zoomed".to_string(), "text-size", false),
+                ("default".to_string(), "icon-size", true),
+            ]
+        );
+        assert_eq!(style["layers"][0]["layout"]["text-size"], 20.0);
+        assert_eq!(style["layers"][1]["layout"]["text-size"], 10);
+        // The zoom curve stays at the top level; only its outputs are bounded.
+        assert_eq!(
+            style["layers"][2]["layout"]["text-size"],
+            json!([
+                "interpolate",
+                ["linear"],
+                ["zoom"],
+                0,
+                10,
+                10,
+                ["min", ["get", "size"], 20.0]
+            ])
+        );
+        assert_eq!(style["layers"][3]["layout"]["icon-size"], 0.5);
+        assert!(style["layers"][4].get("layout").is_none());
+    }
+
+    #[test]
+    fn urls_languages_and_backgrounds_are_validated() {
+        assert!(GlyphsUrl::try_from("https://a.b/{fontstack}/{range}.pbf".to_string()).is_ok());
+        assert!(GlyphsUrl::try_from("https://a.b/{fontstack}.pbf".to_string()).is_err());
+        assert!(SpriteUrl::try_from("app+assets://sprites/base".to_string()).is_ok());
+        for bad in ["sprites/base", "https://", "https://a b", "1x://a"] {
+            assert!(SpriteUrl::try_from(bad.to_string()).is_err(), "{}", bad);
+        }
+        for good in ["de", "zh-Hans", "sr-Latn-RS"] {
+            assert!(Language::try_from(good.to_string()).is_ok(), "{}", good);
+        }
+        for bad in ["", "d", "deutsch", "de_DE", "de-"] {
+            assert!(Language::try_from(bad.to_string()).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            Background::try_from("transparent".to_string()),
+            Ok(Background::Transparent)
+        );
+        assert_eq!(
+            Background::try_from("#fff".to_string()),
+            Ok(Background::Color("#fff".to_string()))
+        );
+        assert!(Background::try_from("white".to_string()).is_err());
+    }
+
+    #[test]
+    fn labels_are_localized_once() {
+        let german = Language("de".to_string());
+        let french = Language("fr".to_string());
+        let mut layer = symbol(
+            "labels",
+            json!({"text-field": ["concat", ["get", "name"], " ", ["get", "ref"]]}),
+        );
+        assert!(localize_labels(&mut layer, &german));
+        assert_eq!(
+            layer["layout"]["text-field"],
+            json!(["concat", german.label(), " ", ["get", "ref"]])
+        );
+        // Localizing again swaps the language instead of nesting.
+        assert!(localize_labels(&mut layer, &french));
+        assert_eq!(layer["layout"]["text-field"][1], french.label());
+
+        let mut tokens = symbol(
+            "tokens",
+            json!({"text-field": {"stops": [[0, "{name:latin}"], [8, "{ref}"]]}}),
+        );
+        assert!(localize_labels(&mut tokens, &german));
+        assert_eq!(
+            tokens["layout"]["text-field"],
+            json!({"stops": [[0, german.label()], [8, "{ref}"]]})
+        );
+
+        let mut literal = symbol(
+            "literal",
+            json!({"text-field": ["literal", ["get", "name"]]}),
+        );
+        assert!(!localize_labels(&mut literal, &german));
+        let mut line = json!({"type": "line", "layout": {"text-field": "{name}"}});
+        assert!(!localize_labels(&mut line, &german));
+    }
+
+    #[test]
+    fn opacity_is_set_or_scaled_per_layer_type() {
+        let mut circle = json!({"id": "c", "type": "circle", "paint": {"circle-opacity": 0.8}});
+        set_opacity(&mut circle, 0.5).unwrap();
+        assert_eq!(
+            circle["paint"],
+            json!({"circle-opacity": 0.5, "circle-stroke-opacity": 0.5})
+        );
+        assert!(set_opacity(&mut json!({"type": "hillshade"}), 0.5).is_err());
+
+        let mut line = json!({"type": "line", "paint": {
+            "line-opacity": ["step", ["zoom"], 0.8, 10, ["get", "o"]]
+        }});
+        scale_opacity(&mut line, 0.5);
+        assert_eq!(
+            line["paint"]["line-opacity"],
+            json!(["step", ["zoom"], 0.4, 10, ["*", ["get", "o"], 0.5]])
+        );
+        let mut fill = json!({"type": "fill"});
+        scale_opacity(&mut fill, 0.25);
+        assert_eq!(fill["paint"]["fill-opacity"], 0.25);
+    }
+
+    #[test]
+    fn backgrounds_hide_or_underlay_the_style() {
+        let style = || {
+            json!({"layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "water", "type": "fill", "source": "s"}
+            ]})
+        };
+        let mut transparent = style();
+        set_background(&mut transparent, &Background::Transparent).unwrap();
+        assert_eq!(transparent["layers"][0]["layout"]["visibility"], "none");
+        assert!(transparent["layers"][1].get("layout").is_none());
+
+        let mut colored = style();
+        set_background(&mut colored, &Background::Color("#123456".to_string())).unwrap();
+        assert_eq!(colored["layers"][0]["id"], BACKGROUND_LAYER_ID);
+        assert_eq!(colored["layers"][0]["paint"]["background-color"], "#123456");
+        assert!(set_background(&mut json!({}), &Background::Transparent).is_err());
+    }
+
+    #[test]
+    fn resource_urls_replace_the_style_fields() {
+        let mut style = json!({"glyphs": "old", "sprite": "old"});
+        let glyphs = GlyphsUrl("https://a/{fontstack}/{range}".to_string());
+        set_resource_urls(&mut style, Some(&glyphs), None).unwrap();
+        assert_eq!(style, json!({"glyphs": glyphs.0, "sprite": "old"}));
+        assert!(set_resource_urls(&mut json!([]), None, None).is_err());
+    }
+
+    #[test]
+    fn feature_state_is_replaced_by_a_table_lookup() {
+        let mut layer = json!({"type": "fill", "paint": {
+            "fill-color": ["case", ["boolean", ["feature-state", "hover"], false], "red", "blue"],
+            "fill-opacity": 1
+        }});
+        let table = json!({"7": {"hover": true}}).as_object().cloned().unwrap();
+        assert!(set_feature_states(&mut layer, &table));
+        assert_eq!(
+            layer["paint"]["fill-color"][1][1],
+            feature_state_lookup(&json!("hover"), &table)
+        );
+        assert_eq!(feature_states(&layer).as_ref(), Some(&table));
+
+        // Writing again replaces the earlier table rather than nesting lookups.
+        let empty = serde_json::Map::new();
+        assert!(set_feature_states(&mut layer, &empty));
+        assert_eq!(feature_states(&layer), Some(empty));
+        assert!(!set_feature_states(
+            &mut json!({"paint": {"fill-opacity": 1}}),
+            &table
+        ));
+
+        assert_eq!(feature_state_id(&json!(7)).unwrap(), "7");
+        assert_eq!(feature_state_id(&json!("a")).unwrap(), "a");
+        assert!(feature_state_id(&json!(null)).is_err());
+    }
+
+    #[test]
+    fn describe_and_attribution_summarize_the_style() {
+        let style = json!({
+            "name": "Test",
+            "sources": {
+                "b": {"type": "vector", "attribution": "<a href=\"x\">&copy; OSM</a>  contributors"},
+                "a": {"type": "raster", "attribution": "Imagery &amp; more"},
+                "c": {"type": "vector", "attribution": "© OSM contributors"}
+            },
+            "layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "roads", "type": "line", "source": "b", "source-layer": "roads"}
+            ]
+        });
+        let description = describe(&style);
+        assert_eq!(description.name.as_deref(), Some("Test"));
+        let sources: Vec<_> = description.sources.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(sources, ["a", "b", "c"]);
+        assert_eq!(description.layers[0].source, None);
+        assert_eq!(description.layers[1].source_layer.as_deref(), Some("roads"));
+        assert_eq!(
+            attribution(&style).as_deref(),
+            Some("Imagery & more | © OSM contributors")
+        );
+        assert_eq!(attribution(&json!({"sources": {}})), None);
+    }
+
+    #[test]
+    fn operations_edit_their_layer() {
+        let mut layer = json!({"id": "dem", "type": "hillshade", "filter": ["has", "x"]});
+        let edits: Vec<Operation> = serde_json::from_value(json!([
+            {"op": "set_paint_property", "layer": "dem", "name": "hillshade-shadow-color", "value": "#000"},
+            {"op": "set_layer_visibility", "layer": "dem", "visible": false},
+            {"op": "set_filter", "layer": "dem"},
+            {"op": "set_hillshade", "layer": "dem", "exaggeration": 0.3}
+        ]))
+        .unwrap();
+        for edit in &edits {
+            assert_eq!(edit.layer(), "dem");
+            edit.apply(&mut layer).unwrap();
+        }
+        assert_eq!(
+            layer,
+            json!({
+                "id": "dem",
+                "type": "hillshade",
+                "layout": {"visibility": "none"},
+                "paint": {"hillshade-shadow-color": "#000", "hillshade-exaggeration": 0.3}
+            })
+        );
+        // A null value restores the default by removing the property.
+        PropertyKind::Paint
+            .operation(
+                "dem".to_string(),
+                "hillshade-shadow-color".to_string(),
+                Value::Null,
+            )
+            .apply(&mut layer)
+            .unwrap();
+        assert_eq!(layer["paint"], json!({"hillshade-exaggeration": 0.3}));
+    }
+
+    #[test]
+    fn invalid_edits_are_rejected() {
+        let mut hillshade = json!({"id": "dem", "type": "hillshade"});
+        let mut fill = json!({"id": "water", "type": "fill"});
+        assert!(set_hillshade(&mut hillshade, None, None).is_err());
+        assert!(set_hillshade(&mut hillshade, Some(1.5), None).is_err());
+        assert!(set_hillshade(&mut hillshade, None, Some(360.0)).is_err());
+        assert_eq!(
+            set_hillshade(&mut fill, Some(0.5), None).unwrap_err(),
+            "layer 'water' is a fill layer, not hillshade"
+        );
+        let opacity = Operation::LayerOpacity {
+            layer: "water".to_string(),
+            opacity: 1.5,
+        };
+        assert!(opacity.apply(&mut fill).is_err());
+    }
+
+    #[test]
+    fn tile_size_applies_only_to_raster_sources() {
+        let mut style = json!({"sources": {
+            "dem": {"type": "raster-dem", "tileSize": 512},
+            "tiles": {"type": "vector"}
+        }});
+        set_source_tile_size(&mut style, "dem", 256).unwrap();
+        assert_eq!(style["sources"]["dem"]["tileSize"], 256);
+        assert!(set_source_tile_size(&mut style, "dem", 300).is_err());
+        assert!(set_source_tile_size(&mut style, "tiles", 256).is_err());
+        assert!(set_source_tile_size(&mut style, "missing", 256).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Interpolated asset positions for `render_track_animation` playback.
+//!
+//! Positions move along great circles between keyframes, so long hops (flights,
+//! shipping) follow the same path a globe would show; for short hops this is
+//! indistinguishable from straight-line interpolation.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const SOURCE_ID: &str = "mlnative-tracks";
+
+/// Longest animation one command may render.
+pub const MAX_FRAMES: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Keyframe {
+    /// Any monotonic unit (seconds, epoch milliseconds, ...), shared by every track.
+    pub time: f64,
+    pub lng: f64,
+    pub lat: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Track {
+    pub id: String,
+    pub keyframes: Vec<Keyframe>,
+    /// Marker colour for this asset; the `marker` colour when absent.
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+/// What to draw for an asset at times before its first or after its last keyframe.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfRange {
+    /// Leave it off the frame.
+    #[default]
+    Hide,
+    /// Keep it at its first or last position.
+    Hold,
+}
+
+/// How the frames are returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Animation {
+    /// One payload per frame, encoded as `format`.
+    #[default]
+    Frames,
+    /// A single looping animated PNG.
+    Apng,
+}
+
+/// How assets are drawn.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Marker {
+    #[serde(default = "default_marker_color")]
+    pub color: String,
+    /// Radius in logical pixels.
+    #[serde(default = "default_marker_radius")]
+    pub radius: f64,
+}
+
+fn default_marker_color() -> String {
+    "#1f77b4".to_string()
+}
+
+fn default_marker_radius() -> f64 {
+    6.0
+}
+
+impl Default for Marker {
+    fn default() -> Self {
+        Self {
+            color: default_marker_color(),
+            radius: default_marker_radius(),
+        }
+    }
+}
+
+/// Sort each track's keyframes by time and return the time of every frame, evenly
+/// spaced over the span of all keyframes.
+pub fn frame_times(tracks: &mut [Track], frames: u32) -> Result<Vec<f64>, String> {
+    if !(1..=MAX_FRAMES).contains(&frames) {
+        return Err(format!("frames must be between 1 and {}", MAX_FRAMES));
+    }
+    let (mut start, mut end) = (f64::INFINITY, f64::NEG_INFINITY);
+    for track in tracks.iter_mut() {
+        if track.keyframes.is_empty() {
+            return Err(format!("track '{}' has no keyframes", track.id));
+        }
+        if let Some(keyframe) = track.keyframes.iter().find(|k| {
+            !(k.time.is_finite() && k.lng.is_finite() && k.lat.is_finite()) || k.lat.abs() > 90.0
+        }) {
+            return Err(format!(
+                "track '{}' has an invalid keyframe at time {}",
+                track.id, keyframe.time
+            ));
+        }
+        track.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        start = start.min(track.keyframes[0].time);
+        end = end.max(track.keyframes[track.keyframes.len() - 1].time);
+    }
+    if tracks.is_empty() {
+        return Err("no tracks given".to_string());
+    }
+    if frames == 1 {
+        return Ok(vec![start]);
+    }
+    let step = (end - start) / f64::from(frames - 1);
+    Ok((0..frames).map(|i| start + step * f64::from(i)).collect())
+}
+
+fn to_vector([lng, lat]: [f64; 2]) -> [f64; 3] {
+    let (lng, lat) = (lng.to_radians(), lat.to_radians());
+    [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+}
+
+fn from_vector([x, y, z]: [f64; 3]) -> [f64; 2] {
+    [y.atan2(x).to_degrees(), z.atan2(x.hypot(y)).to_degrees()]
+}
+
+/// The point `fraction` of the way from `a` to `b` along the great circle.
+fn interpolate(a: [f64; 2], b: [f64; 2], fraction: f64) -> [f64; 2] {
+    let (va, vb) = (to_vector(a), to_vector(b));
+    let dot: f64 = (0..3).map(|i| va[i] * vb[i]).sum();
+    let angle = dot.clamp(-1.0, 1.0).acos();
+    if angle < 1e-12 {
+        return a;
+    }
+    let wa = ((1.0 - fraction) * angle).sin() / angle.sin();
+    let wb = (fraction * angle).sin() / angle.sin();
+    from_vector(std::array::from_fn(|i| wa * va[i] + wb * vb[i]))
+}
+
+/// Initial great-circle bearing from `a` to `b`, degrees clockwise from north.
+fn heading(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (lat_a, lat_b) = (a[1].to_radians(), b[1].to_radians());
+    let d_lng = (b[0] - a[0]).to_radians();
+    let y = d_lng.sin() * lat_b.cos();
+    let x = lat_a.cos() * lat_b.sin() - lat_a.sin() * lat_b.cos() * d_lng.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Position and heading of `track` at `time`.
+fn position(track: &Track, time: f64, out_of_range: OutOfRange) -> Option<([f64; 2], f64)> {
+    let keyframes = &track.keyframes;
+    let point = |k: &Keyframe| [k.lng, k.lat];
+    let segment_heading = |i: usize| match keyframes.get(i + 1) {
+        Some(next) => heading(point(&keyframes[i]), point(next)),
+        None => 0.0,
+    };
+    let (first, last) = (keyframes.first()?, keyframes.last()?);
+    if time < first.time || time > last.time {
+        return match out_of_range {
+            OutOfRange::Hide => None,
+            OutOfRange::Hold if time < first.time => Some((point(first), segment_heading(0))),
+            OutOfRange::Hold => Some((
+                point(last),
+                segment_heading(keyframes.len().saturating_sub(2)),
+            )),
+        };
+    }
+    let i = keyframes
+        .windows(2)
+        .position(|pair| time <= pair[1].time)
+        .unwrap_or(0);
+    let Some(next) = keyframes.get(i + 1) else {
+        return Some((point(first), 0.0));
+    };
+    let span = next.time - keyframes[i].time;
+    let fraction = if span > 0.0 {
+        (time - keyframes[i].time) / span
+    } else {
+        1.0
+    };
+    Some((
+        interpolate(point(&keyframes[i]), point(next), fraction),
+        segment_heading(i),
+    ))
+}
+
+/// Point features for every visible asset at `time`, carrying `id`, `heading` and
+/// `color` properties for styling.
+pub fn features(tracks: &[Track], time: f64, out_of_range: OutOfRange) -> Value {
+    let features: Vec<Value> = tracks
+        .iter()
+        .filter_map(|track| {
+            let (position, heading) = position(track, time, out_of_range)?;
+            Some(json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": position},
+                "properties": {"id": track.id, "heading": heading, "color": track.color},
+            }))
+        })
+        .collect();
+    json!({"type": "FeatureCollection", "features": features})
+}
+
+/// The circle layer drawing the assets.
+pub fn layer(marker: &Marker) -> Value {
+    json!({
+        "id": SOURCE_ID,
+        "type": "circle",
+        "source": SOURCE_ID,
+        "paint": {
+            "circle-color": ["coalesce", ["get", "color"], marker.color],
+            "circle-radius": marker.radius,
+            "circle-stroke-color": "#ffffff",
+            "circle-stroke-width": 1,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str, keyframes: &[(f64, f64, f64)]) -> Track {
+        Track {
+            id: id.to_string(),
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, lng, lat)| Keyframe { time, lng, lat })
+                .collect(),
+            color: None,
+        }
+    }
+
+    fn assert_near(actual: [f64; 2], expected: [f64; 2]) {
+        for axis in 0..2 {
+            assert!(
+                (actual[axis] - expected[axis]).abs() < 1e-9,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn frames_span_every_track_after_sorting() {
+        let mut tracks = [
+            track("a", &[(10.0, 0.0, 0.0), (0.0, 1.0, 0.0)]),
+            track("b", &[(5.0, 0.0, 0.0), (20.0, 1.0, 0.0)]),
+        ];
+        assert_eq!(
+            frame_times(&mut tracks, 5).unwrap(),
+            [0.0, 5.0, 10.0, 15.0, 20.0]
+        );
+        assert_eq!(tracks[0].keyframes[0].time, 0.0);
+        assert_eq!(frame_times(&mut tracks, 1).unwrap(), [0.0]);
+    }
+
+    #[test]
+    fn invalid_tracks_and_frame_counts_are_rejected() {
+        let valid = || vec![track("a", &[(0.0, 0.0, 0.0)])];
+        assert!(frame_times(&mut valid(), 0).is_err());
+        assert!(frame_times(&mut valid(), MAX_FRAMES + 1).is_err());
+        assert_eq!(frame_times(&mut [], 2).unwrap_err(), "no tracks given");
+        assert_eq!(
+            frame_times(&mut [track("empty", &[])], 2).unwrap_err(),
+            "track 'empty' has no keyframes"
+        );
+        assert_eq!(
+            frame_times(&mut [track("pole", &[(3.0, 0.0, 91.0)])], 2).unwrap_err(),
+            "track 'pole' has an invalid keyframe at time 3"
+        );
+    }
+
+    #[test]
+    fn interpolation_follows_the_great_circle() {
+        // Along the equator the great circle is the straight line.
+        assert_near(interpolate([0.0, 0.0], [90.0, 0.0], 0.5), [45.0, 0.0]);
+        // Between two points at 45°N the path bulges towards the pole.
+        let middle = interpolate([-90.0, 45.0], [90.0, 45.0], 0.5);
+        assert_near(middle, [0.0, 90.0]);
+        assert_near(interpolate([10.0, 20.0], [10.0, 20.0], 0.3), [10.0, 20.0]);
+    }
+
+    #[test]
+    fn headings_are_clockwise_from_north() {
+        assert!((heading([0.0, 0.0], [0.0, 10.0]) - 0.0).abs() < 1e-9);
+        assert!((heading([0.0, 0.0], [10.0, 0.0]) - 90.0).abs() < 1e-9);
+        assert!((heading([0.0, 0.0], [0.0, -10.0]) - 180.0).abs() < 1e-9);
+        assert!((heading([0.0, 0.0], [-10.0, 0.0]) - 270.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn out_of_range_assets_are_hidden_or_held() {
+        let track = track("a", &[(10.0, 0.0, 0.0), (20.0, 10.0, 0.0)]);
+        assert_eq!(position(&track, 5.0, OutOfRange::Hide), None);
+        let (held, heading) = position(&track, 30.0, OutOfRange::Hold).unwrap();
+        assert_near(held, [10.0, 0.0]);
+        assert!((heading - 90.0).abs() < 1e-9);
+        let (middle, _) = position(&track, 15.0, OutOfRange::Hide).unwrap();
+        assert_near(middle, [5.0, 0.0]);
+    }
+
+    #[test]
+    fn features_carry_styling_properties() {
+        let mut colored = track("ship", &[(0.0, 0.0, 0.0)]);
+        colored.color = Some("#ff0000".to_string());
+        let collection = features(
+            &[colored, track("late", &[(5.0, 1.0, 1.0)])],
+            0.0,
+            OutOfRange::Hide,
+        );
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            features[0]["properties"],
+            json!({"id": "ship", "heading": 0.0, "color": "#ff0000"})
+        );
+        assert_eq!(layer(&Marker::default())["paint"]["circle-radius"], 6.0);
+    }
+}
@@ -0,0 +1,777 @@
+//! UTFGrid generation and feature queries for inline GeoJSON sources.
+//!
+//! MapLibre Native does not expose rendered-feature queries, so grids are built by
+//! hit-testing each cell against the GeoJSON embedded in the style instead of
+//! reading back a render. Layer filters and data-driven visibility are not applied.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// UTFGrid tiles always cover 256×256 pixels.
+const TILE_SIZE: f64 = 256.0;
+
+#[derive(Debug, Serialize)]
+pub struct UtfGrid {
+    pub grid: Vec<String>,
+    pub keys: Vec<String>,
+    pub data: Map<String, Value>,
+}
+
+/// A feature projected into tile pixel space.
+struct Feature {
+    key: String,
+    properties: Map<String, Value>,
+    shapes: Vec<Shape>,
+}
+
+enum Shape {
+    Point([f64; 2]),
+    Line(Vec<[f64; 2]>),
+    /// Outer ring followed by holes.
+    Polygon(Vec<Vec<[f64; 2]>>),
+}
+
+struct TileProjection {
+    world_size: f64,
+    origin: [f64; 2],
+}
+
+impl TileProjection {
+    fn new(z: u8, x: u32, y: u32) -> Self {
+        Self {
+            world_size: TILE_SIZE * 2f64.powi(z as i32),
+            origin: [x as f64 * TILE_SIZE, y as f64 * TILE_SIZE],
+        }
+    }
+
+    fn project(&self, coordinate: &Value) -> Option<[f64; 2]> {
+        let lng = coordinate.get(0)?.as_f64()?;
+        let lat = coordinate
+            .get(1)?
+            .as_f64()?
+            .clamp(-85.051_128_78, 85.051_128_78);
+        let px = (lng + 180.0) / 360.0 * self.world_size;
+        let sin = (lat * PI / 180.0).sin();
+        let py = (0.5 - ((1.0 + sin) / (1.0 - sin)).ln() / (4.0 * PI)) * self.world_size;
+        Some([px - self.origin[0], py - self.origin[1]])
+    }
+
+    fn project_all(&self, coordinates: &Value) -> Vec<[f64; 2]> {
+        coordinates
+            .as_array()
+            .map(|points| points.iter().filter_map(|c| self.project(c)).collect())
+            .unwrap_or_default()
+    }
+
+    fn polygon(&self, rings: &Value) -> Shape {
+        Shape::Polygon(
+            rings
+                .as_array()
+                .map(|rings| rings.iter().map(|ring| self.project_all(ring)).collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    fn shapes(&self, geometry: &Value, out: &mut Vec<Shape>) {
+        let coordinates = &geometry["coordinates"];
+        let each = |f: &mut dyn FnMut(&Value)| {
+            for part in coordinates.as_array().into_iter().flatten() {
+                f(part);
+            }
+        };
+        match geometry["type"].as_str() {
+            Some("Point") => out.extend(self.project(coordinates).map(Shape::Point)),
+            Some("MultiPoint") => {
+                out.extend(self.project_all(coordinates).into_iter().map(Shape::Point))
+            }
+            Some("LineString") => out.push(Shape::Line(self.project_all(coordinates))),
+            Some("MultiLineString") => {
+                each(&mut |line| out.push(Shape::Line(self.project_all(line))))
+            }
+            Some("Polygon") => out.push(self.polygon(coordinates)),
+            Some("MultiPolygon") => each(&mut |polygon| out.push(self.polygon(polygon))),
+            Some("GeometryCollection") => {
+                for geometry in geometry["geometries"].as_array().into_iter().flatten() {
+                    self.shapes(geometry, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn point_in_ring(point: [f64; 2], ring: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[j]);
+        if (a[1] > point[1]) != (b[1] > point[1])
+            && point[0] < (b[0] - a[0]) * (point[1] - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn distance_to_segment(point: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq == 0.0 {
+        0.0
+    } else {
+        (((point[0] - a[0]) * dx + (point[1] - a[1]) * dy) / length_sq).clamp(0.0, 1.0)
+    };
+    let (cx, cy) = (a[0] + t * dx, a[1] + t * dy);
+    ((point[0] - cx).powi(2) + (point[1] - cy).powi(2)).sqrt()
+}
+
+impl Shape {
+    fn hit(&self, point: [f64; 2], tolerance: f64) -> bool {
+        match self {
+            Shape::Point(p) => {
+                ((point[0] - p[0]).powi(2) + (point[1] - p[1]).powi(2)).sqrt() <= tolerance
+            }
+            Shape::Line(line) => line
+                .windows(2)
+                .any(|pair| distance_to_segment(point, pair[0], pair[1]) <= tolerance),
+            Shape::Polygon(rings) => match rings.split_first() {
+                Some((outer, holes)) => {
+                    point_in_ring(point, outer)
+                        && !holes.iter().any(|hole| point_in_ring(point, hole))
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+/// Whether segment `a`-`b` touches the rectangle `[0, size]` (Liang–Barsky clip).
+fn segment_hits_rect(a: [f64; 2], b: [f64; 2], size: [f64; 2]) -> bool {
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    let delta = [b[0] - a[0], b[1] - a[1]];
+    for axis in 0..2 {
+        for (p, q) in [(-delta[axis], a[axis]), (delta[axis], size[axis] - a[axis])] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return false;
+                }
+            } else {
+                let t = q / p;
+                if p < 0.0 {
+                    t0 = t0.max(t);
+                } else {
+                    t1 = t1.min(t);
+                }
+            }
+        }
+    }
+    t0 <= t1
+}
+
+impl Shape {
+    /// Whether the shape overlaps the rectangle `[0, size]`.
+    fn intersects_rect(&self, size: [f64; 2]) -> bool {
+        let inside =
+            |p: &[f64; 2]| (0.0..=size[0]).contains(&p[0]) && (0.0..=size[1]).contains(&p[1]);
+        let edges_hit = |ring: &[[f64; 2]], closed: bool| {
+            let closing = closed.then(|| ring.last().zip(ring.first())).flatten();
+            ring.windows(2)
+                .map(|pair| (&pair[0], &pair[1]))
+                .chain(closing)
+                .any(|(a, b)| segment_hits_rect(*a, *b, size))
+        };
+        match self {
+            Shape::Point(p) => inside(p),
+            Shape::Line(line) => line.iter().any(inside) || edges_hit(line, false),
+            Shape::Polygon(rings) => {
+                rings.iter().any(|ring| edges_hit(ring, true))
+                    // The viewport may sit entirely inside the polygon.
+                    || self.hit([size[0] / 2.0, size[1] / 2.0], 0.0)
+            }
+        }
+    }
+}
+
+/// Number of features of one layer overlapping the viewport, or why it is unknown.
+#[derive(Debug, Serialize)]
+pub struct LayerFeatureCount {
+    pub layer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A map viewport in logical pixels.
+pub struct Viewport {
+    pub center: [f64; 2],
+    pub zoom: f64,
+    pub bearing: f64,
+    pub size: [f64; 2],
+}
+
+/// A feature under a `query_features` pixel.
+#[derive(Debug, Serialize)]
+pub struct QueriedFeature {
+    pub layer: String,
+    pub properties: Map<String, Value>,
+}
+
+/// How far, in logical pixels, a queried pixel may be from a point or line and
+/// still hit it.
+const QUERY_TOLERANCE: f64 = 3.0;
+
+/// Whether `layer` draws nothing at `zoom` because of `visibility` or its zoom range.
+fn hidden_at(layer: &Value, zoom: f64) -> bool {
+    layer["layout"]["visibility"] == "none"
+        || layer["minzoom"].as_f64().is_some_and(|min| zoom < min)
+        || layer["maxzoom"].as_f64().is_some_and(|max| zoom >= max)
+}
+
+/// Count the features of each layer whose geometry overlaps `viewport`, for
+/// `layers` or, when `None`, every layer with a source. Layers hidden by
+/// `visibility` or their zoom range count zero. At most `limit` layers are
+/// returned; the flag reports whether more were left out.
+pub fn feature_counts(
+    style: &Value,
+    layers: Option<&[String]>,
+    viewport: &Viewport,
+    limit: usize,
+) -> (Vec<LayerFeatureCount>, bool) {
+    let style_layers: Vec<&Value> = style["layers"].as_array().into_iter().flatten().collect();
+    let wanted: Vec<String> = match layers {
+        Some(layers) => layers.to_vec(),
+        None => style_layers
+            .iter()
+            .filter(|layer| layer["source"].is_string())
+            .filter_map(|layer| layer["id"].as_str().map(str::to_string))
+            .collect(),
+    };
+
+    let world = crate::camera::world_size(viewport.zoom);
+    let projection = TileProjection {
+        world_size: world,
+        origin: [0.0, 0.0],
+    };
+    let center = projection
+        .project(&serde_json::json!(viewport.center))
+        .unwrap_or([world / 2.0, world / 2.0]);
+    let (sin, cos) = (-viewport.bearing.to_radians()).sin_cos();
+    // World pixels to screen pixels, rotating about the centre by the bearing.
+    let to_screen = |p: [f64; 2]| {
+        let (dx, dy) = (p[0] - center[0], p[1] - center[1]);
+        [
+            dx * cos - dy * sin + viewport.size[0] / 2.0,
+            dx * sin + dy * cos + viewport.size[1] / 2.0,
+        ]
+    };
+
+    let count_layer = |layer_id: &str| -> Result<usize, String> {
+        let layer = style_layers
+            .iter()
+            .find(|layer| layer["id"] == layer_id)
+            .ok_or_else(|| format!("layer '{}' not found", layer_id))?;
+        let source_id = layer["source"]
+            .as_str()
+            .ok_or_else(|| format!("layer '{}' has no source", layer_id))?;
+        let data = source_data(style, source_id)?;
+        if hidden_at(layer, viewport.zoom) {
+            return Ok(0);
+        }
+        let mut features = Vec::new();
+        collect_features(layer_id, data, &projection, &mut features);
+        Ok(features
+            .iter()
+            .filter(|feature| {
+                feature.shapes.iter().any(|shape| {
+                    let screen = match shape {
+                        Shape::Point(p) => Shape::Point(to_screen(*p)),
+                        Shape::Line(line) => {
+                            Shape::Line(line.iter().map(|p| to_screen(*p)).collect())
+                        }
+                        Shape::Polygon(rings) => Shape::Polygon(
+                            rings
+                                .iter()
+                                .map(|ring| ring.iter().map(|p| to_screen(*p)).collect())
+                                .collect(),
+                        ),
+                    };
+                    screen.intersects_rect(viewport.size)
+                })
+            })
+            .count())
+    };
+
+    let truncated = wanted.len() > limit;
+    let counts = wanted
+        .into_iter()
+        .take(limit)
+        .map(|layer| match count_layer(&layer) {
+            Ok(count) => LayerFeatureCount {
+                layer,
+                count: Some(count),
+                error: None,
+            },
+            Err(error) => LayerFeatureCount {
+                layer,
+                count: None,
+                error: Some(error),
+            },
+        })
+        .collect();
+    (counts, truncated)
+}
+
+/// Features under the logical pixel `pixel` of `viewport`, topmost first, in
+/// `layers` or, when `None`, every layer backed by inline GeoJSON. Points and lines
+/// count as hit within a few pixels; layers hidden at the viewport's zoom are skipped.
+pub fn features_at(
+    style: &Value,
+    layers: Option<&[String]>,
+    viewport: &Viewport,
+    pixel: [f64; 2],
+) -> Result<Vec<QueriedFeature>, String> {
+    let style_layers: Vec<&Value> = style["layers"].as_array().into_iter().flatten().collect();
+    if let Some(missing) = layers.into_iter().flatten().find(|wanted| {
+        !style_layers
+            .iter()
+            .any(|layer| layer["id"] == wanted.as_str())
+    }) {
+        return Err(format!("layer '{}' not found", missing));
+    }
+
+    let projection = TileProjection {
+        world_size: crate::camera::world_size(viewport.zoom),
+        origin: [0.0, 0.0],
+    };
+    let center = projection
+        .project(&serde_json::json!(viewport.center))
+        .ok_or("invalid center")?;
+    // Screen pixels to world pixels, undoing the bearing's rotation about the centre.
+    let (sin, cos) = viewport.bearing.to_radians().sin_cos();
+    let (dx, dy) = (
+        pixel[0] - viewport.size[0] / 2.0,
+        pixel[1] - viewport.size[1] / 2.0,
+    );
+    let point = [
+        center[0] + dx * cos - dy * sin,
+        center[1] + dx * sin + dy * cos,
+    ];
+
+    let mut hits = Vec::new();
+    for layer in style_layers.iter().rev() {
+        let Some(layer_id) = layer["id"].as_str() else {
+            continue;
+        };
+        let data = match layers {
+            Some(wanted) if !wanted.iter().any(|wanted| wanted == layer_id) => continue,
+            Some(_) => {
+                let source_id = layer["source"]
+                    .as_str()
+                    .ok_or_else(|| format!("layer '{}' has no source", layer_id))?;
+                source_data(style, source_id)?
+            }
+            None => match layer["source"]
+                .as_str()
+                .and_then(|source_id| source_data(style, source_id).ok())
+            {
+                Some(data) => data,
+                None => continue,
+            },
+        };
+        if hidden_at(layer, viewport.zoom) {
+            continue;
+        }
+        let mut features = Vec::new();
+        collect_features(layer_id, data, &projection, &mut features);
+        hits.extend(
+            features
+                .into_iter()
+                .rev()
+                .filter(|feature| {
+                    feature
+                        .shapes
+                        .iter()
+                        .any(|shape| shape.hit(point, QUERY_TOLERANCE))
+                })
+                .map(|feature| QueriedFeature {
+                    layer: layer_id.to_string(),
+                    properties: feature.properties,
+                }),
+        );
+    }
+    Ok(hits)
+}
+
+/// Encode a key index as a UTFGrid character, skipping `"` and `\`.
+fn encode_id(index: usize) -> char {
+    let mut code = index as u32 + 32;
+    if code >= 34 {
+        code += 1;
+    }
+    if code >= 92 {
+        code += 1;
+    }
+    char::from_u32(code).unwrap_or(' ')
+}
+
+/// Inline GeoJSON for `source_id`, or an error explaining why it can't be used.
+fn source_data<'a>(style: &'a Value, source_id: &str) -> Result<&'a Value, String> {
+    let source = style["sources"]
+        .get(source_id)
+        .ok_or_else(|| format!("source '{}' not found", source_id))?;
+    if source["type"] != "geojson" {
+        return Err(format!("source '{}' is not a GeoJSON source", source_id));
+    }
+    match &source["data"] {
+        Value::Object(_) => Ok(&source["data"]),
+        _ => Err(format!(
+            "source '{}' does not have inline GeoJSON data",
+            source_id
+        )),
+    }
+}
+
+fn collect_features(
+    layer_id: &str,
+    data: &Value,
+    projection: &TileProjection,
+    out: &mut Vec<Feature>,
+) {
+    let features: Vec<&Value> = match data["type"].as_str() {
+        Some("FeatureCollection") => data["features"].as_array().into_iter().flatten().collect(),
+        Some("Feature") => vec![data],
+        _ => vec![],
+    };
+    for (index, feature) in features.into_iter().enumerate() {
+        let mut shapes = Vec::new();
+        projection.shapes(&feature["geometry"], &mut shapes);
+        let key = match &feature["id"] {
+            Value::String(id) => id.clone(),
+            Value::Number(id) => id.to_string(),
+            _ => format!("{}:{}", layer_id, index),
+        };
+        out.push(Feature {
+            key,
+            properties: feature["properties"]
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+            shapes,
+        });
+    }
+}
+
+/// Fail unless `resolution` pixels per grid cell divide the 256-pixel tile evenly.
+pub fn check_resolution(resolution: u32) -> Result<(), String> {
+    if resolution == 0 || 256 % resolution != 0 {
+        return Err("resolution must divide 256".to_string());
+    }
+    Ok(())
+}
+
+/// Build the UTFGrid for tile `z/x/y` over `layers`, keeping `fields` of each hit
+/// feature's properties. Features in layers drawn later take precedence.
+pub fn build(
+    style: &Value,
+    z: u8,
+    x: u32,
+    y: u32,
+    layers: &[String],
+    fields: &[String],
+    resolution: u32,
+) -> Result<UtfGrid, String> {
+    check_resolution(resolution)?;
+    let projection = TileProjection::new(z, x, y);
+
+    let mut features = Vec::new();
+    for layer in style["layers"].as_array().into_iter().flatten() {
+        let Some(layer_id) = layer["id"].as_str() else {
+            continue;
+        };
+        if !layers.iter().any(|wanted| wanted == layer_id) {
+            continue;
+        }
+        let source_id = layer["source"]
+            .as_str()
+            .ok_or_else(|| format!("layer '{}' has no source", layer_id))?;
+        collect_features(
+            layer_id,
+            source_data(style, source_id)?,
+            &projection,
+            &mut features,
+        );
+    }
+    if let Some(missing) = layers.iter().find(|wanted| {
+        !style["layers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|l| l["id"] == wanted.as_str())
+    }) {
+        return Err(format!("layer '{}' not found", missing));
+    }
+
+    let cells = 256 / resolution;
+    let tolerance = resolution as f64 / 2.0;
+    let mut keys = vec![String::new()];
+    let mut key_index: HashMap<String, usize> = HashMap::new();
+    let mut data = Map::new();
+    let mut grid = Vec::with_capacity(cells as usize);
+    for row in 0..cells {
+        let mut line = String::with_capacity(cells as usize);
+        for col in 0..cells {
+            let point = [
+                (col as f64 + 0.5) * resolution as f64,
+                (row as f64 + 0.5) * resolution as f64,
+            ];
+            let hit = features.iter().rev().find(|feature| {
+                feature
+                    .shapes
+                    .iter()
+                    .any(|shape| shape.hit(point, tolerance))
+            });
+            let index = match hit {
+                Some(feature) => *key_index.entry(feature.key.clone()).or_insert_with(|| {
+                    keys.push(feature.key.clone());
+                    let selected: Map<String, Value> = fields
+                        .iter()
+                        .filter_map(|field| {
+                            feature
+                                .properties
+                                .get(field)
+                                .map(|v| (field.clone(), v.clone()))
+                        })
+                        .collect();
+                    data.insert(feature.key.clone(), Value::Object(selected));
+                    keys.len() - 1
+                }),
+                None => 0,
+            };
+            line.push(encode_id(index));
+        }
+        grid.push(line);
+    }
+
+    Ok(UtfGrid { grid, keys, data })
+}
+
+/// Properties of the topmost feature of `layer_id` under `lng_lat` when viewed at
+/// `zoom`, for layers backed by inline GeoJSON. Points and lines are hit within
+/// `tolerance` logical pixels.
+pub fn properties_at(
+    style: &Value,
+    layer_id: &str,
+    lng_lat: [f64; 2],
+    zoom: f64,
+    tolerance: f64,
+) -> Result<Option<Map<String, Value>>, String> {
+    let layer = style["layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|layer| layer["id"] == layer_id)
+        .ok_or_else(|| format!("layer '{}' not found", layer_id))?;
+    let source_id = layer["source"]
+        .as_str()
+        .ok_or_else(|| format!("layer '{}' has no source", layer_id))?;
+    let projection = TileProjection {
+        world_size: crate::camera::world_size(zoom),
+        origin: [0.0, 0.0],
+    };
+    let point = projection
+        .project(&serde_json::json!(lng_lat))
+        .ok_or("invalid point")?;
+
+    let mut features = Vec::new();
+    collect_features(
+        layer_id,
+        source_data(style, source_id)?,
+        &projection,
+        &mut features,
+    );
+    Ok(features
+        .into_iter()
+        .rev()
+        .find(|feature| {
+            feature
+                .shapes
+                .iter()
+                .any(|shape| shape.hit(point, tolerance))
+        })
+        .map(|feature| feature.properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A style whose `shapes` layer draws a polygon over the western hemisphere
+    /// between ±60° and, above it, a point on the equator at 135°E.
+    fn style() -> Value {
+        json!({
+            "version": 8,
+            "sources": {
+                "shapes": {"type": "geojson", "data": {
+                    "type": "FeatureCollection",
+                    "features": [
+                        {
+                            "type": "Feature",
+                            "id": "west",
+                            "properties": {"name": "West", "area": 1},
+                            "geometry": {"type": "Polygon", "coordinates": [[
+                                [-180, -60], [0, -60], [0, 60], [-180, 60], [-180, -60]
+                            ]]}
+                        },
+                        {
+                            "type": "Feature",
+                            "properties": {"name": "East"},
+                            "geometry": {"type": "Point", "coordinates": [135, 0]}
+                        }
+                    ]
+                }},
+                "remote": {"type": "geojson", "data": "https://example.com/data.geojson"}
+            },
+            "layers": [
+                {"id": "background", "type": "background"},
+                {"id": "shapes", "type": "fill", "source": "shapes"},
+                {"id": "far", "type": "fill", "source": "shapes", "minzoom": 5},
+                {"id": "remote", "type": "fill", "source": "remote"}
+            ]
+        })
+    }
+
+    fn viewport(center: [f64; 2], zoom: f64) -> Viewport {
+        Viewport {
+            center,
+            zoom,
+            bearing: 0.0,
+            size: [200.0, 200.0],
+        }
+    }
+
+    #[test]
+    fn ids_skip_quote_and_backslash() {
+        assert_eq!(encode_id(0), ' ');
+        assert_eq!(encode_id(1), '!');
+        assert_eq!(encode_id(2), '#');
+        assert_eq!(encode_id(58), '[');
+        assert_eq!(encode_id(59), ']');
+    }
+
+    #[test]
+    fn grid_marks_each_feature_with_its_key() {
+        let grid = build(
+            &style(),
+            0,
+            0,
+            0,
+            &["shapes".to_string()],
+            &["name".to_string()],
+            64,
+        )
+        .unwrap();
+        // Cell centres sit 32px apart from the point, within half a cell.
+        assert_eq!(grid.grid, ["    ", "!! #", "!! #", "    "]);
+        assert_eq!(grid.keys, ["", "west", "shapes:1"]);
+        assert_eq!(grid.data["west"], json!({"name": "West"}));
+        assert_eq!(grid.data["shapes:1"], json!({"name": "East"}));
+    }
+
+    #[test]
+    fn grid_rejects_bad_resolutions_and_layers() {
+        let build = |layer: &str, resolution| {
+            build(&style(), 0, 0, 0, &[layer.to_string()], &[], resolution)
+        };
+        assert!(build("shapes", 3).is_err());
+        assert!(build("shapes", 0).is_err());
+        assert_eq!(
+            build("missing", 4).unwrap_err(),
+            "layer 'missing' not found"
+        );
+        assert_eq!(
+            build("remote", 4).unwrap_err(),
+            "source 'remote' does not have inline GeoJSON data"
+        );
+    }
+
+    #[test]
+    fn polygon_holes_are_not_hit() {
+        let square = |min: f64, max: f64| vec![[min, min], [max, min], [max, max], [min, max]];
+        let shape = Shape::Polygon(vec![square(0.0, 10.0), square(4.0, 6.0)]);
+        assert!(shape.hit([2.0, 2.0], 0.0));
+        assert!(!shape.hit([5.0, 5.0], 0.0));
+        assert!(!shape.hit([12.0, 5.0], 0.0));
+    }
+
+    #[test]
+    fn segments_crossing_the_rect_intersect_it() {
+        let size = [10.0, 10.0];
+        assert!(segment_hits_rect([-5.0, 5.0], [15.0, 5.0], size));
+        assert!(!segment_hits_rect([-5.0, -5.0], [-1.0, 15.0], size));
+        // A polygon enclosing the whole rect has no edge inside it.
+        let around = Shape::Polygon(vec![vec![
+            [-1.0, -1.0],
+            [11.0, -1.0],
+            [11.0, 11.0],
+            [-1.0, 11.0],
+        ]]);
+        assert!(around.intersects_rect(size));
+    }
+
+    #[test]
+    fn properties_come_from_the_topmost_feature() {
+        let properties = |lng_lat| properties_at(&style(), "shapes", lng_lat, 2.0, 3.0).unwrap();
+        assert_eq!(properties([-90.0, 0.0]).unwrap()["name"], "West");
+        assert_eq!(properties([135.0, 0.0]).unwrap()["name"], "East");
+        assert_eq!(properties([90.0, 0.0]), None);
+    }
+
+    #[test]
+    fn counts_cover_the_viewport_and_skip_hidden_layers() {
+        let (counts, truncated) = feature_counts(&style(), None, &viewport([-90.0, 0.0], 2.0), 10);
+        assert!(!truncated);
+        let summary: Vec<_> = counts
+            .iter()
+            .map(|c| (c.layer.as_str(), c.count, c.error.is_some()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("shapes", Some(1), false),
+                ("far", Some(0), false),
+                ("remote", None, true)
+            ]
+        );
+
+        let (counts, truncated) = feature_counts(&style(), None, &viewport([0.0, 0.0], 0.0), 1);
+        assert!(truncated);
+        assert_eq!(counts.len(), 1);
+        // 200px of a 512px world spans about ±70°, short of the point at 135°E.
+        assert_eq!(counts[0].count, Some(1));
+    }
+
+    #[test]
+    fn queries_return_hits_topmost_first() {
+        let hits =
+            features_at(&style(), None, &viewport([-90.0, 0.0], 2.0), [100.0, 100.0]).unwrap();
+        let layers: Vec<_> = hits.iter().map(|hit| hit.layer.as_str()).collect();
+        // `far` is hidden below zoom 5 and `remote` has no inline data.
+        assert_eq!(layers, ["shapes"]);
+        assert_eq!(hits[0].properties["name"], "West");
+
+        let error = features_at(
+            &style(),
+            Some(&["missing".to_string()]),
+            &viewport([0.0, 0.0], 0.0),
+            [0.0, 0.0],
+        )
+        .unwrap_err();
+        assert_eq!(error, "layer 'missing' not found");
+    }
+}
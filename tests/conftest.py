@@ -1,8 +1,14 @@
 """Shared pytest fixtures for mlnative tests."""
 
+import json
+import os
+import subprocess
+
 import pytest
 
 from mlnative import Map
+from mlnative._bridge import get_binary_path
+from mlnative.exceptions import MlnativeError
 
 
 @pytest.fixture
@@ -34,3 +40,75 @@ def simple_geojson() -> dict:
             }
         ],
     }
+
+
+@pytest.fixture
+def binary_path():
+    """Return the render daemon binary, skipping the test if it isn't built."""
+    try:
+        return get_binary_path()
+    except MlnativeError as e:
+        if "not found" in str(e):
+            pytest.skip("Binary not built yet")
+        raise
+
+
+@pytest.fixture
+def run_daemon(binary_path):
+    """Return a function that runs the daemon over a script of commands.
+
+    Each command is a dict sent as one JSON line, or bytes sent as they are. Extra
+    positional arguments go on the daemon's command line and ``env`` adds to its
+    environment. The finished ``subprocess.CompletedProcess`` is returned.
+    """
+
+    def run(commands, *args, timeout=60, env=None):
+        stdin = b"".join(
+            command if isinstance(command, bytes) else json.dumps(command).encode() + b"\n"
+            for command in commands
+        )
+        return subprocess.run(
+            [str(binary_path), *args],
+            input=stdin,
+            capture_output=True,
+            timeout=timeout,
+            env={**os.environ, **(env or {})},
+        )
+
+    return run
+
+
+def _payload_lengths(message: dict) -> list[int]:
+    """Sizes of the payloads that follow a message's JSON line, in order."""
+    if "png_len" in message:
+        return [message["png_len"]]
+    if "png_lengths" in message:
+        return message["png_lengths"]
+    # crops and layer groups announce a payload per successful entry.
+    entries = message.get("crops") or message.get("groups") or []
+    return [entry["png_len"] for entry in entries if "png_len" in entry]
+
+
+@pytest.fixture
+def read_messages():
+    """Return a function that splits daemon stdout into its messages.
+
+    Each message is the parsed JSON line with the payloads sent after it, if any, as
+    a ``payloads`` list of bytes.
+    """
+
+    def read(stdout: bytes) -> list[dict]:
+        messages = []
+        while stdout:
+            line, stdout = stdout.split(b"\n", 1)
+            message = json.loads(line)
+            payloads = []
+            for length in _payload_lengths(message):
+                payloads.append(stdout[:length])
+                stdout = stdout[length:]
+            if payloads:
+                message["payloads"] = payloads
+            messages.append(message)
+        return messages
+
+    return read
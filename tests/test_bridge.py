@@ -1,9 +1,23 @@
 """Tests for _bridge module."""
 
+import base64
+import gzip
+import io
+import json
+import math
+import signal
+import subprocess
+import tarfile
+import threading
+import time
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
+
 import pytest
+from PIL import Image
 
 from mlnative._bridge import (
     PATH_BINARY_OPT_IN_ENV,
+    PROTOCOL_VERSION,
     _get_platform_info,
     _get_timeout,
     get_binary_path,
@@ -103,6 +117,14 @@ class TestRenderDaemonValidation:
         with pytest.raises(MlnativeError, match="not initialized"):
             daemon.reload_style("{}")
 
+    def test_resize_without_init(self):
+        """Test that resize fails if daemon not initialized."""
+        from mlnative._bridge import RenderDaemon
+
+        daemon = RenderDaemon()
+        with pytest.raises(MlnativeError, match="not initialized"):
+            daemon.resize(256, 256)
+
     def test_double_start(self):
         """Test that starting twice raises error."""
         from mlnative._bridge import RenderDaemon
@@ -179,3 +201,4093 @@ class TestTimeoutConfiguration:
         """Test timeout must be positive."""
         with pytest.raises(MlnativeError, match="MLNATIVE_TIMEOUT"):
             _get_timeout(0)
+
+
+class TestProtocol:
+    """Tests for command framing, ids, errors and daemon lifetime."""
+
+    def test_ids_round_trip(self, run_daemon):
+        """Test each response leads with the id of the command it answers."""
+        commands = [
+            {"cmd": "validate_camera", "id": "a1", "center": [0, 0], "zoom": 3},
+            {"cmd": "no_such_command", "id": 2},
+            {"cmd": "validate_camera", "center": [0, 0], "zoom": 3},
+        ]
+        result = run_daemon(commands, timeout=30)
+        lines = result.stdout.decode().splitlines()
+        responses = [json.loads(line) for line in lines[:3]]
+
+        assert list(responses[0])[0] == "id"
+        assert responses[0]["id"] == "a1"
+        assert responses[0]["status"] == "ok"
+        assert responses[1]["id"] == 2
+        assert responses[1]["status"] == "error"
+        assert "id" not in responses[2]
+
+    def test_error_codes(self, run_daemon):
+        """Test errors carry a code, and rendering before init reports not_initialized."""
+        commands = [
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "no_such_command"},
+            {"cmd": "validate_camera", "center": [0, 0], "zoom": 3},
+        ]
+        result = run_daemon(commands, timeout=30)
+        responses = [json.loads(line) for line in result.stdout.splitlines()[:3]]
+
+        assert responses[0]["status"] == "error"
+        assert responses[0]["code"] == "not_initialized"
+        assert responses[1]["code"] == "invalid_command"
+        # Commands that don't need the renderer still work before init.
+        assert responses[2]["status"] == "ok"
+        assert "code" not in responses[2]
+
+    def test_capabilities(self, run_daemon):
+        """Test capabilities answers before init with this client's protocol version."""
+        result = run_daemon([{"cmd": "capabilities"}], timeout=30)
+        response = json.loads(result.stdout.splitlines()[0])
+
+        assert response["status"] == "ok"
+        capabilities = response["capabilities"]
+        assert capabilities["protocol_version"] == PROTOCOL_VERSION
+        assert "png" in capabilities["formats"]
+        assert capabilities["max_texture_size"] >= 4096
+        assert {"init", "render", "render_batch", "capabilities"} <= set(capabilities["commands"])
+
+    def test_render_payload_follows_header_raw(self, run_daemon):
+        """Test a render's payload is exactly png_len raw PNG bytes after its header."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        stdout = result.stdout
+
+        init_line, stdout = stdout.split(b"\n", 1)
+        assert json.loads(init_line)["status"] == "ok"
+        header_line, stdout = stdout.split(b"\n", 1)
+        header = json.loads(header_line)
+        assert header["status"] == "ok"
+        payload = stdout[: header["png_len"]]
+        assert len(payload) == header["png_len"]
+        assert payload.startswith(b"\x89PNG\r\n\x1a\n")
+
+        image = Image.open(io.BytesIO(payload))
+        assert image.size == (64, 32)
+        assert image.convert("RGB").getpixel((0, 0)) == (0x12, 0x34, 0x56)
+
+    def test_init_protocol_version_compatibility(self, run_daemon):
+        """Test init accepts any minor version of the daemon's major protocol version."""
+        major, minor = (int(part) for part in PROTOCOL_VERSION.split("."))
+        init = {"cmd": "init", "width": 16, "height": 16, "style": "{}"}
+        commands = [
+            {**init, "protocol_version": PROTOCOL_VERSION},
+            {**init, "protocol_version": f"{major}.{minor + 1}"},
+            {**init, "protocol_version": f"{major + 1}.0"},
+            {**init, "protocol_version": f"{major - 1}.9"},
+            {**init, "protocol_version": "banana"},
+            init,
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        exact, newer_minor, newer_major, older_major, malformed, omitted = (
+            json.loads(line) for line in result.stdout.splitlines()
+        )
+
+        assert exact["status"] == "ok"
+        assert exact["protocol_version"] == PROTOCOL_VERSION
+        assert "warnings" not in exact
+
+        assert newer_minor["status"] == "ok"
+        assert newer_minor["protocol_version"] == PROTOCOL_VERSION
+        assert any("newer" in warning for warning in newer_minor["warnings"])
+
+        for response in (newer_major, older_major, malformed):
+            assert response["status"] == "error"
+            assert response["code"] == "init_failed"
+            assert response["error_code"] == "protocol_mismatch"
+            assert response["protocol_version"] == PROTOCOL_VERSION
+            assert "Protocol version mismatch" in response["error"]
+
+        assert omitted["status"] == "ok"
+        assert omitted["protocol_version"] == PROTOCOL_VERSION
+        assert any("protocol_version" in warning for warning in omitted["warnings"])
+
+    def test_oversized_command_is_rejected(self, run_daemon):
+        """Test a command over --max-command-bytes is skipped and the daemon reads on."""
+        oversized = {"cmd": "init", "width": 16, "height": 16, "style": "x" * 100_000}
+        commands = [
+            {"cmd": "capabilities", "id": 1},
+            {**oversized, "id": 2},
+            {"cmd": "capabilities", "id": 3},
+            {"cmd": "init", "width": 16, "height": 16, "style": "{}", "id": 4},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, "--max-command-bytes", "4096")
+        responses = [json.loads(line) for line in result.stdout.splitlines()]
+
+        assert len(responses) == 4
+        assert responses[0]["id"] == 1
+        assert responses[1]["status"] == "error"
+        assert responses[1]["code"] == "invalid_command"
+        assert responses[1]["error_code"] == "command_too_large"
+        assert "id" not in responses[1]
+        assert responses[2] == {**responses[0], "id": 3}
+        assert responses[3]["status"] == "ok"
+        assert responses[3]["id"] == 4
+
+    def test_init_style_from_stdin(self, run_daemon, read_messages):
+        """A multi-line style sent after init renders, and the stream stays in sync."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        blob = json.dumps(style, indent=2).encode()
+        init = {"cmd": "init", "width": 64, "height": 64, "style": "@stdin"}
+        commands = [
+            {**init, "style_len": len(blob)},
+            blob + b"\n",
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            init,
+            {"cmd": "quit"},
+        ]
+        init_response, render, missing_len = read_messages(run_daemon(commands).stdout)[:3]
+        assert init_response["status"] == "ok"
+        assert render["status"] == "ok"
+        assert missing_len["status"] == "error"
+        assert missing_len["code"] == "invalid_parameter"
+
+    def test_init_style_from_stdin_over_limit(self, run_daemon):
+        """Test a stdin style over --max-command-bytes is skipped and rejected."""
+        blob = b"{" + b" " * 998 + b"}"
+        init = {"cmd": "init", "width": 64, "height": 64, "style": "@stdin"}
+        commands = [
+            {**init, "style_len": len(blob)},
+            blob + b"\n",
+            {"cmd": "capabilities"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, "--max-command-bytes", "200")
+        too_large, capabilities = [json.loads(line) for line in result.stdout.splitlines()]
+        assert too_large["status"] == "error"
+        assert too_large["error_code"] == "command_too_large"
+        assert capabilities["status"] == "ok"
+
+    def test_gzip_base64_style(self, run_daemon, read_messages):
+        """Test init and reload_style accept gzip+base64 styles and reject malformed ones."""
+        def compressed(color):
+            style = {
+                "version": 8,
+                "sources": {},
+                "layers": [
+                    {"id": "bg", "type": "background", "paint": {"background-color": color}}
+                ],
+            }
+            return base64.b64encode(gzip.compress(json.dumps(style).encode())).decode()
+
+        encoding = {"style_encoding": "gzip+base64"}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 16, "height": 16, "style": "not base64!", **encoding},
+            {"cmd": "init", "width": 16, "height": 16, "style": "aGVsbG8=", **encoding},
+            {"cmd": "init", "width": 16, "height": 16, "style": compressed("#ff0000"), **encoding},
+            render,
+            {"cmd": "reload_style", "style": compressed("#0000ff"), **encoding},
+            render,
+            {"cmd": "quit"},
+        ]
+        headers, pixels = read_messages(run_daemon(commands).stdout), []
+        for header in headers:
+            for png in header.get("payloads", []):
+                pixels.append(Image.open(io.BytesIO(png)).convert("RGB").getpixel((8, 8)))
+
+        assert headers[0]["code"] == "invalid_parameter"
+        assert "base64" in headers[0]["error"]
+        # Valid base64 of bytes that aren't gzip.
+        assert headers[1]["code"] == "invalid_parameter"
+        assert "gzip" in headers[1]["error"]
+        assert all(header["status"] == "ok" for header in headers[2:])
+        assert pixels == [(255, 0, 0), (0, 0, 255)]
+
+    def test_pipeline_runs_commands_in_one_response(self, run_daemon, read_messages):
+        """Test pipeline answers once for init plus render, and stops at the first error."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#00ff00"}}
+            ],
+        }
+        init = {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        bad_render = {"cmd": "render", "center": [0, 95], "zoom": 1}
+        commands = [
+            {"cmd": "pipeline", "id": "p1", "commands": [init, {**render, "id": "r1"}]},
+            {"cmd": "pipeline", "commands": [bad_render, render]},
+            {"cmd": "pipeline", "commands": [bad_render, render], "continue_on_error": True},
+            {"cmd": "pipeline", "commands": [render, {"cmd": "quit"}]},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        headers = read_messages(result.stdout)
+        payloads = [payload for header in headers for payload in header.get("payloads", [])]
+
+        # One line per pipeline, with the commands' own responses inside.
+        assert len(headers) == 4
+        first, stopped, continued, rejected = headers
+        assert first["id"] == "p1" and first["status"] == "ok"
+        assert [r["status"] for r in first["responses"]] == ["ok", "ok"]
+        assert first["responses"][1]["id"] == "r1"
+        assert first["png_lengths"][0] == 0
+        assert first["png_lengths"][1] == first["responses"][1]["png_len"]
+        png = payloads[1]
+        assert Image.open(io.BytesIO(png)).convert("RGB").getpixel((8, 8)) == (0, 255, 0)
+
+        assert [r["status"] for r in stopped["responses"]] == ["error"]
+        assert stopped["responses"][0]["error_code"] == "invalid_camera"
+        assert [r["status"] for r in continued["responses"]] == ["error", "ok"]
+
+        assert rejected["status"] == "error"
+        assert rejected["code"] == "invalid_parameter"
+        assert "quit" in rejected["error"]
+
+    def test_render_batch_stream(self, run_daemon, read_messages):
+        """Test a streaming batch sends one line per view and a summary line."""
+        views = [
+            {"center": [0, 0], "zoom": 1},
+            {"center": [10, 10], "zoom": 2, "format": "jpeg", "quality": 80},
+            {"center": [0, 0], "zoom": 1, "width": 0},
+        ]
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": "{}"},
+            {"cmd": "render_batch", "views": views, "stream": True},
+            {"cmd": "quit"},
+        ]
+        init, *events = read_messages(run_daemon(commands).stdout)
+        assert init["status"] == "ok"
+
+        # N view lines, each followed by its payload, then the summary: N + 1 lines.
+        assert len(events) == len(views) + 1
+        for event in events:
+            if "payloads" in event:
+                event["image"] = Image.open(io.BytesIO(event["payloads"][0]))
+
+        *streamed, summary = events
+        assert [event["status"] for event in streamed] == ["view"] * len(views)
+        assert [event["index"] for event in streamed] == [0, 1, 2]
+        assert streamed[0]["image"].format == "PNG"
+        assert streamed[1]["image"].format == "JPEG"
+        assert "non-zero" in streamed[2]["error"]
+        assert summary["status"] == "ok"
+        assert "views" not in summary
+        assert "png_lengths" not in summary
+
+    def test_cancel_render_batch(self, binary_path):
+        """Test cancel stops a running batch at a view boundary with a partial result."""
+        views = [{"center": [i % 180, 0], "zoom": 3} for i in range(500)]
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+        )
+        try:
+            commands = [
+                {"cmd": "init", "width": 256, "height": 256, "style": "{}"},
+                {"cmd": "render_batch", "views": views, "stream": True},
+            ]
+            proc.stdin.write("".join(json.dumps(cmd) + "\n" for cmd in commands).encode())
+            proc.stdin.flush()
+            assert json.loads(proc.stdout.readline())["status"] == "ok"
+
+            # Cancel once the batch is known to be under way.
+            first = json.loads(proc.stdout.readline())
+            assert first["status"] == "view"
+            proc.stdout.read(first["png_len"])
+            proc.stdin.write(b'{"cmd": "cancel"}\n{"cmd": "quit"}\n')
+            proc.stdin.flush()
+
+            events = [first]
+            while True:
+                event = json.loads(proc.stdout.readline())
+                if event["status"] != "view":
+                    break
+                if "png_len" in event:
+                    proc.stdout.read(event["png_len"])
+                events.append(event)
+            assert event["status"] == "cancelled"
+            assert len(events) == len(views)
+            rendered = [event for event in events if "png_len" in event]
+            skipped = [event for event in events if event.get("error") == "cancelled"]
+            assert 0 < len(rendered) < len(views)
+            assert len(rendered) + len(skipped) == len(views)
+
+            # The cancel is acknowledged after the batch it stopped.
+            assert json.loads(proc.stdout.readline())["status"] == "ok"
+            assert proc.wait(timeout=60) == 0
+        finally:
+            if proc.poll() is None:
+                proc.kill()
+                proc.wait()
+
+    def test_estimate_resources_reports_framebuffer_bytes(self, run_daemon):
+        """Test estimate_resources needs no init and sizes the surface by the pixel ratio."""
+        width, height, ratio = 640, 480, 2
+        commands = [
+            {"cmd": "estimate_resources", "width": width, "height": height, "pixel_ratio": ratio},
+            # Over the texture limit on every backend.
+            {"cmd": "estimate_resources", "width": 8192, "height": 8192, "pixel_ratio": 4},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        estimated, too_large = [json.loads(line) for line in result.stdout.splitlines()]
+
+        assert estimated["status"] == "ok"
+        estimate = estimated["estimate"]
+        assert estimate["framebuffer_bytes"] == width * ratio * height * ratio * 4
+        assert (estimate["texture_width"], estimate["texture_height"]) == (1280, 960)
+        assert estimate["fits"] is True
+
+        assert too_large["status"] == "error"
+        assert too_large["error_code"] == "texture_too_large"
+        assert too_large["estimate"]["texture_width"] == 32768
+
+    def test_verbose_logging_keeps_stdout_protocol_only(self, run_daemon, read_messages):
+        """Test RUST_LOG=trace logs to stderr while stdout stays protocol JSON and payloads."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ff0000"}}
+            ],
+        }
+        commands = [
+            {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)},
+            {"cmd": "render", "id": "r1", "center": [0, 0], "zoom": 1},
+            {"cmd": "draw"},
+            {"cmd": "init", "width": 16, "height": 16, "style": "{}", "log_level": "loud"},
+            {"cmd": "init", "width": 16, "height": 16, "style": "{}", "log_level": "off"},
+            {"cmd": "render", "id": "r2", "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, env={"RUST_LOG": "trace"})
+
+        # Every byte of stdout is a JSON line or a payload announced by one.
+        responses = read_messages(result.stdout)
+        for response in responses:
+            for payload in response.get("payloads", []):
+                assert Image.open(io.BytesIO(payload)).size == (16, 16)
+        assert [r["status"] for r in responses] == ["ok", "ok", "error", "error", "ok", "ok"]
+        assert responses[3]["code"] == "invalid_parameter"
+        assert "log level must be one of" in responses[3]["error"]
+
+        stderr = result.stderr.decode()
+        assert "render received with id \"r1\"" in stderr
+        assert "render at [0.0, 0.0] zoom 1 took" in stderr
+        assert "unknown variant `draw`" in stderr
+        # log_level "off" silenced everything after it.
+        assert "r2" not in stderr
+
+    def test_failed_init_keeps_log_level(self, run_daemon, read_messages, tmp_path):
+        """Test an init that fails to load its style leaves the log level as it was."""
+        missing = tmp_path / "missing.json"
+        commands = [
+            {"cmd": "init", "width": 16, "height": 16, "style": "{}"},
+            {
+                "cmd": "init",
+                "width": 16,
+                "height": 16,
+                "style": f"file://{missing}",
+                "log_level": "off",
+            },
+            {"cmd": "render", "id": "r2", "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, env={"RUST_LOG": "trace"})
+
+        responses = read_messages(result.stdout)
+        assert responses[1]["status"] == "error"
+        assert responses[1]["code"] == "init_failed"
+        assert "render received with id \"r2\"" in result.stderr.decode()
+
+    def test_sigterm_exits_cleanly_between_commands(self, binary_path):
+        """Test SIGTERM ends an idle daemon as quit would, after answering what it read."""
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+        try:
+            proc.stdin.write(b'{"cmd": "capabilities"}\n')
+            proc.stdin.flush()
+            assert json.loads(proc.stdout.readline())["status"] == "ok"
+
+            proc.send_signal(signal.SIGTERM)
+            assert proc.wait(timeout=10) == 0
+        finally:
+            if proc.poll() is None:
+                proc.kill()
+                proc.wait()
+
+    def test_closed_stdout_exits_cleanly(self, binary_path):
+        """Test the daemon exits with status 0, not a panic, once its reader closes stdout."""
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+        try:
+            proc.stdin.write(b'{"cmd": "capabilities"}\n')
+            proc.stdin.flush()
+            assert json.loads(proc.stdout.readline())["status"] == "ok"
+
+            # stdin stays open, so only the failed write can end the daemon.
+            proc.stdout.close()
+            proc.stdin.write(b'{"cmd": "capabilities"}\n')
+            proc.stdin.flush()
+            assert proc.wait(timeout=10) == 0
+            assert b"panicked" not in proc.stderr.read()
+        finally:
+            if proc.poll() is None:
+                proc.kill()
+                proc.wait()
+
+    def test_resource_response_answers_client_requests(self, binary_path):
+        """Test the client loader asks for the style and loads the bytes sent back."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        body = json.dumps(style).encode()
+        init = {"cmd": "init", "width": 16, "height": 16}
+        proc = subprocess.Popen(
+            [str(binary_path), "--resource-loader", "client"],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+        )
+
+        def send(cmd, data=b""):
+            proc.stdin.write(json.dumps(cmd).encode() + b"\n" + data)
+            proc.stdin.flush()
+            return json.loads(proc.stdout.readline())
+
+        try:
+            request = send({**init, "style": "https://styles.example/missing.json"})
+            assert request["status"] == "resource_request"
+            assert request["kind"] == "style"
+            assert request["url"] == "https://styles.example/missing.json"
+            missing = send({"cmd": "resource_response", "id": request["id"], "status_code": 404})
+            assert missing["code"] == "init_failed"
+
+            request = send({**init, "style": "https://styles.example/style.json"})
+            reply = {"cmd": "resource_response", "id": request["id"], "data_len": len(body)}
+            # The style bytes follow the command line directly.
+            assert send(reply, body)["status"] == "ok"
+
+            header = send({"cmd": "render", "center": [0, 0], "zoom": 1})
+            image = Image.open(io.BytesIO(proc.stdout.read(header["png_len"])))
+            assert image.convert("RGB").getpixel((8, 8)) == (0x12, 0x34, 0x56)
+        finally:
+            proc.stdin.close()
+            proc.wait(timeout=10)
+
+    def test_check_glyph_coverage_reports_missing_codepoints(
+        self, tmp_path, run_daemon, read_messages
+    ):
+        """Test check_glyph_coverage reads glyph ids from local PBFs without an init."""
+
+        def length_field(field, payload):
+            return bytes([(field << 3) | 2, len(payload)]) + payload
+
+        glyphs = b"".join(length_field(3, bytes([1 << 3, cp])) for cp in b"AB")
+        (tmp_path / "Test").mkdir()
+        (tmp_path / "Test" / "0-255.pbf").write_bytes(
+            length_field(1, length_field(1, b"Test") + glyphs)
+        )
+        check = {
+            "cmd": "check_glyph_coverage",
+            "font_stacks": [["Test"], ["Absent"]],
+            "sample_texts": ["AB C"],
+        }
+        commands = [
+            {**check, "glyphs": f"file://{tmp_path}/{{fontstack}}/{{range}}.pbf"},
+            check,
+            {"cmd": "quit"},
+        ]
+        checked, no_style = read_messages(run_daemon(commands).stdout)
+
+        present, absent = checked["glyph_coverage"]
+        assert present["font_stack"] == ["Test"]
+        assert present["complete"] is False
+        assert present["checked_codepoints"] == 3
+        assert present["missing_codepoints"] == ["U+0043"]
+        assert present["failed_ranges"] == []
+        assert absent["missing_codepoints"] == ["U+0041", "U+0042", "U+0043"]
+        assert [failed["range"] for failed in absent["failed_ranges"]] == ["0-255"]
+
+        assert no_style["code"] == "not_initialized"
+
+    def test_build_sprite_packs_icons_per_pixel_ratio(self, tmp_path, run_daemon, read_messages):
+        """Test build_sprite writes a sheet and index per ratio without an init."""
+        icons = tmp_path / "icons"
+        (icons / "nested").mkdir(parents=True)
+        Image.new("RGBA", (4, 4), (255, 0, 0, 255)).save(icons / "red.png")
+        Image.new("RGBA", (6, 2), (0, 0, 255, 255)).save(icons / "nested" / "blue.png")
+        (tmp_path / "empty").mkdir()
+        output = tmp_path / "sprite"
+        commands = [
+            {"cmd": "build_sprite", "icon_dir": str(icons), "output": str(output)},
+            {"cmd": "build_sprite", "icon_dir": str(tmp_path / "empty"), "output": str(output)},
+            {
+                "cmd": "build_sprite",
+                "icon_dir": str(icons),
+                "output": str(output),
+                "pixel_ratios": [8],
+            },
+            {"cmd": "quit"},
+        ]
+        built, empty, bad_ratio = read_messages(run_daemon(commands).stdout)
+
+        one, two = built["sprite"]
+        assert (one["pixel_ratio"], one["png"]) == (1, f"{output}.png")
+        assert (two["pixel_ratio"], two["json"]) == (2, f"{output}@2x.json")
+        assert one["icons"] == two["icons"] == 2
+        index = json.loads((tmp_path / "sprite@2x.json").read_text())
+        red = index["red"]
+        assert (red["width"], red["height"], red["pixelRatio"]) == (8, 8, 2)
+        assert (index["blue"]["width"], index["blue"]["height"]) == (12, 4)
+        with Image.open(two["png"]) as sheet:
+            assert sheet.size == (two["width"], two["height"])
+            pixel = sheet.convert("RGBA").getpixel((red["x"] + 4, red["y"] + 4))
+            assert pixel == (255, 0, 0, 255)
+
+        assert empty["code"] == "command_failed"
+        assert "no PNG icons" in empty["error"]
+        assert "pixel ratios must be between 1 and 4" in bad_ratio["error"]
+
+    def test_lint_style_reports_spec_violations(self, tmp_path, run_daemon, read_messages):
+        """Test lint_style checks inline and file styles against the spec without an init."""
+        clean = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        broken = {
+            **clean,
+            "extra": True,
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "not-a-color"}}
+            ],
+        }
+        path = tmp_path / "broken.json"
+        path.write_text(json.dumps(broken))
+        commands = [
+            {"cmd": "lint_style", "style": json.dumps(clean)},
+            {"cmd": "lint_style", "style": str(path)},
+            {"cmd": "lint_style", "style": str(tmp_path / "missing.json")},
+            {"cmd": "quit"},
+        ]
+        valid, invalid, missing = read_messages(run_daemon(commands).stdout)
+
+        assert valid["valid"] is True
+        assert valid["violations"] == []
+        assert valid["spec_revision"]
+
+        assert invalid["valid"] is False
+        severities = {v["path"]: v["severity"] for v in invalid["violations"]}
+        assert severities == {
+            "extra": "warning",
+            "layers[0].paint.background-color": "error",
+        }
+
+        assert missing["code"] == "command_failed"
+        assert "Cannot read style" in missing["error"]
+
+    def test_flush_syncs_files_written_since_last_flush(self, tmp_path, run_daemon, read_messages):
+        """Test flush reports how many written files it synced, once each."""
+        (tmp_path / "icons").mkdir()
+        Image.new("RGBA", (4, 4), (255, 0, 0, 255)).save(tmp_path / "icons" / "red.png")
+        commands = [
+            {"cmd": "flush"},
+            {
+                "cmd": "build_sprite",
+                "icon_dir": str(tmp_path / "icons"),
+                "output": str(tmp_path / "sprite"),
+            },
+            {"cmd": "flush"},
+            {"cmd": "flush"},
+            {"cmd": "quit"},
+        ]
+        empty, built, synced, again = read_messages(run_daemon(commands).stdout)
+
+        assert empty["synced_files"] == 0
+        assert built["status"] == "ok"
+        # A PNG and a JSON index for each of the default @1x and @2x sheets.
+        assert synced["synced_files"] == 4
+        assert again["synced_files"] == 0
+
+
+class TestSessions:
+    """Tests for init options, resizing and named sessions."""
+
+    @pytest.mark.parametrize("quit_command", [True, False])
+    def test_inline_style_leaves_no_temp_files(self, run_daemon, tmp_path, quit_command):
+        """Test inline styles leave nothing in the temp dir after quit or stdin EOF."""
+        style = {"version": 8, "sources": {}, "layers": []}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "reload_style", "style": json.dumps({**style, "name": "reloaded"})},
+            {"cmd": "reload_style", "style": json.dumps(style)},
+        ]
+        if quit_command:
+            commands.append({"cmd": "quit"})
+        result = run_daemon(commands, env={"TMPDIR": str(tmp_path)})
+
+        responses = [json.loads(line) for line in result.stdout.splitlines()]
+        assert [response["status"] for response in responses] == ["ok", "ok", "ok"]
+        assert list(tmp_path.iterdir()) == []
+
+    @pytest.mark.usefixtures("binary_path")
+    def test_inline_style_renders_without_temp_files(self, monkeypatch, tmp_path):
+        """Test a running daemon renders an inline style without writing it to disk."""
+        from mlnative._bridge import RenderDaemon
+
+        monkeypatch.setenv("TMPDIR", str(tmp_path))
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+
+        with RenderDaemon() as daemon:
+            daemon.start(32, 32, json.dumps(style))
+            image = Image.open(io.BytesIO(daemon.render([0, 0], 1)))
+
+            assert image.convert("RGB").getpixel((0, 0)) == (0x12, 0x34, 0x56)
+            assert list(tmp_path.iterdir()) == []
+
+    def test_init_background(self, run_daemon, read_messages):
+        """Test a transparent background keeps alpha 0 and a colour fills an empty style."""
+        empty = {"version": 8, "sources": {}, "layers": []}
+        with_background = {
+            **empty,
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        init = {"cmd": "init", "width": 32, "height": 32}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {**init, "style": json.dumps(empty), "background": "transparent"},
+            render,
+            {**init, "style": json.dumps(with_background), "background": "transparent"},
+            render,
+            {**render, "format": "jpeg"},
+            {**init, "style": json.dumps(empty), "background": "#ff0000"},
+            render,
+            {**init, "style": json.dumps(empty), "background": "red"},
+            {"cmd": "quit"},
+        ]
+        messages = iter(read_messages(run_daemon(commands).stdout))
+
+        def read_render():
+            assert next(messages)["status"] == "ok"
+            header = next(messages)
+            assert header["status"] == "ok"
+            return Image.open(io.BytesIO(header["payloads"][0])).convert("RGBA")
+
+        for _ in range(2):
+            image = read_render()
+            for corner in [(0, 0), (31, 0), (0, 31), (31, 31)]:
+                assert image.getpixel(corner)[3] == 0
+
+        jpeg = next(messages)
+        assert jpeg["status"] == "error"
+        assert jpeg["code"] == "invalid_parameter"
+
+        assert read_render().getpixel((0, 0)) == (255, 0, 0, 255)
+
+        named = next(messages)
+        assert named["code"] == "invalid_command"
+        assert "hex colour" in named["error"]
+
+    def test_init_zoom_bounds(self, run_daemon, read_messages):
+        """Test renders outside min_zoom/max_zoom are rejected, or clamped on request."""
+        style = json.dumps({"version": 8, "sources": {}, "layers": []})
+        init = {"cmd": "init", "width": 16, "height": 16, "style": style}
+        render = {"cmd": "render", "center": [0, 0]}
+        commands = [
+            {**init, "min_zoom": 2, "max_zoom": 10},
+            {**render, "zoom": 10},
+            {**render, "zoom": 10.5},
+            {**render, "zoom": 1},
+            {"cmd": "render_batch", "views": [{"center": [0, 0], "zoom": 11}]},
+            {**init, "min_zoom": 2, "max_zoom": 10, "zoom_out_of_range": "clamp"},
+            {**render, "zoom": 12},
+            {**init, "min_zoom": 10, "max_zoom": 2},
+            {"cmd": "quit"},
+        ]
+        responses = read_messages(run_daemon(commands).stdout)
+
+        assert [r["status"] for r in responses[:2]] == ["ok", "ok"]
+        for rejected in responses[2:5]:
+            assert rejected["code"] == "invalid_parameter"
+            assert "allowed range 2 to 10" in rejected["error"]
+        assert [r["status"] for r in responses[5:7]] == ["ok", "ok"]
+        inverted = responses[7]
+        assert inverted["code"] == "invalid_parameter"
+        assert "min_zoom" in inverted["error"]
+
+    def test_init_glyphs_and_sprite_overrides(self, run_daemon):
+        """Test init's glyphs and sprite replace the style's URLs, and bad URLs are rejected."""
+        style = {
+            "version": 8,
+            "glyphs": "custom://old/{fontstack}/{range}.pbf",
+            "sprite": "custom://old/sprite",
+            "sources": {
+                "places": {
+                    "type": "geojson",
+                    "data": {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [0, 0]},
+                        "properties": {"name": "Null Island"},
+                    },
+                }
+            },
+            "layers": [
+                {
+                    "id": "labels",
+                    "type": "symbol",
+                    "source": "places",
+                    "layout": {"text-field": ["get", "name"], "text-font": ["Noto Sans Regular"]},
+                }
+            ],
+        }
+        init = {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)}
+        commands = [
+            {**init, "glyphs": "fonts/{fontstack}/{range}.pbf"},
+            {**init, "glyphs": "custom://new/{fontstack}.pbf"},
+            {**init, "sprite": "custom://new sprite"},
+            {
+                **init,
+                "glyphs": "custom://new/{fontstack}/{range}.pbf",
+                "sprite": "custom://new/sprite",
+            },
+            # Unanswered requests would block for 30 s; the timeout ends the render.
+            {"cmd": "render", "center": [0, 0], "zoom": 2, "timeout_ms": 1000},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, "--resource-loader", "client")
+        lines = [json.loads(line) for line in result.stdout.splitlines()]
+        requests = [line["url"] for line in lines if line["status"] == "resource_request"]
+        responses = [line for line in lines if line["status"] != "resource_request"]
+
+        assert "absolute URL" in responses[0]["error"]
+        assert "{range}" in responses[1]["error"]
+        assert "absolute URL" in responses[2]["error"]
+        assert responses[3]["status"] == "ok"
+        assert any(url.startswith("custom://new/sprite") for url in requests)
+        assert any(url.startswith("custom://new/Noto%20Sans") for url in requests)
+        assert not any(url.startswith("custom://old/") for url in requests)
+
+    @pytest.mark.integration
+    def test_init_glyphs_override_renders_labels(self, run_daemon, read_messages):
+        """Test labels render with glyphs from the init override rather than the style's."""
+        style = {
+            "version": 8,
+            "glyphs": "http://127.0.0.1:9/{fontstack}/{range}.pbf",
+            "sources": {
+                "places": {
+                    "type": "geojson",
+                    "data": {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [0, 0]},
+                        "properties": {"name": "Null Island"},
+                    },
+                }
+            },
+            "layers": [
+                {
+                    "id": "labels",
+                    "type": "symbol",
+                    "source": "places",
+                    "layout": {"text-field": ["get", "name"], "text-font": ["Open Sans Regular"]},
+                }
+            ],
+        }
+        commands = [
+            {
+                "cmd": "init",
+                "width": 128,
+                "height": 64,
+                "style": json.dumps(style),
+                "glyphs": "https://demotiles.maplibre.org/font/{fontstack}/{range}.pbf",
+            },
+            {"cmd": "render", "center": [0, 0], "zoom": 2},
+            {"cmd": "quit"},
+        ]
+        init, header = read_messages(run_daemon(commands).stdout)
+        assert init["status"] == "ok"
+        assert header["status"] == "ok"
+
+        image = Image.open(io.BytesIO(header["payloads"][0])).convert("RGBA")
+        assert image.getchannel("A").getbbox() is not None
+
+    def test_init_cache_path_reuses_tiles_across_sessions(
+        self, run_daemon, read_messages, tmp_path
+    ):
+        """Test a second daemon with the same cache_path renders without fetching tiles."""
+        tile = io.BytesIO()
+        Image.new("RGB", (256, 256), (0, 128, 255)).save(tile, format="PNG")
+        fetched = []
+
+        class TileHandler(BaseHTTPRequestHandler):
+            def do_GET(self):
+                fetched.append(self.path)
+                self.send_response(200)
+                self.send_header("Content-Type", "image/png")
+                self.send_header("Cache-Control", "max-age=3600")
+                self.end_headers()
+                self.wfile.write(tile.getvalue())
+
+            def log_message(self, *args):
+                pass
+
+        server = ThreadingHTTPServer(("127.0.0.1", 0), TileHandler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        try:
+            style = {
+                "version": 8,
+                "sources": {
+                    "tiles": {
+                        "type": "raster",
+                        "tiles": [f"http://127.0.0.1:{server.server_port}/{{z}}/{{x}}/{{y}}.png"],
+                        "tileSize": 256,
+                    }
+                },
+                "layers": [{"id": "tiles", "type": "raster", "source": "tiles"}],
+            }
+            commands = [
+                {
+                    "cmd": "init",
+                    "width": 64,
+                    "height": 64,
+                    "style": json.dumps(style),
+                    "cache_path": str(tmp_path / "cache" / "resources.db"),
+                    "cache_size_mb": 8,
+                },
+                {"cmd": "render", "center": [0, 0], "zoom": 1},
+                {"cmd": "quit"},
+            ]
+            images, fetch_counts = [], []
+            for _ in range(2):
+                init, render = read_messages(run_daemon(commands).stdout)
+                assert init["status"] == "ok"
+                assert render["status"] == "ok"
+                images.append(render["payloads"][0])
+                fetch_counts.append(len(fetched))
+        finally:
+            server.shutdown()
+
+        assert fetch_counts[0] > 0
+        assert fetch_counts[1] == fetch_counts[0]
+        assert images[0] == images[1]
+        centre = Image.open(io.BytesIO(images[1])).convert("RGB").getpixel((32, 32))
+        assert centre == (0, 128, 255)
+
+    def test_init_retries_transient_style_failures(self, run_daemon, read_messages):
+        """Test init retries a style URL after a 503 but not after a 404."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#00ff00"}}
+            ],
+        }
+        requests = []
+
+        class StyleHandler(BaseHTTPRequestHandler):
+            def do_GET(self):
+                requests.append(self.path)
+                if self.path == "/missing.json":
+                    self.send_error(404)
+                elif self.path == "/flaky.json" and requests.count(self.path) == 1:
+                    self.send_error(503)
+                else:
+                    body = json.dumps(style).encode()
+                    self.send_response(200)
+                    self.send_header("Content-Type", "application/json")
+                    self.send_header("Cache-Control", "no-store")
+                    self.end_headers()
+                    self.wfile.write(body)
+
+            def log_message(self, *args):
+                pass
+
+        server = ThreadingHTTPServer(("127.0.0.1", 0), StyleHandler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        base = f"http://127.0.0.1:{server.server_port}"
+        init = {"cmd": "init", "width": 16, "height": 16, "retry_backoff_ms": 50}
+        try:
+            commands = [
+                {**init, "style": f"{base}/missing.json"},
+                {**init, "style": f"{base}/flaky.json"},
+                {"cmd": "render", "center": [0, 0], "zoom": 1},
+                {"cmd": "quit"},
+            ]
+            result = run_daemon(commands)
+        finally:
+            server.shutdown()
+
+        missing, flaky, header = read_messages(result.stdout)
+        assert missing["code"] == "init_failed"
+        assert requests.count("/missing.json") == 1
+
+        assert flaky["status"] == "ok"
+        assert requests.count("/flaky.json") >= 2
+
+        assert header["status"] == "ok"
+        png = header["payloads"][0]
+        assert Image.open(io.BytesIO(png)).convert("RGB").getpixel((8, 8)) == (0, 255, 0)
+
+    def test_retries_belong_to_their_session(self, run_daemon, read_messages):
+        """Test each session's style loads retry as its own init asked, whichever was last."""
+        style = {"version": 8, "sources": {}, "layers": []}
+        requests = []
+
+        class StyleHandler(BaseHTTPRequestHandler):
+            def do_GET(self):
+                requests.append(self.path)
+                if requests.count(self.path) == 1:
+                    self.send_error(503)
+                else:
+                    body = json.dumps(style).encode()
+                    self.send_response(200)
+                    self.send_header("Content-Type", "application/json")
+                    self.send_header("Cache-Control", "no-store")
+                    self.end_headers()
+                    self.wfile.write(body)
+
+            def log_message(self, *args):
+                pass
+
+        server = ThreadingHTTPServer(("127.0.0.1", 0), StyleHandler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        base = f"http://127.0.0.1:{server.server_port}"
+        init = {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)}
+        try:
+            commands = [
+                {**init, "session_id": "strict", "max_retries": 0},
+                {**init, "max_retries": 2, "retry_backoff_ms": 50},
+                {"cmd": "reload_style", "session_id": "strict", "style": f"{base}/a.json"},
+                {"cmd": "reload_style", "style": f"{base}/b.json"},
+                {"cmd": "quit"},
+            ]
+            result = run_daemon(commands)
+        finally:
+            server.shutdown()
+
+        strict_init, default_init, strict, default = read_messages(result.stdout)
+        assert strict_init["status"] == "ok"
+        assert default_init["status"] == "ok"
+        assert strict["status"] == "error"
+        assert requests.count("/a.json") == 1
+        assert default["status"] == "ok"
+        assert requests.count("/b.json") >= 2
+
+    def test_init_offline_rejects_remote_style(self, run_daemon, read_messages, tmp_path):
+        """Test offline init fails at once on a remote style but loads a local one."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#0000ff"}}
+            ],
+        }
+        style_path = tmp_path / "style.json"
+        style_path.write_text(json.dumps(style))
+        init = {"cmd": "init", "width": 16, "height": 16, "offline": True}
+        # A non-routable address: a real fetch would hang until it timed out.
+        commands = [
+            {**init, "style": "http://10.255.255.1/style.json"},
+            {**init, "style": f"file://{style_path}"},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "init", "width": 16, "height": 16, "style": f"file://{style_path}"},
+            {"cmd": "quit"},
+        ]
+        started = time.monotonic()
+        result = run_daemon(commands)
+        assert time.monotonic() - started < 20
+
+        remote, local, render, online = read_messages(result.stdout)
+        assert remote["status"] == "error"
+        assert remote["code"] == "init_failed"
+        assert remote["error_code"] == "network_disabled"
+        assert "10.255.255.1" in remote["error"]
+
+        assert local["status"] == "ok"
+        assert render["status"] == "ok"
+        pixel = Image.open(io.BytesIO(render["payloads"][0])).convert("RGB").getpixel((8, 8))
+        assert pixel == (0, 0, 255)
+
+        # The network loader is gone for good once offline replaced it.
+        assert online["status"] == "error"
+        assert "offline" in online["error"]
+
+    def test_init_render_mode(self, run_daemon, read_messages):
+        """Test draft and final render modes produce the requested size."""
+        coordinates = [[-60, -30], [60, 30]]
+        style = {
+            "version": 8,
+            "sources": {
+                "line": {
+                    "type": "geojson",
+                    "data": {"type": "LineString", "coordinates": coordinates},
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "line",
+                    "type": "line",
+                    "source": "line",
+                    "paint": {"line-color": "#000000", "line-width": 1.5},
+                },
+            ],
+        }
+        init = {
+            "cmd": "init",
+            "width": 128,
+            "height": 128,
+            "style": json.dumps(style),
+            "pixel_ratio": 2,
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = []
+        for mode in ["draft", "standard", "final"]:
+            commands += [{**init, "render_mode": mode}, render]
+        commands += [{**init, "render_mode": "fastest"}, {"cmd": "quit"}]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+        images = [Image.open(io.BytesIO(payload)).convert("RGB") for payload in payloads]
+
+        assert [r["status"] for r in responses[:6]] == ["ok"] * 6
+        draft, standard, final = images
+        assert draft.size == standard.size == final.size == (256, 256)
+        # Each mode draws the line, but with differently resampled edges.
+        for image in images:
+            assert image.getextrema()[0][0] < 128
+        assert draft.tobytes() != standard.tobytes()
+        assert final.tobytes() != standard.tobytes()
+
+        assert responses[6]["status"] == "error"
+        assert responses[6]["code"] == "invalid_command"
+
+    def test_init_pixel_ratio_sets_output_dimensions(self, run_daemon, read_messages):
+        """Test image sizes are the init size times pixel_ratio, which must be positive."""
+        init = {"cmd": "init", "width": 64, "height": 32, "style": "{}"}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {**init, "pixel_ratio": 1.0},
+            render,
+            {**init, "pixel_ratio": 2.0},
+            render,
+            {**init, "pixel_ratio": 0.0},
+            {**init, "pixel_ratio": -1.5},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+        sizes = [Image.open(io.BytesIO(payload)).size for payload in payloads]
+
+        init_1x, render_1x, init_2x, render_2x, zero, negative = responses[:6]
+        assert init_1x["status"] == "ok" and init_1x["pixel_ratio"] == 1.0
+        assert (render_1x["width"], render_1x["height"]) == (64, 32)
+        assert init_2x["status"] == "ok" and init_2x["pixel_ratio"] == 2.0
+        assert (render_2x["width"], render_2x["height"]) == (128, 64)
+        assert sizes == [(64, 32), (128, 64)]
+
+        for response in (zero, negative):
+            assert response["status"] == "error"
+            assert response["code"] == "invalid_parameter"
+            assert "pixel_ratio must be a positive number" in response["error"]
+
+    def test_init_size_too_large(self, run_daemon):
+        """Test init, resize and renders at their own size reject a size past the texture
+        limit before rendering."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        init = {"cmd": "init", "style": json.dumps(style), "pixel_ratio": 2}
+        commands = [
+            {**init, "width": 100000, "height": 64},
+            {**init, "width": 64, "height": 64},
+            {"cmd": "resize", "width": 64, "height": 100000},
+            {
+                "cmd": "render_batch",
+                "views": [
+                    {"center": [0, 0], "zoom": 1},
+                    {"center": [0, 0], "zoom": 1, "width": 100000, "height": 64},
+                ],
+            },
+            {
+                "cmd": "render_window",
+                "zoom": 10,
+                "pixel_origin": [0, 0],
+                "width": 100000,
+                "height": 64,
+            },
+            # Square in Web Mercator, and 65536 logical pixels across at pixel_ratio 2.
+            {
+                "cmd": "render_match_raster",
+                "reference_bounds": [-45, -40.97989807, 45, 40.97989807],
+                "reference_width": 131072,
+                "reference_height": 131072,
+            },
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = [json.loads(line) for line in result.stdout.split(b"\n")[:7]]
+
+        too_large, init_ok, resize, batch, window, match, render = responses
+        assert too_large["status"] == "error"
+        assert too_large["error_code"] == "size_too_large"
+        assert "200000x128" in too_large["error"]
+        assert "texture limit" in too_large["error"]
+        assert init_ok["status"] == "ok"
+        assert resize["status"] == "error"
+        assert resize["error_code"] == "size_too_large"
+        assert batch["error_code"] == "size_too_large"
+        assert "view 1" in batch["error"]
+        assert window["error_code"] == "size_too_large"
+        assert match["error_code"] == "size_too_large"
+        # The session keeps its size and still renders.
+        assert render["status"] == "ok"
+        assert (render["width"], render["height"]) == (128, 128)
+
+    @pytest.mark.usefixtures("binary_path")
+    def test_resize_keeps_loaded_style(self, tmp_path):
+        """Test a resized daemon renders at the new size without loading the style again."""
+        from mlnative._bridge import RenderDaemon
+
+        style_path = tmp_path / "style.json"
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        style_path.write_text(json.dumps(style))
+
+        with RenderDaemon() as daemon:
+            daemon.start(32, 32, str(style_path))
+            assert Image.open(io.BytesIO(daemon.render([0, 0], 1))).size == (32, 32)
+
+            # Loading the style again would now fail.
+            style_path.unlink()
+            daemon.resize(96, 48)
+            image = Image.open(io.BytesIO(daemon.render([0, 0], 1)))
+
+            assert image.size == (96, 48)
+            assert image.convert("RGB").getpixel((0, 0)) == (0x12, 0x34, 0x56)
+
+            with pytest.raises(MlnativeError, match="non-zero"):
+                daemon.resize(0, 48)
+
+    def test_render_uses_init_camera_defaults(self, run_daemon, read_messages):
+        """Test render falls back to the init default camera for fields it leaves out."""
+        init = {"cmd": "init", "width": 32, "height": 32, "style": "{}"}
+        camera = {"center": [115.86, -31.95], "zoom": 5, "bearing": 30, "pitch": 20}
+        commands = [
+            {**init, **{f"default_{key}": value for key, value in camera.items()}},
+            {"cmd": "render", "report_matrix": True},
+            {"cmd": "render", **camera, "report_matrix": True},
+            {"cmd": "render", "zoom": 6, "report_matrix": True},
+            {"cmd": "render", **camera, "zoom": 6, "report_matrix": True},
+            init,
+            {"cmd": "render", "center": [0, 0]},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+
+        assert [r["status"] for r in responses[:6]] == ["ok"] * 6
+        defaulted, explicit, zoomed, explicit_zoomed = responses[1:5]
+        assert defaulted["matrix"] == explicit["matrix"]
+        assert zoomed["matrix"] == explicit_zoomed["matrix"]
+        assert zoomed["matrix"] != defaulted["matrix"]
+
+        missing = responses[6]
+        assert missing["status"] == "error"
+        assert missing["code"] == "invalid_command"
+        assert "zoom is required" in missing["error"]
+
+    def test_sessions_render_independently(self, run_daemon, read_messages):
+        """Test named sessions keep their own renderer and style until closed."""
+        def style(color):
+            layer = {"id": "bg", "type": "background", "paint": {"background-color": color}}
+            return json.dumps({"version": 8, "sources": {}, "layers": [layer]})
+
+        init = {"cmd": "init", "width": 16, "height": 16}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {**init, "session_id": "red", "style": style("#ff0000")},
+            {**init, "session_id": "blue", "style": style("#0000ff")},
+            {**render, "session_id": "red"},
+            {**render, "session_id": "blue"},
+            render,
+            {"cmd": "close_session", "session_id": "red"},
+            {**render, "session_id": "red"},
+            {**render, "session_id": "blue"},
+            {"cmd": "close_session", "session_id": "red"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+        colors = [
+            Image.open(io.BytesIO(payload)).convert("RGB").getpixel((8, 8))
+            for payload in payloads
+        ]
+
+        statuses = [r["status"] for r in responses]
+        assert statuses == ["ok"] * 4 + ["error", "ok", "error", "ok", "error"]
+        assert colors == [(255, 0, 0), (0, 0, 255), (0, 0, 255)]
+        # The unnamed session was never initialized, and the red one is gone once closed.
+        assert responses[4]["code"] == "not_initialized"
+        assert responses[6]["code"] == "not_initialized"
+        assert responses[8]["code"] == "invalid_parameter"
+        assert "no session named 'red'" in responses[8]["error"]
+
+    def test_render_cache_returns_identical_bytes(self, run_daemon, read_messages):
+        """Test render_cache_size answers a repeated camera from the cache until an edit."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        render = {"cmd": "render", "center": [115.86, -31.95], "zoom": 10}
+        commands = [
+            {
+                "cmd": "init",
+                "width": 64,
+                "height": 64,
+                "style": json.dumps(style),
+                "render_cache_size": 1,
+            },
+            render,
+            render,
+            {**render, "zoom": 11},
+            render,
+            {**render, "format": "jpeg"},
+            {"cmd": "set_layer_opacity", "layer": "bg", "opacity": 0.5},
+            {**render, "format": "jpeg"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+
+        assert [r["status"] for r in responses[:8]] == ["ok"] * 8
+        renders = [r for r in responses[1:8] if "png_len" in r]
+        # With room for one entry, the zoom 11 render evicts the first view.
+        assert [r["cached"] for r in renders] == [False, True, False, False, False, False]
+        assert payloads[1] == payloads[0]
+        assert payloads[3] == payloads[0]
+        assert payloads[5] != payloads[4]
+
+    def test_idle_timeout_releases_and_restores_renderer(self, binary_path):
+        """Test an idle session drops its renderer and rebuilds it for the next render."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        init = {
+            "cmd": "init",
+            "width": 32,
+            "height": 32,
+            "style": json.dumps(style),
+            "idle_timeout_ms": 500,
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+
+        def send(cmd):
+            proc.stdin.write(json.dumps(cmd).encode() + b"\n")
+            proc.stdin.flush()
+            response = json.loads(proc.stdout.readline())
+            if "png_len" in response:
+                assert len(proc.stdout.read(response["png_len"])) == response["png_len"]
+            return response
+
+        try:
+            assert send(init)["status"] == "ok"
+            assert send(render)["status"] == "ok"
+            assert send({"cmd": "memory_stats"})["memory"]["renderer_loaded"] is True
+
+            time.sleep(1.5)
+            idle = send({"cmd": "memory_stats"})["memory"]
+            assert idle["renderer_loaded"] is False
+            assert idle["surfaces_created"] == 1
+
+            assert send(render)["status"] == "ok"
+            restored = send({"cmd": "memory_stats"})["memory"]
+            assert restored["renderer_loaded"] is True
+            assert restored["surfaces_created"] == 2
+        finally:
+            proc.stdin.close()
+            proc.wait(timeout=10)
+
+    def test_idle_timeout_releases_while_other_sessions_are_busy(self, binary_path):
+        """Test an idle session is released even while another keeps sending commands."""
+        style = {"version": 8, "sources": {}, "layers": []}
+        init = {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+
+        def send(cmd):
+            proc.stdin.write(json.dumps(cmd).encode() + b"\n")
+            proc.stdin.flush()
+            return json.loads(proc.stdout.readline())
+
+        try:
+            assert send({**init, "session_id": "idle", "idle_timeout_ms": 300})["status"] == "ok"
+            assert send(init)["status"] == "ok"
+            # Keep stdin busy with the unnamed session for well past the timeout.
+            deadline = time.monotonic() + 1.5
+            while time.monotonic() < deadline:
+                assert send({"cmd": "memory_stats"})["memory"]["renderer_loaded"] is True
+                time.sleep(0.02)
+            idle = send({"cmd": "memory_stats", "session_id": "idle"})["memory"]
+            assert idle["renderer_loaded"] is False
+        finally:
+            proc.stdin.close()
+            proc.wait(timeout=10)
+
+    def test_set_bookmark_renders_and_caches_named_camera(self, run_daemon, read_messages):
+        """Test bookmark renders use the stored camera and reuse the frame until it changes."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        render = {"cmd": "render", "bookmark": "home"}
+        commands = [
+            {"cmd": "set_bookmark", "name": "home", "camera": {"center": [-90, 0], "zoom": 2}},
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            render,
+            render,
+            {"cmd": "set_bookmark", "name": "home", "camera": {"center": [90, 0], "zoom": 2}},
+            render,
+            {**render, "bookmark": "away"},
+            {**render, "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        (
+            stored,
+            init,
+            first,
+            again,
+            moved,
+            after_move,
+            unknown,
+            ambiguous,
+        ) = read_messages(run_daemon(commands).stdout)
+
+        assert stored["status"] == "ok"
+        assert init["status"] == "ok"
+        assert first["cached"] is False
+        assert again["cached"] is True
+        assert again["payloads"] == first["payloads"]
+        image = Image.open(io.BytesIO(first["payloads"][0])).convert("L")
+        assert image.getpixel((16, 16)) < 64
+
+        assert moved["status"] == "ok"
+        assert after_move["cached"] is False
+        image = Image.open(io.BytesIO(after_move["payloads"][0])).convert("L")
+        assert image.getpixel((16, 16)) > 192
+
+        assert unknown["code"] == "invalid_parameter"
+        assert "no bookmark named 'away'" in unknown["error"]
+        assert ambiguous["code"] == "invalid_command"
+
+    def test_set_preset_merges_stored_render_options(self, run_daemon, read_messages):
+        """Test preset renders layer the preset, the command's own options and overrides."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        thumb = {"center": [-90, 0], "zoom": 2, "format": "png8"}
+        render = {"cmd": "render", "preset": "thumb"}
+        commands = [
+            {"cmd": "set_preset", "name": "thumb", "config": thumb},
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            render,
+            {**render, "center": [90, 0], "overrides": {"format": None}},
+            {"cmd": "set_preset", "name": "bad", "config": {"cmd": "render"}},
+            {"cmd": "set_preset", "name": "bad", "config": {"format": "gif"}},
+            {**render, "preset": "missing"},
+            {"cmd": "quit"},
+        ]
+        (
+            stored,
+            init,
+            preset,
+            overridden,
+            control_key,
+            bad_option,
+            missing,
+        ) = read_messages(run_daemon(commands).stdout)
+
+        assert stored["status"] == "ok"
+        assert init["status"] == "ok"
+        assert preset["format"] == "png8"
+        image = Image.open(io.BytesIO(preset["payloads"][0]))
+        assert image.mode == "P"
+        assert image.convert("L").getpixel((16, 16)) < 64
+
+        # null drops the preset's format back to the default.
+        assert overridden["format"] == "png"
+        image = Image.open(io.BytesIO(overridden["payloads"][0])).convert("L")
+        assert image.getpixel((16, 16)) > 192
+
+        assert control_key["code"] == "command_failed"
+        assert "preset config cannot contain 'cmd'" in control_key["error"]
+        assert "invalid render options" in bad_option["error"]
+        assert missing["code"] == "invalid_command"
+        assert "no preset named 'missing'" in missing["error"]
+
+
+class TestRendering:
+    """Tests for render commands and their options."""
+
+    def test_render_reports_image_size(self, run_daemon, read_messages):
+        """Test render and render_batch headers give the image size at pixel_ratio 1."""
+        style = {"version": 8, "sources": {}, "layers": []}
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 48, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render", **view},
+            {"cmd": "render_batch", "views": [view, view]},
+            {"cmd": "quit"},
+        ]
+        init, header, batch = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert (header["width"], header["height"]) == (48, 32)
+        assert [(v["width"], v["height"]) for v in batch["views"]] == [(48, 32), (48, 32)]
+        assert [v["png_len"] for v in batch["views"]] == batch["png_lengths"]
+
+    def test_render_reports_timings(self, run_daemon, read_messages):
+        """Test render and each render_batch view report render and encode times."""
+        style = {"version": 8, "sources": {}, "layers": []}
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render", **view},
+            {"cmd": "render_batch", "views": [view]},
+            {"cmd": "quit"},
+        ]
+        _, header, batch = read_messages(run_daemon(commands).stdout)
+
+        for timed in (header, batch["views"][0]):
+            assert timed["render_ms"] >= 0
+            assert timed["encode_ms"] >= 0
+
+    def test_render_tile_uses_init_size(self, run_daemon, read_messages):
+        """Test render_tile renders XYZ tiles at the init size and rejects tiles off the grid."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        commands = [
+            {"cmd": "init", "width": 256, "height": 256, "style": json.dumps(style)},
+            {"cmd": "render_tile", "z": 0, "x": 0, "y": 0},
+            {"cmd": "render_tile", "z": 10, "x": 823, "y": 596},
+            {"cmd": "render_tile", "z": 2, "x": 4, "y": 0},
+            {"cmd": "quit"},
+        ]
+        init, *tiles, error = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        for zoom, header in zip((-1, 9), tiles, strict=True):
+            assert header["status"] == "ok"
+            assert header["zoom"] == pytest.approx(zoom)
+            payload = header["payloads"][0]
+            assert payload.startswith(b"\x89PNG\r\n\x1a\n")
+            assert Image.open(io.BytesIO(payload)).size == (256, 256)
+
+        assert error["status"] == "error"
+        assert "Tile render failed" in error["error"]
+
+    def test_parallel_batch_preserves_view_order(self, run_daemon, read_messages):
+        """Test a batch spread over worker renderers matches the sequential batch."""
+        # A square near the origin, so each view's framing of it is distinct.
+        square = {
+            "type": "Polygon",
+            "coordinates": [[[-10, -10], [10, -10], [10, 10], [-10, 10], [-10, -10]]],
+        }
+        style = {
+            "version": 8,
+            "sources": {"square": {"type": "geojson", "data": square}},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "square",
+                    "type": "fill",
+                    "source": "square",
+                    "paint": {"fill-color": "#ff0000"},
+                },
+            ],
+        }
+        views = [{"center": [i * 3 - 20, 0], "zoom": 2} for i in range(12)]
+
+        def run_batch(workers):
+            commands = [
+                {
+                    "cmd": "init",
+                    "width": 64,
+                    "height": 64,
+                    "style": json.dumps(style),
+                    "batch_workers": workers,
+                },
+                {"cmd": "render_batch", "views": views},
+                {"cmd": "quit"},
+            ]
+            started = time.perf_counter()
+            result = run_daemon(commands, timeout=120)
+            elapsed = time.perf_counter() - started
+            init, header = read_messages(result.stdout)
+            assert init["status"] == "ok"
+            assert header["status"] == "ok", header
+            return header["payloads"], elapsed
+
+        sequential, sequential_time = run_batch(1)
+        parallel, parallel_time = run_batch(4)
+        print(f"12 views: sequential {sequential_time:.2f}s, 4 workers {parallel_time:.2f}s")
+
+        assert len(parallel) == len(views)
+        assert all(png.startswith(b"\x89PNG") for png in parallel)
+        assert len(set(sequential)) > 1
+        assert parallel == sequential
+
+    def test_render_batch_returns_one_payload_per_view(self, run_daemon):
+        """Test a batch of N views comes back as N separate PNG payloads."""
+        from mlnative._bridge import RenderDaemon
+
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        views = [{"center": [0, 0], "zoom": zoom} for zoom in (0, 1, 2)]
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render_batch", "views": views},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        _, rest = result.stdout.split(b"\n", 1)
+        header_line, rest = rest.split(b"\n", 1)
+        header = json.loads(header_line)
+        assert header["status"] == "ok"
+        assert "png" not in header
+        assert len(header["png_lengths"]) == len(views)
+        assert sum(header["png_lengths"]) == len(rest)
+        for length in header["png_lengths"]:
+            payload, rest = rest[:length], rest[length:]
+            assert payload.startswith(b"\x89PNG\r\n\x1a\n")
+
+        with RenderDaemon() as daemon:
+            daemon.start(32, 32, json.dumps(style))
+            pngs = daemon.render_batch(views)
+        assert len(pngs) == len(views)
+        assert all(png.startswith(b"\x89PNG\r\n\x1a\n") for png in pngs)
+
+    def test_render_batch_per_view_format(self, run_daemon, read_messages):
+        """Test batch views override the session format, failing alone on conflicts."""
+        style = json.dumps({"version": 8, "sources": {}, "layers": []})
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": style},
+            {
+                "cmd": "render_batch",
+                "views": [view, {**view, "format": "jpeg", "quality": 50}],
+            },
+            {"cmd": "init", "width": 32, "height": 32, "style": style, "background": "transparent"},
+            {"cmd": "render_batch", "views": [view, {**view, "format": "jpeg"}]},
+            {"cmd": "quit"},
+        ]
+        messages = iter(read_messages(run_daemon(commands).stdout))
+
+        def read_batch():
+            assert next(messages)["status"] == "ok"
+            header = next(messages)
+            assert header["status"] == "ok"
+            return header, header["payloads"]
+
+        header, (png, jpeg) = read_batch()
+        assert [v["format"] for v in header["views"]] == ["png", "jpeg"]
+        assert Image.open(io.BytesIO(png)).format == "PNG"
+        assert Image.open(io.BytesIO(jpeg)).format == "JPEG"
+        assert Image.open(io.BytesIO(jpeg)).size == (32, 32)
+
+        header, payloads = read_batch()
+        assert len(payloads) == 1
+        assert "transparent" in header["views"][1]["error"]
+
+    def test_render_batch_geojson_from_file(self, run_daemon, read_messages, tmp_path):
+        """Test a batch view can load a GeoJSON source from a file path, within the limit."""
+        point = {"type": "Point", "coordinates": [0, 0]}
+        path = tmp_path / "point.geojson"
+        path.write_text(json.dumps(point))
+        broken = tmp_path / "broken.geojson"
+        broken.write_text("{not json")
+        style = {
+            "version": 8,
+            "sources": {
+                "markers": {"type": "geojson", "data": {"type": "Point", "coordinates": [90, 0]}}
+            },
+            "layers": [
+                {
+                    "id": "markers",
+                    "type": "circle",
+                    "source": "markers",
+                    "paint": {"circle-color": "#ff0000", "circle-radius": 6},
+                },
+            ],
+        }
+        view = {"center": [0, 0], "zoom": 1}
+        init = {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+        commands = [
+            init,
+            {
+                "cmd": "render_batch",
+                "views": [
+                    {**view, "geojson": {"markers": str(path)}},
+                    {**view, "geojson": {"markers": point}},
+                    {**view, "geojson": {"markers": str(tmp_path / "missing.geojson")}},
+                    {**view, "geojson": {"markers": str(broken)}},
+                ],
+            },
+            {**init, "max_geojson_bytes": 4},
+            {"cmd": "render_batch", "views": [{**view, "geojson": {"markers": str(path)}}]},
+            {"cmd": "quit"},
+        ]
+        loaded, header, limited, too_large = read_messages(run_daemon(commands).stdout)
+        assert loaded["status"] == "ok"
+        from_file, inline = header["payloads"]
+
+        assert from_file == inline
+        centre = Image.open(io.BytesIO(from_file)).convert("RGBA").getpixel((16, 16))
+        assert centre[:3] == (255, 0, 0)
+        assert "Cannot read" in header["views"][2]["error"]
+        assert "Invalid GeoJSON" in header["views"][3]["error"]
+
+        assert limited["status"] == "ok"
+        assert "max_geojson_bytes" in too_large["error"]
+
+    def test_url_style_edits_are_kept(self, run_daemon, read_messages, tmp_path):
+        """Test a URL style is fetched for its first edit and the edited JSON is kept."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ff0000"}}
+            ],
+        }
+        style_path = tmp_path / "style.json"
+        style_path.write_text(json.dumps(style))
+        commands = [
+            {
+                "cmd": "init",
+                "width": 16,
+                "height": 16,
+                "style": f"file://{style_path}",
+                "surface_reuse": False,
+            },
+            {"cmd": "set_layer_opacity", "layer": "bg", "opacity": 0},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "get_style"},
+            {"cmd": "quit"},
+        ]
+        init, edit, render, get_style = read_messages(run_daemon(commands).stdout)
+        assert init["status"] == "ok"
+        assert edit["status"] == "ok"
+
+        # The rebuilt surface loads the edited style, not the URL again.
+        image = Image.open(io.BytesIO(render["payloads"][0])).convert("RGBA")
+        assert image.getpixel((8, 8))[3] == 0
+        layer = get_style["style_json"]["layers"][0]
+        assert layer["paint"]["background-opacity"] == 0
+
+    def test_render_batch_geojson_outlives_surface(self, run_daemon, read_messages):
+        """Test batch view GeoJSON stays on rebuilt surfaces and in pool workers."""
+        style = {
+            "version": 8,
+            "sources": {
+                "markers": {"type": "geojson", "data": {"type": "Point", "coordinates": [90, 0]}}
+            },
+            "layers": [
+                {
+                    "id": "markers",
+                    "type": "circle",
+                    "source": "markers",
+                    "paint": {"circle-color": "#ff0000", "circle-radius": 6},
+                },
+            ],
+        }
+        view = {"center": [0, 0], "zoom": 1}
+        point = {"type": "Point", "coordinates": [0, 0]}
+
+        def centres(init_options):
+            commands = [
+                {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+                | init_options,
+                {"cmd": "render_batch", "views": [{**view, "geojson": {"markers": point}}]},
+                {"cmd": "render", **view},
+                {"cmd": "render_batch", "views": [view, view]},
+                {"cmd": "quit"},
+            ]
+            init, updated, rendered, batch = read_messages(run_daemon(commands).stdout)
+            assert init["status"] == "ok"
+            payloads = updated["payloads"] + rendered["payloads"] + batch["payloads"]
+            return [
+                Image.open(io.BytesIO(png)).convert("RGBA").getpixel((16, 16))[:3]
+                for png in payloads
+            ]
+
+        assert centres({"surface_reuse": False}) == [(255, 0, 0)] * 4
+        assert centres({"batch_workers": 2}) == [(255, 0, 0)] * 4
+
+    def test_render_output_path(self, run_daemon, tmp_path):
+        """Test render and render_batch can write images to files instead of payloads."""
+        single = tmp_path / "nested" / "single.png"
+        frames = tmp_path / "frames"
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": "{}"},
+            {"cmd": "render", **view, "output_path": str(single)},
+            {
+                "cmd": "render_batch",
+                "views": [view, {**view, "format": "jpeg"}],
+                "output_dir": str(frames),
+                "output_name": "frame-{index}.{ext}",
+            },
+            {"cmd": "render_batch", "views": [view], "output_dir": str(frames), "output_name": "x"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        lines = result.stdout.splitlines()
+        assert json.loads(lines[0])["status"] == "ok"
+
+        rendered = json.loads(lines[1])
+        assert rendered["status"] == "ok"
+        assert "png_len" not in rendered
+        assert rendered["path"] == str(single)
+        assert Image.open(single).size == (32, 32)
+
+        batch = json.loads(lines[2])
+        assert batch["status"] == "ok"
+        assert batch["png_lengths"] == []
+        paths = [outcome["path"] for outcome in batch["views"]]
+        assert paths == [str(frames / "frame-0.png"), str(frames / "frame-1.jpg")]
+        assert Image.open(paths[0]).format == "PNG"
+        assert Image.open(paths[1]).format == "JPEG"
+
+        # No payloads were sent, so the next header follows directly.
+        bad_name = json.loads(lines[3])
+        assert bad_name["code"] == "invalid_parameter"
+        assert "{index}" in bad_name["error"]
+
+    def test_render_path(self, run_daemon, read_messages):
+        """Test render_path returns one distinct frame per step and validates frames."""
+        style = {
+            "version": 8,
+            "sources": {
+                "point": {
+                    "type": "geojson",
+                    "data": {"type": "Point", "coordinates": [0, 0]},
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "point",
+                    "type": "circle",
+                    "source": "point",
+                    "paint": {"circle-color": "#ff0000", "circle-radius": 4},
+                },
+            ],
+        }
+        path = {
+            "cmd": "render_path",
+            "from": {"center": [-10, 0], "zoom": 2},
+            "to": {"center": [10, 0], "zoom": 3},
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            {**path, "frames": 3},
+            {**path, "frames": 1},
+            {"cmd": "quit"},
+        ]
+        init, header, too_few = read_messages(run_daemon(commands).stdout)
+        assert init["status"] == "ok"
+
+        assert header["status"] == "ok"
+        frames = header["payloads"]
+        assert len(frames) == 3
+        assert all(frame[:4] == b"\x89PNG" for frame in frames)
+        assert len(set(frames)) == 3
+
+        assert too_few["code"] == "invalid_parameter"
+        assert "frames" in too_few["error"]
+
+    def test_render_timeout(self, run_daemon):
+        """Test a render waiting on an unreachable tile server fails with render_timeout."""
+        # The client loader forwards tile requests to us; leaving them unanswered keeps the
+        # tiles loading, like a server that never replies, on any network.
+        style = {
+            "version": 8,
+            "sources": {
+                "dead": {
+                    "type": "raster",
+                    "tiles": ["http://10.255.255.1/{z}/{x}/{y}.png"],
+                    "tileSize": 256,
+                }
+            },
+            "layers": [{"id": "dead", "type": "raster", "source": "dead"}],
+        }
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render", **view, "timeout_ms": 0},
+            {"cmd": "render", **view, "timeout_ms": 300},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, "--resource-loader", "client")
+        responses = [
+            response
+            for response in map(json.loads, result.stdout.splitlines())
+            if response["status"] != "resource_request"
+        ]
+        assert responses[0]["status"] == "ok"
+        assert responses[1]["code"] == "invalid_parameter"
+
+        timed_out = responses[2]
+        assert timed_out["code"] == "render_failed"
+        assert timed_out["error_code"] == "render_timeout"
+        assert "300 ms" in timed_out["error"]
+
+    def test_render_progress_counts_pending_tiles(self, run_daemon, read_messages):
+        """Test loading events and the deadline render report the tiles the client has not
+        answered yet."""
+        style = {
+            "version": 8,
+            "sources": {
+                "dead": {
+                    "type": "raster",
+                    "tiles": ["http://10.255.255.1/{z}/{x}/{y}.png"],
+                    "tileSize": 256,
+                }
+            },
+            "layers": [{"id": "dead", "type": "raster", "source": "dead"}],
+        }
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {
+                "cmd": "render",
+                "center": [0, 0],
+                "zoom": 1,
+                "deadline_ms": 500,
+                "progress": True,
+                "progress_interval_ms": 50,
+            },
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands, "--resource-loader", "client")
+        messages = read_messages(result.stdout)
+        loading = [message for message in messages if message["status"] == "loading"]
+        assert loading
+        assert all(event["tiles_pending"] >= 1 for event in loading)
+        # The deadline render's header counts the tiles still outstanding too.
+        (header,) = [message for message in messages if "complete" in message]
+        assert header["complete"] is False
+        assert header["tiles_pending"] >= 1
+
+    def test_render_camera_range(self, run_daemon, read_messages):
+        """Test out-of-range cameras are rejected or clamped before rendering."""
+        square = [[[-20, -10], [20, -10], [20, 10], [-20, 10], [-20, -10]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "square": {
+                    "type": "geojson",
+                    "data": {"type": "Polygon", "coordinates": square},
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "square",
+                    "type": "fill",
+                    "source": "square",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        view = {"cmd": "render", "center": [0, 0], "zoom": 2}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            {**view, "center": [float("nan"), 0]},
+            {**view, "center": [0, 95.0]},
+            {
+                "cmd": "render_batch",
+                "views": [{"center": [0, 0], "zoom": 2}, {"center": [0, -91], "zoom": 2}],
+            },
+            {**view, "pitch": 60},
+            {**view, "pitch": 85},
+            {**view, "center": [360, 0]},
+            {**view},
+            {"cmd": "quit"},
+        ]
+        headers = read_messages(run_daemon(commands).stdout)
+        images = [payload for header in headers for payload in header.get("payloads", [])]
+
+        # JSON has no NaN, so the daemon rejects the line before looking at the camera.
+        assert headers[1]["code"] == "invalid_command"
+        assert headers[2]["error_code"] == "invalid_camera"
+        assert "latitude 95" in headers[2]["error"]
+        assert headers[3]["error_code"] == "invalid_camera"
+        assert "view 1: latitude -91" in headers[3]["error"]
+
+        assert all(header["status"] == "ok" for header in headers[4:])
+        pitched_60, pitched_85, wrapped, flat = images
+        assert pitched_85 == pitched_60
+        assert pitched_60 != flat
+        assert wrapped == flat
+
+    def test_render_padding_offsets_center(self, run_daemon, read_messages):
+        """Test padding moves the camera center into the middle of the padded area."""
+        style = {
+            "version": 8,
+            "sources": {
+                "dot": {
+                    "type": "geojson",
+                    "data": {"type": "Point", "coordinates": [0, 0]},
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "dot",
+                    "type": "circle",
+                    "source": "dot",
+                    "paint": {"circle-color": "#000000", "circle-radius": 6},
+                },
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 2}
+        commands = [
+            {"cmd": "init", "width": 256, "height": 128, "style": json.dumps(style)},
+            render,
+            {**render, "padding": {"left": 100}},
+            {**render, "padding": {"right": 60, "top": 40}},
+            {"cmd": "render_batch", "views": [{**render, "padding": {"bottom": 60}}]},
+            render,
+            {**render, "padding": {"left": 200, "right": 100}},
+            {**render, "padding": {"top": -1}},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+        images = [Image.open(io.BytesIO(payload)).convert("L") for payload in payloads]
+
+        def dot_center(image):
+            dark = [
+                (x, y)
+                for y in range(image.height)
+                for x in range(image.width)
+                if image.getpixel((x, y)) < 128
+            ]
+            return (
+                sum(x for x, _ in dark) / len(dark),
+                sum(y for _, y in dark) / len(dark),
+            )
+
+        assert [r["status"] for r in responses[:6]] == ["ok"] * 6
+        plain, left, right_top, batch_bottom, after = (dot_center(image) for image in images)
+        assert abs(plain[0] - 128) < 2 and abs(plain[1] - 64) < 2
+        # The dot sits in the middle of the padded area: half the inset off centre.
+        assert abs(left[0] - 178) < 2 and abs(left[1] - 64) < 2
+        assert abs(right_top[0] - 98) < 2 and abs(right_top[1] - 84) < 2
+        assert abs(batch_bottom[0] - 128) < 2 and abs(batch_bottom[1] - 34) < 2
+        # Padding does not carry over to later renders.
+        assert abs(after[0] - 128) < 2 and abs(after[1] - 64) < 2
+
+        for response in responses[6:8]:
+            assert response["status"] == "error"
+            assert response["code"] == "invalid_parameter"
+            assert "padding" in response["error"]
+
+    def test_render_crop(self, run_daemon, read_messages):
+        """Test crop returns only the requested rectangle of the render."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 128, "height": 64, "style": json.dumps(style)},
+            {**render, "crop": {"x": 32, "y": 16, "width": 64, "height": 32}},
+            {**render, "crop": {"x": 100, "y": 0, "w": 64, "h": 64}},
+            {
+                **render,
+                "crop": {"x": 0, "y": 0, "w": 8, "h": 8},
+                "crops": [{"id": "a", "x": 0, "y": 0, "w": 8, "h": 8}],
+            },
+            {"cmd": "quit"},
+        ]
+        init, header, outside, combined = read_messages(run_daemon(commands).stdout)
+        assert init["status"] == "ok"
+        assert header["status"] == "ok"
+        assert (header["width"], header["height"]) == (64, 32)
+        image = Image.open(io.BytesIO(header["payloads"][0])).convert("L")
+        assert image.size == (64, 32)
+        # The polygon's eastern edge runs down the middle of the render, and so of the crop.
+        assert image.getpixel((8, 16)) < 64
+        assert image.getpixel((56, 16)) > 192
+
+        assert outside["status"] == "error"
+        assert outside["code"] == "invalid_parameter"
+        assert "outside the 128x64 render" in outside["error"]
+        assert combined["status"] == "error"
+        assert combined["code"] == "invalid_parameter"
+        assert "crop cannot be combined with crops" in combined["error"]
+
+    def test_render_recovers_after_failed_render(self, binary_path):
+        """Test a render after a failed one succeeds on a rebuilt surface."""
+        tile = io.BytesIO()
+        Image.new("RGB", (256, 256), (255, 0, 0)).save(tile, format="PNG")
+        tile = tile.getvalue()
+        broken = threading.Event()
+        broken.set()
+
+        class TileHandler(BaseHTTPRequestHandler):
+            def do_GET(self):
+                if broken.is_set():
+                    self.send_error(500)
+                    return
+                self.send_response(200)
+                self.send_header("Content-Type", "image/png")
+                self.send_header("Cache-Control", "no-store")
+                self.end_headers()
+                self.wfile.write(tile)
+
+            def log_message(self, *args):
+                pass
+
+        server = ThreadingHTTPServer(("127.0.0.1", 0), TileHandler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        style = {
+            "version": 8,
+            "sources": {
+                "tiles": {
+                    "type": "raster",
+                    "tiles": [f"http://127.0.0.1:{server.server_port}/{{z}}/{{x}}/{{y}}.png"],
+                    "tileSize": 256,
+                }
+            },
+            "layers": [{"id": "tiles", "type": "raster", "source": "tiles"}],
+        }
+        render = json.dumps({"cmd": "render", "center": [0, 0], "zoom": 1}) + "\n"
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+        )
+        try:
+            init = {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)}
+            proc.stdin.write((json.dumps(init) + "\n" + render).encode())
+            proc.stdin.flush()
+            assert json.loads(proc.stdout.readline())["status"] == "ok"
+            failed = json.loads(proc.stdout.readline())
+            assert failed["status"] == "error"
+            assert failed["code"] == "render_failed"
+
+            broken.clear()
+            proc.stdin.write((render + '{"cmd": "quit"}\n').encode())
+            proc.stdin.flush()
+            header = json.loads(proc.stdout.readline())
+            assert header["status"] == "ok"
+            image = Image.open(io.BytesIO(proc.stdout.read(header["png_len"])))
+            assert image.convert("RGB").getpixel((32, 32)) == (255, 0, 0)
+            assert proc.wait(timeout=60) == 0
+        finally:
+            server.shutdown()
+            if proc.poll() is None:
+                proc.kill()
+                proc.wait()
+
+    @pytest.mark.integration
+    def test_language_localizes_labels(self, run_daemon, read_messages):
+        """Test language switches labels to name:<code>, per init and per render."""
+        style = {
+            "version": 8,
+            "glyphs": "https://demotiles.maplibre.org/font/{fontstack}/{range}.pbf",
+            "sources": {
+                "places": {
+                    "type": "geojson",
+                    "data": {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [0, 0]},
+                        "properties": {"name": "I", "name:de": "WWWWWWWW"},
+                    },
+                }
+            },
+            "layers": [
+                {
+                    "id": "labels",
+                    "type": "symbol",
+                    "source": "places",
+                    "layout": {"text-field": "{name}", "text-font": ["Open Sans Regular"]},
+                }
+            ],
+        }
+        init = {"cmd": "init", "width": 256, "height": 64, "style": json.dumps(style)}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 2}
+        commands = [
+            init,
+            render,
+            {**render, "language": "de"},
+            render,
+            {**render, "language": "fr"},
+            {**init, "language": "de"},
+            render,
+            {**init, "language": "not a language"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+        images = [Image.open(io.BytesIO(payload)).convert("RGBA") for payload in payloads]
+
+        assert [r["status"] for r in responses[:7]] == ["ok"] * 7
+        plain, german, restored, french, german_init = images
+        plain_box = plain.getchannel("A").getbbox()
+        german_box = german.getchannel("A").getbbox()
+        assert german_box[2] - german_box[0] > plain_box[2] - plain_box[0]
+        assert restored.tobytes() == plain.tobytes()
+        # No name:fr, so the default name is the fallback.
+        assert french.tobytes() == plain.tobytes()
+        assert german_init.tobytes() == german.tobytes()
+
+        assert responses[7]["status"] == "error"
+        assert responses[7]["code"] == "invalid_command"
+        assert "language must be a language code" in responses[7]["error"]
+
+    def test_rgba_output_is_raw_pixels_with_layout(self, run_daemon, read_messages):
+        """Test output_format rgba sends width * height * 4 raw bytes described by the header."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        commands = [
+            {
+                "cmd": "init",
+                "width": 24,
+                "height": 10,
+                "pixel_ratio": 2,
+                "style": json.dumps(style),
+                "output_format": "rgba",
+            },
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "render", "center": [0, 0], "zoom": 1, "format": "png"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+
+        raw_header, png_header = responses[1:]
+        raw, png = payloads
+        assert raw_header["format"] == "rgba"
+        assert raw_header["channel_order"] == "rgba"
+        assert raw_header["premultiplied_alpha"] is False
+        width, height = raw_header["width"], raw_header["height"]
+        assert (width, height) == (48, 20)
+        assert raw_header["stride"] == width * 4
+        assert len(raw) == width * height * 4 == raw_header["png_len"]
+
+        image = Image.frombuffer("RGBA", (width, height), raw, "raw", "RGBA", raw_header["stride"])
+        assert image.getpixel((5, 5)) == (0x33, 0x66, 0x99, 255)
+        assert png_header["format"] == "png"
+        assert png.startswith(b"\x89PNG")
+
+    def test_attribution_marks_the_bottom_right_corner(self, run_daemon, read_messages):
+        """Test attribution draws over the bottom-right pixels, from the style when true."""
+        style = {
+            "version": 8,
+            "sources": {
+                "points": {
+                    "type": "geojson",
+                    "data": {"type": "FeatureCollection", "features": []},
+                    "attribution": "<a href='https://example.com'>&copy; Example</a>",
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        commands = [
+            {"cmd": "init", "width": 128, "height": 64, "style": json.dumps(style)},
+            {"cmd": "render", "center": [0, 0], "zoom": 1, "format": "rgba"},
+            {
+                "cmd": "render",
+                "center": [0, 0],
+                "zoom": 1,
+                "format": "rgba",
+                "attribution": "Example",
+            },
+            {"cmd": "render", "center": [0, 0], "zoom": 1, "format": "rgba", "attribution": True},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+
+        assert all(response["status"] == "ok" for response in responses[1:])
+        plain, given, from_style = (
+            Image.frombuffer("RGBA", (128, 64), raw, "raw", "RGBA", 128 * 4) for raw in payloads
+        )
+        corner = (120, 60, 128, 64)
+        assert plain.crop(corner).tobytes() != given.crop(corner).tobytes()
+        assert plain.crop(corner).tobytes() != from_style.crop(corner).tobytes()
+        # The rest of the map is untouched.
+        assert plain.getpixel((5, 5)) == given.getpixel((5, 5)) == (0x33, 0x66, 0x99, 255)
+
+    def test_watermark_blends_logo_into_corner(self, run_daemon, read_messages):
+        """Test watermark alpha-blends a decoded logo at its position and scales big ones."""
+        def encoded_logo(size):
+            buffer = io.BytesIO()
+            Image.new("RGBA", (size, size), (255, 0, 0, 255)).save(buffer, format="PNG")
+            return base64.b64encode(buffer.getvalue()).decode()
+
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        view = {"cmd": "render", "center": [0, 0], "zoom": 1, "format": "rgba"}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            {**view, "watermark": {"image": encoded_logo(4), "opacity": 0.5}},
+            {**view, "watermark": {"image": encoded_logo(4), "position": "top-left", "margin": 2}},
+            {**view, "watermark": {"image": encoded_logo(128)}},
+            {**view, "watermark": {"image": "not an image"}},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+
+        corner, top_left, scaled = (
+            Image.frombuffer("RGBA", (64, 64), raw, "raw", "RGBA", 64 * 4) for raw in payloads
+        )
+        # Half-opacity red over #336699.
+        blended = (153, 51, 77, 255)
+        for x, y in [(60, 60), (63, 63)]:
+            assert all(abs(a - b) <= 1 for a, b in zip(corner.getpixel((x, y)), blended))
+        assert corner.getpixel((59, 59)) == (0x33, 0x66, 0x99, 255)
+
+        assert top_left.getpixel((2, 2)) == (255, 0, 0, 255)
+        assert top_left.getpixel((1, 1)) == (0x33, 0x66, 0x99, 255)
+        assert top_left.getpixel((6, 6)) == (0x33, 0x66, 0x99, 255)
+
+        # A logo bigger than the canvas is shrunk to cover it.
+        assert scaled.getpixel((0, 0)) == scaled.getpixel((63, 63)) == (255, 0, 0, 255)
+
+        assert responses[4]["status"] == "error"
+        assert responses[4]["code"] == "invalid_parameter"
+
+    def test_snap_zoom_reports_whole_zoom(self, run_daemon, read_messages):
+        """Test snap_zoom renders at the nearest whole zoom and reports it."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}}
+            ],
+        }
+        view = {"cmd": "render", "center": [0, 0], "zoom": 5.4}
+        init = {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+        commands = [
+            init,
+            {**view, "snap_zoom": True},
+            view,
+            {**init, "snap_zoom": True},
+            view,
+            {**view, "snap_zoom": False},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+
+        snapped, plain, _, init_snapped, unsnapped = responses[1:]
+        assert snapped["status"] == "ok"
+        assert snapped["zoom"] == 5
+        assert "zoom" not in plain
+        assert init_snapped["zoom"] == 5
+        assert "zoom" not in unsnapped
+
+    def test_render_large_stitches_tiles_without_seams(self, run_daemon, read_messages):
+        """Test render_large stitches a 2x2 tiling that matches a single render of the view."""
+        triangle = {
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[-60, -50], [70, -40], [-20, 60], [-60, -50]]],
+            },
+        }
+        style = {
+            "version": 8,
+            "sources": {"shape": {"type": "geojson", "data": triangle}},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "fill",
+                    "type": "fill",
+                    "source": "shape",
+                    "paint": {"fill-color": "#2266aa"},
+                },
+                {
+                    "id": "edge",
+                    "type": "line",
+                    "source": "shape",
+                    "paint": {"line-color": "#aa2222", "line-width": 3},
+                },
+            ],
+        }
+        view = {"center": [0, 0], "zoom": 1.5, "bearing": 20, "format": "rgba"}
+        commands = [
+            {"cmd": "init", "width": 256, "height": 256, "style": json.dumps(style)},
+            {"cmd": "render", **view},
+            {"cmd": "render_large", **view, "width": 256, "height": 256, "tile_size": 128},
+            {"cmd": "render_large", **view, "width": 256, "height": 256, "pitch": 30},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = read_messages(result.stdout)
+        payloads = [payload for response in responses for payload in response.get("payloads", [])]
+
+        single_header, large_header, pitched = responses[1:]
+        assert large_header["status"] == "ok"
+        assert large_header["tile_grid"] == [2, 2]
+        assert (large_header["width"], large_header["height"]) == (256, 256)
+        single, large = (
+            Image.frombuffer("RGBA", (256, 256), raw, "raw", "RGBA", 256 * 4) for raw in payloads
+        )
+
+        def difference(a, b):
+            return max(abs(x - y) for x, y in zip(a, b))
+
+        # Across each seam, the stitched image changes no more than the single render does.
+        for i in range(256):
+            for left, right in [((127, i), (128, i)), ((i, 127), (i, 128))]:
+                stitched = difference(large.getpixel(left), large.getpixel(right))
+                expected = difference(single.getpixel(left), single.getpixel(right))
+                assert stitched <= expected + 16
+        mismatched = sum(
+            difference(a, b) > 16 for a, b in zip(single.getdata(), large.getdata())
+        )
+        assert mismatched < 256 * 256 // 100
+
+        assert pitched["status"] == "error"
+        assert pitched["code"] == "invalid_parameter"
+
+    def test_describe_view_lists_tiles_without_rendering(self, run_daemon):
+        """Test describe_view reports bounds and tiles for zoom 2, across the antimeridian."""
+        commands = [
+            {"cmd": "describe_view", "center": [0, 0], "zoom": 2},
+            {"cmd": "init", "width": 512, "height": 512, "style": "{}"},
+            {"cmd": "describe_view", "center": [0, 0], "zoom": 2},
+            {"cmd": "describe_view", "center": [180, 0], "zoom": 2.5},
+            {"cmd": "memory_stats"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = [json.loads(line) for line in result.stdout.splitlines()]
+
+        before_init, equator, antimeridian, memory = responses[0], *responses[2:]
+        assert before_init["code"] == "not_initialized"
+
+        assert equator["status"] == "ok"
+        assert equator["tile_zoom"] == 2
+        assert equator["tiles"] == [[2, 1, 1], [2, 1, 2], [2, 2, 1], [2, 2, 2]]
+        assert equator["bounds"][0] == pytest.approx(-45.0)
+        assert equator["bounds"][2] == pytest.approx(45.0)
+        assert equator["meters_per_pixel"] == pytest.approx(40075016.686 / 2048)
+
+        # West past east; tile columns 3 and 0 on either side of the antimeridian.
+        west, _, east, _ = antimeridian["bounds"]
+        assert west > 0 > east
+        assert {x for _, x, _ in antimeridian["tiles"]} == {0, 3}
+        assert all(z == 2 for z, _, _ in antimeridian["tiles"])
+
+        # Nothing was rendered.
+        assert memory["memory"]["renders"] == 0
+
+    def test_query_features(self, run_daemon):
+        """Test query_features returns a polygon's properties inside it and nothing outside."""
+        square = {
+            "type": "Feature",
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[-10, -10], [10, -10], [10, 10], [-10, 10], [-10, -10]]],
+            },
+            "properties": {"name": "square"},
+        }
+        style = {
+            "version": 8,
+            "sources": {"shapes": {"type": "geojson", "data": square}},
+            "layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "fill", "type": "fill", "source": "shapes"},
+            ],
+        }
+        query = {"cmd": "query_features", "camera": {"center": [0, 0], "zoom": 1}}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            {**query, "x": 32, "y": 32},
+            {**query, "x": 0, "y": 0},
+            {**query, "x": 32, "y": 32, "layers": ["missing"]},
+            {**query, "x": 64, "y": 0},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        lines = [json.loads(line) for line in result.stdout.splitlines()]
+
+        assert lines[0]["status"] == "ok"
+        assert lines[1]["features"] == [{"layer": "fill", "properties": {"name": "square"}}]
+        assert lines[2]["status"] == "ok"
+        assert lines[2]["features"] == []
+        assert "layer 'missing' not found" in lines[3]["error"]
+        assert lines[4]["code"] == "invalid_parameter"
+
+    def test_render_loupe_insets_magnified_focus(self, run_daemon, read_messages):
+        """Test render_loupe draws the focus area inside a circle and leaves the rest."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        # The loupe sits over the black west half but shows the white east.
+        loupe = {
+            "cmd": "render_loupe",
+            "camera": {"center": [0, 0], "zoom": 1},
+            "focus": [90, 0],
+            "magnification": 2,
+            "radius": 12,
+            "position": [16, 32],
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            loupe,
+            {**loupe, "radius": 40},
+            {"cmd": "quit"},
+        ]
+        init, header, too_big = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert header["status"] == "ok"
+        image = Image.open(io.BytesIO(header["payloads"][0])).convert("L")
+        assert image.size == (64, 64)
+        assert image.getpixel((16, 32)) > 192
+        assert image.getpixel((16, 4)) < 64
+        assert image.getpixel((48, 32)) > 192
+
+        assert too_big["code"] == "invalid_parameter"
+        assert "radius must be in (0, 32]" in too_big["error"]
+
+    def test_profile_reports_cold_and_warm_latency(self, run_daemon, read_messages):
+        """Test profile times each camera per pass and keeps warmup passes out of warm."""
+        cameras = [{"center": [0, 0], "zoom": 1}, {"center": [10, 10], "zoom": 3}]
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": "{}"},
+            {"cmd": "profile", "cameras": cameras, "iterations": 3},
+            {"cmd": "profile", "cameras": cameras, "iterations": 2, "warmup": 2},
+            {"cmd": "quit"},
+        ]
+        init, profiled, all_warmup = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert "payloads" not in profiled
+        report = profiled["profile"]
+        assert (report["iterations"], report["warmup"]) == (3, 1)
+        assert report["cold"]["count"] == 2
+        warm = report["warm"]
+        assert warm["count"] == 4
+        assert 0 <= warm["p50_ms"] <= warm["p90_ms"] <= warm["p99_ms"] <= warm["max_ms"]
+        assert sum(bucket["count"] for bucket in report["histogram"]) == 4
+        assert report["histogram"][-1]["le_ms"] is None
+
+        assert all_warmup["profile"]["warm"] is None
+        assert all_warmup["profile"]["cold"]["count"] == 2
+
+    def test_render_utfgrid_maps_cells_to_inline_features(self, run_daemon, read_messages):
+        """Test render_utfgrid keys cells by feature id and keeps only the requested fields."""
+        west = {
+            "type": "Feature",
+            "id": "west",
+            "properties": {"name": "West", "rank": 1},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[-180, -60], [0, -60], [0, 60], [-180, 60], [-180, -60]]],
+            },
+        }
+        style = {
+            "version": 8,
+            "sources": {"areas": {"type": "geojson", "data": west}},
+            "layers": [{"id": "areas", "type": "fill", "source": "areas"}],
+        }
+        grid_cmd = {"cmd": "render_utfgrid", "z": 0, "x": 0, "y": 0, "layers": ["areas"]}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**grid_cmd, "fields": ["name"], "resolution": 64},
+            {**grid_cmd, "resolution": 100},
+            {**grid_cmd, "layers": ["missing"]},
+            {"cmd": "quit"},
+        ]
+        init, grid, bad_resolution, missing = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        utfgrid = grid["utfgrid"]
+        assert utfgrid["grid"] == ["    ", "!!  ", "!!  ", "    "]
+        assert utfgrid["keys"] == ["", "west"]
+        assert utfgrid["data"] == {"west": {"name": "West"}}
+
+        assert bad_resolution["code"] == "invalid_parameter"
+        assert "resolution must divide 256" in bad_resolution["error"]
+        assert "layer 'missing' not found" in missing["error"]
+
+    def test_render_window_crops_global_pixel_space(self, run_daemon, read_messages):
+        """Test render_window renders a pixel window of the world and keeps the init size."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        # The zoom 1 world is 1024px wide, so this window is centred on [0, 0].
+        window = {"cmd": "render_window", "zoom": 1, "pixel_origin": [480, 496]}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**window, "width": 64, "height": 32},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {**window, "width": 600, "height": 32},
+            {"cmd": "quit"},
+        ]
+        init, windowed, rendered, outside = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        image = Image.open(io.BytesIO(windowed["payloads"][0])).convert("L")
+        assert image.size == (64, 32)
+        assert image.getpixel((8, 16)) < 64
+        assert image.getpixel((56, 16)) > 192
+
+        assert Image.open(io.BytesIO(rendered["payloads"][0])).size == (32, 32)
+
+        assert outside["code"] == "invalid_parameter"
+        assert "window must lie within the zoom 1 pixel extent" in outside["error"]
+
+    def test_render_scale_picks_zoom_for_print_scale(self, run_daemon, read_messages):
+        """Test render_scale derives the zoom from scale and dpi, records the dpi, and checks
+        the camera, zoom range and format as render does."""
+        scale = {"cmd": "render_scale", "scale_denominator": 100_000_000, "dpi": 254}
+        start = {"cmd": "init", "width": 32, "height": 32, "style": "{}"}
+        commands = [
+            start,
+            {**scale, "center": [0, 0]},
+            {**scale, "center": [0, 60]},
+            {**scale, "center": [0, 0], "dpi": 0},
+            {**scale, "center": [0, 95]},
+            {**scale, "center": [0, 0], "format": "jpeg", "quality": 80},
+            {**start, "max_zoom": 2, "zoom_out_of_range": "clamp"},
+            {**scale, "center": [0, 0], "scale_denominator": 1000},
+            {"cmd": "quit"},
+        ]
+        (
+            init,
+            equator,
+            north,
+            bad_dpi,
+            bad_latitude,
+            jpeg,
+            capped_init,
+            capped,
+        ) = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        # 1:1e8 at 254 dpi is 10 km per pixel; the zoom 0 world is 512px wide.
+        assert equator["zoom"] == pytest.approx(math.log2(40_075_016.685_578_5 / 512 / 10_000))
+        assert equator["dpi"] == 254
+        image = Image.open(io.BytesIO(equator["payloads"][0]))
+        assert image.size == (32, 32)
+        assert image.info["dpi"] == pytest.approx((254, 254), abs=0.01)
+        # A Mercator pixel covers half the ground at 60 degrees, so one zoom level less.
+        assert north["zoom"] == pytest.approx(equator["zoom"] - 1)
+
+        assert bad_dpi["code"] == "invalid_parameter"
+        assert "scale_denominator and dpi must be positive" in bad_dpi["error"]
+        assert bad_latitude["error_code"] == "invalid_camera"
+
+        assert jpeg["format"] == "jpeg"
+        assert jpeg["payloads"][0][:2] == b"\xff\xd8"
+
+        # A scale past the session's max_zoom is clamped to it, as for render.
+        assert capped_init["status"] == "ok"
+        assert capped["zoom"] == 2
+
+    def test_point_in_layer_probes_one_layer(self, run_daemon, read_messages):
+        """Test point_in_layer ignores other layers and returns inline GeoJSON properties."""
+        west = {
+            "type": "Feature",
+            "properties": {"name": "West"},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]],
+            },
+        }
+        style = {
+            "version": 8,
+            "sources": {"west": {"type": "geojson", "data": west}},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {"id": "land", "type": "fill", "source": "west"},
+            ],
+        }
+        probe = {"cmd": "point_in_layer", "layer": "land", "zoom": 2}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**probe, "point": [-90, 0]},
+            {**probe, "point": [90, 0]},
+            {**probe, "point": [90, 0], "layer": "bg"},
+            {**probe, "point": [90, 0], "layer": "missing"},
+            {"cmd": "quit"},
+        ]
+        init, inside, outside, background, missing = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert inside["hit"] is True
+        assert inside["properties"] == {"name": "West"}
+        assert outside["hit"] is False
+        assert "properties" not in outside
+        assert background["hit"] is True
+
+        assert missing["code"] == "command_failed"
+        assert "layer 'missing' not found" in missing["error"]
+
+    def test_render_to_s3_signs_and_uploads(self, run_daemon, read_messages):
+        """Test render_to_s3 PUTs a signed PNG, or is rejected by builds without s3."""
+        uploads = []
+
+        class BucketHandler(BaseHTTPRequestHandler):
+            def do_PUT(self):
+                body = self.rfile.read(int(self.headers["Content-Length"]))
+                uploads.append((self.path, self.headers, body))
+                self.send_response(200)
+                self.end_headers()
+
+            def log_message(self, *args):
+                pass
+
+        server = ThreadingHTTPServer(("127.0.0.1", 0), BucketHandler)
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        try:
+            commands = [
+                {"cmd": "init", "width": 16, "height": 16, "style": "{}"},
+                {
+                    "cmd": "render_to_s3",
+                    "camera": {"center": [0, 0], "zoom": 1},
+                    "bucket": "maps",
+                    "key": "tiles/a b.png",
+                    "endpoint": f"http://127.0.0.1:{server.server_port}",
+                    "region": "eu-west-1",
+                },
+                {"cmd": "quit"},
+            ]
+            credentials = {"AWS_ACCESS_KEY_ID": "AKIDTEST", "AWS_SECRET_ACCESS_KEY": "secret"}
+            result = run_daemon(commands, env=credentials)
+        finally:
+            server.shutdown()
+
+        init, uploaded = read_messages(result.stdout)
+        assert init["status"] == "ok"
+        if "requires a build with the s3 feature" in uploaded.get("error", ""):
+            assert uploaded["code"] == "invalid_command"
+            assert uploads == []
+            return
+
+        assert uploaded["status"] == "ok"
+        assert uploaded["key"] == "tiles/a b.png"
+        [(path, headers, body)] = uploads
+        assert path == "/maps/tiles/a%20b.png"
+        assert body[:8] == b"\x89PNG\r\n\x1a\n"
+        assert uploaded["uploaded_bytes"] == len(body)
+        assert headers["Content-Type"] == "image/png"
+        assert headers["Authorization"].startswith("AWS4-HMAC-SHA256 Credential=AKIDTEST/")
+        assert "/eu-west-1/s3/aws4_request" in headers["Authorization"]
+
+    def test_feature_counts_counts_features_in_view(self, run_daemon, read_messages):
+        """Test feature_counts counts inline GeoJSON in the viewport, per layer."""
+        points = {
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": coordinates}}
+                for coordinates in ([0, 0], [0.5, 0.5], [100, 0])
+            ],
+        }
+        style = {
+            "version": 8,
+            "sources": {"points": {"type": "geojson", "data": points}},
+            "layers": [
+                {"id": "dots", "type": "circle", "source": "points"},
+                {"id": "late", "type": "circle", "source": "points", "minzoom": 10},
+            ],
+        }
+        counts = {"cmd": "feature_counts", "camera": {"center": [0, 0], "zoom": 4}}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            counts,
+            {**counts, "layers": ["dots", "missing"]},
+            {**counts, "limit": 1},
+            {"cmd": "quit"},
+        ]
+        init, every, named, limited = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert every["feature_counts"] == [
+            {"layer": "dots", "count": 2},
+            {"layer": "late", "count": 0},
+        ]
+        assert every["truncated"] is False
+
+        dots, missing = named["feature_counts"]
+        assert dots == {"layer": "dots", "count": 2}
+        assert missing == {"layer": "missing", "error": "layer 'missing' not found"}
+
+        assert [count["layer"] for count in limited["feature_counts"]] == ["dots"]
+        assert limited["truncated"] is True
+
+    @pytest.mark.integration
+    def test_render_with_legend_docks_panel_beside_map(self, run_daemon, read_messages):
+        """Test render_with_legend widens the image by the panel and swatches each layer."""
+        world = [[[-180, -85], [180, -85], [180, 85], [-180, 85], [-180, -85]]]
+        style = {
+            "version": 8,
+            "glyphs": "https://demotiles.maplibre.org/font/{fontstack}/{range}.pbf",
+            "sources": {
+                "world": {"type": "geojson", "data": {"type": "Polygon", "coordinates": world}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#000000"}},
+                {
+                    "id": "water",
+                    "type": "fill",
+                    "source": "world",
+                    "paint": {"fill-color": "#0000ff"},
+                },
+                {"id": "coast", "type": "line", "source": "world"},
+                {"id": "dots", "type": "circle", "source": "world"},
+                {"id": "land", "type": "fill", "source": "world", "paint": {"fill-opacity": 0}},
+            ],
+        }
+        legend = {
+            "cmd": "render_with_legend",
+            "camera": {"center": [0, 0], "zoom": 1},
+            "legend": {"layers": ["water"], "width": 80},
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 96, "style": json.dumps(style)},
+            legend,
+            {**legend, "legend": {"width": 80, "position": "left"}},
+            {**legend, "legend": {"width": 0}},
+            {"cmd": "quit"},
+        ]
+        init, right, left, no_width = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert right["truncated"] is False
+        image = Image.open(io.BytesIO(right["payloads"][0])).convert("RGB")
+        assert image.size == (144, 96)
+        assert image.getpixel((32, 48)) == (0, 0, 255)
+        # The first swatch sits one padding in from the panel's top-left corner.
+        assert image.getpixel((64 + 20, 24)) == (0, 0, 255)
+        assert image.getpixel((140, 92)) == (255, 255, 255)
+
+        # Four layers don't fit beside 96px with the "+N more" row kept free.
+        assert left["truncated"] is True
+        image = Image.open(io.BytesIO(left["payloads"][0])).convert("RGB")
+        assert image.getpixel((20, 24)) == (0, 0, 255)
+        assert image.getpixel((80 + 32, 48)) == (0, 0, 255)
+
+        assert no_width["code"] == "invalid_parameter"
+        assert "legend width must be non-zero" in no_width["error"]
+
+    def test_render_tile_archive_writes_tar_of_tiles(self, tmp_path, run_daemon, read_messages):
+        """Test render_tile_archive packs listed or covering tiles as {z}/{x}/{y} entries."""
+        output = tmp_path / "tiles.tar.gz"
+        archive = {"cmd": "render_tile_archive"}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": "{}"},
+            {**archive, "tiles": [[1, 1, 1], [1, 0, 0], [1, 1, 1]], "tile_size": 256},
+            {
+                **archive,
+                "bounds": [-170, -60, -10, 60],
+                "zooms": [0, 1],
+                "output": str(output),
+                "gzip": True,
+            },
+            {**archive, "tiles": [[0, 0, 0]], "tile_size": 256},
+            {**archive, "tiles": [[1, 0, 0]], "bounds": [-170, -60, -10, 60]},
+            {"cmd": "quit"},
+        ]
+        init, sent, written, zoom0, both = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert sent["tile_count"] == 2
+        with tarfile.open(fileobj=io.BytesIO(sent["payloads"][0])) as tar:
+            assert tar.getnames() == ["1/0/0.png", "1/1/1.png"]
+            tile = Image.open(tar.extractfile("1/1/1.png"))
+            assert tile.size == (256, 256)
+
+        assert written["tile_count"] == 3
+        assert "payloads" not in written
+        with tarfile.open(output, "r:gz") as tar:
+            assert tar.getnames() == ["0/0/0.png", "1/0/0.png", "1/0/1.png"]
+            assert Image.open(tar.extractfile("0/0/0.png")).size == (512, 512)
+
+        assert zoom0["code"] == "invalid_parameter"
+        assert "256-pixel tiles start at zoom 1" in zoom0["error"]
+        assert "give either tiles or bounds with zooms" in both["error"]
+
+    def test_render_match_raster_shares_reference_pixel_grid(self, run_daemon, read_messages):
+        """Test render_match_raster renders a Web Mercator raster's extent at its size."""
+        # Tile 3/3/3 spans 1/8 of the world, so a 128px raster of it is at zoom 1 (1024px).
+        tile = [-45, 0, 0, 40.97989806962013]
+        west = [[[-45, 0], [-22.5, 0], [-22.5, 41], [-45, 41], [-45, 0]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        match = {"cmd": "render_match_raster", "reference_bounds": tile}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**match, "reference_width": 128, "reference_height": 128},
+            {**match, "reference_width": 128, "reference_height": 64},
+            {"cmd": "quit"},
+        ]
+        init, matched, stretched = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert matched["zoom"] == pytest.approx(1, abs=1e-6)
+        image = Image.open(io.BytesIO(matched["payloads"][0])).convert("L")
+        assert image.size == (128, 128)
+        assert image.getpixel((32, 64)) < 64
+        assert image.getpixel((96, 64)) > 192
+
+        assert stretched["code"] == "invalid_parameter"
+        assert "not square in Web Mercator" in stretched["error"]
+
+    def test_render_layer_groups_isolates_each_group(self, run_daemon, read_messages):
+        """Test render_layer_groups renders each group alone on transparency."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+            ],
+        }
+        view = {"center": [0, 0], "zoom": 1}
+        groups = [
+            {"name": "base", "layers": ["bg"]},
+            {"name": "broken", "layers": ["missing"]},
+            {"name": "overlay", "layers": ["west"]},
+        ]
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "render_layer_groups", "camera": view, "groups": groups},
+            {"cmd": "render", **view},
+            {"cmd": "quit"},
+        ]
+        init, rendered, after = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        base, broken, overlay = rendered["groups"]
+        assert broken == {"name": "broken", "error": "layer 'missing' not found"}
+        assert "png_len" in base and "png_len" in overlay
+        base_image, overlay_image = (
+            Image.open(io.BytesIO(payload)).convert("RGBA") for payload in rendered["payloads"]
+        )
+        assert base_image.getpixel((8, 16)) == (255, 255, 255, 255)
+        assert overlay_image.getpixel((8, 16)) == (0, 0, 0, 255)
+        assert overlay_image.getpixel((24, 16))[3] == 0
+
+        # Hidden layers are shown again afterwards.
+        image = Image.open(io.BytesIO(after["payloads"][0])).convert("RGB")
+        assert image.getpixel((8, 16)) == (0, 0, 0)
+        assert image.getpixel((24, 16)) == (255, 255, 255)
+
+    def test_render_geojson_diff_highlights_changes(self, run_daemon, read_messages):
+        """Test render_geojson_diff matches features by id and draws each change class."""
+
+        def square(feature_id, west, east, **properties):
+            ring = [[west, -5], [east, -5], [east, 5], [west, 5], [west, -5]]
+            return {
+                "type": "Feature",
+                "id": feature_id,
+                "properties": properties,
+                "geometry": {"type": "Polygon", "coordinates": [ring]},
+            }
+
+        def collection(*features):
+            return {"type": "FeatureCollection", "features": list(features)}
+
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        diff = {
+            "cmd": "render_geojson_diff",
+            "before": collection(square(1, -1, 1), square(2, -1, 1, rank=1), square(3, -10, -2)),
+            "after": collection(square(1, -1, 1), square(2, -1, 1, rank=2), square(4, 2, 10)),
+            "camera": {"center": [0, 0], "zoom": 1},
+            "added_style": {"color": "#00ff00", "fill_opacity": 1},
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            diff,
+            {**diff, "before": {"features": []}},
+            {"cmd": "quit"},
+        ]
+        init, rendered, not_geojson = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert rendered["diff"] == {"added": 1, "removed": 1, "changed": 1, "unchanged": 1}
+        image = Image.open(io.BytesIO(rendered["payloads"][0])).convert("RGB")
+        assert image.getpixel((48, 32)) == (0, 255, 0)
+        assert image.getpixel((16, 32)) != (255, 255, 255)
+        assert image.getpixel((32, 56)) == (255, 255, 255)
+
+        assert not_geojson["code"] == "invalid_parameter"
+        assert "before: not a GeoJSON object" in not_geojson["error"]
+
+    def test_render_constrained_keeps_camera_in_boundary(self, run_daemon, read_messages):
+        """Test render_constrained reports and renders the camera it adjusted."""
+        ring = [[-10, -10], [10, -10], [10, 10], [-10, 10], [-10, -10]]
+        boundary = {"type": "Polygon", "coordinates": [ring]}
+        constrained = {"cmd": "render_constrained", "boundary": boundary}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": "{}"},
+            {**constrained, "camera": {"center": [30, 0], "zoom": 3}, "mode": "clamp_center"},
+            {**constrained, "camera": {"center": [0, 0], "zoom": 3}, "mode": "clamp_center"},
+            {**constrained, "camera": {"center": [0, 0], "zoom": 3}, "mode": "contain"},
+            {
+                **constrained,
+                "camera": {"center": [0, 0], "zoom": 3},
+                "mode": "contain",
+                "boundary": {"type": "Point", "coordinates": [0, 0]},
+            },
+            {"cmd": "quit"},
+        ]
+        init, clamped, inside, contained, no_polygon = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert clamped["constrained"]["adjusted"] == ["center"]
+        assert clamped["constrained"]["camera"]["center"] == pytest.approx([10, 0])
+        assert Image.open(io.BytesIO(clamped["payloads"][0])).size == (64, 64)
+
+        assert inside["constrained"]["adjusted"] == []
+        assert inside["constrained"]["camera"]["center"] == [0, 0]
+
+        # A 64px view shows 22.5 degrees at zoom 1, so the 20 degree box fits just above it.
+        assert contained["constrained"]["adjusted"] == ["zoom"]
+        assert 1 < contained["constrained"]["camera"]["zoom"] < 1.2
+
+        assert no_polygon["code"] == "invalid_parameter"
+        assert "boundary has no Polygon or MultiPolygon geometry" in no_polygon["error"]
+
+    def test_render_track_animation_moves_markers_per_frame(self, run_daemon, read_messages):
+        """Test render_track_animation interpolates positions across evenly spaced frames."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        ship = {
+            "id": "ship",
+            "color": "#ff0000",
+            "keyframes": [
+                {"time": 10, "lng": 8, "lat": 0},
+                {"time": 0, "lng": -8, "lat": 0},
+            ],
+        }
+        animation = {
+            "cmd": "render_track_animation",
+            "camera": {"center": [0, 0], "zoom": 1},
+            "tracks": [ship],
+            "frames": 3,
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            animation,
+            {**animation, "animation": "apng", "fps": 5},
+            {**animation, "frames": 0},
+            {"cmd": "quit"},
+        ]
+        init, frames, apng, no_frames = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert frames["frame_times"] == [0, 5, 10]
+        images = [Image.open(io.BytesIO(p)).convert("RGB") for p in frames["payloads"]]
+        # 8 degrees is about 23px at zoom 1, so the ship crosses the middle.
+        for image, x in zip(images, (9, 32, 55), strict=True):
+            red, green, _ = image.getpixel((x, 32))
+            assert red > 200 and green < 80
+        assert images[0].getpixel((55, 32)) == (255, 255, 255)
+
+        assert apng["format"] == "apng"
+        assert Image.open(io.BytesIO(apng["payloads"][0])).n_frames == 3
+
+        assert no_frames["code"] == "invalid_parameter"
+        assert "frames must be between 1 and 1000" in no_frames["error"]
+
+    def test_render_zoom_ladder_arranges_labelled_cells(self, run_daemon, read_messages):
+        """Test render_zoom_ladder lays out one labelled cell per zoom on a white grid."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#123456"}}
+            ],
+        }
+        ladder = {"cmd": "render_zoom_ladder", "center": [0, 0], "zooms": [1, 2, 3]}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            ladder,
+            {**ladder, "columns": 5},
+            {**ladder, "zooms": []},
+            {"cmd": "quit"},
+        ]
+        init, square, row, empty = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        # Three zooms make a 2x2 grid with 4px gaps and the last slot left blank.
+        assert square["columns"] == 2
+        image = Image.open(io.BytesIO(square["payloads"][0])).convert("RGB")
+        assert image.size == (68, 68)
+        assert image.getpixel((16, 24)) == (0x12, 0x34, 0x56)
+        assert image.getpixel((34, 16)) == (255, 255, 255)
+        assert image.getpixel((52, 52)) == (255, 255, 255)
+        # The label is drawn over the cell's top-left corner.
+        label = image.crop((0, 0, 16, 16)).getcolors()
+        assert len(label) > 1
+
+        assert row["columns"] == 3
+        assert Image.open(io.BytesIO(row["payloads"][0])).size == (104, 32)
+
+        assert empty["code"] == "invalid_parameter"
+        assert "give between 1 and" in empty["error"]
+
+    def test_render_bounds_fits_bounds_inside_padding(self, run_daemon, read_messages):
+        """Test render_bounds picks the camera that fits the bounds and reports it."""
+        # Tile 4/7/7: 1/16 of the world, so it exactly fills 64px at zoom 1.
+        west, south, east, north = -22.5, 0, 0, 21.943045533438177
+        box = [[[west, south], [east, south], [east, north], [west, north], [west, south]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "box": {"type": "geojson", "data": {"type": "Polygon", "coordinates": box}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {"id": "box", "type": "fill", "source": "box", "paint": {"fill-color": "#000000"}},
+            ],
+        }
+        fit = {"cmd": "render_bounds", "bounds": [[west, south], [east, north]]}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            fit,
+            {**fit, "padding": 16},
+            {**fit, "bounds": [[170, -10], [-170, 10]]},
+            {**fit, "padding": 40},
+            {"cmd": "quit"},
+        ]
+        init, tight, padded, wrapped, too_padded = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert tight["zoom"] == pytest.approx(1, abs=1e-6)
+        assert tight["center"][0] == pytest.approx(-11.25)
+        image = Image.open(io.BytesIO(tight["payloads"][0])).convert("L")
+        assert image.getpixel((4, 4)) < 64 and image.getpixel((60, 60)) < 64
+
+        assert padded["zoom"] == pytest.approx(0, abs=1e-6)
+        image = Image.open(io.BytesIO(padded["payloads"][0])).convert("L")
+        assert image.getpixel((32, 32)) < 64
+        assert image.getpixel((4, 32)) > 192
+
+        # Bounds whose east is west of their west cross the antimeridian.
+        assert abs(wrapped["center"][0]) == pytest.approx(180)
+
+        assert too_padded["code"] == "invalid_parameter"
+        assert "padding must be non-negative and leave room" in too_padded["error"]
+
+    def test_render_with_graticule_draws_grid_lines(self, run_daemon, read_messages):
+        """Test render_with_graticule draws meridians and parallels at the chosen interval."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        graticule = {
+            "cmd": "render_with_graticule",
+            "camera": {"center": [0, 0], "zoom": 3},
+            "color": [255, 0, 0, 255],
+            "label": False,
+        }
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            {**graticule, "interval_degrees": 5},
+            graticule,
+            {**graticule, "interval_degrees": 1},
+            {**graticule, "interval_degrees": 100},
+            {"cmd": "quit"},
+        ]
+        init, five, automatic, thinned, too_wide = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert five["interval_degrees"] == 5
+        image = Image.open(io.BytesIO(five["payloads"][0])).convert("RGB")
+
+        def is_line(pixels):
+            return any(red > 200 and green < 100 for red, green, _ in pixels)
+
+        # The equator and prime meridian cross in the middle; 5 degrees is 57px here.
+        assert is_line(image.getpixel((x, 10)) for x in range(30, 35))
+        assert is_line(image.getpixel((10, y)) for y in range(30, 35))
+        assert image.getpixel((10, 10)) == (255, 255, 255)
+
+        # At zoom 3 the automatic interval keeps lines at least 128px apart.
+        assert automatic["interval_degrees"] == 20
+        # 1 degree lines would be 11px apart, so only every fifth is drawn.
+        assert thinned["interval_degrees"] == 5
+
+        assert too_wide["code"] == "invalid_parameter"
+        assert "interval_degrees must be in (0, 90]" in too_wide["error"]
+
+    def test_render_dpi_set_keeps_extent_across_dpis(self, run_daemon, read_messages):
+        """Test render_dpi_set sizes each variant by dpi and scales lines with it."""
+        west = [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]
+        equator = [[0, 0], [180, 0]]
+        style = {
+            "version": 8,
+            "sources": {
+                "west": {"type": "geojson", "data": {"type": "Polygon", "coordinates": west}},
+                "equator": {
+                    "type": "geojson",
+                    "data": {"type": "LineString", "coordinates": equator},
+                },
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "west",
+                    "type": "fill",
+                    "source": "west",
+                    "paint": {"fill-color": "#000000"},
+                },
+                {
+                    "id": "equator",
+                    "type": "line",
+                    "source": "equator",
+                    "paint": {"line-color": "#ff0000", "line-width": 4},
+                },
+            ],
+        }
+        # A 1 x 0.5 inch print.
+        dpi_set = {
+            "cmd": "render_dpi_set",
+            "camera": {"center": [0, 0], "zoom": 1},
+            "width_mm": 25.4,
+            "height_mm": 12.7,
+        }
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**dpi_set, "dpis": [96, 192]},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {**dpi_set, "dpis": [96, 96]},
+            {**dpi_set, "dpis": []},
+            {**dpi_set, "dpis": [96 * 200]},
+            {"cmd": "quit"},
+        ]
+        init, variants, after, repeated, empty, huge = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        low, high = variants["dpi_variants"]
+        assert (low["dpi"], low["width"], low["height"]) == (96, 96, 48)
+        assert (high["dpi"], high["width"], high["height"]) == (192, 192, 96)
+        for variant, payload in zip(variants["dpi_variants"], variants["payloads"], strict=True):
+            image = Image.open(io.BytesIO(payload))
+            assert image.size == (variant["width"], variant["height"])
+            assert image.info["dpi"] == pytest.approx((variant["dpi"],) * 2, abs=0.01)
+            # Both show the same extent: the west half black, the east white.
+            gray = image.convert("L")
+            assert gray.getpixel((variant["width"] // 4, variant["height"] // 2)) < 64
+            assert gray.getpixel((variant["width"] * 3 // 4, variant["height"] // 4)) > 192
+            # The 4 px line is drawn at dpi / 96 times its width.
+            rgb = image.convert("RGB")
+            column = [rgb.getpixel((variant["width"] * 3 // 4, y)) for y in range(image.height)]
+            red = sum(1 for r, g, b in column if r > 192 and g < 64 and b < 64)
+            assert red == pytest.approx(4 * variant["dpi"] / 96, abs=1)
+
+        assert Image.open(io.BytesIO(after["payloads"][0])).size == (32, 32)
+
+        assert repeated["code"] == "invalid_parameter"
+        assert "dpi 96 is listed twice" in repeated["error"]
+        assert "dpis must be a non-empty list of positive DPIs" in empty["error"]
+        assert huge["error_code"] == "size_too_large"
+        assert "texture limit" in huge["error"]
+
+
+class TestStyleEdits:
+    """Tests for inspecting and editing the loaded style."""
+
+    def test_validate_style(self, run_daemon, read_messages, tmp_path):
+        """Test validate_style accepts valid styles and rejects broken ones, sparing the session."""
+        red = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ff0000"}}
+            ],
+        }
+        path = tmp_path / "style.json"
+        path.write_text(json.dumps(red))
+        commands = [
+            {"cmd": "validate_style", "style": json.dumps(red)},
+            {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(red)},
+            {"cmd": "validate_style", "style": '{"version": 8, "layers": ['},
+            {"cmd": "validate_style", "style": str(path)},
+            {"cmd": "render", "center": [0, 0], "zoom": 1},
+            {"cmd": "quit"},
+        ]
+        valid, init, broken, from_path, header = read_messages(run_daemon(commands).stdout)
+        assert valid == {"status": "ok"}
+        assert init["status"] == "ok"
+
+        assert broken["code"] == "command_failed"
+        assert broken["error_code"] == "style_parse_error"
+        assert from_path == {"status": "ok"}
+
+        # The broken style never replaced the session's.
+        png = header["payloads"][0]
+        assert Image.open(io.BytesIO(png)).convert("RGB").getpixel((8, 8)) == (255, 0, 0)
+
+    def test_describe_style(self, run_daemon):
+        """Test describe_style lists the loaded style's sources and layers in order."""
+        style = {
+            "version": 8,
+            "name": "Known",
+            "sources": {
+                "roads": {"type": "vector", "tiles": ["custom://roads/{z}/{x}/{y}.pbf"]},
+                "points": {
+                    "type": "geojson",
+                    "data": {"type": "FeatureCollection", "features": []},
+                },
+            },
+            "layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "road-casing", "type": "line", "source": "roads", "source-layer": "roads"},
+                {"id": "road", "type": "line", "source": "roads", "source-layer": "roads"},
+                {"id": "pins", "type": "circle", "source": "points"},
+            ],
+        }
+        commands = [
+            {"cmd": "describe_style"},
+            {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)},
+            {"cmd": "describe_style"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = [json.loads(line) for line in result.stdout.splitlines()]
+        assert responses[0]["code"] == "not_initialized"
+
+        described = responses[2]["style"]
+        assert described["name"] == "Known"
+        assert described["sources"] == [
+            {"id": "points", "type": "geojson"},
+            {"id": "roads", "type": "vector"},
+        ]
+        assert [layer["id"] for layer in described["layers"]] == [
+            "bg",
+            "road-casing",
+            "road",
+            "pins",
+        ]
+        assert described["layers"][0]["source"] is None
+        assert described["layers"][2] == {
+            "id": "road",
+            "type": "line",
+            "source": "roads",
+            "source-layer": "roads",
+        }
+
+    def test_get_style_returns_edited_style(self, run_daemon):
+        """Test get_style returns the loaded style with runtime edits applied."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#336699"}},
+                {"id": "tint", "type": "background", "paint": {"background-color": "#ff0000"}},
+            ],
+        }
+        commands = [
+            {"cmd": "get_style"},
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "set_layer_visibility", "layer": "tint", "visible": False},
+            {"cmd": "get_style"},
+            {"cmd": "quit"},
+        ]
+        result = run_daemon(commands)
+        responses = [json.loads(line) for line in result.stdout.split(b"\n")[:4]]
+
+        before_init, init, hidden, fetched = responses
+        assert before_init["code"] == "not_initialized"
+        assert init["status"] == "ok"
+        assert hidden["status"] == "ok"
+        assert fetched["status"] == "ok"
+        layers = {layer["id"]: layer for layer in fetched["style_json"]["layers"]}
+        assert layers["tint"]["layout"]["visibility"] == "none"
+        assert layers["bg"].get("layout", {}).get("visibility", "visible") == "visible"
+        assert layers["bg"]["paint"] == style["layers"][0]["paint"]
+
+    def test_set_layer_visibility(self, run_daemon, read_messages):
+        """Test hiding a layer changes later renders and a missing layer is an error."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {"id": "tint", "type": "background", "paint": {"background-color": "#0000ff"}},
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)},
+            render,
+            {"cmd": "set_layer_visibility", "layer": "tint", "visible": False},
+            render,
+            {"cmd": "set_layer_visibility", "layer": "missing", "visible": False},
+            {"cmd": "quit"},
+        ]
+        messages = iter(read_messages(run_daemon(commands).stdout))
+
+        def read():
+            response = next(messages)
+            return response, response.get("payloads", [b""])[0]
+
+        assert read()[0]["status"] == "ok"
+        tinted = Image.open(io.BytesIO(read()[1])).convert("RGB")
+        assert read()[0]["status"] == "ok"
+        hidden = Image.open(io.BytesIO(read()[1])).convert("RGB")
+        assert tinted.getpixel((8, 8)) == (0, 0, 255)
+        assert hidden.getpixel((8, 8)) == (255, 255, 255)
+
+        missing = read()[0]
+        assert missing["code"] == "command_failed"
+        assert "layer 'missing' not found" in missing["error"]
+
+    def test_set_layer_property(self, run_daemon, read_messages):
+        """Test a paint property change shows in the next render and bad properties fail."""
+        world = {
+            "type": "Polygon",
+            "coordinates": [[[-170, -80], [170, -80], [170, 80], [-170, 80], [-170, -80]]],
+        }
+        style = {
+            "version": 8,
+            "sources": {"land": {"type": "geojson", "data": world}},
+            "layers": [
+                {
+                    "id": "land",
+                    "type": "fill",
+                    "source": "land",
+                    "paint": {"fill-color": "#00ff00"},
+                },
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 2}
+        set_color = {"cmd": "set_layer_property", "layer": "land", "kind": "paint"}
+        commands = [
+            {"cmd": "init", "width": 16, "height": 16, "style": json.dumps(style)},
+            render,
+            {**set_color, "property": "fill-color", "value": "#ff0000"},
+            render,
+            {**set_color, "property": "line-width", "value": 2},
+            {**set_color, "layer": "missing", "property": "fill-color", "value": "#ff0000"},
+            {"cmd": "quit"},
+        ]
+        messages = iter(read_messages(run_daemon(commands).stdout))
+
+        def read():
+            response = next(messages)
+            return response, response.get("payloads", [b""])[0]
+
+        assert read()[0]["status"] == "ok"
+        before = Image.open(io.BytesIO(read()[1])).convert("RGB")
+        assert read()[0]["status"] == "ok"
+        after = Image.open(io.BytesIO(read()[1])).convert("RGB")
+        assert before.getpixel((8, 8)) == (0, 255, 0)
+        assert after.getpixel((8, 8)) == (255, 0, 0)
+
+        wrong_property = read()[0]
+        assert wrong_property["code"] == "command_failed"
+        missing = read()[0]
+        assert "layer 'missing' not found" in missing["error"]
+
+    def test_set_layer_filter(self, run_daemon, read_messages):
+        """Test a filter hides features in later renders until it is cleared."""
+        def square(west, kind):
+            ring = [[west, -20], [west + 30, -20], [west + 30, 20], [west, 20], [west, -20]]
+            return {
+                "type": "Feature",
+                "geometry": {"type": "Polygon", "coordinates": [ring]},
+                "properties": {"kind": kind},
+            }
+
+        shapes = {"type": "FeatureCollection", "features": [square(-40, "a"), square(10, "b")]}
+        style = {
+            "version": 8,
+            "sources": {"shapes": {"type": "geojson", "data": shapes}},
+            "layers": [
+                {
+                    "id": "shapes",
+                    "type": "fill",
+                    "source": "shapes",
+                    "paint": {"fill-color": "#ff0000"},
+                },
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            render,
+            {"cmd": "set_layer_filter", "layer": "shapes", "filter": ["==", ["get", "kind"], "a"]},
+            render,
+            {"cmd": "set_layer_filter", "layer": "shapes", "filter": None},
+            render,
+            {"cmd": "set_layer_filter", "layer": "missing", "filter": None},
+            {"cmd": "quit"},
+        ]
+        messages = iter(read_messages(run_daemon(commands).stdout))
+
+        def read():
+            response = next(messages)
+            return response, response.get("payloads", [b""])[0]
+
+        def drawn_pixels(payload):
+            alpha = Image.open(io.BytesIO(payload)).convert("RGBA").getchannel("A")
+            return sum(1 for value in alpha.getdata() if value > 0)
+
+        assert read()[0]["status"] == "ok"
+        both = drawn_pixels(read()[1])
+        assert read()[0]["status"] == "ok"
+        filtered = drawn_pixels(read()[1])
+        assert read()[0]["status"] == "ok"
+        cleared = drawn_pixels(read()[1])
+
+        assert 0 < filtered < both
+        assert cleared == both
+        assert "layer 'missing' not found" in read()[0]["error"]
+
+    def test_add_and_remove_source(self, run_daemon, read_messages):
+        """Test add_source and remove_source edit the sources of the live style."""
+        empty = {"type": "FeatureCollection", "features": []}
+        style = {
+            "version": 8,
+            "sources": {"pins": {"type": "geojson", "data": empty}},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "pins",
+                    "type": "circle",
+                    "source": "pins",
+                    "paint": {"circle-radius": 8, "circle-color": "#ff0000"},
+                },
+            ],
+        }
+        point = {"type": "Point", "coordinates": [0, 0]}
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            render,
+            {"cmd": "add_source", "id": "pins", "geojson": point},
+            {"cmd": "add_source", "id": "pins", "geojson": point, "replace": True},
+            render,
+            {"cmd": "add_source", "id": "notes", "geojson": empty},
+            {"cmd": "remove_source", "id": "notes"},
+            {"cmd": "remove_source", "id": "notes"},
+            {"cmd": "remove_source", "id": "pins"},
+            {"cmd": "quit"},
+        ]
+        headers, centres = read_messages(run_daemon(commands).stdout), []
+        for header in headers:
+            for png in header.get("payloads", []):
+                centres.append(Image.open(io.BytesIO(png)).convert("RGB").getpixel((16, 16)))
+
+        assert headers[2]["code"] == "command_failed"
+        assert "already exists" in headers[2]["error"]
+        assert [headers[i]["status"] for i in (3, 5, 6)] == ["ok", "ok", "ok"]
+        # The replaced source keeps its circle layer, which now has a point to draw.
+        assert centres == [(255, 255, 255), (255, 0, 0)]
+        assert "source 'notes' not found" in headers[7]["error"]
+        assert "drawn by layers pins" in headers[8]["error"]
+
+    def test_add_and_remove_layer(self, run_daemon, read_messages):
+        """Test add_layer draws a new layer where it is inserted until remove_layer."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        square = [[[-60, -60], [60, -60], [60, 60], [-60, 60], [-60, -60]]]
+        fill = {
+            "id": "notes",
+            "type": "fill",
+            "source": "notes",
+            "paint": {"fill-color": "#00ff00"},
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 0}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {"cmd": "add_layer", "layer": fill},
+            {
+                "cmd": "add_source",
+                "id": "notes",
+                "geojson": {"type": "Polygon", "coordinates": square},
+            },
+            {"cmd": "add_layer", "layer": fill, "before": "missing"},
+            {"cmd": "add_layer", "layer": fill, "before": "bg"},
+            render,
+            {"cmd": "add_layer", "layer": fill},
+            {"cmd": "remove_layer", "id": "notes"},
+            {"cmd": "add_layer", "layer": fill},
+            render,
+            {"cmd": "remove_layer", "id": "notes"},
+            render,
+            {"cmd": "remove_layer", "id": "notes"},
+            {"cmd": "quit"},
+        ]
+        headers, centres = read_messages(run_daemon(commands).stdout), []
+        for header in headers:
+            for png in header.get("payloads", []):
+                centres.append(Image.open(io.BytesIO(png)).convert("RGB").getpixel((16, 16)))
+
+        assert "source 'notes' not found" in headers[1]["error"]
+        assert "layer 'missing' not found" in headers[3]["error"]
+        assert "layer 'notes' already exists" in headers[6]["error"]
+        assert "layer 'notes' not found" in headers[12]["error"]
+        assert [headers[i]["status"] for i in (2, 4, 7, 8, 10)] == ["ok"] * 5
+        # Below the background the fill is hidden; on top it shows until removed.
+        assert centres == [(255, 255, 255), (0, 255, 0), (255, 255, 255)]
+
+    def test_feature_state(self, run_daemon, read_messages):
+        """Test feature-state expressions react to set_feature_state until it is removed."""
+        square = [[[-60, -60], [60, -60], [60, 60], [-60, 60], [-60, -60]]]
+        style = {
+            "version": 8,
+            "sources": {
+                "parcels": {
+                    "type": "geojson",
+                    "data": {
+                        "type": "Feature",
+                        "id": 7,
+                        "geometry": {"type": "Polygon", "coordinates": square},
+                        "properties": {},
+                    },
+                }
+            },
+            "layers": [
+                {
+                    "id": "parcels",
+                    "type": "fill",
+                    "source": "parcels",
+                    "paint": {
+                        "fill-color": [
+                            "case",
+                            ["boolean", ["feature-state", "selected"], False],
+                            "#ff0000",
+                            "#0000ff",
+                        ]
+                    },
+                }
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 0}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            render,
+            {"cmd": "set_feature_state", "source": "parcels", "id": 7, "state": {"selected": True}},
+            render,
+            render,
+            {"cmd": "remove_feature_state", "source": "parcels", "id": 7, "key": "selected"},
+            render,
+            {"cmd": "set_feature_state", "source": "missing", "id": 7, "state": {}},
+            {"cmd": "quit"},
+        ]
+        headers, centres = read_messages(run_daemon(commands).stdout), []
+        for header in headers:
+            for png in header.get("payloads", []):
+                centres.append(Image.open(io.BytesIO(png)).convert("RGB").getpixel((16, 16)))
+
+        assert all(header["status"] == "ok" for header in headers[:-1])
+        assert centres == [(0, 0, 255), (255, 0, 0), (255, 0, 0), (0, 0, 255)]
+        assert headers[-1]["code"] == "command_failed"
+        assert "source 'missing' not found" in headers[-1]["error"]
+
+    def test_set_debug_tile_borders(self, run_daemon, read_messages):
+        """Test set_debug draws tile borders over later renders until cleared."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}}
+            ],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 64, "height": 64, "style": json.dumps(style)},
+            render,
+            {"cmd": "set_debug", "tile_borders": True},
+            render,
+            {"cmd": "set_debug"},
+            render,
+            {"cmd": "quit"},
+        ]
+        images = []
+        for header in read_messages(run_daemon(commands).stdout):
+            assert header["status"] == "ok"
+            images.extend(header.get("payloads", []))
+
+        baseline, debug, cleared = images
+        assert debug != baseline
+        assert cleared == baseline
+
+    def test_transaction_applies_all_operations_or_none(self, run_daemon, read_messages):
+        """Test transaction renders after its edits and rolls back when one fails."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+            ],
+        }
+
+        def paint(color):
+            return {
+                "op": "set_paint_property",
+                "layer": "bg",
+                "name": "background-color",
+                "value": color,
+            }
+
+        view = {"center": [0, 0], "zoom": 1}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {
+                "cmd": "transaction",
+                "operations": [paint("#00ff00"), paint("#ff0000")],
+                "render": view,
+            },
+            {
+                "cmd": "transaction",
+                "operations": [
+                    paint("#0000ff"),
+                    {"op": "set_layer_opacity", "layer": "missing", "opacity": 0.5},
+                ],
+            },
+            {"cmd": "render", **view},
+            {"cmd": "quit"},
+        ]
+        init, applied, failed, after = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        image = Image.open(io.BytesIO(applied["payloads"][0])).convert("RGB")
+        assert image.getpixel((16, 16)) == (255, 0, 0)
+
+        assert failed["code"] == "command_failed"
+        assert failed["failed_operation"] == 1
+        assert "layer 'missing' not found" in failed["error"]
+        image = Image.open(io.BytesIO(after["payloads"][0])).convert("RGB")
+        assert image.getpixel((16, 16)) == (255, 0, 0)
+
+    def test_reload_resources_refetches_sprite_images(self, tmp_path, binary_path):
+        """Test reload_resources picks up a changed sprite sheet without a style reload."""
+        Image.new("RGBA", (8, 8), (255, 0, 0, 255)).save(tmp_path / "sprite.png")
+        index = {"dot": {"x": 0, "y": 0, "width": 8, "height": 8, "pixelRatio": 1}}
+        (tmp_path / "sprite.json").write_text(json.dumps(index))
+        style = {
+            "version": 8,
+            "sprite": f"file://{tmp_path}/sprite",
+            "sources": {
+                "point": {"type": "geojson", "data": {"type": "Point", "coordinates": [0, 0]}}
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "dot",
+                    "type": "symbol",
+                    "source": "point",
+                    "layout": {"icon-image": "dot", "icon-allow-overlap": True},
+                },
+            ],
+        }
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+        )
+
+        def send(cmd):
+            proc.stdin.write(json.dumps(cmd).encode() + b"\n")
+            proc.stdin.flush()
+            return json.loads(proc.stdout.readline())
+
+        def center_pixel():
+            header = send({"cmd": "render", "center": [0, 0], "zoom": 1})
+            image = Image.open(io.BytesIO(proc.stdout.read(header["png_len"])))
+            return image.convert("RGB").getpixel((16, 16))
+
+        try:
+            init = {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+            assert send(init)["status"] == "ok"
+            assert center_pixel() == (255, 0, 0)
+
+            Image.new("RGBA", (8, 8), (0, 0, 255, 255)).save(tmp_path / "sprite.png")
+            reloaded = send({"cmd": "reload_resources", "sprites": True})
+            assert reloaded["reloaded"] == ["sprites"]
+            assert reloaded["sprite_images"] == 1
+            assert center_pixel() == (0, 0, 255)
+
+            glyphs = send({"cmd": "reload_resources", "glyphs": True})
+            assert glyphs["code"] == "invalid_command"
+            assert "use reload_style" in glyphs["error"]
+        finally:
+            proc.stdin.close()
+            proc.wait(timeout=10)
+
+    def test_refresh_and_render_reloads_named_sources(self, tmp_path, binary_path):
+        """Test refresh_and_render refetches a GeoJSON data URL before rendering."""
+        data = tmp_path / "area.json"
+
+        def write_area(color):
+            ring = [[-180, -80], [180, -80], [180, 80], [-180, 80], [-180, -80]]
+            area = {
+                "type": "Feature",
+                "properties": {"color": color},
+                "geometry": {"type": "Polygon", "coordinates": [ring]},
+            }
+            data.write_text(json.dumps(area))
+
+        write_area("#ff0000")
+        style = {
+            "version": 8,
+            "sources": {"area": {"type": "geojson", "data": f"file://{data}"}},
+            "layers": [
+                {
+                    "id": "area",
+                    "type": "fill",
+                    "source": "area",
+                    "paint": {"fill-color": ["get", "color"]},
+                }
+            ],
+        }
+        proc = subprocess.Popen(
+            [str(binary_path)],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.DEVNULL,
+        )
+
+        def send(cmd):
+            proc.stdin.write(json.dumps(cmd).encode() + b"\n")
+            proc.stdin.flush()
+            return json.loads(proc.stdout.readline())
+
+        def center_pixel(header):
+            image = Image.open(io.BytesIO(proc.stdout.read(header["png_len"])))
+            return image.convert("RGB").getpixel((16, 16))
+
+        camera = {"center": [0, 0], "zoom": 1}
+        try:
+            init = {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)}
+            assert send(init)["status"] == "ok"
+            assert center_pixel(send({"cmd": "render", **camera})) == (255, 0, 0)
+
+            write_area("#0000ff")
+            refresh = {"cmd": "refresh_and_render", "refresh_sources": ["area"], "camera": camera}
+            refreshed = send(refresh)
+            assert refreshed["refreshed_sources"] == ["area"]
+            assert center_pixel(refreshed) == (0, 0, 255)
+
+            missing = send({**refresh, "refresh_sources": ["area", "missing"]})
+            assert missing["code"] == "render_failed"
+            assert "source 'missing' not found" in missing["error"]
+        finally:
+            proc.stdin.close()
+            proc.wait(timeout=10)
+
+    def test_set_hillshade_tunes_relief_paint(self, tmp_path, run_daemon, read_messages):
+        """Test set_hillshade edits only hillshade layers and checks its ranges."""
+        style = {
+            "version": 8,
+            "sources": {
+                "dem": {
+                    "type": "raster-dem",
+                    "tiles": [f"file://{tmp_path}/{{z}}/{{x}}/{{y}}.png"],
+                    "tileSize": 256,
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background"},
+                {"id": "relief", "type": "hillshade", "source": "dem"},
+            ],
+        }
+        hillshade = {"cmd": "set_hillshade", "layer": "relief"}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            {**hillshade, "exaggeration": 0.8},
+            {**hillshade, "illumination_direction": 90},
+            {"cmd": "get_style"},
+            {**hillshade, "exaggeration": 2},
+            hillshade,
+            {**hillshade, "layer": "bg", "exaggeration": 0.5},
+            {"cmd": "quit"},
+        ]
+        (
+            init,
+            exaggerated,
+            turned,
+            fetched,
+            too_steep,
+            nothing,
+            not_hillshade,
+        ) = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        assert exaggerated["status"] == "ok"
+        assert turned["status"] == "ok"
+        relief = fetched["style_json"]["layers"][1]
+        assert relief["paint"] == {
+            "hillshade-exaggeration": 0.8,
+            "hillshade-illumination-direction": 90,
+        }
+
+        assert too_steep["code"] == "command_failed"
+        assert "exaggeration must be between 0 and 1" in too_steep["error"]
+        assert "give exaggeration, illumination_direction or both" in nothing["error"]
+        assert "layer 'bg' is a background layer, not hillshade" in not_hillshade["error"]
+
+    def test_set_source_tile_size_changes_requested_tile_zoom(
+        self, tmp_path, run_daemon, read_messages
+    ):
+        """Test set_source_tile_size reloads a raster source with the new tileSize."""
+        # Map zoom 1 reads zoom 1 tiles at 512px and zoom 2 tiles at 256px.
+        for z, color in ((1, (255, 0, 0)), (2, (0, 0, 255))):
+            for x in range(2**z):
+                for y in range(2**z):
+                    (tmp_path / f"{z}/{x}").mkdir(parents=True, exist_ok=True)
+                    Image.new("RGB", (256, 256), color).save(tmp_path / f"{z}/{x}/{y}.png")
+        style = {
+            "version": 8,
+            "sources": {
+                "tiles": {
+                    "type": "raster",
+                    "tiles": [f"file://{tmp_path}/{{z}}/{{x}}/{{y}}.png"],
+                    "tileSize": 512,
+                },
+                "points": {"type": "geojson", "data": {"type": "Point", "coordinates": [0, 0]}},
+            },
+            "layers": [{"id": "tiles", "type": "raster", "source": "tiles"}],
+        }
+        render = {"cmd": "render", "center": [0, 0], "zoom": 1}
+        resize = {"cmd": "set_source_tile_size", "source": "tiles"}
+        commands = [
+            {"cmd": "init", "width": 32, "height": 32, "style": json.dumps(style)},
+            render,
+            {**resize, "tile_size": 256},
+            render,
+            {**resize, "tile_size": 300},
+            {**resize, "source": "points", "tile_size": 256},
+            {"cmd": "quit"},
+        ]
+        (
+            init,
+            large,
+            resized,
+            small,
+            not_power,
+            not_raster,
+        ) = read_messages(run_daemon(commands).stdout)
+
+        assert init["status"] == "ok"
+        image = Image.open(io.BytesIO(large["payloads"][0])).convert("RGB")
+        assert image.getpixel((16, 16)) == (255, 0, 0)
+        assert resized["tile_size"] == 256
+        image = Image.open(io.BytesIO(small["payloads"][0])).convert("RGB")
+        assert image.getpixel((16, 16)) == (0, 0, 255)
+
+        assert not_power["code"] == "command_failed"
+        assert "tile_size must be a power of two, got 300" in not_power["error"]
+        assert "source 'points' is a geojson source, not raster" in not_raster["error"]
@@ -250,18 +250,26 @@ class TestRenderBatchValidation:
         with pytest.raises(MlnativeError, match="too large"):
             m.render_batch(views)
 
-    def test_render_batch_rejects_per_view_geojson(self):
-        """Per-view GeoJSON in batch mode should fail fast."""
+    def test_render_batch_rejects_malformed_per_view_operations(self):
+        """Per-view operations must be a list of operation dicts."""
         m = Map(width=512, height=512)
         m.load_style({"version": 8, "sources": {}, "layers": []})
 
-        with pytest.raises(MlnativeError, match="does not support per-view geojson"):
+        with pytest.raises(MlnativeError, match="operations must be a list of dicts"):
+            m.render_batch([{"center": [0, 0], "zoom": 1, "operations": [{"layer": "water"}]}])
+
+    def test_render_batch_rejects_malformed_per_view_geojson(self):
+        """Per-view GeoJSON must map source ids to GeoJSON dicts."""
+        m = Map(width=512, height=512)
+        m.load_style({"version": 8, "sources": {}, "layers": []})
+
+        with pytest.raises(MlnativeError, match="must map source ids to GeoJSON dicts"):
             m.render_batch(
                 [
                     {
                         "center": [0, 0],
                         "zoom": 1,
-                        "geojson": {"markers": {"type": "FeatureCollection", "features": []}},
+                        "geojson": {"markers": "not geojson"},
                     }
                 ]
             )
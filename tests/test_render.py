@@ -4,7 +4,10 @@ Tests for mlnative.
 Integration tests that actually render maps.
 """
 
+from io import BytesIO
+
 import pytest
+from PIL import Image
 
 from mlnative import Map, MlnativeError, __version__
 from mlnative._bridge import get_binary_path
@@ -104,6 +107,150 @@ class TestIntegration:
                 assert png[:4] == b"\x89PNG"
                 assert len(png) > 1000
 
+    @pytest.mark.integration
+    def test_render_batch_updates_geojson_sources(self):
+        """Per-view GeoJSON replaces source data before that view renders."""
+        style = {
+            "version": 8,
+            "sources": {
+                "markers": {
+                    "type": "geojson",
+                    "data": {"type": "FeatureCollection", "features": []},
+                }
+            },
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+                {
+                    "id": "markers",
+                    "type": "circle",
+                    "source": "markers",
+                    "paint": {"circle-color": "#ff0000", "circle-radius": 20},
+                },
+            ],
+        }
+        point = {
+            "type": "FeatureCollection",
+            "features": [
+                {
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [0, 0]},
+                    "properties": {},
+                }
+            ],
+        }
+        empty = {"type": "FeatureCollection", "features": []}
+
+        with Map(width=128, height=128) as m:
+            m.load_style(style)
+            before, with_point, cleared = m.render_batch(
+                [
+                    {"center": [0, 0], "zoom": 3},
+                    {"center": [0, 0], "zoom": 3, "geojson": {"markers": point}},
+                    {"center": [0, 0], "zoom": 3, "geojson": {"markers": empty}},
+                ]
+            )
+
+            assert before != with_point
+            assert cleared == before
+
+            with pytest.raises(MlnativeError, match="'missing' does not exist"):
+                m.render_batch([{"center": [0, 0], "zoom": 3, "geojson": {"missing": point}}])
+
+    @pytest.mark.integration
+    def test_render_batch_reverts_per_view_operations(self):
+        """Per-view operations restyle only their own view."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+            ],
+        }
+        blue = {
+            "op": "set_paint_property",
+            "layer": "bg",
+            "name": "background-color",
+            "value": "#0000ff",
+        }
+
+        with Map(width=64, height=64) as m:
+            m.load_style(style)
+            before, restyled, after = m.render_batch(
+                [
+                    {"center": [0, 0], "zoom": 1},
+                    {"center": [0, 0], "zoom": 1, "operations": [blue]},
+                    {"center": [0, 0], "zoom": 1},
+                ]
+            )
+
+            assert restyled != before
+            assert after == before
+
+            bad = {**blue, "layer": "missing"}
+            with pytest.raises(MlnativeError, match="view 1: layer 'missing' not found"):
+                m.render_batch(
+                    [
+                        {"center": [0, 0], "zoom": 1},
+                        {"center": [0, 0], "zoom": 1, "operations": [bad]},
+                    ]
+                )
+
+    @pytest.mark.integration
+    def test_render_batch_outcomes_keep_successful_views(self):
+        """A failing view doesn't discard the views rendered around it."""
+        style = {
+            "version": 8,
+            "sources": {},
+            "layers": [
+                {"id": "bg", "type": "background", "paint": {"background-color": "#ffffff"}},
+            ],
+        }
+        bad = {
+            "op": "set_paint_property",
+            "layer": "missing",
+            "name": "background-color",
+            "value": "#0000ff",
+        }
+
+        with Map(width=64, height=64) as m:
+            m.load_style(style)
+            first, failed, last = m.render_batch_outcomes(
+                [
+                    {"center": [0, 0], "zoom": 1},
+                    {"center": [0, 0], "zoom": 1, "operations": [bad]},
+                    {"center": [10, 10], "zoom": 2},
+                ]
+            )
+
+            assert isinstance(first, bytes) and first[:4] == b"\x89PNG"
+            assert isinstance(last, bytes) and last[:4] == b"\x89PNG"
+            assert isinstance(failed, MlnativeError)
+            assert "view 1: layer 'missing' not found" in str(failed)
+
+            # Only a batch where every view failed raises.
+            with pytest.raises(MlnativeError, match="view 0: layer 'missing' not found"):
+                m.render_batch_outcomes([{"center": [0, 0], "zoom": 1, "operations": [bad]}])
+
+    @pytest.mark.integration
+    def test_render_batch_per_view_size(self):
+        """Views with their own width and height render at that size."""
+        with Map(width=64, height=48) as m:
+            m.load_style({"version": 8, "sources": {}, "layers": []})
+            wide, default, tall = m.render_batch(
+                [
+                    {"center": [0, 0], "zoom": 1, "width": 96, "height": 32},
+                    {"center": [0, 0], "zoom": 1},
+                    {"center": [0, 0], "zoom": 1, "width": 40, "height": 80},
+                ]
+            )
+
+            assert Image.open(BytesIO(wide)).size == (96, 32)
+            assert Image.open(BytesIO(default)).size == (64, 48)
+            assert Image.open(BytesIO(tall)).size == (40, 80)
+
+            # The init size is restored after the batch.
+            assert Image.open(BytesIO(m.render(center=[0, 0], zoom=1))).size == (64, 48)
+
     @pytest.mark.integration
     def test_fit_bounds_and_render(self):
         """Test fit_bounds combined with render using shapely geometry."""